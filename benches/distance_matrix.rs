@@ -0,0 +1,85 @@
+//! Scoring every pair of a corpus the way `main` builds the distance matrix, against the
+//! sequential loop it replaced. Run with `cargo bench --bench distance_matrix`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/metrics.rs"]
+mod metrics;
+
+const DIMENSIONS: usize = 1536;
+
+fn corpus(documents: usize) -> Vec<(String, Vec<f64>)> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..documents)
+        .map(|i| {
+            let vector = (0..DIMENSIONS).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            (format!("document {i}"), vector)
+        })
+        .collect()
+}
+
+fn pairs(documents: usize) -> Vec<(usize, usize)> {
+    (0..documents)
+        .flat_map(|i| (i..documents).map(move |j| (i, j)))
+        .collect()
+}
+
+/// The loop before the matrix was computed in parallel: one clone of both documents per pair.
+fn cloned_combinations(documents: &[(String, Vec<f64>)]) -> Vec<f64> {
+    documents
+        .iter()
+        .cloned()
+        .enumerate()
+        .combinations_with_replacement(2)
+        .unique_by(|pair| (pair[0].0, pair[1].0))
+        .map(|pair| metrics::cosine(&pair[0].1 .1, &pair[1].1 .1))
+        .collect()
+}
+
+fn sequential(documents: &[(String, Vec<f64>)], pairs: &[(usize, usize)]) -> Vec<f64> {
+    pairs
+        .iter()
+        .map(|&(i, j)| metrics::cosine(&documents[i].1, &documents[j].1))
+        .collect()
+}
+
+fn parallel(documents: &[(String, Vec<f64>)], pairs: &[(usize, usize)]) -> Vec<f64> {
+    pairs
+        .par_iter()
+        .map(|&(i, j)| metrics::cosine(&documents[i].1, &documents[j].1))
+        .collect()
+}
+
+fn distance_matrix(c: &mut Criterion) {
+    let mut group = c.benchmark_group("distance_matrix");
+    group.sample_size(10);
+
+    for size in [200, 500] {
+        let documents = corpus(size);
+        let pairs = pairs(size);
+        // Same scores in the same order, up to the rounding of semanticsimilarity_rs' reductions
+        cloned_combinations(&documents)
+            .into_iter()
+            .zip(parallel(&documents, &pairs))
+            .for_each(|(before, after)| assert!((before - after).abs() < 1e-12));
+
+        group.bench_with_input(BenchmarkId::new("cloned", size), &size, |b, _| {
+            b.iter(|| cloned_combinations(&documents))
+        });
+        group.bench_with_input(BenchmarkId::new("sequential", size), &size, |b, _| {
+            b.iter(|| sequential(&documents, &pairs))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", size), &size, |b, _| {
+            b.iter(|| parallel(&documents, &pairs))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, distance_matrix);
+criterion_main!(benches);
@@ -0,0 +1,287 @@
+use std::{
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    sync::{Arc, OnceLock},
+};
+
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect, Url,
+};
+
+use crate::http;
+
+/// Hosts the tool may contact, set once from `--allowed-hosts`. Every host is allowed without it.
+static ALLOWED_HOSTS: OnceLock<Vec<Allowed>> = OnceLock::new();
+
+/// URL schemes of databases that are files on this machine, whatever their URL's host part.
+const LOCAL_SCHEMES: &[&str] = &["sqlite"];
+
+/// One entry of `--allowed-hosts`.
+#[derive(Debug, PartialEq)]
+enum Allowed {
+    /// A host name, lowercase
+    Host(String),
+    /// `*.domain`: every subdomain of the domain
+    Subdomains(String),
+    /// An IP address or `address/prefix` network the hosts must resolve into
+    Network(IpAddr, u8),
+}
+
+impl Allowed {
+    fn parse(entry: &str) -> Self {
+        let entry = entry.to_lowercase();
+        if let Some(domain) = entry.strip_prefix("*.") {
+            return Allowed::Subdomains(domain.to_string());
+        }
+
+        let (address, prefix) = match entry.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (entry.as_str(), None),
+        };
+        let Ok(address) = address.parse::<IpAddr>() else {
+            return Allowed::Host(entry);
+        };
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.map_or(bits, |prefix| {
+            prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .unwrap_or_else(|| panic!("Invalid network prefix in --allowed-hosts {entry}"))
+        });
+        Allowed::Network(address, prefix)
+    }
+
+    fn matches_name(&self, host: &str) -> bool {
+        match self {
+            Allowed::Host(allowed) => host == allowed,
+            Allowed::Subdomains(domain) => host.ends_with(&format!(".{domain}")),
+            Allowed::Network(..) => false,
+        }
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        let Allowed::Network(network, prefix) = self else {
+            return false;
+        };
+        let (network, address, bits) = match (network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                (u32::from(*network) as u128, u32::from(address) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                (u128::from(*network), u128::from(address), 128)
+            }
+            _ => return false,
+        };
+        let shift = bits - *prefix as u32;
+        shift == bits || network >> shift == address >> shift
+    }
+}
+
+pub fn set_allowed_hosts(hosts: Vec<String>) {
+    let hosts = hosts.iter().map(|host| Allowed::parse(host)).collect();
+    ALLOWED_HOSTS.set(hosts).expect("Allowed hosts already set");
+}
+
+/// Whether `host` matches an allowed host exactly or a `*.domain` pattern as a subdomain, or is
+/// an IP address within an allowed network.
+fn is_allowed_in(allowed_hosts: &[Allowed], host: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+    match host.parse::<IpAddr>() {
+        Ok(address) => allowed_hosts
+            .iter()
+            .any(|allowed| allowed.contains(address)),
+        Err(_) => allowed_hosts
+            .iter()
+            .any(|allowed| allowed.matches_name(&host)),
+    }
+}
+
+/// The `addresses` outside the allowed networks, none if the list has no networks: names are
+/// then trusted to resolve where they should.
+fn unexpected_addresses(allowed_hosts: &[Allowed], addresses: &[IpAddr]) -> Vec<IpAddr> {
+    if !allowed_hosts
+        .iter()
+        .any(|allowed| matches!(allowed, Allowed::Network(..)))
+    {
+        return vec![];
+    }
+    addresses
+        .iter()
+        .copied()
+        .filter(|address| {
+            !allowed_hosts
+                .iter()
+                .any(|allowed| allowed.contains(*address))
+        })
+        .collect()
+}
+
+fn is_allowed(host: &str) -> bool {
+    ALLOWED_HOSTS
+        .get()
+        .is_none_or(|allowed_hosts| is_allowed_in(allowed_hosts, host))
+}
+
+/// Fails if `url` points to a host outside `--allowed-hosts`. SQLite files and URLs without a
+/// host, such as Unix sockets, stay on the machine and are always allowed.
+///
+/// When the list has networks, the host must also resolve into them. That resolution happens
+/// here for connections made outside [http_client], e.g. to databases, Redis or Kafka, which
+/// resolve the name again when they connect.
+pub fn check(url: &str) {
+    let Some(allowed_hosts) = ALLOWED_HOSTS.get() else {
+        return;
+    };
+    let Ok(url) = Url::parse(url) else {
+        return;
+    };
+    if LOCAL_SCHEMES.contains(&url.scheme()) {
+        return;
+    }
+    let Some(host) = url.host_str() else {
+        return;
+    };
+
+    if !is_allowed_in(allowed_hosts, host) {
+        panic!("Refusing to contact {host}: it isn't in --allowed-hosts");
+    }
+    let addresses = (host, url.port_or_known_default().unwrap_or(0))
+        .to_socket_addrs()
+        .map(|addresses| addresses.map(|address| address.ip()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let unexpected = unexpected_addresses(allowed_hosts, &addresses);
+    if !unexpected.is_empty() {
+        panic!(
+            "Refusing to contact {host}: it resolves to {unexpected:?}, outside --allowed-hosts"
+        );
+    }
+}
+
+/// Resolves host names like the system resolver, failing for names resolving outside the
+/// allowed networks, so that the addresses checked are the ones connected to.
+struct AllowlistResolver;
+
+impl Resolve for AllowlistResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addresses = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+
+            if let Some(allowed_hosts) = ALLOWED_HOSTS.get() {
+                let ips = addresses.iter().map(SocketAddr::ip).collect::<Vec<_>>();
+                let unexpected = unexpected_addresses(allowed_hosts, &ips);
+                if !unexpected.is_empty() {
+                    let error =
+                        format!("{host} resolves to {unexpected:?}, outside --allowed-hosts");
+                    return Err(error.into());
+                }
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// An HTTP client that only follows redirects to allowed hosts and only connects to addresses
+/// within the allowed networks, with the `--timeout` and `--proxy` options.
+pub fn http_client() -> reqwest::Client {
+    let policy = redirect::Policy::custom(|attempt| {
+        match attempt.url().host_str().filter(|host| !is_allowed(host)) {
+            Some(host) => {
+                let error = format!("redirected to {host}, which isn't in --allowed-hosts");
+                attempt.error(error)
+            }
+            None if attempt.previous().len() >= 10 => attempt.stop(),
+            None => attempt.follow(),
+        }
+    });
+    let builder = reqwest::Client::builder()
+        .redirect(policy)
+        .dns_resolver(Arc::new(AllowlistResolver));
+    http::configure(builder)
+        .build()
+        .expect("Failed to build the HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(entries: &[&str]) -> Vec<Allowed> {
+        entries.iter().map(|entry| Allowed::parse(entry)).collect()
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn entries_are_hosts_patterns_or_networks() {
+        assert_eq!(
+            allowed(&["API.OpenAI.com", "*.example.com", "10.0.0.0/8", "::1"]),
+            [
+                Allowed::Host("api.openai.com".to_string()),
+                Allowed::Subdomains("example.com".to_string()),
+                Allowed::Network(ip("10.0.0.0"), 8),
+                Allowed::Network(ip("::1"), 128),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid network prefix")]
+    fn network_prefixes_fit_the_address() {
+        Allowed::parse("10.0.0.0/33");
+    }
+
+    #[test]
+    fn hosts_match_by_name_case_insensitively() {
+        let allowed_hosts = allowed(&["api.openai.com", "*.example.com"]);
+        assert!(is_allowed_in(&allowed_hosts, "api.openai.com"));
+        assert!(is_allowed_in(&allowed_hosts, "API.OPENAI.COM"));
+        assert!(is_allowed_in(&allowed_hosts, "cache.example.com"));
+        assert!(is_allowed_in(&allowed_hosts, "a.b.example.com"));
+
+        // The pattern only covers subdomains, and names must match whole labels
+        assert!(!is_allowed_in(&allowed_hosts, "example.com"));
+        assert!(!is_allowed_in(&allowed_hosts, "evil-example.com"));
+        assert!(!is_allowed_in(&allowed_hosts, "api.openai.com.evil.com"));
+        assert!(!is_allowed_in(&allowed_hosts, "10.0.0.1"));
+    }
+
+    #[test]
+    fn addresses_match_networks() {
+        let allowed_hosts = allowed(&["10.0.0.0/8", "192.168.1.7", "fd00::/8", "0.0.0.0/0"]);
+        assert!(allowed_hosts[0].contains(ip("10.255.0.1")));
+        assert!(!allowed_hosts[0].contains(ip("11.0.0.1")));
+        assert!(allowed_hosts[1].contains(ip("192.168.1.7")));
+        assert!(!allowed_hosts[1].contains(ip("192.168.1.8")));
+        assert!(allowed_hosts[2].contains(ip("fd12::1")));
+        assert!(!allowed_hosts[2].contains(ip("fe80::1")));
+        assert!(allowed_hosts[3].contains(ip("8.8.8.8")));
+
+        // IPv4-mapped IPv6 addresses are their IPv4 address
+        assert!(allowed_hosts[0].contains(ip("::ffff:10.1.2.3")));
+        assert!(is_allowed_in(&allowed_hosts[..1], "10.1.2.3"));
+        assert!(is_allowed_in(&allowed(&["::1"]), "[::1]"));
+    }
+
+    #[test]
+    fn resolved_addresses_are_only_checked_against_networks() {
+        let names = allowed(&["api.openai.com"]);
+        assert!(unexpected_addresses(&names, &[ip("203.0.113.9")]).is_empty());
+
+        let networks = allowed(&["api.openai.com", "162.159.0.0/16"]);
+        assert!(unexpected_addresses(&networks, &[ip("162.159.140.245")]).is_empty());
+        assert_eq!(
+            unexpected_addresses(&networks, &[ip("162.159.140.245"), ip("203.0.113.9")]),
+            [ip("203.0.113.9")]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{format_header, pairs::Sort, table, DistanceMetric};
+
+/// Position of the `--anchor` document: the document with this id, else with this text, else at
+/// this position in the input.
+pub fn resolve(anchor: &str, input_ids: &[String], input_strings: &[String]) -> usize {
+    find(anchor, input_ids, input_strings).unwrap_or_else(|| {
+        panic!("--anchor {anchor} is neither the id, text nor position of an input document")
+    })
+}
+
+/// Position of the document with the id, else the text, else at the position `reference`.
+pub fn find(reference: &str, input_ids: &[String], input_strings: &[String]) -> Option<usize> {
+    input_ids
+        .iter()
+        .position(|id| id == reference)
+        .or_else(|| input_strings.iter().position(|text| text == reference))
+        .or_else(|| reference.parse().ok().filter(|i| *i < input_strings.len()))
+}
+
+/// Every other document and its score against the `anchor` document, ordered by `sort` or
+/// closest first.
+pub fn scores(
+    anchor: usize,
+    matrix: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+    sort: Option<&Sort>,
+) -> Vec<(usize, f64)> {
+    let mut scores = (0..matrix.len())
+        .filter(|j| *j != anchor)
+        .map(|j| (j, matrix[anchor][j]))
+        .collect::<Vec<_>>();
+    scores.sort_by(|(_, a), (_, b)| match sort {
+        Some(Sort::Asc) => a.total_cmp(b),
+        Some(Sort::Desc) => b.total_cmp(a),
+        None => distance_metric.cmp_closeness(*b, *a),
+    });
+    scores
+}
+
+/// Prints the `scores` against the `anchor` document as a single column, or only the scores
+/// one per line for `scalar` output.
+pub fn print_scores(
+    anchor: usize,
+    input_strings: &[String],
+    scores: &[(usize, f64)],
+    distance_metric: &DistanceMetric,
+    output_format: &table::OutputFormat,
+) {
+    let mut table = vec![vec![
+        "document".to_string(),
+        format!(
+            "{distance_metric} to {}",
+            format_header(anchor, &input_strings[anchor])
+        ),
+    ]];
+    table.extend(scores.iter().map(|(j, score)| {
+        vec![
+            format_header(*j, &input_strings[*j]),
+            table::format_score(*score),
+        ]
+    }));
+
+    match output_format {
+        table::OutputFormat::Table => table::print(table),
+        table::OutputFormat::Markdown => table::print_markdown(&table),
+        table::OutputFormat::Html => {
+            let values = scores.iter().map(|(_, score)| *score).collect::<Vec<_>>();
+            let closeness = table::closeness(&values, distance_metric.higher_is_closer());
+            table::print_html(&table, |row, column| {
+                (column == 1).then(|| closeness(scores[row].1))
+            });
+        }
+        table::OutputFormat::Scalar => {
+            for (_, score) in scores {
+                println!("{}", table::format_score(*score));
+            }
+        }
+        table::OutputFormat::Parquet => unreachable!("--anchor doesn't write Parquet"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|string| string.to_string()).collect()
+    }
+
+    #[test]
+    fn anchors_are_ids_then_texts_then_positions() {
+        let input_ids = strings(&["10", "2", "article-3"]);
+        let input_strings = strings(&["cats", "dogs", "1"]);
+        assert_eq!(resolve("article-3", &input_ids, &input_strings), 2);
+        assert_eq!(resolve("2", &input_ids, &input_strings), 1);
+        assert_eq!(resolve("dogs", &input_ids, &input_strings), 1);
+        // The text "1" wins over position 1
+        assert_eq!(resolve("1", &input_ids, &input_strings), 2);
+        assert_eq!(resolve("0", &input_ids, &input_strings), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "--anchor 3 is neither")]
+    fn unknown_anchors_fail() {
+        resolve("3", &strings(&["0", "1"]), &strings(&["a", "b"]));
+    }
+
+    #[test]
+    fn scores_are_closest_first_unless_sorted() {
+        let matrix = vec![
+            vec![1.0, 0.2, 0.9, 0.5],
+            vec![0.2, 1.0, 0.1, 0.3],
+            vec![0.9, 0.1, 1.0, 0.4],
+            vec![0.5, 0.3, 0.4, 1.0],
+        ];
+        assert_eq!(
+            scores(0, &matrix, &DistanceMetric::Cosine, None),
+            [(2, 0.9), (3, 0.5), (1, 0.2)]
+        );
+        assert_eq!(
+            scores(0, &matrix, &DistanceMetric::L2, None),
+            [(1, 0.2), (3, 0.5), (2, 0.9)]
+        );
+        assert_eq!(
+            scores(1, &matrix, &DistanceMetric::Cosine, Some(&Sort::Asc)),
+            [(2, 0.1), (0, 0.2), (3, 0.3)]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{ledger::data_dir, warnings, Provider};
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+/// Audit log set once from `--audit-log`.
+static AUDIT_LOG: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_audit_log(path: PathBuf) {
+    AUDIT_LOG.set(path).expect("Audit log already set");
+}
+
+fn audit_log_path() -> PathBuf {
+    AUDIT_LOG
+        .get()
+        .cloned()
+        .unwrap_or_else(|| data_dir().join(AUDIT_FILE))
+}
+
+/// One embedding request as recorded in the audit log. Documents are only identified by their
+/// hash, the log never holds their text.
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: DateTime<Utc>,
+    provider: String,
+    model: &'a str,
+    /// SHA-256 of every document sent, in request order
+    document_hashes: Vec<String>,
+    /// Tokens billed, absent for failed requests
+    tokens: Option<u64>,
+    /// Why the request failed, absent for successful ones
+    error: Option<String>,
+}
+
+/// SHA-256 of every document, identifying them in the audit log.
+pub fn hashes(documents: &[String]) -> Vec<String> {
+    documents
+        .iter()
+        .map(|document| format!("{:x}", Sha256::digest(document)))
+        .collect()
+}
+
+/// Appends `entry` as one line of JSON to the log at `path`, creating its directory.
+fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+    path.parent().map_or(Ok(()), fs::create_dir_all)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+/// Appends a request for the documents of `document_hashes`, successful if billed for
+/// `tokens`, to the audit log.
+///
+/// Failing to write the log only warns: the request has already been sent.
+pub fn record(
+    provider: &Provider,
+    model: &str,
+    document_hashes: Vec<String>,
+    tokens: Option<u64>,
+    error: Option<String>,
+) {
+    let entry = Entry {
+        timestamp: Utc::now(),
+        provider: provider.to_string(),
+        model,
+        document_hashes,
+        tokens,
+        error,
+    };
+
+    let path = audit_log_path();
+    if let Err(error) = append(&path, &entry) {
+        warnings::warn(format!(
+            "Failed to record the request in the audit log {}: {error}",
+            path.display()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn entry(document_hashes: Vec<String>, tokens: Option<u64>, error: Option<&str>) -> Entry<'_> {
+        Entry {
+            timestamp: DateTime::from_timestamp(1_730_800_000, 0).unwrap(),
+            provider: "openai".to_string(),
+            model: "text-embedding-3-small",
+            document_hashes,
+            tokens,
+            error: error.map(String::from),
+        }
+    }
+
+    #[test]
+    fn documents_are_identified_by_their_sha256() {
+        assert_eq!(
+            hashes(&["hello".to_string(), String::new()]),
+            [
+                "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+            ]
+        );
+    }
+
+    #[test]
+    fn requests_are_appended_one_per_line() {
+        let directory = std::env::temp_dir().join(format!(
+            "distance-calculator-audit-{}-log",
+            std::process::id()
+        ));
+        let path = directory.join("nested").join(AUDIT_FILE);
+        let _ = fs::remove_dir_all(&directory);
+
+        let secret = "patient 1234 has diabetes".to_string();
+        append(
+            &path,
+            &entry(hashes(std::slice::from_ref(&secret)), Some(7), None),
+        )
+        .unwrap();
+        append(&path, &entry(vec![], None, Some("HTTP 429"))).unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(!log.contains(&secret));
+        let lines = log
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["timestamp"], "2024-11-05T09:46:40Z");
+        assert_eq!(lines[0]["provider"], "openai");
+        assert_eq!(lines[0]["model"], "text-embedding-3-small");
+        assert_eq!(lines[0]["document_hashes"][0], hashes(&[secret])[0]);
+        assert_eq!(lines[0]["tokens"], 7);
+        assert_eq!(lines[0]["error"], Value::Null);
+        assert_eq!(lines[1]["tokens"], Value::Null);
+        assert_eq!(lines[1]["error"], "HTTP 429");
+    }
+}
@@ -0,0 +1,401 @@
+use std::{fs::File, io::BufWriter};
+
+use chrono::{DateTime, Utc};
+use clap::{error::ErrorKind, Args, Subcommand, ValueEnum};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::EmbeddingCache, files, format_header, models, providers::Embedding, table,
+    DistanceMetric, Provider, ProviderArgs,
+};
+
+/// Largest absolute change of a pair score from the baseline that still passes by default.
+const DEFAULT_TOLERANCE: f64 = 0.01;
+
+#[derive(Subcommand, Debug)]
+pub enum BaselineCommand {
+    /// Embed a corpus and save the score of every pair of its documents as the baseline
+    Save(SaveArgs),
+    /// Embed the corpus of a baseline again and fail if any pair score drifted from the baseline
+    Check(CheckArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct SaveArgs {
+    /// JSON array of the documents of the corpus
+    #[arg(short)]
+    input_file: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// File the baseline is written to
+    #[arg(long, default_value = "baseline.json")]
+    baseline: String,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// Baseline written by `baseline save`
+    #[arg(long, default_value = "baseline.json")]
+    baseline: String,
+    /// JSON array of the current documents, in the order of the baseline's [default: the
+    /// documents of the baseline]
+    #[arg(short)]
+    input_file: Option<String>,
+    /// Provider to embed with [default: the baseline's]
+    #[arg(short, long)]
+    provider: Option<Provider>,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    /// Model to embed with, e.g. a candidate replacement [default: the baseline's]
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: Option<String>,
+    /// Largest absolute change of a pair score from the baseline that passes
+    #[arg(long, default_value_t = DEFAULT_TOLERANCE, value_parser = parse_tolerance)]
+    tolerance: f64,
+    /// Number of the most drifted pairs to print
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+    /// Reuse cached embeddings instead of embedding every document again, to only catch edited
+    /// documents without paying for the others
+    #[arg(long)]
+    cached: bool,
+}
+
+fn parse_tolerance(tolerance: &str) -> Result<f64, String> {
+    match tolerance.parse::<f64>() {
+        Ok(tolerance) if tolerance >= 0.0 => Ok(tolerance),
+        _ => Err(format!("{tolerance} isn't a non-negative number")),
+    }
+}
+
+/// The pair scores of a corpus at one point in time.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Baseline {
+    created_at: DateTime<Utc>,
+    provider: String,
+    model: String,
+    /// Snapshot of the model the provider reported, if any
+    model_version: Option<String>,
+    /// Dimensions asked of the provider with `--dimensions`
+    dimensions: Option<usize>,
+    metric: String,
+    documents: Vec<String>,
+    /// Score of document `i` against every later document, for every `i`
+    scores: Vec<Vec<f64>>,
+}
+
+impl Baseline {
+    fn read(path: &str) -> Self {
+        files::read_json(path)
+    }
+
+    fn write(&self, path: &str) {
+        let file = File::create(files::long_path(path))
+            .unwrap_or_else(|error| panic!("Failed to create {path}: {error}"));
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .unwrap_or_else(|error| panic!("Failed to write {path}: {error}"));
+    }
+
+    fn distance_metric(&self) -> DistanceMetric {
+        DistanceMetric::from_str(&self.metric, false)
+            .unwrap_or_else(|error| panic!("Unknown metric in the baseline: {error}"))
+    }
+}
+
+/// Score of every document against every later document.
+fn upper_scores(embeddings: &[Embedding], distance_metric: &DistanceMetric) -> Vec<Vec<f64>> {
+    (0..embeddings.len())
+        .map(|i| {
+            embeddings[i + 1..]
+                .iter()
+                .map(|other| distance_metric.distance(&embeddings[i].vec, &other.vec))
+                .collect()
+        })
+        .collect()
+}
+
+/// A pair of documents whose score moved from the baseline.
+#[derive(Debug, PartialEq)]
+struct Drift {
+    i: usize,
+    j: usize,
+    baseline: f64,
+    current: f64,
+}
+
+impl Drift {
+    fn change(&self) -> f64 {
+        self.current - self.baseline
+    }
+}
+
+/// Every pair of `baseline` and `current` scores, most drifted first.
+fn drifts(baseline: &[Vec<f64>], current: &[Vec<f64>]) -> Vec<Drift> {
+    baseline
+        .iter()
+        .zip(current)
+        .enumerate()
+        .flat_map(|(i, (baseline, current))| {
+            baseline
+                .iter()
+                .zip(current)
+                .enumerate()
+                .map(move |(offset, (baseline, current))| Drift {
+                    i,
+                    j: i + 1 + offset,
+                    baseline: *baseline,
+                    current: *current,
+                })
+        })
+        .sorted_by(|a, b| b.change().abs().total_cmp(&a.change().abs()))
+        .collect()
+}
+
+pub async fn run(command: BaselineCommand) {
+    match command {
+        BaselineCommand::Save(args) => save(args).await,
+        BaselineCommand::Check(args) => check(args).await,
+    }
+}
+
+/// Embeds the corpus and writes its pair scores to the baseline file.
+async fn save(args: SaveArgs) {
+    let documents = files::read_documents(&args.input_file);
+    let mut cache =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model);
+    let (embeddings, _) = cache
+        .embed(
+            &args.provider,
+            &args.provider_args,
+            &args.embedding_model,
+            &documents,
+            args.provider.max_batch_size(),
+        )
+        .await;
+
+    let baseline = Baseline {
+        created_at: Utc::now(),
+        provider: args.provider.to_string(),
+        model: args.embedding_model.clone(),
+        model_version: cache.latest_version().map(str::to_string),
+        dimensions: args.provider_args.dimensions,
+        metric: args.distance_metric.to_string(),
+        scores: upper_scores(&embeddings, &args.distance_metric),
+        documents,
+    };
+    baseline.write(&args.baseline);
+    let pairs = baseline.scores.iter().map(Vec::len).sum::<usize>();
+    println!(
+        "Saved the {} scores of {pairs} pairs of {} documents to {}",
+        baseline.metric,
+        baseline.documents.len(),
+        args.baseline
+    );
+}
+
+/// Checks that the current documents, read from `input`, are as many as the baseline's.
+fn check_document_count(
+    baseline: &Baseline,
+    documents: &[String],
+    input: &str,
+) -> Result<(), String> {
+    if documents.len() == baseline.documents.len() {
+        return Ok(());
+    }
+    Err(format!(
+        "the baseline has {} documents but {input} has {}: save a new baseline after adding or \
+         removing documents",
+        baseline.documents.len(),
+        documents.len()
+    ))
+}
+
+/// Embeds the documents of the baseline (or their current version) again, prints the pairs
+/// that drifted most and exits with status 1 if any drifted beyond the tolerance.
+async fn check(mut args: CheckArgs) {
+    let baseline = Baseline::read(&args.baseline);
+    let distance_metric = baseline.distance_metric();
+    let provider = args.provider.clone().unwrap_or_else(|| {
+        Provider::from_str(&baseline.provider, false)
+            .unwrap_or_else(|error| panic!("Unknown provider in the baseline: {error}"))
+    });
+    let model = args
+        .embedding_model
+        .clone()
+        .unwrap_or_else(|| baseline.model.clone());
+    args.provider_args.dimensions = args.provider_args.dimensions.or(baseline.dimensions);
+    let documents = match &args.input_file {
+        Some(input_file) => files::read_documents(input_file),
+        None => baseline.documents.clone(),
+    };
+    // Without -i the documents are those of the baseline
+    let input = args.input_file.as_deref().unwrap_or(&args.baseline);
+    if let Err(message) = check_document_count(&baseline, &documents, input) {
+        clap::Error::raw(ErrorKind::InvalidValue, format!("{message}\n")).exit();
+    }
+    let edited = (0..documents.len())
+        .filter(|i| documents[*i] != baseline.documents[*i])
+        .collect::<Vec<_>>();
+    if !edited.is_empty() {
+        println!(
+            "Documents edited since the baseline: {}",
+            edited.iter().join(", ")
+        );
+    }
+
+    let (embeddings, _) = EmbeddingCache::open(&provider, &args.provider_args, &model)
+        .with_refresh(!args.cached)
+        .embed(
+            &provider,
+            &args.provider_args,
+            &model,
+            &documents,
+            provider.max_batch_size(),
+        )
+        .await;
+    let drifts = drifts(
+        &baseline.scores,
+        &upper_scores(&embeddings, &distance_metric),
+    );
+    if drifts.is_empty() {
+        println!("The baseline has no pairs to check");
+        return;
+    }
+
+    let mut table = vec![vec![
+        "doc_i".to_string(),
+        "doc_j".to_string(),
+        "baseline".to_string(),
+        "current".to_string(),
+        "change".to_string(),
+    ]];
+    table.extend(drifts.iter().take(args.top).map(|drift| {
+        vec![
+            format_header(drift.i, &documents[drift.i]),
+            format_header(drift.j, &documents[drift.j]),
+            table::format_score(drift.baseline),
+            table::format_score(drift.current),
+            table::format_score(drift.change()),
+        ]
+    }));
+    table::print(table);
+
+    let failed = drifts
+        .iter()
+        .filter(|drift| drift.change().abs() > args.tolerance)
+        .count();
+    let largest = drifts[0].change().abs();
+    println!(
+        "{failed} of {} pairs drifted by more than {} from the {}/{} baseline of {} \
+         (largest change {})",
+        drifts.len(),
+        args.tolerance,
+        baseline.provider,
+        baseline.model,
+        baseline.created_at.format("%Y-%m-%d"),
+        table::format_score(largest)
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(vec: Vec<f64>) -> Embedding {
+        Embedding {
+            document: String::new(),
+            vec,
+        }
+    }
+
+    #[test]
+    fn upper_scores_pair_every_document_with_the_later_ones() {
+        let embeddings = [
+            embedding(vec![1.0, 0.0]),
+            embedding(vec![0.0, 1.0]),
+            embedding(vec![1.0, 1.0]),
+        ];
+        let scores = upper_scores(&embeddings, &DistanceMetric::Dot);
+        assert_eq!(scores, [vec![0.0, 1.0], vec![1.0], vec![]]);
+    }
+
+    #[test]
+    fn drifts_are_sorted_by_the_size_of_the_change() {
+        let baseline = [vec![0.5, 0.8], vec![0.9], vec![]];
+        let current = [vec![0.5, 0.7], vec![0.95], vec![]];
+        let drifts = drifts(&baseline, &current);
+        assert_eq!(
+            drifts.iter().map(|d| (d.i, d.j)).collect::<Vec<_>>(),
+            [(0, 2), (1, 2), (0, 1)]
+        );
+        assert!((drifts[0].change() + 0.1).abs() < 1e-12);
+        assert_eq!(drifts[2].change(), 0.0);
+    }
+
+    #[test]
+    fn tolerances_are_non_negative() {
+        assert_eq!(parse_tolerance("0.05"), Ok(0.05));
+        assert_eq!(parse_tolerance("0"), Ok(0.0));
+        assert!(parse_tolerance("-0.1").is_err());
+        assert!(parse_tolerance("NaN").is_err());
+    }
+
+    fn baseline() -> Baseline {
+        Baseline {
+            created_at: Utc::now(),
+            provider: "mock".to_string(),
+            model: "demo".to_string(),
+            model_version: None,
+            dimensions: Some(8),
+            metric: "cosine-distance".to_string(),
+            documents: vec!["a".to_string(), "b".to_string()],
+            scores: vec![vec![0.25], vec![]],
+        }
+    }
+
+    #[test]
+    fn baselines_round_trip() {
+        let baseline = baseline();
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-baseline-{}.json",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        baseline.write(path);
+        let read = Baseline::read(path);
+        assert_eq!(read, baseline);
+        assert!(matches!(
+            read.distance_metric(),
+            DistanceMetric::CosineDistance
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_current_documents_must_be_as_many_as_the_baseline() {
+        let baseline = baseline();
+        let documents = ["a", "b edited"].map(String::from);
+        assert_eq!(
+            check_document_count(&baseline, &documents, "docs.json"),
+            Ok(())
+        );
+        assert_eq!(
+            check_document_count(&baseline, &documents[..1], "docs.json"),
+            Err(
+                "the baseline has 2 documents but docs.json has 1: save a new baseline after \
+                 adding or removing documents"
+                    .to_string()
+            )
+        );
+    }
+}
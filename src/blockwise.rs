@@ -0,0 +1,96 @@
+use std::io::Write;
+
+use rayon::prelude::*;
+
+use crate::{providers::Embedding, DistanceMetric};
+
+/// Writes one `source_id,target_id,metric,score` CSV row per pair of distinct documents,
+/// scoring `block_size` rows of the upper triangle at a time.
+///
+/// Only one block of scores is held in memory, so the output can be far larger than what the
+/// full matrix and its table would take.
+pub fn write_pairs(
+    writer: impl Write,
+    input_ids: &[String],
+    documents: &[Embedding],
+    distance_metric: &DistanceMetric,
+    block_size: usize,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["source_id", "target_id", "metric", "score"])?;
+    let metric = distance_metric.to_string();
+
+    let rows = (0..documents.len()).collect::<Vec<_>>();
+    for block in rows.chunks(block_size.max(1)) {
+        let scores = block
+            .par_iter()
+            .map(|&i| {
+                (i + 1..documents.len())
+                    .map(|j| distance_metric.distance(&documents[i].vec, &documents[j].vec))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for (&i, scores) in block.iter().zip(scores) {
+            for (j, score) in (i + 1..).zip(scores) {
+                writer.write_record([
+                    input_ids[i].as_str(),
+                    input_ids[j].as_str(),
+                    metric.as_str(),
+                    score.to_string().as_str(),
+                ])?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documents() -> Vec<Embedding> {
+        (0..7)
+            .map(|i| Embedding {
+                document: format!("document {i}"),
+                vec: vec![1.0 + i as f64, (i * i) as f64 - 3.0, 0.5],
+            })
+            .collect()
+    }
+
+    fn pairs(block_size: usize) -> String {
+        let documents = documents();
+        let input_ids = (0..documents.len())
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        let mut written = vec![];
+        write_pairs(
+            &mut written,
+            &input_ids,
+            &documents,
+            &DistanceMetric::Cosine,
+            block_size,
+        )
+        .unwrap();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn blocks_write_the_upper_triangle_of_the_full_matrix() {
+        let documents = documents();
+        let mut expected = "source_id,target_id,metric,score\n".to_string();
+        for i in 0..documents.len() {
+            for j in i + 1..documents.len() {
+                let score = DistanceMetric::Cosine.distance(&documents[i].vec, &documents[j].vec);
+                expected.push_str(&format!("{i},{j},cosine,{score}\n"));
+            }
+        }
+        assert_eq!(expected.lines().count(), 1 + 7 * 6 / 2);
+        // 3 doesn't divide the 7 rows, 0 is taken as 1 and 100 holds every row in one block
+        for block_size in [3, 0, 1, 7, 100] {
+            assert_eq!(pairs(block_size), expected, "{block_size}");
+        }
+    }
+}
@@ -0,0 +1,664 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::{
+    deadline, embed_versioned, ledger::data_dir, progress, providers::Embedding,
+    shared_cache::SharedCache, warnings, Provider, ProviderArgs,
+};
+
+const CACHE_DIR: &str = "cache";
+const COMPRESSION_LEVEL: i32 = 3;
+/// Document length marking a record that sets the model version of the records after it.
+const VERSION_MARKER: u32 = u32::MAX;
+
+/// One line of the JSON lines caches written by earlier versions.
+#[derive(Deserialize)]
+struct LegacyEntry {
+    document: String,
+    vec: Vec<f64>,
+}
+
+/// A cached vector and the snapshot of the model that embedded it, when the provider reported
+/// one.
+struct Entry {
+    version: Option<String>,
+    vec: Vec<f64>,
+}
+
+/// The model version and vector of a document in the shared cache, if it is there.
+type SharedEntry = Option<(Option<String>, Vec<f64>)>;
+
+/// Vectors already paid for, by document text, so unchanged documents are never embedded twice
+/// with the same model.
+///
+/// Each provider and model has its own `cache/<provider>/<model>.zst` file: a sequence of zstd
+/// frames, one per batch of stored embeddings, that decompress to records of
+/// `document length (u32) | document (UTF-8) | dimensions (u32) | vector (f64 each)`, all
+/// little-endian. Every frame starts with a `u32::MAX | version length (u32) | version (UTF-8)`
+/// record naming the model snapshot that embedded it, empty when the provider reports none.
+///
+/// Once a provider reports a new snapshot of the model, entries embedded by an older one are
+/// stale and embedded again, so a run never mixes vectors of two snapshots. Entries of unknown
+/// version are kept.
+pub struct EmbeddingCache {
+    path: PathBuf,
+    vectors: HashMap<String, Entry>,
+    /// Most recent model snapshot stored in the cache
+    latest_version: Option<String>,
+    refresh: bool,
+    /// Provider and cache name the shared cache of this model is keyed by
+    shared_name: Option<(String, String)>,
+}
+
+impl EmbeddingCache {
+    pub fn open(provider: &Provider, provider_args: &ProviderArgs, embedding_model: &str) -> Self {
+        let name = provider_args.cache_name(embedding_model);
+        let directory = data_dir().join(CACHE_DIR).join(provider.to_string());
+        let mut cache = Self::open_at(cache_path(&directory, &name));
+        // Older versions named JSON lines caches with their extension set, not appended
+        let legacy_path = directory
+            .join(name.replace(['/', '\\'], "_"))
+            .with_extension("jsonl");
+        cache.migrate(&legacy_path);
+        cache.shared_name = Some((provider.to_string(), name));
+        cache
+    }
+
+    /// Opens a cache file at an explicit location, e.g. the checkpoint of a long run.
+    pub fn open_at(path: PathBuf) -> Self {
+        let (vectors, latest_version) = File::open(&path).map(read_records).unwrap_or_default();
+        EmbeddingCache {
+            path,
+            vectors,
+            latest_version,
+            refresh: false,
+            shared_name: None,
+        }
+    }
+
+    /// Most recent snapshot of the model reported by the provider, if any.
+    pub fn latest_version(&self) -> Option<&str> {
+        self.latest_version.as_deref()
+    }
+
+    /// Embeds every document again instead of reading it from the cache.
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Moves the entries of an uncompressed JSON lines cache into the compressed one.
+    fn migrate(&mut self, legacy_path: &Path) {
+        let Ok(file) = File::open(legacy_path) else {
+            return;
+        };
+
+        let embeddings = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<LegacyEntry>(&line).ok())
+            .filter(|entry| !self.vectors.contains_key(&entry.document))
+            .map(|entry| Embedding {
+                document: entry.document,
+                vec: entry.vec,
+            })
+            .collect::<Vec<_>>();
+
+        if self.store(&embeddings, None).is_ok() {
+            let _ = fs::remove_file(legacy_path);
+        }
+        self.insert(embeddings, None);
+    }
+
+    fn insert(&mut self, embeddings: Vec<Embedding>, version: Option<String>) {
+        self.vectors.extend(embeddings.into_iter().map(|embedding| {
+            let entry = Entry {
+                version: version.clone(),
+                vec: embedding.vec,
+            };
+            (embedding.document, entry)
+        }));
+    }
+
+    /// Whether `document` must be embedded (again): it is missing, or was embedded by an older
+    /// snapshot of the model than the latest one.
+    fn is_stale(&self, document: &str) -> bool {
+        match self.vectors.get(document) {
+            None => true,
+            Some(entry) => match (&entry.version, &self.latest_version) {
+                (Some(version), Some(latest)) => version != latest,
+                _ => false,
+            },
+        }
+    }
+
+    /// The distinct `documents` that [EmbeddingCache::embed] would send to the provider, ignoring
+    /// the shared cache.
+    pub fn uncached(&self, documents: &[String]) -> Vec<String> {
+        documents
+            .iter()
+            .filter(|document| self.refresh || self.is_stale(document))
+            .unique()
+            .cloned()
+            .collect()
+    }
+
+    /// Embeds the documents missing from the cache `batch_size` at a time, stores every batch as
+    /// soon as it is embedded, and returns the embeddings of all `input_strings` in order along
+    /// with the number of documents that were embedded.
+    ///
+    /// An interrupted run thus only loses its last batch: rerunning it resumes from the cache.
+    /// Documents cached under an older model snapshot than the one that answers are embedded
+    /// again, as are all documents with [EmbeddingCache::with_refresh].
+    ///
+    /// With a shared cache, missing documents are first looked up there and the embedded ones
+    /// are added to it.
+    pub async fn embed(
+        &mut self,
+        provider: &Provider,
+        provider_args: &ProviderArgs,
+        embedding_model: &str,
+        input_strings: &[String],
+        batch_size: usize,
+    ) -> (Vec<Embedding>, usize) {
+        let embedded = self
+            .embed_pending(
+                provider,
+                provider_args,
+                embedding_model,
+                input_strings,
+                batch_size,
+            )
+            .await;
+        (self.vectors_of(input_strings), embedded)
+    }
+
+    /// [EmbeddingCache::embed], stopping once the `--deadline` passes, even in the middle of a
+    /// request. The documents that weren't embedded by then have no embedding.
+    pub async fn embed_by_deadline(
+        &mut self,
+        provider: &Provider,
+        provider_args: &ProviderArgs,
+        embedding_model: &str,
+        input_strings: &[String],
+        batch_size: usize,
+    ) -> Vec<Option<Embedding>> {
+        self.embed_pending(
+            provider,
+            provider_args,
+            embedding_model,
+            input_strings,
+            batch_size,
+        )
+        .await;
+        input_strings
+            .iter()
+            .map(|document| {
+                self.vectors.get(document).map(|entry| Embedding {
+                    document: document.clone(),
+                    vec: entry.vec.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Embeds and stores the documents of `input_strings` missing from the cache, until the
+    /// deadline if there is one, and returns how many were embedded.
+    async fn embed_pending(
+        &mut self,
+        provider: &Provider,
+        provider_args: &ProviderArgs,
+        embedding_model: &str,
+        input_strings: &[String],
+        batch_size: usize,
+    ) -> usize {
+        let mut pending = self.lookup(input_strings);
+        let mut shared = connect_shared(self.shared_name.as_ref(), &pending).await;
+        if let (Some(shared), false) = (&mut shared, self.refresh) {
+            let entries = fetch_shared(shared, &pending).await;
+            self.add_shared(&mut pending, entries);
+        }
+
+        let mut embedded = 0;
+
+        // A second pass embeds again the documents a newly reported snapshot made stale
+        'passes: for pass in 0..2 {
+            let bar = progress::bar(pending.len(), "documents").with_message("Embedding");
+            for batch in pending.chunks(batch_size.max(1)) {
+                let request =
+                    embed_batch(provider, provider_args, embedding_model, batch, &mut shared);
+                let response = match deadline::remaining() {
+                    Some(remaining) => tokio::time::timeout(remaining, request).await.ok(),
+                    None => Some(request.await),
+                };
+                let Some((embeddings, version)) = response else {
+                    bar.finish_and_clear();
+                    break 'passes;
+                };
+                self.add(embeddings, version);
+                embedded += batch.len();
+                bar.inc(batch.len() as u64);
+            }
+            bar.finish_and_clear();
+            pending = self.still_stale(input_strings, pass, embedding_model);
+        }
+
+        embedded
+    }
+
+    /// [EmbeddingCache::embed] on a cache shared by concurrent requests, only locked while it is
+    /// read or updated: requests for cached documents don't wait for the provider to answer
+    /// another request. Documents two requests miss at the same time are embedded by both.
+    pub async fn embed_locked(
+        cache: &Mutex<Self>,
+        provider: &Provider,
+        provider_args: &ProviderArgs,
+        embedding_model: &str,
+        input_strings: &[String],
+        batch_size: usize,
+    ) -> Vec<Embedding> {
+        let (mut pending, shared_name, refresh) = {
+            let cache = cache.lock().await;
+            let pending = cache.lookup(input_strings);
+            (pending, cache.shared_name.clone(), cache.refresh)
+        };
+        let mut shared = connect_shared(shared_name.as_ref(), &pending).await;
+        if let (Some(shared), false) = (&mut shared, refresh) {
+            let entries = fetch_shared(shared, &pending).await;
+            cache.lock().await.add_shared(&mut pending, entries);
+        }
+
+        for pass in 0..2 {
+            for batch in pending.chunks(batch_size.max(1)) {
+                let (embeddings, version) =
+                    embed_batch(provider, provider_args, embedding_model, batch, &mut shared).await;
+                cache.lock().await.add(embeddings, version);
+            }
+            pending = cache
+                .lock()
+                .await
+                .still_stale(input_strings, pass, embedding_model);
+        }
+
+        cache.lock().await.vectors_of(input_strings)
+    }
+
+    /// The documents of `input_strings` to embed, logging the lookup.
+    fn lookup(&self, input_strings: &[String]) -> Vec<String> {
+        let pending = self.uncached(input_strings);
+        tracing::info!(
+            cache = %self.path.display(),
+            documents = input_strings.len(),
+            uncached = pending.len(),
+            "Cache lookup"
+        );
+        pending
+    }
+
+    /// Stores `embeddings` of the model `version` and adds them to the cache.
+    pub fn add(&mut self, embeddings: Vec<Embedding>, version: Option<String>) {
+        if let Err(error) = self.store(&embeddings, version.as_deref()) {
+            warnings::warn(format!(
+                "Failed to cache embeddings in {}: {error}",
+                self.path.display()
+            ));
+        }
+        if version.is_some() {
+            self.latest_version.clone_from(&version);
+        }
+        self.insert(embeddings, version);
+    }
+
+    /// The documents of `input_strings` that are stale after embedding `pass`, warning after the
+    /// first one: a newly reported snapshot made them stale.
+    fn still_stale(
+        &self,
+        input_strings: &[String],
+        pass: usize,
+        embedding_model: &str,
+    ) -> Vec<String> {
+        let pending = input_strings
+            .iter()
+            .filter(|document| self.is_stale(document))
+            .unique()
+            .cloned()
+            .collect::<Vec<_>>();
+        if pass == 0 && !pending.is_empty() {
+            warnings::warn(format!(
+                "{} cached documents were embedded by an older snapshot of {embedding_model} \
+                 than {} and are embedded again",
+                pending.len(),
+                self.latest_version.as_deref().unwrap_or_default()
+            ));
+        }
+        pending
+    }
+
+    fn vectors_of(&self, input_strings: &[String]) -> Vec<Embedding> {
+        input_strings
+            .iter()
+            .map(|document| Embedding {
+                document: document.clone(),
+                vec: self.vectors[document].vec.clone(),
+            })
+            .collect()
+    }
+
+    /// Adds the `entries` of the shared cache for the `pending` documents to this cache and its
+    /// file, and keeps the documents still to embed in `pending`.
+    fn add_shared(&mut self, pending: &mut Vec<String>, entries: Option<Vec<SharedEntry>>) {
+        let mut versions = HashMap::<_, Vec<_>>::new();
+        for (document, entry) in pending.iter().zip(entries.into_iter().flatten()) {
+            if let Some((version, vec)) = entry {
+                versions.entry(version).or_default().push(Embedding {
+                    document: document.clone(),
+                    vec,
+                });
+            }
+        }
+
+        for (version, embeddings) in versions {
+            if let Err(error) = self.store(&embeddings, version.as_deref()) {
+                warnings::warn(format!(
+                    "Failed to cache embeddings in {}: {error}",
+                    self.path.display()
+                ));
+            }
+            self.insert(embeddings, version);
+        }
+
+        let uncached = pending.len();
+        pending.retain(|document| self.is_stale(document));
+        tracing::info!(hits = uncached - pending.len(), "Shared cache lookup");
+    }
+
+    /// Appends `embeddings` to the cache file as a new zstd frame, tagged with the model
+    /// `version` that embedded them.
+    fn store(&self, embeddings: &[Embedding], version: Option<&str>) -> io::Result<()> {
+        if embeddings.is_empty() {
+            return Ok(());
+        }
+
+        let version = version.unwrap_or_default();
+        let mut records = vec![];
+        records.extend(VERSION_MARKER.to_le_bytes());
+        records.extend((version.len() as u32).to_le_bytes());
+        records.extend(version.as_bytes());
+        for embedding in embeddings {
+            write_record(&mut records, &embedding.document, &embedding.vec);
+        }
+        let frame = zstd::encode_all(records.as_slice(), COMPRESSION_LEVEL)?;
+
+        fs::create_dir_all(self.path.parent().unwrap())?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&frame)
+    }
+}
+
+/// Connects to the shared cache of the model `shared_name`, if any and if `pending` documents
+/// could be found there.
+async fn connect_shared(
+    shared_name: Option<&(String, String)>,
+    pending: &[String],
+) -> Option<SharedCache> {
+    match shared_name {
+        Some((provider, name)) if !pending.is_empty() => SharedCache::connect(provider, name).await,
+        _ => None,
+    }
+}
+
+/// The file of the cache named `name` in `directory`.
+fn cache_path(directory: &Path, name: &str) -> PathBuf {
+    // Appended rather than set, as model names like `embed-v4.0` have a dot of their own
+    directory.join(format!("{}.zst", name.replace(['/', '\\'], "_")))
+}
+
+/// The shared cache entries of `pending`, none if it can't be read.
+async fn fetch_shared(shared: &mut SharedCache, pending: &[String]) -> Option<Vec<SharedEntry>> {
+    shared
+        .fetch(pending)
+        .await
+        .inspect_err(|error| warnings::warn(format!("Failed to read the shared cache: {error}")))
+        .ok()
+}
+
+/// Embeds `batch` and adds the embeddings to the `shared` cache, if any.
+async fn embed_batch(
+    provider: &Provider,
+    provider_args: &ProviderArgs,
+    embedding_model: &str,
+    batch: &[String],
+    shared: &mut Option<SharedCache>,
+) -> (Vec<Embedding>, Option<String>) {
+    let (embeddings, version) =
+        embed_versioned(provider, provider_args, embedding_model, batch.to_vec()).await;
+    if let Some(shared) = shared {
+        if let Err(error) = shared.store(&embeddings, version.as_deref()).await {
+            warnings::warn(format!("Failed to write the shared cache: {error}"));
+        }
+    }
+    (embeddings, version)
+}
+
+fn write_record(buffer: &mut Vec<u8>, document: &str, vector: &[f64]) {
+    buffer.extend((document.len() as u32).to_le_bytes());
+    buffer.extend(document.as_bytes());
+    buffer.extend((vector.len() as u32).to_le_bytes());
+    for value in vector {
+        buffer.extend(value.to_le_bytes());
+    }
+}
+
+/// A record of a cache file.
+enum Record {
+    /// Model version of the records that follow, up to the end of the frame, empty if unknown
+    Version(String),
+    Vector(String, Vec<f64>),
+}
+
+/// Reads every complete record of a cache file, stopping at the first truncated or corrupt one
+/// (e.g. a frame left half-written by an interrupted run), and returns the entries along with
+/// the most recent model version.
+fn read_records(file: File) -> (HashMap<String, Entry>, Option<String>) {
+    let mut vectors = HashMap::new();
+    let mut latest_version = None;
+    let Ok(mut decoder) = zstd::Decoder::new(file) else {
+        return (vectors, latest_version);
+    };
+
+    // Frames written before versions were recorded have no marker and come first
+    let mut version = None;
+    while let Some(record) = read_record(&mut decoder) {
+        match record {
+            Record::Version(frame_version) => {
+                version = Some(frame_version).filter(|version| !version.is_empty());
+                if version.is_some() {
+                    latest_version.clone_from(&version);
+                }
+            }
+            Record::Vector(document, vec) => {
+                let entry = Entry {
+                    version: version.clone(),
+                    vec,
+                };
+                vectors.insert(document, entry);
+            }
+        }
+    }
+    (vectors, latest_version)
+}
+
+fn read_record(reader: &mut impl Read) -> Option<Record> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length).ok()?;
+    if u32::from_le_bytes(length) == VERSION_MARKER {
+        reader.read_exact(&mut length).ok()?;
+        let mut version = vec![0; u32::from_le_bytes(length) as usize];
+        reader.read_exact(&mut version).ok()?;
+        return Some(Record::Version(String::from_utf8(version).ok()?));
+    }
+    let mut document = vec![0; u32::from_le_bytes(length) as usize];
+    reader.read_exact(&mut document).ok()?;
+
+    reader.read_exact(&mut length).ok()?;
+    let mut bytes = vec![0; u32::from_le_bytes(length) as usize * 8];
+    reader.read_exact(&mut bytes).ok()?;
+    let vector = bytes
+        .chunks_exact(8)
+        .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+
+    Some(Record::Vector(String::from_utf8(document).ok()?, vector))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("distance-calculator-cache-{}", std::process::id()))
+            .join(format!("{name}.zst"));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn embedding(document: &str, vec: Vec<f64>) -> Embedding {
+        Embedding {
+            document: document.to_string(),
+            vec,
+        }
+    }
+
+    #[test]
+    fn frames_round_trip_with_their_versions() {
+        let path = path("versions");
+        let cache = EmbeddingCache::open_at(path.clone());
+        cache
+            .store(&[embedding("old", vec![1.0, -2.5])], Some("v1"))
+            .unwrap();
+        cache
+            .store(
+                &[embedding("new", vec![0.0]), embedding("é", vec![])],
+                Some("v2"),
+            )
+            .unwrap();
+        cache
+            .store(&[embedding("unknown", vec![3.0])], None)
+            .unwrap();
+        // Nothing is written for an empty batch
+        cache.store(&[], Some("v3")).unwrap();
+
+        let cache = EmbeddingCache::open_at(path);
+        assert_eq!(cache.vectors.len(), 4);
+        assert_eq!(cache.vectors["old"].vec, vec![1.0, -2.5]);
+        assert_eq!(cache.vectors["old"].version.as_deref(), Some("v1"));
+        assert_eq!(cache.vectors["é"].vec, Vec::<f64>::new());
+        assert_eq!(cache.vectors["unknown"].version, None);
+        assert_eq!(cache.latest_version.as_deref(), Some("v2"));
+    }
+
+    #[test]
+    fn entries_of_older_snapshots_are_stale() {
+        let path = path("stale");
+        let cache = EmbeddingCache::open_at(path.clone());
+        cache
+            .store(&[embedding("old", vec![1.0])], Some("v1"))
+            .unwrap();
+        cache
+            .store(&[embedding("new", vec![2.0])], Some("v2"))
+            .unwrap();
+        cache
+            .store(&[embedding("unknown", vec![3.0])], None)
+            .unwrap();
+
+        let cache = EmbeddingCache::open_at(path);
+        let documents = ["old", "new", "unknown", "missing", "missing"].map(str::to_string);
+        assert_eq!(cache.uncached(&documents), ["old", "missing"]);
+        assert_eq!(cache.with_refresh(true).uncached(&documents).len(), 4);
+    }
+
+    #[test]
+    fn frames_without_a_version_marker_are_read_as_unknown() {
+        let path = path("legacy-frame");
+        let mut records = vec![];
+        write_record(&mut records, "first", &[1.0, 2.0]);
+        let frame = zstd::encode_all(records.as_slice(), COMPRESSION_LEVEL).unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, frame).unwrap();
+
+        let cache = EmbeddingCache::open_at(path);
+        assert_eq!(cache.vectors["first"].vec, vec![1.0, 2.0]);
+        assert_eq!(cache.vectors["first"].version, None);
+        assert_eq!(cache.latest_version, None);
+    }
+
+    #[test]
+    fn a_truncated_frame_keeps_the_complete_records() {
+        let path = path("truncated");
+        let cache = EmbeddingCache::open_at(path.clone());
+        cache
+            .store(&[embedding("kept", vec![1.0])], Some("v1"))
+            .unwrap();
+        let complete = fs::metadata(&path).unwrap().len();
+        cache
+            .store(&[embedding("lost", vec![2.0; 100])], Some("v1"))
+            .unwrap();
+
+        // An interrupted run leaves half of its last frame
+        let contents = fs::read(&path).unwrap();
+        let written = contents.len() as u64 - complete;
+        fs::write(&path, &contents[..(complete + written / 2) as usize]).unwrap();
+
+        let cache = EmbeddingCache::open_at(path);
+        assert!(cache.vectors.contains_key("kept"));
+        assert!(!cache.vectors.contains_key("lost"));
+    }
+
+    #[test]
+    fn options_of_dotted_model_names_have_their_own_file() {
+        let directory = Path::new("cache");
+        assert_eq!(
+            cache_path(directory, "embed-v4.0"),
+            directory.join("embed-v4.0.zst")
+        );
+        assert_ne!(
+            cache_path(directory, "embed-v4.0"),
+            cache_path(directory, "embed-v4.0@image")
+        );
+        assert_eq!(
+            cache_path(directory, "org/model"),
+            directory.join("org_model.zst")
+        );
+    }
+
+    #[test]
+    fn json_lines_caches_are_migrated() {
+        let path = path("migrated");
+        let legacy_path = path.with_extension("jsonl");
+        fs::write(
+            &legacy_path,
+            "{\"document\": \"first\", \"vec\": [1.0]}\nnot json\n{\"document\": \"second\", \"vec\": [2.0]}\n",
+        )
+        .unwrap();
+
+        let mut cache = EmbeddingCache::open_at(path.clone());
+        cache.migrate(&legacy_path);
+        assert!(!legacy_path.exists());
+        assert_eq!(cache.vectors.len(), 2);
+
+        let cache = EmbeddingCache::open_at(path);
+        assert_eq!(cache.vectors["second"].vec, vec![2.0]);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{fmt::Display, ops::Range};
+
+use clap::ValueEnum;
+
+use crate::{providers::Embedding, DistanceMetric};
+
+/// How the chunks of a document combine into its score against another document.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Aggregation {
+    /// The mean of the chunk vectors represents the document
+    Mean,
+    /// Two documents score as their closest pair of chunks
+    MaxSim,
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Aggregation::Mean => write!(f, "mean"),
+            Aggregation::MaxSim => write!(f, "max-sim"),
+        }
+    }
+}
+
+/// Splits `text` into chunks of `size` words, each starting `overlap` words before the end of
+/// the previous one. Texts of at most `size` words are a single chunk.
+fn chunk(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    if words.len() <= size {
+        return vec![text.to_string()];
+    }
+
+    let step = size - overlap;
+    (0..words.len())
+        .step_by(step)
+        .take_while(|start| start + overlap < words.len())
+        .map(|start| words[start..(start + size).min(words.len())].join(" "))
+        .collect()
+}
+
+/// The chunks of all `documents`, in order, and the range of the chunks of each document.
+///
+/// Fails on documents without words: they would be an empty chunk, which providers reject or
+/// embed as noise.
+pub fn split(
+    documents: &[String],
+    size: usize,
+    overlap: usize,
+) -> (Vec<String>, Vec<Range<usize>>) {
+    assert!(size > 0, "--chunk-size must be positive");
+    assert!(
+        overlap < size,
+        "--chunk-overlap must be smaller than --chunk-size"
+    );
+
+    let mut chunks = vec![];
+    let ranges = documents
+        .iter()
+        .enumerate()
+        .map(|(i, document)| {
+            assert!(
+                document.split_whitespace().next().is_some(),
+                "Document {i} is empty: it has no words to chunk"
+            );
+            let start = chunks.len();
+            chunks.extend(chunk(document, size, overlap));
+            start..chunks.len()
+        })
+        .collect();
+    (chunks, ranges)
+}
+
+/// One embedding per document, the mean of the vectors of its chunks.
+pub fn mean_pool(
+    documents: &[String],
+    chunks: &[Embedding],
+    ranges: &[Range<usize>],
+) -> Vec<Embedding> {
+    documents
+        .iter()
+        .zip(ranges)
+        .map(|(document, range)| {
+            let vectors = &chunks[range.clone()];
+            let mut mean = vec![0.0; vectors[0].vec.len()];
+            for vector in vectors {
+                for (sum, value) in mean.iter_mut().zip(&vector.vec) {
+                    *sum += value / vectors.len() as f64;
+                }
+            }
+            Embedding {
+                document: document.clone(),
+                vec: mean,
+            }
+        })
+        .collect()
+}
+
+/// Score of the closest pair of a chunk of `a` and a chunk of `b`.
+pub fn max_sim(a: &[Embedding], b: &[Embedding], distance_metric: &DistanceMetric) -> f64 {
+    a.iter()
+        .flat_map(|a| b.iter().map(|b| distance_metric.distance(&a.vec, &b.vec)))
+        .max_by(|x, y| distance_metric.cmp_closeness(*x, *y))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(document: &str, vec: Vec<f64>) -> Embedding {
+        Embedding {
+            document: document.to_string(),
+            vec,
+        }
+    }
+
+    #[test]
+    fn short_documents_are_one_chunk() {
+        assert_eq!(chunk("one  two\nthree", 3, 1), ["one  two\nthree"]);
+    }
+
+    #[test]
+    fn chunks_overlap_and_end_with_the_document() {
+        assert_eq!(chunk("a b c d e f g", 3, 1), ["a b c", "c d e", "e f g"]);
+        // The last chunk can be shorter, but never only the overlap of the previous one
+        assert_eq!(chunk("a b c d e", 3, 1), ["a b c", "c d e"]);
+        assert_eq!(chunk("a b c d e f", 3, 1), ["a b c", "c d e", "e f"]);
+        assert_eq!(chunk("a b c d", 2, 0), ["a b", "c d"]);
+    }
+
+    #[test]
+    fn ranges_locate_the_chunks_of_each_document() {
+        let documents = ["a b c d".to_string(), "e".to_string(), "f g h".to_string()];
+        let (chunks, ranges) = split(&documents, 2, 0);
+        assert_eq!(chunks, ["a b", "c d", "e", "f g", "h"]);
+        assert_eq!(ranges, [0..2, 2..3, 3..5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Document 1 is empty")]
+    fn empty_documents_are_refused() {
+        split(&["a b".to_string(), " \n".to_string()], 2, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "--chunk-overlap must be smaller")]
+    fn overlap_is_smaller_than_the_chunks() {
+        split(&["a b".to_string()], 2, 2);
+    }
+
+    #[test]
+    fn mean_pool_averages_the_chunks_of_each_document() {
+        let documents = ["first".to_string(), "second".to_string()];
+        let chunks = [
+            embedding("a", vec![1.0, 0.0]),
+            embedding("b", vec![0.0, 1.0]),
+            embedding("c", vec![2.0, 4.0]),
+        ];
+        let pooled = mean_pool(&documents, &chunks, &[0..2, 2..3]);
+        assert_eq!(
+            pooled,
+            [
+                embedding("first", vec![0.5, 0.5]),
+                embedding("second", vec![2.0, 4.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_sim_scores_the_closest_chunks() {
+        let a = [
+            embedding("a", vec![1.0, 0.0]),
+            embedding("b", vec![0.0, 1.0]),
+        ];
+        let b = [
+            embedding("c", vec![0.0, 2.0]),
+            embedding("d", vec![-1.0, 0.0]),
+        ];
+        assert!((max_sim(&a, &b, &DistanceMetric::Cosine) - 1.0).abs() < 1e-12);
+        assert!((max_sim(&a, &b, &DistanceMetric::L2) - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+
+use clap::Args;
+use serde::Deserialize;
+
+use crate::{
+    cache::EmbeddingCache, cluster, files, format_header, models, table, DistanceMetric, Provider,
+    ProviderArgs, EMPTY,
+};
+
+#[derive(Args, Debug)]
+pub struct ClassifyArgs {
+    /// JSON array of labeled `{"text", "label"}` training documents
+    #[arg(long)]
+    train: String,
+    /// JSON array of the documents to classify, plain strings or `{"text", "label"}` objects
+    /// whose labels are compared with the predictions
+    #[arg(long)]
+    test: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InputDocument {
+    Text(String),
+    Labeled { text: String, label: Option<String> },
+}
+
+fn read_documents(path: &str) -> Vec<(String, Option<String>)> {
+    files::read_json::<Vec<InputDocument>>(path)
+        .into_iter()
+        .map(|document| match document {
+            InputDocument::Text(text) => (text, None),
+            InputDocument::Labeled { text, label } => (text, label),
+        })
+        .collect()
+}
+
+/// Every label and the mean of the vectors of its documents, ordered by label.
+fn centroids(vectors: &[Vec<f64>], labels: &[&str]) -> Vec<(String, Vec<f64>)> {
+    let mut members = BTreeMap::<_, Vec<_>>::new();
+    for (vector, label) in vectors.iter().zip(labels) {
+        members.entry(*label).or_default().push(vector);
+    }
+    members
+        .into_iter()
+        .map(|(label, vectors)| (label.to_string(), cluster::mean(&vectors)))
+        .collect()
+}
+
+/// The centroid closest to a document, its score against it and how much closer it is than the
+/// runner-up, `None` with a single label.
+#[derive(Debug, PartialEq)]
+struct Prediction {
+    centroid: usize,
+    score: f64,
+    margin: Option<f64>,
+}
+
+fn classify(
+    vector: &[f64],
+    centroids: &[(String, Vec<f64>)],
+    distance_metric: &DistanceMetric,
+) -> Prediction {
+    let mut scores = centroids
+        .iter()
+        .map(|(_, centroid)| distance_metric.distance(vector, centroid))
+        .enumerate()
+        .collect::<Vec<_>>();
+    // Closest first, ties going to the first label
+    scores.sort_by(|(a, a_score), (b, b_score)| {
+        distance_metric
+            .cmp_closeness(*b_score, *a_score)
+            .then(a.cmp(b))
+    });
+    let (centroid, score) = scores[0];
+    Prediction {
+        centroid,
+        score,
+        margin: scores.get(1).map(|(_, second)| (score - second).abs()),
+    }
+}
+
+/// Classifies every test document by the label whose training centroid is closest, printing the
+/// predictions and, when the test documents are labeled, the accuracy.
+pub async fn run(args: ClassifyArgs) {
+    let train = read_documents(&args.train);
+    let test = read_documents(&args.test);
+    let train_labels = train
+        .iter()
+        .map(|(text, label)| {
+            label.as_deref().unwrap_or_else(|| {
+                panic!("Training document {text:?} of {} has no label", args.train)
+            })
+        })
+        .collect::<Vec<_>>();
+    assert!(
+        !train.is_empty(),
+        "{} has no training documents",
+        args.train
+    );
+    let texts = train
+        .iter()
+        .chain(&test)
+        .map(|(text, _)| text.clone())
+        .collect::<Vec<_>>();
+
+    let (embeddings, _) =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &texts,
+                args.provider.max_batch_size(),
+            )
+            .await;
+    let mut vectors = embeddings
+        .into_iter()
+        .map(|embedding| embedding.vec)
+        .collect::<Vec<_>>();
+    let test_vectors = vectors.split_off(train.len());
+    let centroids = centroids(&vectors, &train_labels);
+
+    let labeled = test.iter().any(|(_, label)| label.is_some());
+    let mut header = vec![
+        "document".to_string(),
+        "predicted".to_string(),
+        format!("{} to centroid", args.distance_metric),
+        "margin".to_string(),
+    ];
+    if labeled {
+        header.push("expected".to_string());
+    }
+    let mut table = vec![header];
+    let (mut correct, mut evaluated) = (0, 0);
+    for (i, ((text, label), vector)) in test.iter().zip(&test_vectors).enumerate() {
+        let prediction = classify(vector, &centroids, &args.distance_metric);
+        let predicted = &centroids[prediction.centroid].0;
+        let mut row = vec![
+            format_header(i, text),
+            predicted.clone(),
+            table::format_score(prediction.score),
+            prediction
+                .margin
+                .map_or(EMPTY.to_string(), table::format_score),
+        ];
+        if labeled {
+            row.push(label.clone().unwrap_or(EMPTY.to_string()));
+        }
+        if let Some(label) = label {
+            evaluated += 1;
+            correct += usize::from(label == predicted);
+        }
+        table.push(row);
+    }
+    table::print(table);
+
+    if evaluated > 0 {
+        println!(
+            "accuracy {} ({correct} of {evaluated} labeled documents)",
+            correct as f64 / evaluated as f64
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centroids_are_the_means_of_every_label() {
+        let vectors = vec![vec![0.0, 0.0], vec![10.0, 10.0], vec![2.0, 0.0]];
+        let centroids = centroids(&vectors, &["b", "a", "b"]);
+        assert_eq!(
+            centroids,
+            [
+                ("a".to_string(), vec![10.0, 10.0]),
+                ("b".to_string(), vec![1.0, 0.0])
+            ]
+        );
+    }
+
+    #[test]
+    fn documents_get_the_closest_centroid() {
+        let centroids = vec![
+            ("a".to_string(), vec![1.0, 0.0]),
+            ("b".to_string(), vec![0.0, 1.0]),
+        ];
+        let prediction = classify(&[0.0, 3.0], &centroids, &DistanceMetric::L2);
+        assert_eq!(prediction.centroid, 1);
+        assert_eq!(prediction.score, 2.0);
+        assert!((prediction.margin.unwrap() - (10f64.sqrt() - 2.0)).abs() < 1e-12);
+
+        let prediction = classify(&[0.9, 0.1], &centroids, &DistanceMetric::Cosine);
+        assert_eq!(prediction.centroid, 0);
+        assert!(prediction.margin.unwrap() > 0.8);
+    }
+
+    #[test]
+    fn a_single_label_has_no_margin() {
+        let centroids = vec![("a".to_string(), vec![1.0, 0.0])];
+        let prediction = classify(&[0.0, 1.0], &centroids, &DistanceMetric::L2);
+        assert_eq!(prediction.centroid, 0);
+        assert_eq!(prediction.margin, None);
+    }
+}
@@ -0,0 +1,439 @@
+use itertools::Itertools;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+
+use serde::Deserialize;
+
+use crate::{files, format_header, metrics::euclidean, table, DistanceMetric};
+
+const MAX_ITERATIONS: usize = 100;
+
+/// Assigns each vector to one of `k` clusters using Lloyd's algorithm with k-means++ seeding.
+pub fn kmeans(vectors: &[Vec<f64>], k: usize, rng: &mut impl Rng) -> Vec<usize> {
+    kmeans_with_centroids(vectors, k, rng).0
+}
+
+/// [`kmeans`], also returning the centroid of every cluster. No vectors make no clusters.
+pub fn kmeans_with_centroids(
+    vectors: &[Vec<f64>],
+    k: usize,
+    rng: &mut impl Rng,
+) -> (Vec<usize>, Vec<Vec<f64>>) {
+    if vectors.is_empty() {
+        return (vec![], vec![]);
+    }
+    let k = k.clamp(1, vectors.len());
+    let mut centroids = seed_centroids(vectors, k, rng);
+    let mut assignments = vec![];
+
+    for _ in 0..MAX_ITERATIONS {
+        let next = vectors
+            .iter()
+            .map(|vector| nearest_centroid(vector, &centroids))
+            .collect::<Vec<_>>();
+
+        if next == assignments {
+            break;
+        }
+        assignments = next;
+
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let members = vectors
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, assignment)| **assignment == cluster)
+                .map(|(vector, _)| vector)
+                .collect::<Vec<_>>();
+
+            // Keep the previous centroid of a cluster that lost all of its members
+            if !members.is_empty() {
+                *centroid = mean(&members);
+            }
+        }
+    }
+
+    (assignments, centroids)
+}
+
+fn seed_centroids(vectors: &[Vec<f64>], k: usize, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+    let mut centroids = vec![vectors[rng.gen_range(0..vectors.len())].clone()];
+
+    while centroids.len() < k {
+        let weights = vectors
+            .iter()
+            .map(|vector| {
+                let nearest = &centroids[nearest_centroid(vector, &centroids)];
+                euclidean(vector, nearest).powi(2)
+            })
+            .collect::<Vec<_>>();
+
+        let next = match WeightedIndex::new(&weights) {
+            Ok(distribution) => distribution.sample(rng),
+            // Every vector coincides with a centroid already: duplicates are all that is left
+            Err(_) => rng.gen_range(0..vectors.len()),
+        };
+        centroids.push(vectors[next].clone());
+    }
+
+    centroids
+}
+
+pub fn nearest_centroid(vector: &[f64], centroids: &[Vec<f64>]) -> usize {
+    centroids
+        .iter()
+        .map(|centroid| euclidean(vector, centroid))
+        .position_min_by(f64::total_cmp)
+        .unwrap()
+}
+
+pub fn mean(vectors: &[&Vec<f64>]) -> Vec<f64> {
+    let mut mean = vec![0.0; vectors[0].len()];
+    for vector in vectors {
+        for (total, value) in mean.iter_mut().zip(vector.iter()) {
+            *total += value;
+        }
+    }
+
+    mean.iter()
+        .map(|total| total / vectors.len() as f64)
+        .collect()
+}
+
+/// Mean pairwise score between the members of a cluster, or `None` for singleton clusters.
+pub fn cohesion(vectors: &[&Vec<f64>], distance_metric: &DistanceMetric) -> Option<f64> {
+    let scores = vectors
+        .iter()
+        .tuple_combinations()
+        .map(|(first, second)| distance_metric.distance(first, second))
+        .collect::<Vec<_>>();
+
+    if scores.is_empty() {
+        None
+    } else {
+        Some(scores.iter().sum::<f64>() / scores.len() as f64)
+    }
+}
+
+/// Mean score between the members of a cluster and the documents outside it, or `None` when
+/// every document is in the cluster.
+pub fn separation(
+    members: &[&Vec<f64>],
+    others: &[&Vec<f64>],
+    distance_metric: &DistanceMetric,
+) -> Option<f64> {
+    if members.is_empty() || others.is_empty() {
+        return None;
+    }
+    let total = members
+        .iter()
+        .cartesian_product(others)
+        .map(|(member, other)| distance_metric.distance(member, other))
+        .sum::<f64>();
+    Some(total / (members.len() * others.len()) as f64)
+}
+
+/// Silhouette of every vector: how much closer it is to its own cluster than to the nearest other
+/// one, from -1 (misplaced) to 1 (well separated), 0 for singleton clusters. Closeness is the
+/// metric's dissimilarity, so the silhouettes of dot product scores aren't bounded.
+pub fn silhouettes(
+    vectors: &[Vec<f64>],
+    assignments: &[usize],
+    distance_metric: &DistanceMetric,
+) -> Vec<f64> {
+    let clusters = assignments.iter().copied().unique().collect::<Vec<_>>();
+    (0..vectors.len())
+        .map(|i| {
+            // Mean dissimilarity of vector i to the other members of every cluster
+            let mean_to = |cluster: usize| {
+                let dissimilarities = (0..vectors.len())
+                    .filter(|j| *j != i && assignments[*j] == cluster)
+                    .map(|j| distance_metric.dissimilarity(&vectors[i], &vectors[j]))
+                    .collect::<Vec<_>>();
+                (!dissimilarities.is_empty())
+                    .then(|| dissimilarities.iter().sum::<f64>() / dissimilarities.len() as f64)
+            };
+            let Some(within) = mean_to(assignments[i]) else {
+                return 0.0;
+            };
+            let Some(nearest) = clusters
+                .iter()
+                .filter(|cluster| **cluster != assignments[i])
+                .filter_map(|cluster| mean_to(*cluster))
+                .min_by(f64::total_cmp)
+            else {
+                return 0.0;
+            };
+            let scale = within.max(nearest);
+            if scale == 0.0 {
+                0.0
+            } else {
+                (nearest - within) / scale
+            }
+        })
+        .collect()
+}
+
+/// Davies–Bouldin index: the mean over clusters of the ratio of the spreads of a cluster and its
+/// most similar other cluster to the dissimilarity of their centroids. Lower is better, and it
+/// needs at least two clusters.
+pub fn davies_bouldin(
+    vectors: &[Vec<f64>],
+    assignments: &[usize],
+    distance_metric: &DistanceMetric,
+) -> Option<f64> {
+    let clusters = assignments
+        .iter()
+        .copied()
+        .unique()
+        .sorted()
+        .collect::<Vec<_>>();
+    if clusters.len() < 2 {
+        return None;
+    }
+    let (centroids, spreads): (Vec<_>, Vec<_>) = clusters
+        .iter()
+        .map(|cluster| {
+            let members = members(vectors, assignments, *cluster);
+            let centroid = mean(&members);
+            let spread = members
+                .iter()
+                .map(|member| distance_metric.dissimilarity(member, &centroid))
+                .sum::<f64>()
+                / members.len() as f64;
+            (centroid, spread)
+        })
+        .unzip();
+    let ratios = (0..clusters.len()).map(|a| {
+        (0..clusters.len())
+            .filter(|b| *b != a)
+            .map(|b| {
+                (spreads[a] + spreads[b])
+                    / distance_metric.dissimilarity(&centroids[a], &centroids[b])
+            })
+            .fold(f64::NEG_INFINITY, f64::max)
+    });
+    Some(ratios.sum::<f64>() / clusters.len() as f64)
+}
+
+fn members<'a>(
+    vectors: &'a [Vec<f64>],
+    assignments: &[usize],
+    cluster: usize,
+) -> Vec<&'a Vec<f64>> {
+    vectors
+        .iter()
+        .zip(assignments)
+        .filter(|(_, assignment)| **assignment == cluster)
+        .map(|(vector, _)| vector)
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Label {
+    Plain(String),
+    Labeled { label: String },
+}
+
+/// Labels of a `--labels` file, a JSON array of labels or of labeled documents.
+pub fn read_labels(path: &str) -> Vec<String> {
+    files::read_json::<Vec<Label>>(path)
+        .into_iter()
+        .map(|label| match label {
+            Label::Plain(label) | Label::Labeled { label } => label,
+        })
+        .collect()
+}
+
+pub fn print_clusters(
+    input_strings: &[String],
+    vectors: &[Vec<f64>],
+    assignments: &[usize],
+    distance_metric: &DistanceMetric,
+) {
+    let mut documents = vec![vec!["document".to_string(), "cluster".to_string()]];
+    documents.extend(
+        input_strings
+            .iter()
+            .zip(assignments)
+            .enumerate()
+            .map(|(i, (string, cluster))| vec![format_header(i, string), cluster.to_string()]),
+    );
+    table::print(documents);
+
+    let names = (0..=assignments.iter().copied().max().unwrap_or(0))
+        .map(|cluster| cluster.to_string())
+        .collect::<Vec<_>>();
+    print_quality(&names, vectors, assignments, distance_metric);
+}
+
+/// Prints the size, cohesion, separation and mean silhouette of every cluster, `names[cluster]`
+/// naming it, then the silhouette score and Davies–Bouldin index of the whole clustering.
+pub fn print_quality(
+    names: &[String],
+    vectors: &[Vec<f64>],
+    assignments: &[usize],
+    distance_metric: &DistanceMetric,
+) {
+    let silhouettes = silhouettes(vectors, assignments, distance_metric);
+    let mut clusters = vec![vec![
+        "cluster".to_string(),
+        "size".to_string(),
+        format!("cohesion ({distance_metric})"),
+        format!("separation ({distance_metric})"),
+        "silhouette".to_string(),
+    ]];
+    let format = |score: Option<f64>| score.map_or(crate::EMPTY.to_string(), table::format_score);
+    for cluster in assignments.iter().copied().unique().sorted() {
+        let members = members(vectors, assignments, cluster);
+        let others = vectors
+            .iter()
+            .zip(assignments)
+            .filter(|(_, assignment)| **assignment != cluster)
+            .map(|(vector, _)| vector)
+            .collect::<Vec<_>>();
+        let silhouette = silhouettes
+            .iter()
+            .zip(assignments)
+            .filter(|(_, assignment)| **assignment == cluster)
+            .map(|(silhouette, _)| silhouette)
+            .sum::<f64>()
+            / members.len() as f64;
+
+        clusters.push(vec![
+            names[cluster].clone(),
+            members.len().to_string(),
+            format(cohesion(&members, distance_metric)),
+            format(separation(&members, &others, distance_metric)),
+            table::format_score(silhouette),
+        ]);
+    }
+    table::print(clusters);
+
+    if let Some(davies_bouldin) = davies_bouldin(vectors, assignments, distance_metric) {
+        println!(
+            "silhouette {}, davies-bouldin {davies_bouldin}",
+            silhouettes.iter().sum::<f64>() / silhouettes.len() as f64
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Two groups of three points around (0, 0) and (10, 10).
+    fn two_groups() -> Vec<Vec<f64>> {
+        vec![
+            vec![0.0, 0.0],
+            vec![0.5, 0.0],
+            vec![0.0, 0.5],
+            vec![10.0, 10.0],
+            vec![10.5, 10.0],
+            vec![10.0, 10.5],
+        ]
+    }
+
+    #[test]
+    fn separated_groups_are_found() {
+        let assignments = kmeans(&two_groups(), 2, &mut StdRng::seed_from_u64(0));
+
+        assert!(assignments[..3]
+            .iter()
+            .all(|cluster| *cluster == assignments[0]));
+        assert!(assignments[3..]
+            .iter()
+            .all(|cluster| *cluster == assignments[3]));
+        assert_ne!(assignments[0], assignments[3]);
+    }
+
+    #[test]
+    fn centroids_are_the_means_of_their_clusters() {
+        let (assignments, centroids) =
+            kmeans_with_centroids(&two_groups(), 2, &mut StdRng::seed_from_u64(1));
+
+        let first = &centroids[assignments[0]];
+        assert!((first[0] - 1.0 / 6.0).abs() < 1e-12);
+        assert!((first[1] - 1.0 / 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn k_is_capped_by_the_number_of_vectors() {
+        let (assignments, centroids) =
+            kmeans_with_centroids(&two_groups()[..2], 5, &mut StdRng::seed_from_u64(0));
+        assert_eq!(centroids.len(), 2);
+        assert_eq!(assignments.len(), 2);
+    }
+
+    #[test]
+    fn duplicates_still_get_k_centroids() {
+        let vectors = vec![vec![1.0, 1.0]; 4];
+        let (assignments, centroids) =
+            kmeans_with_centroids(&vectors, 3, &mut StdRng::seed_from_u64(0));
+        assert_eq!(centroids.len(), 3);
+        assert!(assignments.iter().all(|cluster| *cluster == assignments[0]));
+    }
+
+    #[test]
+    fn no_vectors_make_no_clusters() {
+        let (assignments, centroids) = kmeans_with_centroids(&[], 3, &mut StdRng::seed_from_u64(0));
+        assert!(assignments.is_empty());
+        assert!(centroids.is_empty());
+    }
+
+    #[test]
+    fn cohesion_is_the_mean_pairwise_score() {
+        let vectors = two_groups();
+        let members = vectors[..3].iter().collect::<Vec<_>>();
+
+        let score = cohesion(&members, &DistanceMetric::L2).unwrap();
+        assert!((score - (0.5 + 0.5 + 0.5f64.sqrt()) / 3.0).abs() < 1e-12);
+        assert_eq!(cohesion(&[&vectors[0]], &DistanceMetric::L2), None);
+    }
+
+    #[test]
+    fn separated_groups_score_well() {
+        let vectors = two_groups();
+        let assignments = [0, 0, 0, 1, 1, 1];
+        let separated = silhouettes(&vectors, &assignments, &DistanceMetric::L2);
+        assert!(separated.iter().all(|silhouette| *silhouette > 0.9));
+
+        let mixed = [0, 1, 0, 1, 0, 1];
+        let mixed_silhouettes = silhouettes(&vectors, &mixed, &DistanceMetric::L2);
+        assert!(mixed_silhouettes.iter().sum::<f64>() < 0.0);
+
+        let separated = davies_bouldin(&vectors, &assignments, &DistanceMetric::L2).unwrap();
+        let overlapping = davies_bouldin(&vectors, &mixed, &DistanceMetric::L2).unwrap();
+        assert!(separated < 0.1 && overlapping > 1.0);
+        assert_eq!(davies_bouldin(&vectors, &[0; 6], &DistanceMetric::L2), None);
+    }
+
+    #[test]
+    fn singletons_have_a_zero_silhouette() {
+        let vectors = two_groups();
+        let silhouettes = silhouettes(&vectors[..4], &[0, 0, 0, 1], &DistanceMetric::L2);
+        assert_eq!(silhouettes[3], 0.0);
+        assert!(silhouettes[0] > 0.9);
+    }
+
+    #[test]
+    fn separation_is_the_mean_score_to_other_clusters() {
+        let members = [vec![0.0, 0.0], vec![0.0, 2.0]];
+        let others = [vec![3.0, 0.0]];
+        let members = members.iter().collect::<Vec<_>>();
+        let others = others.iter().collect::<Vec<_>>();
+        let score = separation(&members, &others, &DistanceMetric::L2).unwrap();
+        assert!((score - (3.0 + 13f64.sqrt()) / 2.0).abs() < 1e-12);
+        assert_eq!(separation(&members, &[], &DistanceMetric::L2), None);
+    }
+
+    #[test]
+    fn labels_are_read_plain_or_from_labeled_documents() {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-labels-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, r#"["a", {"text": "some text", "label": "b"}]"#).unwrap();
+        assert_eq!(read_labels(path.to_str().unwrap()), ["a", "b"]);
+    }
+}
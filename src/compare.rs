@@ -0,0 +1,270 @@
+use clap::Args;
+use itertools::Itertools;
+
+use crate::{
+    cache::EmbeddingCache,
+    estimate::{self, Estimate},
+    files, format_header, keys, models, stats, table, DistanceMetric, Provider, ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// JSON array of the documents to compare
+    #[arg(short)]
+    input_file: String,
+    /// Provider of each model, in the order of the models (a single provider applies to all of
+    /// them)
+    #[arg(short, long, default_values_t = [Provider::Openai])]
+    provider: Vec<Provider>,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    /// Embedding model to compare (repeat for every model)
+    #[arg(short, long, required = true, value_parser = models::parse_model)]
+    embedding_model: Vec<String>,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// Embed every document again, even those already cached
+    #[arg(long)]
+    refresh: bool,
+    /// Print the tokens and estimated cost of embedding the uncached documents with every model,
+    /// without embedding them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl CompareArgs {
+    fn input_strings(&self) -> Vec<String> {
+        files::read_documents(&self.input_file)
+    }
+
+    /// Checks there's a single `--provider` or one per `--embedding-model`.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.provider.len() == 1 || self.provider.len() == self.embedding_model.len() {
+            return Ok(());
+        }
+        Err(format!(
+            "expected a single '--provider' or one per '--embedding-model', got {} for {} models",
+            self.provider.len(),
+            self.embedding_model.len()
+        ))
+    }
+
+    /// Every model along with its provider.
+    fn models(&self) -> Vec<(&Provider, &String)> {
+        self.provider
+            .iter()
+            .cycle()
+            .zip(&self.embedding_model)
+            .collect()
+    }
+}
+
+/// The score of every pair of documents under each model, under a header of the `labels` of
+/// the models.
+fn score_table(
+    input_strings: &[String],
+    labels: &[String],
+    model_scores: &[Vec<f64>],
+) -> Vec<Vec<String>> {
+    let mut table = vec![["doc_i".to_string(), "doc_j".to_string()]
+        .into_iter()
+        .chain(labels.iter().cloned())
+        .collect::<Vec<_>>()];
+    for (pair, (i, j)) in (0..input_strings.len()).tuple_combinations().enumerate() {
+        let mut row = vec![
+            format_header(i, &input_strings[i]),
+            format_header(j, &input_strings[j]),
+        ];
+        row.extend(
+            model_scores
+                .iter()
+                .map(|scores| table::format_score(scores[pair])),
+        );
+        table.push(row);
+    }
+    table
+}
+
+/// The Spearman rank correlation between the scores of every two models.
+fn correlation_table(labels: &[String], model_scores: &[Vec<f64>]) -> Vec<Vec<String>> {
+    let mut correlations = vec![vec![
+        "model_a".to_string(),
+        "model_b".to_string(),
+        "spearman".to_string(),
+    ]];
+    for (a, b) in (0..labels.len()).tuple_combinations() {
+        correlations.push(vec![
+            labels[a].clone(),
+            labels[b].clone(),
+            table::format_score(stats::spearman(&model_scores[a], &model_scores[b])),
+        ]);
+    }
+    correlations
+}
+
+/// Embeds the same documents with every model and prints the score of every pair of documents
+/// under each model side by side, followed by the Spearman rank correlation between the scores
+/// of every two models.
+pub async fn run(args: CompareArgs) {
+    keys::check_unscoped(&args.provider);
+    let input_strings = args.input_strings();
+    let models = args.models();
+
+    if args.dry_run {
+        let estimates = models
+            .iter()
+            .map(|(provider, model)| Estimate {
+                provider: (*provider).clone(),
+                model: (*model).clone(),
+                documents: input_strings.len(),
+                uncached: EmbeddingCache::open(provider, &args.provider_args, model)
+                    .with_refresh(args.refresh)
+                    .uncached(&input_strings),
+            })
+            .collect::<Vec<_>>();
+        estimate::print_report(&estimates);
+        return;
+    }
+
+    let mut model_scores = vec![];
+    for (provider, model) in &models {
+        let (documents, _) = EmbeddingCache::open(provider, &args.provider_args, model)
+            .with_refresh(args.refresh)
+            .embed(
+                provider,
+                &args.provider_args,
+                model,
+                &input_strings,
+                provider.max_batch_size(),
+            )
+            .await;
+
+        let scores = documents
+            .iter()
+            .tuple_combinations()
+            .map(|(first, second)| args.distance_metric.distance(&first.vec, &second.vec))
+            .collect::<Vec<_>>();
+        model_scores.push(scores);
+    }
+
+    let labels = models
+        .iter()
+        .map(|(provider, model)| format!("{provider}/{model}"))
+        .collect::<Vec<_>>();
+
+    table::print(score_table(&input_strings, &labels, &model_scores));
+
+    if models.len() < 2 {
+        return;
+    }
+    println!();
+    table::print(correlation_table(&labels, &model_scores));
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        compare: CompareArgs,
+    }
+
+    fn parse(args: &[&str]) -> CompareArgs {
+        Cli::parse_from(["compare", "-i", "documents.json"].iter().chain(args)).compare
+    }
+
+    #[test]
+    fn a_single_provider_applies_to_every_model() {
+        let args = parse(&[
+            "-p",
+            "cohere",
+            "-e",
+            "embed-english-v3.0",
+            "-e",
+            "embed-v4.0",
+        ]);
+        assert_eq!(args.validate(), Ok(()));
+        assert_eq!(
+            args.models(),
+            [
+                (&Provider::Cohere, &"embed-english-v3.0".to_string()),
+                (&Provider::Cohere, &"embed-v4.0".to_string())
+            ]
+        );
+        let args = parse(&["-e", "text-embedding-3-small"]);
+        assert_eq!(
+            args.models(),
+            [(&Provider::Openai, &"text-embedding-3-small".to_string())]
+        );
+    }
+
+    #[test]
+    fn providers_pair_with_the_models_in_order() {
+        let args = parse(&[
+            "-p",
+            "openai",
+            "-e",
+            "text-embedding-3-small",
+            "-p",
+            "cohere",
+            "-e",
+            "embed-english-v3.0",
+        ]);
+        assert_eq!(args.validate(), Ok(()));
+        assert_eq!(
+            args.models(),
+            [
+                (&Provider::Openai, &"text-embedding-3-small".to_string()),
+                (&Provider::Cohere, &"embed-english-v3.0".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn other_provider_counts_are_rejected() {
+        let args = parse(&[
+            "-p",
+            "openai",
+            "-p",
+            "cohere",
+            "-e",
+            "text-embedding-3-small",
+            "-e",
+            "text-embedding-3-large",
+            "-e",
+            "embed-english-v3.0",
+        ]);
+        assert_eq!(
+            args.validate(),
+            Err(
+                "expected a single '--provider' or one per '--embedding-model', got 2 for 3 models"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn every_pair_is_scored_by_every_model() {
+        let input_strings = ["cat", "dog", "car"].map(String::from);
+        let labels = ["openai/small", "cohere/english"].map(String::from);
+        let model_scores = [vec![0.9, 0.2, 0.1], vec![0.1, 0.3, 0.4]];
+        assert_eq!(
+            score_table(&input_strings, &labels, &model_scores),
+            [
+                vec!["doc_i", "doc_j", "openai/small", "cohere/english"],
+                vec!["0: cat", "1: dog", "0.9", "0.1"],
+                vec!["0: cat", "2: car", "0.2", "0.3"],
+                vec!["1: dog", "2: car", "0.1", "0.4"],
+            ]
+        );
+        let correlations = correlation_table(&labels, &model_scores);
+        assert_eq!(correlations[0], ["model_a", "model_b", "spearman"]);
+        assert_eq!(correlations[1][..2], ["openai/small", "cohere/english"]);
+        let spearman = correlations[1][2].parse::<f64>().unwrap();
+        assert!((spearman + 1.0).abs() < 1e-12, "{spearman}");
+    }
+}
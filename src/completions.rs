@@ -0,0 +1,80 @@
+use std::{io::Write, path::Path};
+
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to complete the commands and flags of the tool in
+    shell: Shell,
+}
+
+#[derive(Args, Debug)]
+pub struct ManArgs {
+    /// Directory to write a page per command to, e.g. `/usr/local/share/man/man1` [default: print
+    /// the page of the tool to stdout]
+    #[arg(long)]
+    out_dir: Option<String>,
+}
+
+/// The arguments of the tool, named after its binary rather than its display name, which the
+/// completion scripts and man pages refer to.
+fn command() -> clap::Command {
+    let binary = env!("CARGO_BIN_NAME");
+    crate::Args::command().name(binary).bin_name(binary)
+}
+
+/// Writes the completion script of `shell` for every command and flag of the tool.
+fn write_completions(shell: Shell, writer: &mut impl Write) {
+    clap_complete::generate(shell, &mut command(), env!("CARGO_BIN_NAME"), writer);
+}
+
+/// Prints the completion script of a shell, to source from its startup file.
+pub fn run(args: CompletionsArgs) {
+    write_completions(args.shell, &mut std::io::stdout());
+}
+
+/// Prints the man page of the tool, or writes one per command to a directory.
+pub fn run_man(args: ManArgs) {
+    let command = command();
+    match &args.out_dir {
+        Some(out_dir) => clap_mangen::generate_to(command, Path::new(out_dir))
+            .unwrap_or_else(|error| panic!("Failed to write the man pages to {out_dir}: {error}")),
+        None => clap_mangen::Man::new(command)
+            .render(&mut std::io::stdout())
+            .unwrap_or_else(|error| panic!("Failed to print the man page: {error}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_consistent() {
+        crate::Args::command().debug_assert();
+    }
+
+    #[test]
+    fn completions_cover_subcommands_and_flags() {
+        let mut script = vec![];
+        write_completions(Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("gen-corpus"));
+        assert!(script.contains("--label-width"));
+    }
+
+    #[test]
+    fn man_pages_are_written_per_command() {
+        let out_dir =
+            std::env::temp_dir().join(format!("distance-calculator-man-{}", std::process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        run_man(ManArgs {
+            out_dir: Some(out_dir.to_str().unwrap().to_string()),
+        });
+        let page = std::fs::read_to_string(out_dir.join("distance-calculator.1")).unwrap();
+        assert!(page.starts_with(".ie"));
+        assert!(page.contains("\\-\\-precision"));
+        assert!(out_dir.join("distance-calculator-classify.1").exists());
+    }
+}
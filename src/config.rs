@@ -0,0 +1,266 @@
+use std::{env, ffi::OsString, path::Path};
+
+use clap::{Command, CommandFactory};
+use toml::{Table, Value};
+
+use crate::{files, Args};
+
+/// Config file read from the working directory when no `--config` is given.
+const DEFAULT_CONFIG: &str = "distance-calculator.toml";
+
+/// The command-line arguments preceded by those of the config file.
+///
+/// The config file sets options by their name (`embedding_model = "text-embedding-3-small"`,
+/// `normalize = true`, ...), top-level keys for the main command and `[<subcommand>]` tables
+/// for subcommands. Options given on the command line or through their environment variable
+/// take precedence over the file.
+pub fn args() -> Vec<OsString> {
+    let args = env::args_os().collect::<Vec<_>>();
+    load_env_file(&args);
+    match read_config(&args) {
+        Some(config) => with_config(args, config),
+        None => args,
+    }
+}
+
+/// `args` preceded by the options of `config` they don't already set.
+fn with_config(mut args: Vec<OsString>, config: Table) -> Vec<OsString> {
+    let command = Args::command();
+    let subcommand = args.iter().enumerate().skip(1).find_map(|(position, arg)| {
+        command
+            .get_subcommands()
+            .find(|subcommand| arg == subcommand.get_name())
+            .map(|subcommand| (position, subcommand))
+    });
+
+    if let Some((position, subcommand)) = subcommand {
+        if let Some(Value::Table(table)) = config.get(subcommand.get_name()) {
+            let values = config_args(subcommand, table, &args[position + 1..]);
+            args.splice(position + 1..position + 1, values);
+        }
+    }
+
+    let main_table = config
+        .into_iter()
+        .filter(|(_, value)| !value.is_table())
+        .collect::<Table>();
+    let cli_args = &args[1..subcommand.map_or(args.len(), |(position, _)| position)];
+    let values = config_args(&command, &main_table, cli_args);
+    args.splice(1..1, values);
+    args
+}
+
+/// Value of the `option` in `args`, looked up before clap parses them.
+fn option_value(args: &[OsString], option: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == option)
+        .and_then(|position| args.get(position + 1))
+        .map(|value| value.to_string_lossy().into_owned())
+        .or_else(|| {
+            args.iter()
+                .filter_map(|arg| arg.to_str()?.strip_prefix(&format!("{option}=")))
+                .map(str::to_string)
+                .next()
+        })
+}
+
+/// Sets the variables of `--env-file`, or of `.env` in the working directory if present, that
+/// aren't already set, so API keys don't have to be exported.
+fn load_env_file(args: &[OsString]) {
+    match option_value(args, "--env-file") {
+        Some(path) => {
+            dotenvy::from_path(&path)
+                .unwrap_or_else(|error| panic!("Failed to read env file {path}: {error}"));
+        }
+        None => {
+            let _ = dotenvy::dotenv();
+        }
+    }
+}
+
+fn read_config(args: &[OsString]) -> Option<Table> {
+    let (path, explicit) = match option_value(args, "--config") {
+        Some(path) => (path, true),
+        None if Path::new(DEFAULT_CONFIG).exists() => (DEFAULT_CONFIG.to_string(), false),
+        None => return None,
+    };
+
+    let contents = files::read_text(&path);
+    if !explicit && contents.is_err() {
+        return None;
+    }
+    let contents =
+        contents.unwrap_or_else(|error| panic!("Failed to read config file {path}: {error}"));
+    Some(
+        contents
+            .parse()
+            .unwrap_or_else(|error| panic!("Invalid config file {path}: {error}")),
+    )
+}
+
+/// Options of `command` set by `table`, except those already in `cli_args`.
+fn config_args(command: &Command, table: &Table, cli_args: &[OsString]) -> Vec<OsString> {
+    let mut args = vec![];
+    for (key, value) in table {
+        let id = key.replace('-', "_");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str())
+            .unwrap_or_else(|| panic!("Unknown config option {key} for {}", command.get_name()));
+
+        let long = arg.get_long().map(|long| format!("--{long}"));
+        let short = arg.get_short().map(|short| format!("-{short}"));
+        let on_command_line = cli_args.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+            long.as_ref()
+                .is_some_and(|long| arg == long || arg.starts_with(&format!("{long}=")))
+                || short.as_ref().is_some_and(|short| arg.starts_with(short))
+        });
+        let in_environment = arg
+            .get_env()
+            .is_some_and(|name| env::var_os(name).is_some());
+        if on_command_line || in_environment {
+            continue;
+        }
+
+        let flag = OsString::from(long.or(short).unwrap());
+        let values = match value {
+            Value::Array(values) => values.clone(),
+            value => vec![value.clone()],
+        };
+        for value in values {
+            match value {
+                Value::Boolean(true) => args.push(flag.clone()),
+                Value::Boolean(false) => {}
+                Value::String(value) => args.extend([flag.clone(), value.into()]),
+                value => args.extend([flag.clone(), value.to_string().into()]),
+            }
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn merged(args: &[&str], config: &str) -> Vec<String> {
+        with_config(os_args(args), config.parse().unwrap())
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn config_options_come_before_the_command_line() {
+        let args = merged(
+            &["distance-calculator", "-i", "input.json"],
+            "embedding_model = \"small\"\nnormalize = true\nstats = false\nlimit = 5",
+        );
+        assert_eq!(
+            args,
+            [
+                "distance-calculator",
+                "--embedding-model",
+                "small",
+                "--limit",
+                "5",
+                "--normalize",
+                "-i",
+                "input.json"
+            ]
+        );
+        assert!(Args::try_parse_from(args).is_ok());
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_config() {
+        for cli in [["-e", "large"], ["--embedding-model", "large"]] {
+            let args = merged(
+                &["distance-calculator", cli[0], cli[1]],
+                "embedding_model = \"small\"",
+            );
+            assert_eq!(args, ["distance-calculator", cli[0], cli[1]]);
+        }
+        let args = merged(
+            &["distance-calculator", "--embedding-model=large"],
+            "embedding-model = \"small\"",
+        );
+        assert_eq!(args, ["distance-calculator", "--embedding-model=large"]);
+    }
+
+    #[test]
+    fn subcommand_tables_set_subcommand_options() {
+        let args = merged(
+            &[
+                "distance-calculator",
+                "--offline",
+                "compare",
+                "-i",
+                "input.json",
+            ],
+            "offline = false\nrefresh = true\n[compare]\nembedding_model = [\"small\", \"large\"]",
+        );
+        assert_eq!(
+            args,
+            [
+                "distance-calculator",
+                "--refresh",
+                "--offline",
+                "compare",
+                "--embedding-model",
+                "small",
+                "--embedding-model",
+                "large",
+                "-i",
+                "input.json"
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown config option embeding_model")]
+    fn unknown_options_are_rejected() {
+        merged(&["distance-calculator"], "embeding_model = \"small\"");
+    }
+
+    #[test]
+    fn option_values_are_found_in_both_forms() {
+        let args = os_args(&[
+            "distance-calculator",
+            "--config",
+            "a.toml",
+            "--env-file=b.env",
+        ]);
+        assert_eq!(option_value(&args, "--config"), Some("a.toml".to_string()));
+        assert_eq!(option_value(&args, "--env-file"), Some("b.env".to_string()));
+        assert_eq!(option_value(&args, "--data-dir"), None);
+    }
+
+    #[test]
+    fn env_files_do_not_override_the_environment() {
+        let path = env::temp_dir().join(format!("distance-calculator-{}.env", std::process::id()));
+        std::fs::write(
+            &path,
+            "DISTANCE_CALCULATOR_TEST_NEW=from-file\nDISTANCE_CALCULATOR_TEST_SET=from-file\n",
+        )
+        .unwrap();
+        env::set_var("DISTANCE_CALCULATOR_TEST_SET", "exported");
+
+        let path = path.to_string_lossy().into_owned();
+        load_env_file(&os_args(&["distance-calculator", "--env-file", &path]));
+        assert_eq!(
+            env::var("DISTANCE_CALCULATOR_TEST_NEW").unwrap(),
+            "from-file"
+        );
+        assert_eq!(
+            env::var("DISTANCE_CALCULATOR_TEST_SET").unwrap(),
+            "exported"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+use clap::Args;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::{
+    cache::EmbeddingCache, files, models, stats, table, DistanceMetric, Provider, ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct CrosslingualArgs {
+    /// JSON object of a parallel corpus: every language code maps to the same sentences
+    /// translated, in the same order, e.g. `{"en": ["Hello"], "fr": ["Bonjour"]}`
+    #[arg(short)]
+    input_file: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+}
+
+/// Alignment of the sentences of two languages.
+struct Alignment {
+    /// Mean score of every sentence and its translation
+    translations: f64,
+    /// Mean score of every sentence and the translations of the other sentences
+    random: f64,
+    /// Share of the sentences of the first language whose nearest sentence of the second is
+    /// their translation
+    forward: f64,
+    /// The same from the second language to the first
+    backward: f64,
+}
+
+impl Alignment {
+    /// How much closer translations score than unrelated sentences, positive when they do.
+    fn margin(&self, distance_metric: &DistanceMetric) -> f64 {
+        if distance_metric.higher_is_closer() {
+            self.translations - self.random
+        } else {
+            self.random - self.translations
+        }
+    }
+}
+
+fn align(first: &[Vec<f64>], second: &[Vec<f64>], distance_metric: &DistanceMetric) -> Alignment {
+    let scores = first
+        .par_iter()
+        .map(|a| {
+            second
+                .iter()
+                .map(|b| distance_metric.distance(a, b))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let n = scores.len();
+
+    let translations = (0..n).map(|i| scores[i][i]).collect::<Vec<_>>();
+    let random = (0..n)
+        .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+        .map(|(i, j)| scores[i][j])
+        .collect::<Vec<_>>();
+    // Share of the sentences `i` that no candidate `j` scores closer to than their translation
+    let accuracy = |score: &dyn Fn(usize, usize) -> f64| {
+        let correct = (0..n)
+            .filter(|&i| {
+                !(0..n).any(|j| {
+                    j != i
+                        && distance_metric
+                            .cmp_closeness(score(i, j), score(i, i))
+                            .is_gt()
+                })
+            })
+            .count();
+        correct as f64 / n as f64
+    };
+
+    Alignment {
+        translations: stats::mean(&translations),
+        random: if random.is_empty() {
+            f64::NAN
+        } else {
+            stats::mean(&random)
+        },
+        forward: accuracy(&|i, j| scores[i][j]),
+        backward: accuracy(&|i, j| scores[j][i]),
+    }
+}
+
+/// Prints, for every pair of languages of a parallel corpus, how much closer translations score
+/// than unrelated sentences and how often a sentence's nearest neighbor in the other language is
+/// its translation.
+pub async fn run(args: CrosslingualArgs) {
+    let corpus: BTreeMap<String, Vec<String>> = files::read_json(&args.input_file);
+    assert!(corpus.len() > 1, "Expected at least two languages");
+    let sentences = corpus.values().next().unwrap().len();
+    assert!(sentences > 0, "The parallel corpus is empty");
+    for (language, translations) in &corpus {
+        assert_eq!(
+            translations.len(),
+            sentences,
+            "{language} has {} sentences instead of {sentences}",
+            translations.len()
+        );
+    }
+
+    let input_strings = corpus.values().flatten().cloned().collect::<Vec<_>>();
+    let (embeddings, _) =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &input_strings,
+                args.provider.max_batch_size(),
+            )
+            .await;
+    let vectors = embeddings
+        .into_iter()
+        .map(|embedding| embedding.vec)
+        .chunks(sentences)
+        .into_iter()
+        .map(|language| language.collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let mut table = vec![vec![
+        "languages".to_string(),
+        "translations".to_string(),
+        "random".to_string(),
+        "margin".to_string(),
+        "forward accuracy".to_string(),
+        "backward accuracy".to_string(),
+    ]];
+    let mut accuracies = vec![];
+    let languages = corpus.keys().collect::<Vec<_>>();
+    for (a, b) in (0..languages.len()).tuple_combinations() {
+        let alignment = align(&vectors[a], &vectors[b], &args.distance_metric);
+        accuracies.extend([alignment.forward, alignment.backward]);
+        table.push(vec![
+            format!("{} / {}", languages[a], languages[b]),
+            table::format_score(alignment.translations),
+            table::format_score(alignment.random),
+            table::format_score(alignment.margin(&args.distance_metric)),
+            format!("{:.1}%", alignment.forward * 100.0),
+            format!("{:.1}%", alignment.backward * 100.0),
+        ]);
+    }
+
+    println!(
+        "{sentences} sentences in {} languages, translations retrieved {:.1}% of the time",
+        languages.len(),
+        stats::mean(&accuracies) * 100.0
+    );
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_translations_are_retrieved_both_ways() {
+        let english = [vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]];
+        let french = [vec![0.9, 0.1], vec![0.1, 0.9], vec![0.8, 1.0]];
+        let alignment = align(&english, &french, &DistanceMetric::L2);
+        assert_eq!((alignment.forward, alignment.backward), (1.0, 1.0));
+        assert!(alignment.translations < alignment.random);
+        assert!(alignment.margin(&DistanceMetric::L2) > 0.0);
+    }
+
+    #[test]
+    fn accuracy_counts_the_sentences_nearest_their_translation() {
+        // The French translations are swapped
+        let english = [vec![1.0, 0.0], vec![0.0, 1.0]];
+        let french = [vec![0.0, 1.0], vec![1.0, 0.1]];
+        let alignment = align(&english, &french, &DistanceMetric::Cosine);
+        assert_eq!((alignment.forward, alignment.backward), (0.0, 0.0));
+        assert!(alignment.margin(&DistanceMetric::Cosine) < 0.0);
+
+        let french = [vec![1.0, 0.0], vec![1.0, 0.1]];
+        let alignment = align(&english, &french, &DistanceMetric::Cosine);
+        // Both English sentences are nearest their translation, but the second French one is
+        // nearest the first English one
+        assert_eq!((alignment.forward, alignment.backward), (1.0, 0.5));
+    }
+
+    #[test]
+    fn a_single_sentence_has_no_random_pairs() {
+        let alignment = align(&[vec![1.0, 0.0]], &[vec![0.6, 0.8]], &DistanceMetric::Dot);
+        assert!((alignment.translations - 0.6).abs() < 1e-12);
+        assert!(alignment.random.is_nan());
+        assert_eq!((alignment.forward, alignment.backward), (1.0, 1.0));
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{files, providers::EmbeddingError, warnings, Provider};
+
+/// Dead-letter file set once from `--dead-letter`. Failed batches are only reported on stderr
+/// without it.
+static DEAD_LETTER: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_dead_letter(path: PathBuf) {
+    DEAD_LETTER.set(path).expect("Dead-letter file already set");
+}
+
+/// Whether failed batches are written to a dead-letter file.
+pub fn enabled() -> bool {
+    DEAD_LETTER.get().is_some()
+}
+
+/// A batch the provider failed to embed, with everything needed to retry it or to report it to
+/// the provider.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Entry {
+    timestamp: DateTime<Utc>,
+    provider: String,
+    model: String,
+    /// Identifier the provider assigned to the request, absent if it never answered
+    request_id: Option<String>,
+    error: String,
+    /// The documents of the batch, in request order
+    documents: Vec<String>,
+}
+
+/// Appends the batch of `documents` that failed with `error` to the dead-letter file, if any.
+///
+/// Failing to write the file only warns: the batch has already failed.
+pub fn record(provider: &Provider, model: &str, documents: Vec<String>, error: &EmbeddingError) {
+    let Some(path) = DEAD_LETTER.get() else {
+        return;
+    };
+
+    let entry = Entry {
+        timestamp: Utc::now(),
+        provider: provider.to_string(),
+        model: model.to_string(),
+        request_id: error.request_id().map(str::to_string),
+        error: error.to_string(),
+        documents,
+    };
+    if let Err(error) = append(path, &entry) {
+        warnings::warn(format!(
+            "Failed to write the failed batch to {}: {error}",
+            path.display()
+        ));
+    }
+}
+
+fn append(path: &Path, entry: &Entry) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(files::long_path(parent))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(files::long_path(path))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(request_id: Option<&str>) -> Entry {
+        Entry {
+            timestamp: Utc::now(),
+            provider: "openai".to_string(),
+            model: "text-embedding-3-small".to_string(),
+            request_id: request_id.map(str::to_string),
+            error: "provider error: rate limited".to_string(),
+            documents: vec!["first".to_string(), "second\nline".to_string()],
+        }
+    }
+
+    #[test]
+    fn failed_batches_are_appended_one_per_line() {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "distance-calculator-dead-letter-{}",
+                std::process::id()
+            ))
+            .join("failed.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let entries = [entry(Some("req_123")), entry(None)];
+        for entry in &entries {
+            append(&path, entry).unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let read = contents
+            .lines()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(read, entries);
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    ops::Range,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use crate::providers::Embedding;
+
+/// When the run stops embedding, set once from `--deadline`. Runs have no deadline without it.
+static DEADLINE: OnceLock<Instant> = OnceLock::new();
+
+/// Starts the `--deadline` budget, which spans every batch of every provider from now on.
+pub fn set_deadline(budget: Duration) {
+    DEADLINE
+        .set(Instant::now() + budget)
+        .expect("Deadline already set");
+}
+
+/// Time left before the deadline, none without a deadline.
+pub fn remaining() -> Option<Duration> {
+    DEADLINE
+        .get()
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// The documents whose texts (the ranges of `embeddings` they span) were all embedded before the
+/// deadline: their positions, the embeddings of their texts, and the ranges of these texts among
+/// the embeddings kept.
+pub fn complete_documents(
+    ranges: &[Range<usize>],
+    mut embeddings: Vec<Option<Embedding>>,
+) -> (Vec<usize>, Vec<Embedding>, Vec<Range<usize>>) {
+    let mut kept = vec![];
+    let mut kept_embeddings = vec![];
+    let mut kept_ranges = vec![];
+    for (i, range) in ranges.iter().enumerate() {
+        let texts = &mut embeddings[range.clone()];
+        if texts.iter().any(Option::is_none) {
+            continue;
+        }
+        let start = kept_embeddings.len();
+        kept_embeddings.extend(texts.iter_mut().filter_map(Option::take));
+        kept.push(i);
+        kept_ranges.push(start..kept_embeddings.len());
+    }
+    (kept, kept_embeddings, kept_ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(document: &str) -> Option<Embedding> {
+        Some(Embedding {
+            document: document.to_string(),
+            vec: vec![1.0],
+        })
+    }
+
+    #[test]
+    fn documents_missing_any_text_are_left_out() {
+        // Document 1 lost its second chunk to the deadline, document 2 its only text
+        let ranges = [0..2, 2..4, 4..5, 5..6];
+        let embeddings = vec![
+            embedding("a1"),
+            embedding("a2"),
+            embedding("b1"),
+            None,
+            None,
+            embedding("d1"),
+        ];
+        let (kept, embeddings, ranges) = complete_documents(&ranges, embeddings);
+        assert_eq!(kept, [0, 3]);
+        let documents = embeddings
+            .iter()
+            .map(|embedding| embedding.document.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(documents, ["a1", "a2", "d1"]);
+        assert_eq!(ranges, [0..2, 2..3]);
+    }
+}
@@ -0,0 +1,264 @@
+use std::{collections::BTreeMap, fs::File, path::Path};
+
+use clap::Args;
+use serde::Deserialize;
+
+use crate::{files, stats, table, warnings};
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Results of the earlier run: a `--pairs-out` CSV file, or a JSON array of
+    /// `{"source_id", "target_id", "score"}` objects or `POST /compare` response
+    before: String,
+    /// Results of the later run, in either format of `before`
+    after: String,
+    /// Number of pairs with the largest shifts to print
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+}
+
+/// One pair of a results file.
+#[derive(Deserialize)]
+struct ScoredPair {
+    source_id: String,
+    target_id: String,
+    metric: Option<String>,
+    score: f64,
+}
+
+/// Shapes of JSON results files.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonResults {
+    Pairs(Vec<ScoredPair>),
+    /// A `POST /compare` response, whose documents are identified by position
+    Matrix {
+        metric: String,
+        matrix: Vec<Vec<f64>>,
+    },
+}
+
+/// Scores of a results file by pair of document ids, and the metric they were computed with if
+/// the file records it.
+struct Results {
+    metric: Option<String>,
+    scores: BTreeMap<(String, String), f64>,
+}
+
+impl Results {
+    fn read(path: &str) -> Self {
+        let is_csv = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension == "csv");
+        let pairs = if is_csv {
+            csv::Reader::from_reader(
+                File::open(files::long_path(path))
+                    .unwrap_or_else(|error| panic!("Failed to open {path}: {error}")),
+            )
+            .deserialize()
+            .collect::<Result<Vec<ScoredPair>, _>>()
+            .unwrap_or_else(|error| panic!("Failed to read the pairs of {path}: {error}"))
+        } else {
+            match files::read_json(path) {
+                JsonResults::Pairs(pairs) => pairs,
+                JsonResults::Matrix { metric, matrix } => (0..matrix.len())
+                    .flat_map(|i| (i + 1..matrix.len()).map(move |j| (i, j)))
+                    .map(|(i, j)| ScoredPair {
+                        source_id: i.to_string(),
+                        target_id: j.to_string(),
+                        metric: Some(metric.clone()),
+                        score: matrix[i][j],
+                    })
+                    .collect(),
+            }
+        };
+        Self::from_pairs(pairs)
+    }
+
+    fn from_pairs(pairs: Vec<ScoredPair>) -> Self {
+        let metric = pairs.first().and_then(|pair| pair.metric.clone());
+        let scores = pairs
+            .into_iter()
+            .map(|pair| (key(pair.source_id, pair.target_id), pair.score))
+            .collect();
+        Results { metric, scores }
+    }
+}
+
+/// The pair of ids in a fixed order, since runs may list a pair either way.
+fn key(source_id: String, target_id: String) -> (String, String) {
+    if source_id <= target_id {
+        (source_id, target_id)
+    } else {
+        (target_id, source_id)
+    }
+}
+
+/// A pair scored in both runs.
+#[derive(Debug, PartialEq)]
+struct Shift<'a> {
+    pair: &'a (String, String),
+    before: f64,
+    after: f64,
+}
+
+impl Shift<'_> {
+    fn delta(&self) -> f64 {
+        self.after - self.before
+    }
+}
+
+/// The pairs scored in both runs, largest shifts first.
+fn shifts<'a>(before: &'a Results, after: &'a Results) -> Vec<Shift<'a>> {
+    let mut shifts = before
+        .scores
+        .iter()
+        .filter_map(|(pair, before)| {
+            after.scores.get(pair).map(|after| Shift {
+                pair,
+                before: *before,
+                after: *after,
+            })
+        })
+        .collect::<Vec<_>>();
+    shifts.sort_by(|a, b| b.delta().abs().total_cmp(&a.delta().abs()));
+    shifts
+}
+
+/// Prints the pairs whose scores shifted the most between two runs, then how much the scores
+/// moved overall and how well the later run keeps the ranking of the pairs.
+pub fn run(args: DiffArgs) {
+    let before = Results::read(&args.before);
+    let after = Results::read(&args.after);
+    if let (Some(before_metric), Some(after_metric)) = (&before.metric, &after.metric) {
+        if before_metric != after_metric {
+            warnings::warn(format!(
+                "Warning: {} has {before_metric} scores but {} has {after_metric} scores",
+                args.before, args.after
+            ));
+        }
+    }
+
+    let shifts = shifts(&before, &after);
+    let mut table = vec![vec![
+        "source_id".to_string(),
+        "target_id".to_string(),
+        "before".to_string(),
+        "after".to_string(),
+        "delta".to_string(),
+    ]];
+    for shift in shifts.iter().take(args.top) {
+        table.push(vec![
+            shift.pair.0.clone(),
+            shift.pair.1.clone(),
+            shift.before.to_string(),
+            shift.after.to_string(),
+            format!("{:+}", shift.delta()),
+        ]);
+    }
+    table::print(table);
+
+    let only_before = before.scores.len() - shifts.len();
+    let only_after = after.scores.len() - shifts.len();
+    println!(
+        "{} pairs in both runs, {only_before} only before, {only_after} only after",
+        shifts.len()
+    );
+    if shifts.len() < 2 {
+        return;
+    }
+    let deltas = shifts
+        .iter()
+        .map(|shift| shift.delta().abs())
+        .collect::<Vec<_>>();
+    let before_scores = shifts.iter().map(|shift| shift.before).collect::<Vec<_>>();
+    let after_scores = shifts.iter().map(|shift| shift.after).collect::<Vec<_>>();
+    println!(
+        "absolute delta: mean {}, median {}, max {}",
+        stats::mean(&deltas),
+        stats::median(&deltas),
+        deltas[0] // shifts are largest first
+    );
+    println!(
+        "spearman {}, pearson {}",
+        stats::spearman(&before_scores, &after_scores),
+        stats::pearson(&before_scores, &after_scores)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(pairs: &[(&str, &str, f64)]) -> Results {
+        Results::from_pairs(
+            pairs
+                .iter()
+                .map(|(source_id, target_id, score)| ScoredPair {
+                    source_id: source_id.to_string(),
+                    target_id: target_id.to_string(),
+                    metric: Some("cosine".to_string()),
+                    score: *score,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn pairs_are_matched_whatever_their_order() {
+        let before = results(&[("a", "b", 0.9), ("a", "c", 0.5), ("b", "c", 0.4)]);
+        let after = results(&[("b", "a", 0.7), ("c", "a", 0.55), ("c", "d", 0.1)]);
+        let shifts = shifts(&before, &after);
+
+        let pairs = shifts
+            .iter()
+            .map(|shift| (shift.pair.0.as_str(), shift.pair.1.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, [("a", "b"), ("a", "c")]);
+        assert!((shifts[0].delta() + 0.2).abs() < 1e-12);
+        assert!((shifts[1].delta() - 0.05).abs() < 1e-12);
+    }
+
+    #[test]
+    fn results_are_read_from_csv_and_json() {
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!(
+                "distance-calculator-diff-{}.{name}",
+                std::process::id()
+            ))
+        };
+        std::fs::write(
+            path("csv"),
+            "source_id,target_id,metric,score\na,b,cosine,0.9\na,c,cosine,0.5\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("json"),
+            r#"[{"source_id": "a", "target_id": "b", "score": 0.8}]"#,
+        )
+        .unwrap();
+        std::fs::write(
+            path("compare.json"),
+            r#"{"metric": "l2", "matrix": [[0, 1, 2], [1, 0, 3], [2, 3, 0]]}"#,
+        )
+        .unwrap();
+
+        let csv = Results::read(path("csv").to_str().unwrap());
+        assert_eq!(csv.metric.as_deref(), Some("cosine"));
+        assert_eq!(csv.scores[&key("a".into(), "c".into())], 0.5);
+
+        let json = Results::read(path("json").to_str().unwrap());
+        assert_eq!((json.metric, json.scores.len()), (None, 1));
+
+        let compare = Results::read(path("compare.json").to_str().unwrap());
+        assert_eq!(compare.metric.as_deref(), Some("l2"));
+        assert_eq!(
+            compare.scores.into_iter().collect::<Vec<_>>(),
+            [
+                (key("0".into(), "1".into()), 1.0),
+                (key("0".into(), "2".into()), 2.0),
+                (key("1".into(), "2".into()), 3.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use std::{collections::HashMap, fmt::Display};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use crate::format_header;
+
+/// What two texts may differ by and still be duplicates.
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum Normalization {
+    /// Letter case
+    Lowercase,
+    /// Runs of whitespace, and whitespace at either end
+    Whitespace,
+    /// Punctuation and symbols
+    Punctuation,
+}
+
+impl Display for Normalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Normalization::Lowercase => write!(f, "lowercase"),
+            Normalization::Whitespace => write!(f, "whitespace"),
+            Normalization::Punctuation => write!(f, "punctuation"),
+        }
+    }
+}
+
+/// Hash of `text` once normalized, equal for the texts that duplicate each other.
+fn key(text: &str, normalizations: &[Normalization]) -> [u8; 32] {
+    let mut text = text.to_string();
+    if normalizations.contains(&Normalization::Punctuation) {
+        text = text
+            .chars()
+            .filter(|character| character.is_alphanumeric() || character.is_whitespace())
+            .collect();
+    }
+    if normalizations.contains(&Normalization::Lowercase) {
+        text = text.to_lowercase();
+    }
+    if normalizations.contains(&Normalization::Whitespace) {
+        text = text.split_whitespace().join(" ");
+    }
+    Sha256::digest(text).into()
+}
+
+/// The groups of at least two texts that duplicate each other, in the order of their first
+/// text, each in the order of its texts.
+pub fn groups(texts: &[String], normalizations: &[Normalization]) -> Vec<Vec<usize>> {
+    let mut groups = HashMap::<_, Vec<usize>>::new();
+    for (i, text) in texts.iter().enumerate() {
+        groups.entry(key(text, normalizations)).or_default().push(i);
+    }
+    groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .sorted_by_key(|group| group[0])
+        .collect()
+}
+
+/// The texts with every duplicate replaced by the first text of its group, so that each group
+/// is embedded once and every text of it gets that embedding.
+pub fn representatives(texts: &[String], groups: &[Vec<usize>]) -> Vec<String> {
+    let mut representatives = texts.to_vec();
+    for group in groups {
+        for &i in &group[1..] {
+            representatives[i].clone_from(&texts[group[0]]);
+        }
+    }
+    representatives
+}
+
+/// Prints every group of duplicates to stderr, before anything is embedded.
+pub fn print_report(groups: &[Vec<usize>], texts: &[String], normalizations: &[Normalization]) {
+    let duplicates = groups.iter().map(|group| group.len() - 1).sum::<usize>();
+    let normalized = match normalizations {
+        [] => String::new(),
+        _ => format!(" (normalized: {})", normalizations.iter().join(", ")),
+    };
+    eprintln!(
+        "{duplicates} of {} documents duplicate an earlier one{normalized}, {} are unique",
+        texts.len(),
+        texts.len() - duplicates
+    );
+    for group in groups {
+        eprintln!(
+            "  {} duplicated by {}",
+            format_header(group[0], &texts[group[0]]),
+            group[1..].iter().join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts() -> Vec<String> {
+        [
+            "Apple pie",
+            "banana bread",
+            "apple  pie!",
+            "Apple pie",
+            "banana bread",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    #[test]
+    fn exact_duplicates_are_grouped_with_the_first_text() {
+        assert_eq!(groups(&texts(), &[]), [vec![0, 3], vec![1, 4]]);
+        assert!(groups(&texts()[..3], &[]).is_empty());
+    }
+
+    #[test]
+    fn normalized_duplicates_ignore_case_whitespace_and_punctuation() {
+        let all = [
+            Normalization::Lowercase,
+            Normalization::Whitespace,
+            Normalization::Punctuation,
+        ];
+        assert_eq!(groups(&texts(), &all), [vec![0, 2, 3], vec![1, 4]]);
+        assert_eq!(
+            groups(&texts(), &all[..2]),
+            [vec![0, 3], vec![1, 4]],
+            "the exclamation mark still differs"
+        );
+    }
+
+    #[test]
+    fn duplicates_are_replaced_by_their_representative() {
+        let texts = texts();
+        let groups = groups(
+            &texts,
+            &[Normalization::Lowercase, Normalization::Punctuation],
+        );
+        assert_eq!(
+            representatives(&texts, &groups),
+            [
+                "Apple pie",
+                "banana bread",
+                "apple  pie!",
+                "Apple pie",
+                "banana bread"
+            ]
+        );
+        let groups = self::groups(
+            &texts,
+            &[
+                Normalization::Lowercase,
+                Normalization::Whitespace,
+                Normalization::Punctuation,
+            ],
+        );
+        assert_eq!(representatives(&texts, &groups)[2], "Apple pie");
+    }
+}
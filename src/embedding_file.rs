@@ -0,0 +1,582 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use half::{bf16, f16};
+use itertools::Itertools;
+use memmap2::Mmap;
+use safetensors::{tensor::TensorView, Dtype, SafeTensors};
+
+use crate::{files, providers::Embedding, DistanceMetric};
+
+const MAGIC: &[u8; 4] = b"EDCM";
+const VERSION: u32 = 2;
+/// Version 1 files have no storage type and always hold f64.
+const V1_HEADER_SIZE: usize = 24;
+const HEADER_SIZE: usize = 32;
+/// Name of the `[rows, dimensions]` tensor of safetensors files.
+const TENSOR_NAME: &str = "embeddings";
+/// Metadata key of safetensors files holding the documents as a JSON array.
+const DOCUMENTS_KEY: &str = "documents";
+
+/// Float type the vectors are stored as. They are always converted back to f64 for computation.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum StorageType {
+    F64,
+    F32,
+    /// Half precision, a quarter of the size of f64
+    F16,
+    /// bfloat16, as small as f16 with f32's range but fewer significant digits
+    Bf16,
+}
+
+impl Display for StorageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageType::F64 => write!(f, "f64"),
+            StorageType::F32 => write!(f, "f32"),
+            StorageType::F16 => write!(f, "f16"),
+            StorageType::Bf16 => write!(f, "bf16"),
+        }
+    }
+}
+
+impl StorageType {
+    const ALL: [StorageType; 4] = [
+        StorageType::F64,
+        StorageType::F32,
+        StorageType::F16,
+        StorageType::Bf16,
+    ];
+
+    fn size(&self) -> usize {
+        match self {
+            StorageType::F64 => 8,
+            StorageType::F32 => 4,
+            StorageType::F16 | StorageType::Bf16 => 2,
+        }
+    }
+
+    fn code(&self) -> u32 {
+        Self::ALL.iter().position(|dtype| dtype == self).unwrap() as u32
+    }
+
+    fn dtype(&self) -> Dtype {
+        match self {
+            StorageType::F64 => Dtype::F64,
+            StorageType::F32 => Dtype::F32,
+            StorageType::F16 => Dtype::F16,
+            StorageType::Bf16 => Dtype::BF16,
+        }
+    }
+
+    fn from_dtype(dtype: Dtype) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|storage| storage.dtype() == dtype)
+    }
+
+    fn encode(&self, value: f64) -> Vec<u8> {
+        match self {
+            StorageType::F64 => value.to_le_bytes().to_vec(),
+            StorageType::F32 => (value as f32).to_le_bytes().to_vec(),
+            StorageType::F16 => f16::from_f64(value).to_le_bytes().to_vec(),
+            StorageType::Bf16 => bf16::from_f64(value).to_le_bytes().to_vec(),
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f64 {
+        match self {
+            StorageType::F64 => f64::from_le_bytes(bytes.try_into().unwrap()),
+            StorageType::F32 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            StorageType::F16 => f16::from_le_bytes(bytes.try_into().unwrap()).to_f64(),
+            StorageType::Bf16 => bf16::from_le_bytes(bytes.try_into().unwrap()).to_f64(),
+        }
+    }
+
+    fn decode_all(&self, bytes: &[u8]) -> Vec<f64> {
+        bytes
+            .chunks_exact(self.size())
+            .map(|value| self.decode(value))
+            .collect()
+    }
+
+    /// `value` as it reads back after being stored with this type.
+    fn round_trip(&self, value: f64) -> f64 {
+        self.decode(&self.encode(value))
+    }
+}
+
+/// Prints to stderr how much storing the vectors as `storage` changes their pairwise scores.
+pub fn report_storage_error(
+    embeddings: &[Embedding],
+    storage: StorageType,
+    distance_metric: &DistanceMetric,
+) {
+    let stored = embeddings
+        .iter()
+        .map(|embedding| {
+            embedding
+                .vec
+                .iter()
+                .map(|value| storage.round_trip(*value))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let errors = (0..embeddings.len())
+        .tuple_combinations()
+        .map(|(i, j)| {
+            let exact = distance_metric.distance(&embeddings[i].vec, &embeddings[j].vec);
+            (distance_metric.distance(&stored[i], &stored[j]) - exact).abs()
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "Storing as {storage} changes the {distance_metric} scores by {:e} on average and {:e} at most",
+        errors.iter().sum::<f64>() / errors.len() as f64,
+        errors.iter().copied().fold(0.0, f64::max)
+    );
+}
+
+fn is_safetensors(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension == "safetensors")
+}
+
+/// Saves `embeddings` as `storage` floats with string `metadata`, as safetensors when `path`
+/// ends in `.safetensors` and in the native layout of [`save_native`] otherwise.
+pub fn save(
+    path: &str,
+    embeddings: &[Embedding],
+    storage: StorageType,
+    metadata: HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    if is_safetensors(path) {
+        save_safetensors(path, embeddings, storage, metadata)
+    } else {
+        save_native(path, embeddings, storage, &metadata)
+    }
+}
+
+/// Loads a file written by [`save`], picking the format from the extension of `path`.
+pub fn load(path: &str) -> Vec<Embedding> {
+    load_matrix(path).into_embeddings()
+}
+
+/// Loads a file written by [`save`] as a [`Matrix`], without a vector per document.
+pub fn load_matrix(path: &str) -> Matrix {
+    if is_safetensors(path) {
+        load_safetensors(path)
+    } else {
+        load_native(path)
+    }
+}
+
+/// The vectors of an embeddings file as one row-major buffer along with their documents.
+///
+/// f64 vectors are read in place from the memory-mapped file, others are decoded once into a
+/// single buffer, so that searching a corpus of millions of vectors neither copies nor
+/// allocates them one by one.
+pub struct Matrix {
+    values: Values,
+    dimensions: usize,
+    pub documents: Vec<String>,
+}
+
+enum Values {
+    /// f64 values starting at this offset of the mapped file
+    Mapped(Mmap, usize),
+    Decoded(Vec<f64>),
+}
+
+impl Matrix {
+    /// Reads the `rows × dimensions` `storage` floats from `offset` of the mapping `bytes`.
+    fn new(
+        bytes: Mmap,
+        offset: usize,
+        storage: StorageType,
+        dimensions: usize,
+        documents: Vec<String>,
+    ) -> Self {
+        let data = &bytes[offset..offset + documents.len() * dimensions * storage.size()];
+        // Mappings are page-aligned, so f64 rows are aligned whenever their offset is too
+        let in_place = storage == StorageType::F64
+            && cfg!(target_endian = "little")
+            && data.as_ptr().align_offset(std::mem::align_of::<f64>()) == 0;
+        let values = if in_place {
+            Values::Mapped(bytes, offset)
+        } else {
+            Values::Decoded(storage.decode_all(data))
+        };
+
+        Matrix {
+            values,
+            dimensions,
+            documents,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// All the values, row after row.
+    fn values(&self) -> &[f64] {
+        match &self.values {
+            Values::Mapped(bytes, offset) => {
+                let length = self.rows() * self.dimensions;
+                // Safety: `Matrix::new` checked that the `length` f64 values from `offset` are
+                // within the mapping, aligned and in the byte order of the machine
+                unsafe { std::slice::from_raw_parts(bytes[*offset..].as_ptr().cast(), length) }
+            }
+            Values::Decoded(values) => values,
+        }
+    }
+
+    pub fn row(&self, row: usize) -> &[f64] {
+        &self.values()[row * self.dimensions..(row + 1) * self.dimensions]
+    }
+
+    pub fn into_embeddings(self) -> Vec<Embedding> {
+        (0..self.rows())
+            .map(|row| Embedding {
+                document: self.documents[row].clone(),
+                vec: self.row(row).to_vec(),
+            })
+            .collect()
+    }
+}
+
+/// Dimensions of the vectors of `embeddings`, which must all have the same.
+pub fn dimensions(embeddings: &[Embedding]) -> usize {
+    let dimensions = embeddings
+        .first()
+        .map_or(0, |embedding| embedding.vec.len());
+    assert!(
+        embeddings
+            .iter()
+            .all(|embedding| embedding.vec.len() == dimensions),
+        "All embeddings must have the same number of dimensions"
+    );
+    dimensions
+}
+
+/// Writes `embeddings` in a layout that can be memory-mapped and read back without parsing:
+///
+/// - header: `EDCM`, version (u32), rows (u64), dimensions (u64), storage type (u32, the
+///   index in `f64`, `f32`, `f16`, `bf16`) and 4 bytes of padding
+/// - the `rows × dimensions` matrix as row-major floats of the storage type, 8-byte aligned
+/// - the documents, each as its length (u64) followed by its UTF-8 bytes
+/// - if there is metadata, its length (u64) followed by it as a JSON object of strings
+///
+/// All integers and floats are little-endian.
+fn save_native(
+    path: &str,
+    embeddings: &[Embedding],
+    storage: StorageType,
+    metadata: &HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let dimensions = dimensions(embeddings);
+    let mut writer = BufWriter::new(File::create(files::long_path(path))?);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(embeddings.len() as u64).to_le_bytes())?;
+    writer.write_all(&(dimensions as u64).to_le_bytes())?;
+    writer.write_all(&storage.code().to_le_bytes())?;
+    writer.write_all(&[0; 4])?;
+
+    for embedding in embeddings {
+        for value in &embedding.vec {
+            writer.write_all(&storage.encode(*value))?;
+        }
+    }
+
+    // Keep the size of the file a multiple of 8 bytes, f16 matrices can end mid-word
+    let matrix_size = embeddings.len() * dimensions * storage.size();
+    writer.write_all(&vec![0; matrix_size.next_multiple_of(8) - matrix_size])?;
+
+    for embedding in embeddings {
+        writer.write_all(&(embedding.document.len() as u64).to_le_bytes())?;
+        writer.write_all(embedding.document.as_bytes())?;
+    }
+
+    if !metadata.is_empty() {
+        let metadata = serde_json::to_vec(metadata)?;
+        writer.write_all(&(metadata.len() as u64).to_le_bytes())?;
+        writer.write_all(&metadata)?;
+    }
+
+    Ok(writer.flush()?)
+}
+
+/// Memory-maps a file written by [`save_native`] and returns its embeddings.
+fn load_native(path: &str) -> Matrix {
+    let file = File::open(files::long_path(path))
+        .unwrap_or_else(|error| panic!("Failed to open {path}: {error}"));
+    // Safety: embedding files are not expected to be modified while an analysis reads them
+    let bytes =
+        unsafe { Mmap::map(&file) }.unwrap_or_else(|error| panic!("Failed to map {path}: {error}"));
+
+    assert!(
+        bytes.len() >= V1_HEADER_SIZE && &bytes[..4] == MAGIC,
+        "{path} is not an embeddings file"
+    );
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    let (header_size, storage) = match version {
+        1 => (V1_HEADER_SIZE, StorageType::F64),
+        VERSION => {
+            let code = bytes
+                .get(24..28)
+                .map(|code| u32::from_le_bytes(code.try_into().unwrap()) as usize)
+                .unwrap_or_else(|| panic!("{path} is truncated"));
+            let storage = *StorageType::ALL
+                .get(code)
+                .unwrap_or_else(|| panic!("Unknown storage type {code} in {path}"));
+            (HEADER_SIZE, storage)
+        }
+        version => panic!("Unsupported embeddings file version {version}"),
+    };
+
+    let rows = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) as usize;
+    let dimensions = u64::from_le_bytes(bytes[16..24].try_into().unwrap()) as usize;
+    let row_size = dimensions * storage.size();
+    let matrix_end = header_size + rows * row_size;
+    assert!(bytes.len() >= matrix_end, "{path} is truncated");
+
+    let mut offset = header_size + (rows * row_size).next_multiple_of(8);
+    let mut next_document = || {
+        let length = bytes
+            .get(offset..offset + 8)
+            .map(|length| u64::from_le_bytes(length.try_into().unwrap()) as usize)
+            .unwrap_or_else(|| panic!("{path} is truncated"));
+        let document = bytes
+            .get(offset + 8..offset + 8 + length)
+            .unwrap_or_else(|| panic!("{path} is truncated"));
+        offset += 8 + length;
+        String::from_utf8(document.to_vec()).expect("Documents must be valid UTF-8")
+    };
+
+    let documents = (0..rows).map(|_| next_document()).collect();
+
+    Matrix::new(bytes, header_size, storage, dimensions, documents)
+}
+
+/// Writes a single `embeddings` tensor of shape `[rows, dimensions]`, with the documents stored
+/// in the metadata so that the file can be loaded back without the original input.
+fn save_safetensors(
+    path: &str,
+    embeddings: &[Embedding],
+    storage: StorageType,
+    mut metadata: HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let dimensions = dimensions(embeddings);
+    let data = embeddings
+        .iter()
+        .flat_map(|embedding| {
+            embedding
+                .vec
+                .iter()
+                .flat_map(|value| storage.encode(*value))
+        })
+        .collect::<Vec<_>>();
+    let tensor = TensorView::new(storage.dtype(), vec![embeddings.len(), dimensions], &data)?;
+
+    let documents = embeddings
+        .iter()
+        .map(|embedding| embedding.document.as_str())
+        .collect::<Vec<_>>();
+    metadata.insert(
+        DOCUMENTS_KEY.to_string(),
+        serde_json::to_string(&documents)?,
+    );
+
+    safetensors::serialize_to_file(
+        [(TENSOR_NAME, tensor)],
+        &Some(metadata),
+        &files::long_path(path),
+    )?;
+    Ok(())
+}
+
+/// Reads the `embeddings` tensor of a safetensors file stored as any of the [`StorageType`]s,
+/// e.g. f32 or bf16 tensors saved from PyTorch. Files without documents in their metadata get their row numbers as documents.
+fn load_safetensors(path: &str) -> Matrix {
+    let file = File::open(files::long_path(path))
+        .unwrap_or_else(|error| panic!("Failed to open {path}: {error}"));
+    // Safety: see `load_native`
+    let bytes =
+        unsafe { Mmap::map(&file) }.unwrap_or_else(|error| panic!("Failed to map {path}: {error}"));
+
+    let tensors = SafeTensors::deserialize(&bytes)
+        .unwrap_or_else(|error| panic!("Failed to read {path}: {error}"));
+    let tensor = tensors
+        .tensor(TENSOR_NAME)
+        .unwrap_or_else(|_| panic!("{path} has no `{TENSOR_NAME}` tensor"));
+    let [rows, dimensions] = tensor.shape() else {
+        panic!("`{TENSOR_NAME}` tensor of {path} must have two dimensions");
+    };
+    let (rows, dimensions) = (*rows, *dimensions);
+
+    let storage = StorageType::from_dtype(tensor.dtype()).unwrap_or_else(|| {
+        panic!(
+            "Unsupported `{TENSOR_NAME}` dtype {:?} in {path}",
+            tensor.dtype()
+        )
+    });
+    let offset = tensor.data().as_ptr() as usize - bytes.as_ptr() as usize;
+
+    let (_, metadata) = SafeTensors::read_metadata(&bytes).unwrap();
+    let documents: Vec<String> = match metadata
+        .metadata()
+        .as_ref()
+        .and_then(|metadata| metadata.get(DOCUMENTS_KEY))
+    {
+        Some(documents) => serde_json::from_str(documents)
+            .unwrap_or_else(|error| panic!("Invalid documents in {path}: {error}")),
+        None => (0..rows).map(|row| row.to_string()).collect(),
+    };
+    assert_eq!(
+        documents.len(),
+        rows,
+        "{path} has {rows} embeddings but {} documents",
+        documents.len()
+    );
+
+    Matrix::new(bytes, offset, storage, dimensions, documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        let directory = std::env::temp_dir().join(format!(
+            "distance-calculator-embeddings-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Values every storage type represents exactly.
+    fn embeddings() -> Vec<Embedding> {
+        [
+            ("first", [0.5, -2.0, 1.25]),
+            ("sécond\n", [0.0, 3.0, -0.125]),
+        ]
+        .into_iter()
+        .map(|(document, vec)| Embedding {
+            document: document.to_string(),
+            vec: vec.to_vec(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn every_storage_type_round_trips_in_both_formats() {
+        for storage in StorageType::ALL {
+            for extension in ["bin", "safetensors"] {
+                let path = path(&format!("round-trip-{storage}.{extension}"));
+                save(&path, &embeddings(), storage, HashMap::new()).unwrap();
+                assert_eq!(load(&path), embeddings(), "{storage} {extension}");
+            }
+        }
+    }
+
+    #[test]
+    fn f64_matrices_are_read_in_place() {
+        let path = path("in-place.bin");
+        save(&path, &embeddings(), StorageType::F64, HashMap::new()).unwrap();
+
+        let matrix = load_matrix(&path);
+        assert!(matches!(matrix.values, Values::Mapped(..)));
+        assert_eq!(matrix.rows(), 2);
+        assert_eq!(matrix.row(1), [0.0, 3.0, -0.125]);
+    }
+
+    #[test]
+    fn native_files_are_padded_after_odd_f16_matrices() {
+        let path = path("padded.bin");
+        let embeddings = vec![Embedding {
+            document: "odd".to_string(),
+            vec: vec![1.0, 2.0, 3.0],
+        }];
+        let metadata = HashMap::from([("model".to_string(), "small".to_string())]);
+        save(&path, &embeddings, StorageType::F16, metadata).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        // 6 bytes of f16 values padded to 8, the document, then the metadata
+        let documents = HEADER_SIZE + 8;
+        assert_eq!(&bytes[documents..documents + 8], &3u64.to_le_bytes());
+        assert_eq!(&bytes[documents + 8..documents + 11], b"odd");
+        assert_eq!(load(&path), embeddings);
+    }
+
+    #[test]
+    fn version_1_files_hold_f64() {
+        let path = path("v1.bin");
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend(0.25f64.to_le_bytes());
+        bytes.extend((-1.0f64).to_le_bytes());
+        bytes.extend(4u64.to_le_bytes());
+        bytes.extend(b"only");
+        std::fs::write(&path, bytes).unwrap();
+
+        let embeddings = load(&path);
+        assert_eq!(embeddings[0].document, "only");
+        assert_eq!(embeddings[0].vec, [0.25, -1.0]);
+    }
+
+    #[test]
+    fn safetensors_without_documents_are_numbered() {
+        let path = path("no-documents.safetensors");
+        let data = [1.0f32, 2.0, 3.0, 4.0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        let tensor = TensorView::new(Dtype::F32, vec![2, 2], &data).unwrap();
+        safetensors::serialize_to_file([(TENSOR_NAME, tensor)], &None, Path::new(&path)).unwrap();
+
+        let matrix = load_matrix(&path);
+        assert_eq!(matrix.documents, ["0", "1"]);
+        assert_eq!(matrix.row(1), [3.0, 4.0]);
+    }
+
+    #[test]
+    fn storage_types_round_values_to_their_precision() {
+        assert_eq!(StorageType::F64.round_trip(0.1), 0.1);
+        assert_eq!(StorageType::F32.round_trip(0.1), 0.1f32 as f64);
+        assert!((StorageType::F16.round_trip(0.1) - 0.1).abs() < 1e-4);
+        assert!((StorageType::Bf16.round_trip(0.1) - 0.1).abs() < 1e-3);
+        // bf16 keeps f32's range, f16 overflows
+        assert!(StorageType::F16.round_trip(1e6).is_infinite());
+        assert!((StorageType::Bf16.round_trip(1e6) / 1e6 - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an embeddings file")]
+    fn other_files_are_rejected() {
+        let path = path("other.bin");
+        std::fs::write(&path, b"not an embeddings file at all").unwrap();
+        load(&path);
+    }
+}
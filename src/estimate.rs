@@ -0,0 +1,155 @@
+use crate::{ledger::price_per_million_tokens, table, Provider};
+
+/// Characters per token assumed for providers whose tokenizer isn't available offline.
+const CHARACTERS_PER_TOKEN: f64 = 4.0;
+
+/// What embedding documents with one model would cost.
+pub struct Estimate {
+    pub provider: Provider,
+    pub model: String,
+    pub documents: usize,
+    /// Documents that aren't cached, i.e. that would be sent to the provider
+    pub uncached: Vec<String>,
+}
+
+/// Tokens the provider would bill for `documents`: counted with the `cl100k_base` encoding of
+/// the OpenAI embedding models, estimated from their length for the others.
+pub fn count_tokens(provider: &Provider, documents: &[String]) -> u64 {
+    match provider {
+        Provider::Openai => {
+            let encoding = tiktoken_rs::cl100k_base().expect("Failed to load cl100k_base");
+            documents
+                .iter()
+                .map(|document| encoding.encode_ordinary(document).len() as u64)
+                .sum()
+        }
+        Provider::Cohere | Provider::Mock => documents
+            .iter()
+            .map(|document| (document.chars().count() as f64 / CHARACTERS_PER_TOKEN).ceil() as u64)
+            .sum(),
+    }
+}
+
+/// The table of the tokens and estimated cost of embedding the uncached documents of every
+/// estimate, with their total when there are several.
+fn report_table(estimates: &[Estimate]) -> Vec<Vec<String>> {
+    let mut table = vec![vec![
+        "model".to_string(),
+        "documents".to_string(),
+        "to embed".to_string(),
+        "tokens".to_string(),
+        "estimated cost".to_string(),
+    ]];
+    let mut total_cost = Some(0.0);
+    for estimate in estimates {
+        let tokens = count_tokens(&estimate.provider, &estimate.uncached);
+        let cost = price_per_million_tokens(&estimate.provider, &estimate.model)
+            .map(|price| price * tokens as f64 / 1_000_000.0);
+        total_cost = total_cost.zip(cost).map(|(total, cost)| total + cost);
+
+        let approximate = match estimate.provider {
+            Provider::Openai => "",
+            Provider::Cohere | Provider::Mock => "~",
+        };
+        table.push(vec![
+            format!("{}/{}", estimate.provider, estimate.model),
+            estimate.documents.to_string(),
+            estimate.uncached.len().to_string(),
+            format!("{approximate}{tokens}"),
+            cost.map_or("unknown".to_string(), |cost| format!("${cost:.6}")),
+        ]);
+    }
+    if estimates.len() > 1 {
+        table.push(vec![
+            "total".to_string(),
+            String::new(),
+            String::new(),
+            String::new(),
+            total_cost.map_or("unknown".to_string(), |cost| format!("${cost:.6}")),
+        ]);
+    }
+
+    table
+}
+
+/// Prints the tokens and estimated cost of embedding the uncached documents of every estimate.
+pub fn print_report(estimates: &[Estimate]) {
+    table::print(report_table(estimates));
+    println!("Dry run: no documents were embedded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate(provider: Provider, model: &str, uncached: &[&str]) -> Estimate {
+        Estimate {
+            provider,
+            model: model.to_string(),
+            documents: 3,
+            uncached: uncached
+                .iter()
+                .map(|document| document.to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn openai_tokens_are_counted_with_cl100k_base() {
+        let documents = ["hello world".to_string(), "tiktoken is great!".to_string()];
+        assert_eq!(count_tokens(&Provider::Openai, &documents), 2 + 6);
+        assert_eq!(count_tokens(&Provider::Openai, &[]), 0);
+    }
+
+    #[test]
+    fn other_tokens_are_estimated_from_the_characters() {
+        let documents = [
+            "abcd".to_string(),
+            "abcde".to_string(),
+            "日本語".to_string(),
+        ];
+        assert_eq!(count_tokens(&Provider::Cohere, &documents), 1 + 2 + 1);
+        assert_eq!(count_tokens(&Provider::Mock, &[String::new()]), 0);
+    }
+
+    #[test]
+    fn costs_follow_the_price_of_the_model() {
+        let table = report_table(&[estimate(
+            Provider::Cohere,
+            "embed-english-v3.0",
+            &["a".repeat(4_000_000).as_str()],
+        )]);
+        assert_eq!(
+            table[1],
+            [
+                "cohere/embed-english-v3.0",
+                "3",
+                "1",
+                "~1000000",
+                "$0.100000"
+            ]
+        );
+        assert_eq!(table.len(), 2, "no total for a single model");
+    }
+
+    #[test]
+    fn the_total_is_unknown_with_an_unpriced_model() {
+        let table = report_table(&[
+            estimate(Provider::Openai, "text-embedding-3-small", &["hello world"]),
+            estimate(Provider::Openai, "my-fine-tune", &["hello world"]),
+        ]);
+        assert_eq!(
+            table[1],
+            ["openai/text-embedding-3-small", "3", "1", "2", "$0.000000"]
+        );
+        assert_eq!(table[2][4], "unknown");
+        assert_eq!(table[3], ["total", "", "", "", "unknown"]);
+
+        let table = report_table(&[
+            estimate(Provider::Mock, "m", &[]),
+            estimate(Provider::Cohere, "embed-v4.0", &["abcdefgh"]),
+        ]);
+        assert_eq!(table[1][2..], ["0", "~0", "$0.000000"]);
+        assert_eq!(table[3][4], "$0.000000");
+    }
+}
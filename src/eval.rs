@@ -0,0 +1,273 @@
+use std::{collections::HashMap, fs::File, path::Path};
+
+use clap::Args;
+use itertools::Itertools;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    cache::EmbeddingCache, files, models, stats, table, DistanceMetric, Provider, ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct EvalArgs {
+    /// JSON file of `{"sentence1", "sentence2", "score"}` objects, or CSV/TSV file whose header
+    /// names `sentence1`, `sentence2` and `score` columns (e.g. STS-B)
+    #[arg(short, long)]
+    gold_file: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    /// Embedding model to evaluate (repeat to compare models)
+    #[arg(short, long, required = true, value_parser = models::parse_model)]
+    embedding_model: Vec<String>,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// Number of rounds of the paired permutation test, run when exactly two models are given
+    #[arg(long, default_value_t = 10_000)]
+    permutations: usize,
+    /// Resample the gold pairs this many times for 95% confidence intervals of the correlations
+    /// and of their difference
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    bootstrap: Option<u32>,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Deserialize)]
+struct GoldPair {
+    sentence1: String,
+    sentence2: String,
+    score: f64,
+}
+
+impl EvalArgs {
+    fn gold_pairs(&self) -> Vec<GoldPair> {
+        let delimiter = match Path::new(&self.gold_file)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("csv") => b',',
+            Some("tsv") => b'\t',
+            _ => return files::read_json(&self.gold_file),
+        };
+        // STS files leave quotes in sentences unescaped, so only CSV gets quoting
+        csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .quoting(delimiter == b',')
+            .from_reader(File::open(files::long_path(&self.gold_file)).unwrap())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .expect("Failed to read gold pairs")
+    }
+}
+
+/// The similarity of the sentences of every gold pair, higher being closer whatever the metric
+/// so that the correlations with the gold scores are positive for a good model.
+fn pair_scores(
+    gold_pairs: &[GoldPair],
+    vectors: &HashMap<String, Vec<f64>>,
+    distance_metric: &DistanceMetric,
+) -> Vec<f64> {
+    gold_pairs
+        .iter()
+        .map(|pair| {
+            distance_metric.similarity(&vectors[&pair.sentence1], &vectors[&pair.sentence2])
+        })
+        .collect()
+}
+
+/// The Pearson and Spearman correlations of the `scores` of a model with the `gold` scores.
+fn correlations(scores: &[f64], gold: &[f64]) -> (f64, f64) {
+    (stats::pearson(scores, gold), stats::spearman(scores, gold))
+}
+
+pub async fn run(args: EvalArgs) {
+    let gold_pairs = args.gold_pairs();
+    let gold = gold_pairs.iter().map(|pair| pair.score).collect::<Vec<_>>();
+
+    let sentences = gold_pairs
+        .iter()
+        .flat_map(|pair| [pair.sentence1.clone(), pair.sentence2.clone()])
+        .unique()
+        .collect::<Vec<_>>();
+
+    let mut model_scores = vec![];
+    for model in &args.embedding_model {
+        let (embeddings, _) = EmbeddingCache::open(&args.provider, &args.provider_args, model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                model,
+                &sentences,
+                args.provider.max_batch_size(),
+            )
+            .await;
+        let vectors = embeddings
+            .into_iter()
+            .map(|embedding| (embedding.document, embedding.vec))
+            .collect::<HashMap<_, _>>();
+
+        model_scores.push(pair_scores(&gold_pairs, &vectors, &args.distance_metric));
+    }
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut header = vec!["model", "pearson", "spearman"]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    if args.bootstrap.is_some() {
+        header.extend([
+            stats::interval_header("pearson"),
+            stats::interval_header("spearman"),
+        ]);
+    }
+    let mut table = vec![header];
+    for (model, scores) in args.embedding_model.iter().zip(&model_scores) {
+        let (pearson, spearman) = correlations(scores, &gold);
+        let mut row = vec![model.clone(), pearson.to_string(), spearman.to_string()];
+        if let Some(rounds) = args.bootstrap {
+            for correlation in [stats::pearson, stats::spearman] {
+                let interval =
+                    stats::bootstrap_interval(gold.len(), rounds, &mut rng, |resample| {
+                        correlation(
+                            &stats::resampled(scores, resample),
+                            &stats::resampled(&gold, resample),
+                        )
+                    });
+                row.push(stats::format_interval(interval));
+            }
+        }
+        table.push(row);
+    }
+    table::print(table);
+
+    if let [a, b] = model_scores.as_slice() {
+        // Seeded apart from the bootstrap so that --bootstrap leaves the p-value as it was
+        let mut permutation_rng = StdRng::seed_from_u64(args.seed);
+        let (difference, p_value) =
+            stats::paired_permutation_test(&gold, a, b, args.permutations, &mut permutation_rng);
+
+        println!("spearman difference: {difference}");
+        if let Some(rounds) = args.bootstrap {
+            let interval = stats::bootstrap_interval(gold.len(), rounds, &mut rng, |resample| {
+                let gold = stats::resampled(&gold, resample);
+                stats::spearman(&stats::resampled(a, resample), &gold)
+                    - stats::spearman(&stats::resampled(b, resample), &gold)
+            });
+            println!(
+                "{}: {}",
+                stats::interval_header("spearman difference"),
+                stats::format_interval(interval)
+            );
+        }
+        println!("p-value ({} permutations): {p_value}", args.permutations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        eval: EvalArgs,
+    }
+
+    fn args(gold_file: &str) -> EvalArgs {
+        Cli::parse_from(["eval", "-g", gold_file, "-e", "demo"]).eval
+    }
+
+    fn gold_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-eval-{}-{name}",
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn gold_pairs_are_read_from_json_csv_and_tsv() {
+        let files = [
+            gold_file(
+                "gold.json",
+                r#"[{"sentence1": "a cat", "sentence2": "a dog", "score": 2.5}]"#,
+            ),
+            gold_file(
+                "gold.csv",
+                "sentence1,sentence2,score\n\"a cat\",a dog,2.5\n",
+            ),
+            gold_file(
+                "gold.tsv",
+                "score\tsentence1\tsentence2\n2.5\ta cat\ta dog\n",
+            ),
+        ];
+        for file in &files {
+            let pairs = args(file).gold_pairs();
+            assert_eq!(pairs.len(), 1, "{file}");
+            assert_eq!(
+                (
+                    pairs[0].sentence1.as_str(),
+                    pairs[0].sentence2.as_str(),
+                    pairs[0].score
+                ),
+                ("a cat", "a dog", 2.5),
+                "{file}"
+            );
+            std::fs::remove_file(file).unwrap();
+        }
+
+        // TSV sentences keep their quotes
+        let file = gold_file(
+            "quoted.tsv",
+            "sentence1\tsentence2\tscore\n\"hi\tthere\t1\n",
+        );
+        assert_eq!(args(&file).gold_pairs()[0].sentence1, "\"hi");
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn pairs_are_scored_by_similarity() {
+        let pair = |sentence1: &str, sentence2: &str| GoldPair {
+            sentence1: sentence1.to_string(),
+            sentence2: sentence2.to_string(),
+            score: 0.0,
+        };
+        let pairs = [pair("x", "y"), pair("x", "xy"), pair("x", "x")];
+        let vectors = HashMap::from([
+            ("x".to_string(), vec![1.0, 0.0]),
+            ("y".to_string(), vec![0.0, 2.0]),
+            ("xy".to_string(), vec![1.0, 1.0]),
+        ]);
+        let cosine = pair_scores(&pairs, &vectors, &DistanceMetric::Cosine);
+        assert_close(cosine[0], 0.0);
+        assert_close(cosine[1], 0.5f64.sqrt());
+        assert_close(cosine[2], 1.0);
+        // Distances are negated so that closer pairs still score higher
+        let l2 = pair_scores(&pairs, &vectors, &DistanceMetric::L2);
+        assert!(l2[0] < l2[1] && l2[1] < l2[2]);
+    }
+
+    #[test]
+    fn correlations_match_hand_computed_values() {
+        let (pearson, spearman) = correlations(&[1.0, 2.0, 3.0], &[1.0, 3.0, 2.0]);
+        assert_close(pearson, 0.5);
+        assert_close(spearman, 0.5);
+        // Spearman only sees the ranks
+        let (pearson, spearman) = correlations(&[1.0, 2.0, 3.0, 100.0], &[0.1, 0.2, 0.3, 0.4]);
+        assert!(pearson < 0.9);
+        assert_close(spearman, 1.0);
+        let (pearson, spearman) = correlations(&[3.0, 2.0, 1.0], &[1.0, 2.0, 3.0]);
+        assert_close(pearson, -1.0);
+        assert_close(spearman, -1.0);
+    }
+}
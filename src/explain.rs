@@ -0,0 +1,131 @@
+use itertools::Itertools;
+
+use crate::{format_header, metrics, table, DistanceMetric};
+
+/// Dimensions printed without a `--limit`.
+pub const DEFAULT_EXPLAINED_DIMENSIONS: usize = 10;
+
+/// The term of one dimension in the dot product or cosine of two vectors.
+#[derive(Debug)]
+pub struct Contribution {
+    pub dimension: usize,
+    pub first: f64,
+    pub second: f64,
+    /// Weighted product of both values, over the product of the norms for the cosine, so that
+    /// the contributions of every dimension sum to the score
+    pub contribution: f64,
+}
+
+/// Whether `--explain` decomposes the scores of a metric, which must be a sum over dimensions.
+pub fn explains(distance_metric: &DistanceMetric) -> bool {
+    matches!(
+        distance_metric,
+        DistanceMetric::Cosine | DistanceMetric::CosineDistance | DistanceMetric::Dot
+    )
+}
+
+/// The contribution of every dimension to the dot product, or the cosine with `cosine`, of two
+/// vectors, largest in absolute value first.
+pub fn contributions(first: &[f64], second: &[f64], cosine: bool) -> Vec<Contribution> {
+    let weights = metrics::dim_weights();
+    let weight = |i: usize| weights.map_or(1.0, |weights| weights[i]);
+    let norms = if cosine {
+        let norm = |vector: &[f64]| {
+            vector
+                .iter()
+                .enumerate()
+                .map(|(i, value)| weight(i) * value * value)
+                .sum::<f64>()
+                .sqrt()
+        };
+        norm(first) * norm(second)
+    } else {
+        1.0
+    };
+    first
+        .iter()
+        .zip(second)
+        .enumerate()
+        .map(|(dimension, (a, b))| Contribution {
+            dimension,
+            first: *a,
+            second: *b,
+            contribution: weight(dimension) * a * b / norms,
+        })
+        .sorted_by(|a, b| b.contribution.abs().total_cmp(&a.contribution.abs()))
+        .collect()
+}
+
+/// Prints the score of two documents and the dimensions contributing most to it.
+pub fn print_explanation(
+    (i, j): (usize, usize),
+    (first, second): (&[f64], &[f64]),
+    input_strings: &[String],
+    distance_metric: &DistanceMetric,
+    limit: usize,
+) {
+    println!(
+        "{distance_metric} between {} and {}: {}",
+        format_header(i, &input_strings[i]),
+        format_header(j, &input_strings[j]),
+        table::format_score(distance_metric.distance(first, second))
+    );
+
+    let cosine = !matches!(distance_metric, DistanceMetric::Dot);
+    let contributions = contributions(first, second, cosine);
+    let mut table = vec![vec![
+        "dimension".to_string(),
+        format!("value in {i}"),
+        format!("value in {j}"),
+        "contribution".to_string(),
+    ]];
+    table.extend(contributions.iter().take(limit).map(|contribution| {
+        vec![
+            contribution.dimension.to_string(),
+            table::format_score(contribution.first),
+            table::format_score(contribution.second),
+            table::format_score(contribution.contribution),
+        ]
+    }));
+    table::print(table);
+
+    let shown = limit.min(contributions.len());
+    let total = |contributions: &[Contribution]| {
+        contributions
+            .iter()
+            .map(|contribution| contribution.contribution)
+            .sum::<f64>()
+            // Sums of no terms are -0
+            + 0.0
+    };
+    println!(
+        "the {shown} dimensions above contribute {} to the {}, the other {} contribute {}",
+        table::format_score(total(&contributions[..shown])),
+        if cosine { "cosine" } else { "dot product" },
+        contributions.len() - shown,
+        table::format_score(total(&contributions[shown..]))
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contributions_sum_to_the_score_largest_first() {
+        let (first, second) = ([0.5, -2.0, 1.0, 0.0], [1.0, 1.5, 0.25, 3.0]);
+        let dot = contributions(&first, &second, false);
+        assert_eq!(
+            dot.iter().map(|c| c.dimension).collect::<Vec<_>>(),
+            [1, 0, 2, 3]
+        );
+        assert_eq!(dot[0].contribution, -3.0);
+        assert_eq!((dot[0].first, dot[0].second), (-2.0, 1.5));
+        let sum = dot.iter().map(|c| c.contribution).sum::<f64>();
+        assert!((sum - metrics::dot(&first, &second)).abs() < 1e-12);
+
+        let cosine = contributions(&first, &second, true);
+        let sum = cosine.iter().map(|c| c.contribution).sum::<f64>();
+        assert!((sum - metrics::cosine(&first, &second)).abs() < 1e-12);
+    }
+}
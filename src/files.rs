@@ -0,0 +1,295 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+/// Length from which Windows refuses paths not in the `\\?\` form.
+const MAX_PATH: usize = 260;
+
+/// Byte order mark some Windows editors start UTF-8 files with.
+const BOM: char = '\u{feff}';
+
+/// `path` in the `\\?\` form Windows needs for paths of `MAX_PATH` characters or more. Shorter
+/// paths, and all paths on other platforms, are returned unchanged.
+pub fn long_path(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    if cfg!(windows) && path.as_os_str().len() >= MAX_PATH {
+        if let Some(verbatim) = std::path::absolute(path)
+            .ok()
+            .and_then(|absolute| absolute.to_str().map(verbatim))
+        {
+            return verbatim.into();
+        }
+    }
+    path.to_path_buf()
+}
+
+/// The `\\?\` form of the absolute Windows path `path`, which Windows uses as is: it already
+/// has to be free of `.`, `..` and forward slashes.
+fn verbatim(path: &str) -> String {
+    let path = path.replace('/', "\\");
+    if path.starts_with(r"\\?\") {
+        path
+    } else if let Some(share) = path.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{share}")
+    } else {
+        format!(r"\\?\{path}")
+    }
+}
+
+/// Contents of the text file at `path`, without a byte order mark and with Windows line endings
+/// converted to `\n`. Files starting with a UTF-16 byte order mark, as Windows tools save
+/// "Unicode" text, are decoded from UTF-16.
+pub fn read_text(path: impl AsRef<Path>) -> std::io::Result<String> {
+    let contents = decode(fs::read(long_path(path))?)?;
+    let contents = contents.strip_prefix(BOM).unwrap_or(&contents);
+    Ok(contents.replace("\r\n", "\n"))
+}
+
+/// `bytes` as text: UTF-16 after a little- or big-endian byte order mark, UTF-8 otherwise.
+fn decode(bytes: Vec<u8>) -> std::io::Result<String> {
+    let invalid = |error: String| std::io::Error::new(std::io::ErrorKind::InvalidData, error);
+    let units = match bytes.get(..2) {
+        Some([0xff, 0xfe]) => u16::from_le_bytes,
+        Some([0xfe, 0xff]) => u16::from_be_bytes,
+        _ => return String::from_utf8(bytes).map_err(|error| invalid(error.to_string())),
+    };
+    if !bytes.len().is_multiple_of(2) {
+        return Err(invalid("UTF-16 text of an odd number of bytes".to_string()));
+    }
+    let units = bytes[2..]
+        .chunks_exact(2)
+        .map(|unit| units([unit[0], unit[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|error| invalid(error.to_string()))
+}
+
+/// Parses the JSON file at `path`, whichever platform wrote it.
+pub fn read_json<T: DeserializeOwned>(path: &str) -> T {
+    let contents = read_text(path).unwrap_or_else(|error| panic!("Failed to read {path}: {error}"));
+    serde_json::from_str(&contents)
+        .unwrap_or_else(|error| panic!("Invalid JSON in {path}: {error}"))
+}
+
+/// A document of a JSON input file: its text, or an object of its text and of the tenant (or
+/// project) it is embedded for.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InputDocument {
+    Text(String),
+    Record {
+        text: String,
+        #[serde(alias = "project")]
+        tenant: Option<String>,
+    },
+}
+
+/// Documents of the JSON array file at `path`. Windows line endings inside documents become `\n`
+/// so that a corpus embeds, and hits the cache, the same whichever platform exported it.
+pub fn read_documents(path: &str) -> Vec<String> {
+    read_records(path)
+        .into_iter()
+        .map(|(document, _)| document)
+        .collect()
+}
+
+/// [read_documents] along with the tenant of every document, if the file records one.
+pub fn read_records(path: &str) -> Vec<(String, Option<String>)> {
+    read_json::<Vec<InputDocument>>(path)
+        .into_iter()
+        .map(|document| match document {
+            InputDocument::Text(text) => (text, None),
+            InputDocument::Record { text, tenant } => (text, tenant),
+        })
+        .map(|(document, tenant)| (document.replace("\r\n", "\n"), tenant))
+        .collect()
+}
+
+/// `(file name, contents)` of every file in the directory at `path`, sorted by file name, so a
+/// directory of text files is a corpus of one document per file. Hidden files and
+/// subdirectories are skipped.
+///
+/// File names that aren't valid Unicode, such as Windows names with unpaired UTF-16 surrogates,
+/// are kept with replacement characters.
+pub fn read_directory(path: &str) -> Vec<(String, String)> {
+    let entries = fs::read_dir(long_path(path))
+        .unwrap_or_else(|error| panic!("Failed to read the directory {path}: {error}"));
+    let mut documents = entries
+        .map(|entry| entry.unwrap_or_else(|error| panic!("Failed to read {path}: {error}")))
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| !file_type.is_dir()))
+        .map(|entry| {
+            (
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            )
+        })
+        .filter(|(name, _)| !name.starts_with('.'))
+        .map(|(name, path)| {
+            let contents = read_text(&path)
+                .unwrap_or_else(|error| panic!("Failed to read {}: {error}", path.display()));
+            (name, contents)
+        })
+        .collect::<Vec<_>>();
+    documents.sort_by(|(a, _), (b, _)| a.cmp(b));
+    documents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file `name` of `contents` in a temporary directory of its own for `test`.
+    fn temp_file(test: &str, name: &str, contents: &[u8]) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("distance-calculator-{test}-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn verbatim_prefixes_drive_and_unc_paths() {
+        assert_eq!(verbatim(r"C:\data\corpus.json"), r"\\?\C:\data\corpus.json");
+        assert_eq!(
+            verbatim(r"\\server\share\corpus.json"),
+            r"\\?\UNC\server\share\corpus.json"
+        );
+        assert_eq!(verbatim("C:/data/corpus.json"), r"\\?\C:\data\corpus.json");
+        assert_eq!(
+            verbatim(r"\\?\C:\data\corpus.json"),
+            r"\\?\C:\data\corpus.json"
+        );
+    }
+
+    #[test]
+    fn short_paths_are_unchanged() {
+        assert_eq!(long_path("corpus.json"), PathBuf::from("corpus.json"));
+    }
+
+    #[test]
+    fn files_beyond_max_path_round_trip() {
+        let directory = (0..8).fold(
+            std::env::temp_dir().join(format!("distance-calculator-long-{}", std::process::id())),
+            |directory, _| directory.join("nested-directory-with-a-long-name"),
+        );
+        assert!(directory.as_os_str().len() > MAX_PATH);
+        fs::create_dir_all(long_path(&directory)).unwrap();
+
+        let path = directory.join("documents.json");
+        fs::write(long_path(&path), r#"["first", "second"]"#).unwrap();
+        assert_eq!(read_documents(path.to_str().unwrap()), ["first", "second"]);
+    }
+
+    #[test]
+    fn byte_order_marks_and_crlf_are_ignored() {
+        let path = temp_file(
+            "bom",
+            "documents.json",
+            b"\xef\xbb\xbf[\r\n  \"first\",\r\n  \"second\"\r\n]\r\n",
+        );
+        assert_eq!(read_documents(path.to_str().unwrap()), ["first", "second"]);
+    }
+
+    #[test]
+    fn documents_may_carry_their_tenant() {
+        let path = temp_file(
+            "tenants",
+            "documents.json",
+            br#"["plain", {"text": "billed", "tenant": "acme"}, {"text": "p", "project": "x"}]"#,
+        );
+        assert_eq!(
+            read_records(path.to_str().unwrap()),
+            [
+                ("plain".to_string(), None),
+                ("billed".to_string(), Some("acme".to_string())),
+                ("p".to_string(), Some("x".to_string())),
+            ]
+        );
+        assert_eq!(
+            read_documents(path.to_str().unwrap()),
+            ["plain", "billed", "p"]
+        );
+    }
+
+    #[test]
+    fn crlf_inside_documents_becomes_lf() {
+        let path = temp_file(
+            "crlf",
+            "documents.json",
+            br#"["one\r\ntwo", "three\nfour"]"#,
+        );
+        assert_eq!(
+            read_documents(path.to_str().unwrap()),
+            ["one\ntwo", "three\nfour"]
+        );
+    }
+
+    #[test]
+    fn non_ascii_file_names_are_read() {
+        let path = temp_file("unicode", "données 文档.json", r#"["café"]"#.as_bytes());
+        assert_eq!(read_documents(path.to_str().unwrap()), ["café"]);
+    }
+
+    #[test]
+    fn utf16_files_are_decoded() {
+        let text = "crème\r\nbrûlée 文档";
+        let little_endian = [0xff, 0xfe]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect::<Vec<_>>();
+        let big_endian = [0xfe, 0xff]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect::<Vec<_>>();
+
+        for (name, contents) in [("le.txt", little_endian), ("be.txt", big_endian)] {
+            let path = temp_file("utf16", name, &contents);
+            assert_eq!(read_text(&path).unwrap(), "crème\nbrûlée 文档");
+        }
+
+        let path = temp_file("utf16", "odd.txt", &[0xff, 0xfe, 0x41]);
+        assert!(read_text(&path).is_err());
+    }
+
+    #[test]
+    fn directories_are_one_document_per_file_by_name() {
+        let directory = temp_file("directory", "b.txt", b"second\r\n")
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        fs::write(directory.join("a 文档.md"), "first").unwrap();
+        fs::write(directory.join(".hidden"), "skipped").unwrap();
+        fs::create_dir_all(directory.join("nested")).unwrap();
+
+        assert_eq!(
+            read_directory(directory.to_str().unwrap()),
+            [
+                ("a 文档.md".to_string(), "first".to_string()),
+                ("b.txt".to_string(), "second\n".to_string()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_unicode_file_names_are_kept_lossily() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let directory = temp_file("lossy", "a.txt", b"first")
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let name = std::ffi::OsStr::from_bytes(b"b\xffc.txt");
+        fs::write(directory.join(name), "second").unwrap();
+
+        let names = read_directory(directory.to_str().unwrap())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.txt", "b\u{fffd}c.txt"]);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{f32::consts::PI, fmt::Display};
+
+use clap::ValueEnum;
+
+use crate::providers::Embedding;
+
+/// Lanes of the f32 kernels, a 256-bit register of f32 values.
+const LANES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum VectorPrecision {
+    F64,
+    /// Store the vectors in f32 once embedded and score pairs in f32, for half the memory
+    F32,
+}
+
+impl Display for VectorPrecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VectorPrecision::F64 => write!(f, "f64"),
+            VectorPrecision::F32 => write!(f, "f32"),
+        }
+    }
+}
+
+/// The vectors of the documents as one row-major f32 buffer.
+pub struct Vectors32 {
+    values: Vec<f32>,
+    dimensions: usize,
+}
+
+impl Vectors32 {
+    /// Converts the vectors of `documents`, freeing each f64 vector once copied so that both
+    /// never coexist for the whole corpus.
+    pub fn take(documents: &mut [Embedding], dimensions: usize) -> Self {
+        let mut values = Vec::with_capacity(documents.len() * dimensions);
+        for document in documents {
+            values.extend(
+                std::mem::take(&mut document.vec)
+                    .into_iter()
+                    .map(|value| value as f32),
+            );
+        }
+        Vectors32 { values, dimensions }
+    }
+
+    pub fn row(&self, i: usize) -> &[f32] {
+        &self.values[i * self.dimensions..(i + 1) * self.dimensions]
+    }
+
+    /// Bytes the vectors take.
+    pub fn bytes(&self) -> usize {
+        self.values.capacity() * size_of::<f32>()
+    }
+}
+
+/// Bytes the f64 vectors of `documents` take.
+pub fn f64_bytes(documents: &[Embedding]) -> usize {
+    documents
+        .iter()
+        .map(|document| document.vec.capacity() * size_of::<f64>())
+        .sum()
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB)
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
+
+/// Sums `term` over the dimensions in f32 lanes.
+#[inline(always)]
+fn lane_sum(first: &[f32], second: &[f32], term: impl Fn(f32, f32) -> f32) -> f32 {
+    let mut sums = [0.0; LANES];
+    let first_chunks = first.chunks_exact(LANES);
+    let second_chunks = second.chunks_exact(LANES);
+    let remainder = first_chunks
+        .remainder()
+        .iter()
+        .zip(second_chunks.remainder())
+        .map(|(a, b)| term(*a, *b))
+        .sum::<f32>();
+
+    for (a, b) in first_chunks.zip(second_chunks) {
+        for lane in 0..LANES {
+            sums[lane] += term(a[lane], b[lane]);
+        }
+    }
+    sums.iter().sum::<f32>() + remainder
+}
+
+pub fn dot(first: &[f32], second: &[f32]) -> f32 {
+    lane_sum(first, second, |a, b| a * b)
+}
+
+pub fn euclidean(first: &[f32], second: &[f32]) -> f32 {
+    lane_sum(first, second, |a, b| (a - b) * (a - b)).sqrt()
+}
+
+pub fn cosine(first: &[f32], second: &[f32]) -> f32 {
+    let norms = dot(first, first).sqrt() * dot(second, second).sqrt();
+    dot(first, second) / norms
+}
+
+pub fn manhattan(first: &[f32], second: &[f32]) -> f32 {
+    lane_sum(first, second, |a, b| (a - b).abs())
+}
+
+pub fn chebyshev(first: &[f32], second: &[f32]) -> f32 {
+    first
+        .iter()
+        .zip(second)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0, f32::max)
+}
+
+pub fn minkowski(first: &[f32], second: &[f32], p: f32) -> f32 {
+    lane_sum(first, second, |a, b| (a - b).abs().powf(p)).powf(1.0 / p)
+}
+
+pub fn angular(first: &[f32], second: &[f32]) -> f32 {
+    cosine(first, second).clamp(-1.0, 1.0).acos() / PI
+}
+
+pub fn jaccard(first: &[f32], second: &[f32]) -> f32 {
+    let (intersection, union) =
+        first
+            .iter()
+            .zip(second)
+            .fold((0u32, 0u32), |(intersection, union), (a, b)| {
+                let (a, b) = (*a > 0.0, *b > 0.0);
+                (intersection + u32::from(a && b), union + u32::from(a || b))
+            });
+    if union == 0 {
+        0.0
+    } else {
+        1.0 - intersection as f32 / union as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics;
+
+    fn vectors() -> (Vec<f64>, Vec<f64>) {
+        let first = (0..37).map(|i| (f64::from(i) * 0.37).sin()).collect();
+        let second = (0..37).map(|i| (f64::from(i) * 0.11).cos() - 0.2).collect();
+        (first, second)
+    }
+
+    #[test]
+    fn f32_kernels_match_the_f64_metrics() {
+        let (first, second) = vectors();
+        let (a, b) = (
+            first.iter().map(|value| *value as f32).collect::<Vec<_>>(),
+            second.iter().map(|value| *value as f32).collect::<Vec<_>>(),
+        );
+        let close = |f32: f32, f64: f64| (f64::from(f32) - f64).abs() < 1e-5 * f64.abs().max(1.0);
+        assert!(close(cosine(&a, &b), metrics::cosine(&first, &second)));
+        assert!(close(dot(&a, &b), metrics::dot(&first, &second)));
+        assert!(close(
+            euclidean(&a, &b),
+            metrics::euclidean(&first, &second)
+        ));
+        assert!(close(
+            manhattan(&a, &b),
+            metrics::manhattan(&first, &second)
+        ));
+        assert!(close(
+            chebyshev(&a, &b),
+            metrics::chebyshev_distance(&first, &second)
+        ));
+        assert!(close(
+            minkowski(&a, &b, 3.0),
+            metrics::minkowski_distance(&first, &second, 3.0)
+        ));
+        assert!(close(
+            angular(&a, &b),
+            metrics::angular_distance(&first, &second)
+        ));
+        assert!(close(
+            jaccard(&a, &b),
+            metrics::jaccard_distance(&first, &second)
+        ));
+    }
+
+    #[test]
+    fn converting_frees_the_f64_vectors() {
+        let (first, second) = vectors();
+        let mut documents = [first, second].map(|vec| Embedding {
+            document: String::new(),
+            vec,
+        });
+        let before = f64_bytes(&documents);
+        let vectors = Vectors32::take(&mut documents, 37);
+        assert_eq!(f64_bytes(&documents), 0);
+        assert_eq!(vectors.bytes(), before / 2);
+        assert_eq!(vectors.row(1)[0], (0.0f64.cos() - 0.2) as f32);
+    }
+}
@@ -0,0 +1,330 @@
+use std::{fs::File, io::BufWriter};
+
+use clap::Args;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::Serialize;
+
+use crate::files;
+
+#[derive(Args, Debug)]
+pub struct GenCorpusArgs {
+    /// Number of documents
+    #[arg(long, default_value_t = 100)]
+    size: usize,
+    /// Number of topics the documents are spread across, evenly
+    #[arg(long, default_value_t = 4, value_parser = parse_topics)]
+    topics: usize,
+    /// JSON file the corpus is written to [default: stdout]
+    #[arg(short, long)]
+    output: Option<String>,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// Vocabulary of a topic: every sentence has a subject, a verb, an object and a detail.
+struct Topic {
+    label: &'static str,
+    subjects: &'static [&'static str],
+    verbs: &'static [&'static str],
+    objects: &'static [&'static str],
+    details: &'static [&'static str],
+}
+
+const TOPICS: &[Topic] = &[
+    Topic {
+        label: "sports",
+        subjects: &[
+            "the coach",
+            "the striker",
+            "the goalkeeper",
+            "the captain",
+            "the referee",
+        ],
+        verbs: &["praised", "trained", "criticized", "substituted", "watched"],
+        objects: &[
+            "the midfielders",
+            "the youth team",
+            "the defense",
+            "the new signing",
+        ],
+        details: &[
+            "before the final",
+            "after a tense penalty shootout",
+            "during the derby",
+        ],
+    },
+    Topic {
+        label: "cooking",
+        subjects: &[
+            "the chef",
+            "the baker",
+            "my grandmother",
+            "the sous-chef",
+            "the caterer",
+        ],
+        verbs: &["seasoned", "roasted", "simmered", "kneaded", "plated"],
+        objects: &[
+            "the risotto",
+            "the sourdough",
+            "a pot of lentil soup",
+            "the lamb shoulder",
+        ],
+        details: &[
+            "with fresh thyme",
+            "over a low flame",
+            "for the dinner service",
+        ],
+    },
+    Topic {
+        label: "finance",
+        subjects: &[
+            "the central bank",
+            "the fund manager",
+            "the analyst",
+            "the treasurer",
+        ],
+        verbs: &["raised", "hedged", "forecast", "downgraded", "rebalanced"],
+        objects: &[
+            "interest rates",
+            "the bond portfolio",
+            "quarterly earnings",
+            "the pension fund",
+        ],
+        details: &[
+            "amid rising inflation",
+            "ahead of the market open",
+            "despite weak demand",
+        ],
+    },
+    Topic {
+        label: "astronomy",
+        subjects: &[
+            "the telescope",
+            "the astronomer",
+            "the space probe",
+            "the observatory",
+        ],
+        verbs: &[
+            "observed",
+            "photographed",
+            "measured",
+            "detected",
+            "tracked",
+        ],
+        objects: &[
+            "a distant galaxy",
+            "the comet's tail",
+            "a supernova",
+            "the rings of Saturn",
+        ],
+        details: &[
+            "through the night sky",
+            "in infrared light",
+            "beyond the asteroid belt",
+        ],
+    },
+    Topic {
+        label: "medicine",
+        subjects: &[
+            "the surgeon",
+            "the nurse",
+            "the pediatrician",
+            "the clinical trial",
+        ],
+        verbs: &[
+            "diagnosed",
+            "treated",
+            "vaccinated",
+            "monitored",
+            "prescribed",
+        ],
+        objects: &[
+            "the patient",
+            "a rare infection",
+            "the elderly ward",
+            "chronic back pain",
+        ],
+        details: &[
+            "with antibiotics",
+            "after the blood test",
+            "in the intensive care unit",
+        ],
+    },
+    Topic {
+        label: "programming",
+        subjects: &[
+            "the compiler",
+            "the developer",
+            "the test suite",
+            "the code reviewer",
+        ],
+        verbs: &[
+            "refactored",
+            "optimized",
+            "rejected",
+            "debugged",
+            "deployed",
+        ],
+        objects: &[
+            "the parser",
+            "a memory leak",
+            "the database migration",
+            "the API endpoint",
+        ],
+        details: &[
+            "before the release",
+            "in the main branch",
+            "without breaking the build",
+        ],
+    },
+    Topic {
+        label: "gardening",
+        subjects: &[
+            "the gardener",
+            "my neighbor",
+            "the botanist",
+            "the allotment club",
+        ],
+        verbs: &["pruned", "planted", "watered", "repotted", "fertilized"],
+        objects: &[
+            "the rose bushes",
+            "a row of tomatoes",
+            "the apple trees",
+            "the tulip bulbs",
+        ],
+        details: &[
+            "in early spring",
+            "after the first frost",
+            "along the garden fence",
+        ],
+    },
+    Topic {
+        label: "music",
+        subjects: &[
+            "the orchestra",
+            "the pianist",
+            "the band",
+            "the conductor",
+            "the choir",
+        ],
+        verbs: &[
+            "rehearsed",
+            "performed",
+            "recorded",
+            "composed",
+            "improvised",
+        ],
+        objects: &[
+            "the symphony",
+            "a jazz ballad",
+            "the new album",
+            "a string quartet",
+        ],
+        details: &[
+            "in front of a sold-out hall",
+            "in the studio",
+            "for the festival",
+        ],
+    },
+];
+
+fn parse_topics(topics: &str) -> Result<usize, String> {
+    let topics = topics.parse::<usize>().map_err(|error| error.to_string())?;
+    if topics == 0 || topics > TOPICS.len() {
+        return Err(format!("there are 1 to {} topics", TOPICS.len()));
+    }
+    Ok(topics)
+}
+
+const TEMPLATES: &[&str] = &[
+    "{subject} {verb} {object} {detail}.",
+    "Yesterday, {subject} {verb} {object} {detail}.",
+    "Everyone agreed that {subject} {verb} {object} {detail}.",
+    "According to the report, {subject} {verb} {object} {detail}.",
+];
+
+#[derive(Serialize, Debug, PartialEq)]
+struct LabeledDocument {
+    text: String,
+    label: &'static str,
+}
+
+/// `size` template sentences, the document at position `i` about topic `i % topics`.
+fn generate(size: usize, topics: usize, rng: &mut StdRng) -> Vec<LabeledDocument> {
+    (0..size)
+        .map(|i| {
+            let topic = &TOPICS[i % topics];
+            let pick = |words: &[&'static str], rng: &mut StdRng| *words.choose(rng).unwrap();
+            let text = pick(TEMPLATES, rng)
+                .replace("{subject}", pick(topic.subjects, rng))
+                .replace("{verb}", pick(topic.verbs, rng))
+                .replace("{object}", pick(topic.objects, rng))
+                .replace("{detail}", pick(topic.details, rng));
+            LabeledDocument {
+                text: capitalize(&text),
+                label: topic.label,
+            }
+        })
+        .collect()
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map_or(String::new(), |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+/// Writes a JSON array of `{"text", "label"}` documents, readable as `-i` input and by `split`.
+pub fn run(args: GenCorpusArgs) {
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let corpus = generate(args.size, args.topics, &mut rng);
+    match &args.output {
+        Some(output) => {
+            let file = File::create(files::long_path(output))
+                .unwrap_or_else(|error| panic!("Failed to create {output}: {error}"));
+            serde_json::to_writer_pretty(BufWriter::new(file), &corpus)
+                .unwrap_or_else(|error| panic!("Failed to write {output}: {error}"));
+        }
+        None => println!("{}", serde_json::to_string_pretty(&corpus).unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_spread_evenly_across_topics() {
+        let corpus = generate(10, 3, &mut StdRng::seed_from_u64(0));
+        assert_eq!(corpus.len(), 10);
+        let count = |label| {
+            corpus
+                .iter()
+                .filter(|document| document.label == label)
+                .count()
+        };
+        assert_eq!(
+            (count("sports"), count("cooking"), count("finance")),
+            (4, 3, 3)
+        );
+        assert!(corpus
+            .iter()
+            .all(|document| document.text.ends_with('.') && !document.text.contains('{')));
+        assert!(corpus[0].text.starts_with(char::is_uppercase));
+    }
+
+    #[test]
+    fn corpora_are_reproducible_from_their_seed() {
+        let generate = |seed| generate(20, 8, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(generate(7), generate(7));
+        assert_ne!(generate(7), generate(8));
+    }
+
+    #[test]
+    fn topics_are_bounded_by_the_built_in_ones() {
+        assert_eq!(parse_topics("8"), Ok(8));
+        assert!(parse_topics("0").is_err());
+        assert!(parse_topics("9").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+
+use clap::ValueEnum;
+
+use crate::{format_header, DistanceMetric};
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum GraphFormat {
+    /// `source,target,weight` edge list, as read by Gephi and `networkx`
+    Csv,
+    /// GraphViz
+    Dot,
+    /// Gephi's XML format, which also keeps the documents without edges
+    Gexf,
+}
+
+impl Display for GraphFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphFormat::Csv => write!(f, "csv"),
+            GraphFormat::Dot => write!(f, "dot"),
+            GraphFormat::Gexf => write!(f, "gexf"),
+        }
+    }
+}
+
+/// Pairs of distinct documents scoring at least as close as `threshold`, with their scores.
+pub fn edges(
+    matrix: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+    threshold: f64,
+) -> Vec<(usize, usize, f64)> {
+    (0..matrix.len())
+        .flat_map(|i| (i + 1..matrix.len()).map(move |j| (i, j, matrix[i][j])))
+        .filter(|(_, _, score)| distance_metric.cmp_closeness(*score, threshold).is_ge())
+        .collect()
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes the similarity graph of the documents: one node per document, identified by its id and
+/// labeled with its text, and one edge weighted by its score per pair of `edges`.
+pub fn write(
+    mut writer: impl Write,
+    format: &GraphFormat,
+    input_ids: &[String],
+    input_strings: &[String],
+    edges: &[(usize, usize, f64)],
+) -> io::Result<()> {
+    match format {
+        GraphFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(writer);
+            csv.write_record(["source", "target", "weight"])?;
+            for (i, j, score) in edges {
+                csv.write_record([&input_ids[*i], &input_ids[*j], &score.to_string()])?;
+            }
+            csv.flush()
+        }
+        GraphFormat::Dot => {
+            writeln!(writer, "graph similarity {{")?;
+            for (i, (id, string)) in input_ids.iter().zip(input_strings).enumerate() {
+                writeln!(
+                    writer,
+                    "  \"{}\" [label=\"{}\"];",
+                    escape_dot(id),
+                    escape_dot(&format_header(i, string))
+                )?;
+            }
+            for (i, j, score) in edges {
+                writeln!(
+                    writer,
+                    "  \"{}\" -- \"{}\" [weight={score}];",
+                    escape_dot(&input_ids[*i]),
+                    escape_dot(&input_ids[*j])
+                )?;
+            }
+            writeln!(writer, "}}")
+        }
+        GraphFormat::Gexf => {
+            writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(
+                writer,
+                r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#
+            )?;
+            writeln!(writer, r#"  <graph defaultedgetype="undirected">"#)?;
+            writeln!(writer, "    <nodes>")?;
+            for (id, string) in input_ids.iter().zip(input_strings) {
+                writeln!(
+                    writer,
+                    r#"      <node id="{}" label="{}"/>"#,
+                    escape_xml(id),
+                    escape_xml(string)
+                )?;
+            }
+            writeln!(writer, "    </nodes>")?;
+            writeln!(writer, "    <edges>")?;
+            for (edge, (i, j, score)) in edges.iter().enumerate() {
+                writeln!(
+                    writer,
+                    r#"      <edge id="{edge}" source="{}" target="{}" weight="{score}"/>"#,
+                    escape_xml(&input_ids[*i]),
+                    escape_xml(&input_ids[*j])
+                )?;
+            }
+            writeln!(writer, "    </edges>")?;
+            writeln!(writer, "  </graph>")?;
+            writeln!(writer, "</gexf>")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 0.9, 0.2],
+            vec![0.9, 1.0, 0.8],
+            vec![0.2, 0.8, 1.0],
+        ]
+    }
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|string| string.to_string()).collect()
+    }
+
+    fn written(format: GraphFormat, edges: &[(usize, usize, f64)]) -> String {
+        let mut output = vec![];
+        write(
+            &mut output,
+            &format,
+            &strings(&["a", "b", "c"]),
+            &strings(&["say \"hi\"", "fish & chips", "<tag>"]),
+            edges,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn edges_are_the_pairs_reaching_the_threshold() {
+        assert_eq!(
+            edges(&matrix(), &DistanceMetric::Cosine, 0.8),
+            [(0, 1, 0.9), (1, 2, 0.8)]
+        );
+        assert_eq!(edges(&matrix(), &DistanceMetric::L2, 0.5), [(0, 2, 0.2)]);
+    }
+
+    #[test]
+    fn csv_lists_the_edges_by_id() {
+        assert_eq!(
+            written(GraphFormat::Csv, &[(0, 1, 0.9)]),
+            "source,target,weight\na,b,0.9\n"
+        );
+    }
+
+    #[test]
+    fn dot_and_gexf_escape_the_labels() {
+        let dot = written(GraphFormat::Dot, &[(0, 1, 0.9)]);
+        assert!(dot.contains(r#""a" [label="0: say \"hi\""];"#));
+        assert!(dot.contains(r#""a" -- "b" [weight=0.9];"#));
+
+        let gexf = written(GraphFormat::Gexf, &[(1, 2, 0.8)]);
+        assert!(gexf.contains(r#"<node id="b" label="fish &amp; chips"/>"#));
+        assert!(gexf.contains(r#"<node id="c" label="&lt;tag&gt;"/>"#));
+        assert!(gexf.contains(r#"<edge id="0" source="b" target="c" weight="0.8"/>"#));
+    }
+}
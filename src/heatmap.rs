@@ -0,0 +1,138 @@
+use plotters::prelude::*;
+
+use crate::{files, format_header, DistanceMetric};
+
+const CELL_SIZE: u32 = 40;
+const LABEL_AREA_SIZE: u32 = 160;
+const CAPTION_SIZE: u32 = 40;
+const MARGIN: u32 = 10;
+
+/// Renders the full symmetric `matrix` as a PNG heatmap with the document labels on both axes.
+///
+/// Colors follow the viridis scale from the lowest to the highest score in the matrix,
+/// flipped for similarity metrics so that yellow always means "closest". Fails without
+/// documents, as there is no axis to draw.
+pub fn render(
+    path: &str,
+    input_strings: &[String],
+    matrix: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let n = input_strings.len();
+    if n == 0 {
+        return Err("there are no documents to render".into());
+    }
+
+    let width = LABEL_AREA_SIZE + CELL_SIZE * n as u32 + 2 * MARGIN;
+    let height = width + CAPTION_SIZE;
+
+    let path = files::long_path(path);
+    let root = BitMapBackend::new(&path, (width, height)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(distance_metric.to_string(), ("sans-serif", 20))
+        .margin(MARGIN)
+        .x_label_area_size(LABEL_AREA_SIZE)
+        .y_label_area_size(LABEL_AREA_SIZE)
+        // Segmented ranges include their end, hence `n - 1` for `n` cells
+        .build_cartesian_2d((0..n - 1).into_segmented(), (0..n - 1).into_segmented())?;
+
+    // Rows are drawn bottom-up, so document `i` sits at `y = n - 1 - i` to read top-down
+    let label = |i: usize| format_header(i, &input_strings[i]);
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_labels(n)
+        .y_labels(n)
+        .x_label_formatter(&|value| match value {
+            SegmentValue::CenterOf(i) if *i < n => label(*i),
+            _ => String::new(),
+        })
+        .y_label_formatter(&|value| match value {
+            SegmentValue::CenterOf(i) if *i < n => label(n - 1 - *i),
+            _ => String::new(),
+        })
+        .x_label_style(
+            ("sans-serif", 12)
+                .into_font()
+                .transform(FontTransform::Rotate90),
+        )
+        .draw()?;
+
+    let scores = matrix.iter().flatten().copied();
+    let min = scores.clone().fold(f64::INFINITY, f64::min);
+    let max = scores.fold(f64::NEG_INFINITY, f64::max);
+    let closeness = |score: f64| {
+        let scaled = if max > min {
+            (score - min) / (max - min)
+        } else {
+            1.0
+        };
+        if distance_metric.higher_is_closer() {
+            scaled
+        } else {
+            1.0 - scaled
+        }
+    };
+
+    chart.draw_series(matrix.iter().enumerate().flat_map(|(i, row)| {
+        row.iter().enumerate().map(move |(j, score)| {
+            let color = ViridisRGB.get_color(closeness(*score));
+            Rectangle::new(
+                [
+                    (SegmentValue::Exact(j), SegmentValue::Exact(n - 1 - i)),
+                    (SegmentValue::Exact(j + 1), SegmentValue::Exact(n - i)),
+                ],
+                color.filled(),
+            )
+        })
+    }))?;
+
+    root.present()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "distance-calculator-heatmap-{}-{name}.png",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn renders_one_cell_per_pair() {
+        let path = path("pairs");
+        let input_strings = vec!["first".to_string(), "second".to_string()];
+        let matrix = vec![vec![1.0, 0.5], vec![0.5, 1.0]];
+        render(&path, &input_strings, &matrix, &DistanceMetric::Cosine).unwrap();
+
+        let contents = std::fs::read(&path).unwrap();
+        assert!(contents.starts_with(b"\x89PNG"));
+        // The image size is stored big-endian in the IHDR chunk
+        let width = u32::from_be_bytes(contents[16..20].try_into().unwrap());
+        assert_eq!(width, LABEL_AREA_SIZE + 2 * CELL_SIZE + 2 * MARGIN);
+    }
+
+    #[test]
+    fn renders_a_single_document() {
+        let path = path("single");
+        let input_strings = vec!["only".to_string()];
+        render(&path, &input_strings, &[vec![1.0]], &DistanceMetric::Cosine).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+    }
+
+    #[test]
+    fn refuses_to_render_no_documents() {
+        let path = path("empty");
+        assert!(render(&path, &[], &[], &DistanceMetric::Cosine).is_err());
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}
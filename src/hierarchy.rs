@@ -0,0 +1,447 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::{format_header, DistanceMetric};
+
+/// Width in characters of the merge-height axis of the ASCII dendrogram.
+const DENDROGRAM_WIDTH: usize = 40;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Linkage {
+    Single,
+    Complete,
+    Average,
+}
+
+impl Display for Linkage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Linkage::Single => write!(f, "single"),
+            Linkage::Complete => write!(f, "complete"),
+            Linkage::Average => write!(f, "average"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum TreeFormat {
+    Newick,
+    Json,
+}
+
+impl Display for TreeFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeFormat::Newick => write!(f, "newick"),
+            TreeFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+pub enum Tree {
+    Leaf {
+        index: usize,
+    },
+    Merge {
+        height: f64,
+        size: usize,
+        left: Box<Tree>,
+        right: Box<Tree>,
+    },
+}
+
+impl Tree {
+    fn height(&self) -> f64 {
+        match self {
+            Tree::Leaf { .. } => 0.0,
+            Tree::Merge { height, .. } => *height,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Tree::Leaf { .. } => 1,
+            Tree::Merge { size, .. } => *size,
+        }
+    }
+
+    fn leaves(&self) -> Vec<usize> {
+        match self {
+            Tree::Leaf { index } => vec![*index],
+            Tree::Merge { left, right, .. } => {
+                let mut leaves = left.leaves();
+                leaves.extend(right.leaves());
+                leaves
+            }
+        }
+    }
+}
+
+/// Agglomerative clustering of `vectors`, merging the two closest clusters under
+/// `distance_metric` until a single tree remains, or `None` without vectors.
+///
+/// Merge heights are dissimilarities, shifted for metrics such as dot whose dissimilarities
+/// can be negative so that the closest pair merges at 0: every linkage merges the same
+/// clusters either way, and heights and branch lengths stay non-negative.
+pub fn agglomerate(
+    vectors: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+    linkage: &Linkage,
+) -> Option<Tree> {
+    let mut distances = vectors
+        .iter()
+        .map(|first| {
+            vectors
+                .iter()
+                .map(|second| distance_metric.dissimilarity(first, second))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let closest = (0..vectors.len())
+        .flat_map(|i| (i + 1..vectors.len()).map(move |j| (i, j)))
+        .map(|(i, j)| distances[i][j])
+        .fold(f64::INFINITY, f64::min);
+    if closest < 0.0 {
+        distances
+            .iter_mut()
+            .flatten()
+            .for_each(|distance| *distance -= closest);
+    }
+    let mut clusters = (0..vectors.len())
+        .map(|index| Some(Tree::Leaf { index }))
+        .collect::<Vec<_>>();
+
+    for _ in 1..vectors.len() {
+        let (i, j) = (0..clusters.len())
+            .flat_map(|i| (i + 1..clusters.len()).map(move |j| (i, j)))
+            .filter(|(i, j)| clusters[*i].is_some() && clusters[*j].is_some())
+            .min_by(|(a, b), (c, d)| distances[*a][*b].total_cmp(&distances[*c][*d]))
+            .unwrap();
+
+        let left = clusters[i].take().unwrap();
+        let right = clusters[j].take().unwrap();
+        let (left_size, right_size) = (left.size() as f64, right.size() as f64);
+
+        // Lance-Williams update: row `i` now holds the distances of the merged cluster
+        for k in 0..clusters.len() {
+            if k == i || clusters[k].is_none() {
+                continue;
+            }
+
+            let (to_left, to_right) = (distances[i][k], distances[j][k]);
+            let merged = match linkage {
+                Linkage::Single => to_left.min(to_right),
+                Linkage::Complete => to_left.max(to_right),
+                Linkage::Average => {
+                    (left_size * to_left + right_size * to_right) / (left_size + right_size)
+                }
+            };
+            distances[i][k] = merged;
+            distances[k][i] = merged;
+        }
+
+        clusters[i] = Some(Tree::Merge {
+            height: distances[i][j],
+            size: left.size() + right.size(),
+            left: Box::new(left),
+            right: Box::new(right),
+        });
+    }
+
+    clusters.into_iter().flatten().next()
+}
+
+/// Renders the tree sideways: leaves on the left, merges further right the later they happen.
+pub fn dendrogram(tree: &Tree, input_strings: &[String]) -> String {
+    let labels = tree
+        .leaves()
+        .into_iter()
+        .map(|index| format_header(index, &input_strings[index]))
+        .collect::<Vec<_>>();
+    let label_width = labels
+        .iter()
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let max_height = tree.height();
+    let mut grid: Vec<Vec<char>> = vec![vec![]; labels.len()];
+    let mut next_row = 0;
+    let (row, column) = draw(tree, max_height, &mut grid, &mut next_row);
+
+    // Nothing hangs off the root, so its junction must not point right
+    if let Some(character) = grid[row].get_mut(column) {
+        *character = match *character {
+            '├' => '┤',
+            '┬' => '┐',
+            '┴' => '┘',
+            character => character,
+        };
+    }
+
+    let mut lines = labels
+        .iter()
+        .zip(grid)
+        .map(|(label, row)| {
+            let row = row.into_iter().collect::<String>();
+            format!("{label:<label_width$} {}", row.trim_end())
+        })
+        .collect::<Vec<_>>();
+    lines.push(format!(
+        "{:<label_width$} 0{:>width$}",
+        "",
+        max_height,
+        width = DENDROGRAM_WIDTH
+    ));
+
+    lines.join("\n")
+}
+
+/// Draws `tree` into `grid` and returns its (row, column) anchor.
+fn draw(
+    tree: &Tree,
+    max_height: f64,
+    grid: &mut [Vec<char>],
+    next_row: &mut usize,
+) -> (usize, usize) {
+    let (left, right) = match tree {
+        Tree::Leaf { .. } => {
+            let row = *next_row;
+            *next_row += 1;
+            return (row, 0);
+        }
+        Tree::Merge { left, right, .. } => (left, right),
+    };
+
+    let (top, left_column) = draw(left, max_height, grid, next_row);
+    let (bottom, right_column) = draw(right, max_height, grid, next_row);
+
+    let scaled = if max_height > 0.0 {
+        1 + (tree.height() / max_height * (DENDROGRAM_WIDTH - 1) as f64).round() as usize
+    } else {
+        1
+    };
+    let column = scaled.max(left_column.max(right_column) + 1);
+    let row = (top + bottom) / 2;
+
+    put_line(&mut grid[top], left_column, column);
+    put_line(&mut grid[bottom], right_column, column);
+    for (offset, line) in grid[top..=bottom].iter_mut().enumerate() {
+        let character = match (top + offset, row) {
+            (current, row) if current == top && current == row => '┬',
+            (current, row) if current == bottom && current == row => '┴',
+            (current, _) if current == top => '┐',
+            (current, _) if current == bottom => '┘',
+            (current, row) if current == row => '├',
+            _ => '│',
+        };
+        put(line, column, character);
+    }
+
+    (row, column)
+}
+
+fn put(line: &mut Vec<char>, column: usize, character: char) {
+    if line.len() <= column {
+        line.resize(column + 1, ' ');
+    }
+    line[column] = character;
+}
+
+fn put_line(line: &mut Vec<char>, from: usize, to: usize) {
+    for column in from..to {
+        if line.get(column).is_none_or(|character| *character == ' ') {
+            put(line, column, '─');
+        }
+    }
+}
+
+pub fn newick(tree: &Tree, input_strings: &[String]) -> String {
+    fn node(tree: &Tree, parent_height: f64, input_strings: &[String]) -> String {
+        let branch = parent_height - tree.height();
+        match tree {
+            Tree::Leaf { index } => {
+                let label = format_header(*index, &input_strings[*index]).replace('\'', "''");
+                format!("'{label}':{branch}")
+            }
+            Tree::Merge {
+                left,
+                right,
+                height,
+                ..
+            } => format!(
+                "({},{}):{branch}",
+                node(left, *height, input_strings),
+                node(right, *height, input_strings)
+            ),
+        }
+    }
+
+    match tree {
+        Tree::Leaf { .. } => format!("{};", node(tree, 0.0, input_strings)),
+        Tree::Merge {
+            left,
+            right,
+            height,
+            ..
+        } => format!(
+            "({},{});",
+            node(left, *height, input_strings),
+            node(right, *height, input_strings)
+        ),
+    }
+}
+
+pub fn json(tree: &Tree, input_strings: &[String]) -> Value {
+    match tree {
+        Tree::Leaf { index } => json!({
+            "index": index,
+            "document": input_strings[*index],
+        }),
+        Tree::Merge {
+            height,
+            left,
+            right,
+            ..
+        } => json!({
+            "height": height,
+            "children": [json(left, input_strings), json(right, input_strings)],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documents(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("doc {i}")).collect()
+    }
+
+    /// Two tight pairs far apart on a line.
+    fn two_pairs() -> Vec<Vec<f64>> {
+        vec![vec![0.0], vec![1.0], vec![10.0], vec![11.0]]
+    }
+
+    fn merge_heights(tree: &Tree) -> Vec<f64> {
+        match tree {
+            Tree::Leaf { .. } => vec![],
+            Tree::Merge {
+                height,
+                left,
+                right,
+                ..
+            } => {
+                let mut heights = merge_heights(left);
+                heights.extend(merge_heights(right));
+                heights.push(*height);
+                heights
+            }
+        }
+    }
+
+    #[test]
+    fn closest_documents_merge_first() {
+        let tree = agglomerate(&two_pairs(), &DistanceMetric::L2, &Linkage::Single).unwrap();
+        let Tree::Merge { left, right, .. } = &tree else {
+            panic!("expected a merge");
+        };
+
+        let mut sides = [left.leaves(), right.leaves()];
+        sides.sort();
+        assert_eq!(sides, [vec![0, 1], vec![2, 3]]);
+        assert_eq!(tree.size(), 4);
+    }
+
+    #[test]
+    fn linkages_set_the_height_of_the_last_merge() {
+        let vectors = two_pairs();
+        let height = |linkage| {
+            agglomerate(&vectors, &DistanceMetric::L2, &linkage)
+                .unwrap()
+                .height()
+        };
+
+        assert_eq!(height(Linkage::Single), 9.0);
+        assert_eq!(height(Linkage::Complete), 11.0);
+        assert_eq!(height(Linkage::Average), 10.0);
+    }
+
+    #[test]
+    fn dot_products_give_non_negative_heights() {
+        let vectors = vec![vec![3.0, 0.0], vec![2.9, 0.1], vec![0.0, 1.0]];
+        let tree = agglomerate(&vectors, &DistanceMetric::Dot, &Linkage::Average).unwrap();
+
+        let heights = merge_heights(&tree);
+        assert!(heights.iter().all(|height| *height >= 0.0), "{heights:?}");
+        assert_eq!(heights[0], 0.0);
+        assert!(tree.height() > 0.0);
+        assert!(!newick(&tree, &documents(3)).contains(":-"));
+    }
+
+    #[test]
+    fn newick_branches_are_the_height_differences() {
+        let tree = agglomerate(
+            &[vec![0.0], vec![2.0], vec![7.0]],
+            &DistanceMetric::L2,
+            &Linkage::Single,
+        )
+        .unwrap();
+
+        assert_eq!(
+            newick(&tree, &documents(3)),
+            "(('0: doc 0':2,'1: doc 1':2):3,'2: doc 2':5);"
+        );
+    }
+
+    #[test]
+    fn json_trees_nest_documents_under_merges() {
+        let tree = agglomerate(
+            &[vec![0.0], vec![1.0]],
+            &DistanceMetric::L2,
+            &Linkage::Single,
+        )
+        .unwrap();
+
+        assert_eq!(
+            json(&tree, &documents(2)),
+            json!({
+                "height": 1.0,
+                "children": [
+                    {"index": 0, "document": "doc 0"},
+                    {"index": 1, "document": "doc 1"},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn dendrograms_list_every_document_once() {
+        let tree = agglomerate(&two_pairs(), &DistanceMetric::L2, &Linkage::Average).unwrap();
+        let dendrogram = dendrogram(&tree, &documents(4));
+
+        let lines = dendrogram.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        for i in 0..4 {
+            assert_eq!(
+                lines
+                    .iter()
+                    .filter(|line| line.contains(&format!("doc {i}")))
+                    .count(),
+                1
+            );
+        }
+        assert!(lines[4].trim_end().ends_with("10"));
+    }
+
+    #[test]
+    fn no_vectors_make_no_tree() {
+        assert!(agglomerate(&[], &DistanceMetric::L2, &Linkage::Average).is_none());
+
+        let tree = agglomerate(&[vec![1.0]], &DistanceMetric::L2, &Linkage::Average).unwrap();
+        assert_eq!(tree.leaves(), [0]);
+        assert_eq!(newick(&tree, &documents(1)), "'0: doc 0':0;");
+    }
+}
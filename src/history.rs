@@ -0,0 +1,186 @@
+use chrono::{DateTime, Utc};
+
+use crate::sql;
+
+/// Tables of the run history, created in the `--db` database if missing.
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS runs (run_id TEXT PRIMARY KEY, started_at TEXT NOT NULL, \
+     provider TEXT, model TEXT, model_version TEXT, metric TEXT NOT NULL, \
+     documents BIGINT NOT NULL)",
+    "CREATE TABLE IF NOT EXISTS run_documents (run_id TEXT NOT NULL, position BIGINT NOT NULL, \
+     document_id TEXT NOT NULL, document TEXT NOT NULL)",
+    "CREATE TABLE IF NOT EXISTS run_scores (run_id TEXT NOT NULL, i BIGINT NOT NULL, \
+     j BIGINT NOT NULL, score DOUBLE PRECISION NOT NULL)",
+];
+
+const RUN_COLUMNS: &[&str] = &[
+    "run_id",
+    "started_at",
+    "provider",
+    "model",
+    "model_version",
+    "metric",
+    "documents",
+];
+const DOCUMENT_COLUMNS: &[&str] = &["run_id", "position", "document_id", "document"];
+const SCORE_COLUMNS: &[&str] = &["run_id", "i", "j", "score"];
+
+/// What a run compared and how, as recorded in the `runs` table.
+pub struct Run<'a> {
+    pub started_at: DateTime<Utc>,
+    /// Absent for runs on saved `--embeddings`
+    pub provider: Option<String>,
+    pub model: Option<&'a str>,
+    /// Snapshot of the model the provider reported, if any
+    pub model_version: Option<&'a str>,
+    pub metric: String,
+}
+
+impl Run<'_> {
+    /// Identifier of the run, unique across the runs of any process.
+    fn id(&self) -> String {
+        format!(
+            "{}-{}",
+            self.started_at.format("%Y%m%dT%H%M%S%.6fZ"),
+            std::process::id()
+        )
+    }
+}
+
+/// Appends `run`, its documents (`(id, text)` in input order) and the score of every pair of
+/// its documents to the history tables of the database at `url`, in one transaction. Returns
+/// the id of the run.
+pub async fn record(
+    url: &str,
+    run: &Run<'_>,
+    documents: &[(&str, &str)],
+    matrix: &[Vec<f64>],
+) -> String {
+    let pool = sql::connect(url).await;
+    for statement in SCHEMA {
+        sqlx::query(statement)
+            .execute(&pool)
+            .await
+            .unwrap_or_else(|error| panic!("Failed to create the run history tables: {error}"));
+    }
+
+    let run_id = run.id();
+    let mut transaction = pool.begin().await.unwrap();
+
+    let statement = sql::insert_statement(url, "runs", RUN_COLUMNS, 1);
+    sqlx::query(&statement)
+        .bind(&run_id)
+        .bind(run.started_at.to_rfc3339())
+        .bind(run.provider.as_deref())
+        .bind(run.model)
+        .bind(run.model_version)
+        .bind(&run.metric)
+        .bind(documents.len() as i64)
+        .execute(&mut *transaction)
+        .await
+        .unwrap_or_else(failed(url));
+
+    let positions = documents.iter().enumerate().collect::<Vec<_>>();
+    for batch in positions.chunks(sql::batch_rows(DOCUMENT_COLUMNS)) {
+        let statement = sql::insert_statement(url, "run_documents", DOCUMENT_COLUMNS, batch.len());
+        let mut query = sqlx::query(&statement);
+        for (position, (id, document)) in batch {
+            query = query
+                .bind(&run_id)
+                .bind(*position as i64)
+                .bind(*id)
+                .bind(*document);
+        }
+        query
+            .execute(&mut *transaction)
+            .await
+            .unwrap_or_else(failed(url));
+    }
+
+    let pairs = (0..matrix.len())
+        .flat_map(|i| (i + 1..matrix.len()).map(move |j| (i, j)))
+        .collect::<Vec<_>>();
+    for batch in pairs.chunks(sql::batch_rows(SCORE_COLUMNS)) {
+        let statement = sql::insert_statement(url, "run_scores", SCORE_COLUMNS, batch.len());
+        let mut query = sqlx::query(&statement);
+        for (i, j) in batch {
+            query = query
+                .bind(&run_id)
+                .bind(*i as i64)
+                .bind(*j as i64)
+                .bind(matrix[*i][*j]);
+        }
+        query
+            .execute(&mut *transaction)
+            .await
+            .unwrap_or_else(failed(url));
+    }
+
+    transaction.commit().await.unwrap_or_else(failed(url));
+    run_id
+}
+
+fn failed<T>(url: &str) -> impl FnOnce(sqlx::Error) -> T + '_ {
+    move |error| panic!("Failed to record the run in {url}: {error}")
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Row;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_are_appended_with_their_documents_and_scores() {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-history-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let url = sql::parse_database_url(path.to_str().unwrap()).unwrap();
+
+        let documents = [("a", "cat"), ("b", "kitten"), ("c", "car")];
+        let matrix = vec![
+            vec![1.0, 0.8, 0.1],
+            vec![0.8, 1.0, 0.2],
+            vec![0.1, 0.2, 1.0],
+        ];
+        let mut run_ids = vec![];
+        for model_version in ["v1", "v2"] {
+            let run = Run {
+                started_at: Utc::now(),
+                provider: Some("openai".to_string()),
+                model: Some("text-embedding-3-small"),
+                model_version: Some(model_version),
+                metric: "cosine".to_string(),
+            };
+            run_ids.push(record(&url, &run, &documents, &matrix).await);
+        }
+        assert_ne!(run_ids[0], run_ids[1]);
+
+        // How the score of a pair changed across model versions
+        let pool = sql::connect(&url).await;
+        let rows = sqlx::query(
+            "SELECT r.model_version, s.score FROM run_scores s \
+             JOIN runs r ON r.run_id = s.run_id \
+             JOIN run_documents a ON a.run_id = s.run_id AND a.position = s.i \
+             JOIN run_documents b ON b.run_id = s.run_id AND b.position = s.j \
+             WHERE a.document = 'cat' AND b.document = 'kitten' ORDER BY r.started_at",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let scores = rows
+            .iter()
+            .map(|row| (row.get::<String, _>(0), row.get::<f64, _>(1)))
+            .collect::<Vec<_>>();
+        assert_eq!(scores, [("v1".to_string(), 0.8), ("v2".to_string(), 0.8)]);
+
+        let pairs = sqlx::query("SELECT COUNT(*) FROM run_scores")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get::<i64, _>(0);
+        assert_eq!(pairs, 6);
+    }
+}
@@ -0,0 +1,637 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashSet},
+    fmt::Display,
+    path::Path,
+    time::Instant,
+};
+
+use clap::{Args, ValueEnum};
+use indicatif::ProgressBar;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::{progress, search, table, DistanceMetric};
+
+/// Corpus size from which `--index auto` searches the HNSW index instead of every document.
+pub const HNSW_THRESHOLD: usize = 10_000;
+/// Candidates a search keeps by default, more for a higher recall at a higher latency.
+pub const DEFAULT_EF_SEARCH: usize = 64;
+/// Neighbors of a node on the upper layers.
+const M: usize = 16;
+/// Neighbors of a node on the bottom layer, which holds every node.
+const M0: usize = 2 * M;
+/// Candidates considered for the neighbors of an inserted node.
+const EF_CONSTRUCTION: usize = 100;
+const MAGIC: &[u8; 4] = b"DCHN";
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum IndexKind {
+    /// HNSW from 10k documents on, every document below
+    Auto,
+    /// Always search the HNSW index
+    Hnsw,
+}
+
+impl Display for IndexKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexKind::Auto => write!(f, "auto"),
+            IndexKind::Hnsw => write!(f, "hnsw"),
+        }
+    }
+}
+
+/// How documents are searched for their nearest neighbors.
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    /// Search with an HNSW index rather than scoring every document
+    #[arg(long, default_value_t = IndexKind::Auto)]
+    pub index: IndexKind,
+    /// Score every document of the corpus, whatever its size
+    #[arg(long, conflicts_with = "index")]
+    pub exact: bool,
+    /// Candidates the HNSW index keeps while searching, at least the neighbors searched for:
+    /// more find more of the exact neighbors, more slowly
+    #[arg(long, default_value_t = DEFAULT_EF_SEARCH)]
+    pub ef_search: usize,
+    /// Check the neighbors the HNSW index finds for a random sample of this many documents
+    /// against brute force, and report their recall
+    #[arg(long, conflicts_with = "exact")]
+    pub verify_sample: Option<usize>,
+}
+
+impl IndexArgs {
+    /// Whether a corpus of `count` documents is searched with the HNSW index.
+    pub fn indexed(&self, count: usize) -> bool {
+        !self.exact && (self.index == IndexKind::Hnsw || count >= HNSW_THRESHOLD)
+    }
+}
+
+/// Cost of `second` from `first` for the HNSW index, lower being closer.
+pub fn cost(distance_metric: &DistanceMetric, first: &[f64], second: &[f64]) -> f64 {
+    -distance_metric.similarity(first, second)
+}
+
+/// A node and its cost from the node or query being searched for, lower being closer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    cost: f64,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost
+            .total_cmp(&other.cost)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A hierarchical navigable small world graph (Malkov & Yashunin, 2016) over the items of a
+/// corpus: a search descends greedily through sparse upper layers, then explores the
+/// neighborhood of the closest node found on the bottom layer.
+#[derive(Debug, PartialEq)]
+pub struct Hnsw {
+    /// Neighbors of every node on each of its layers, from the bottom one up
+    neighbors: Vec<Vec<Vec<u32>>>,
+    /// Node of the top layer every search starts from
+    entry: u32,
+}
+
+impl Hnsw {
+    /// Indexes `count` items, `cost(a, b)` being lower the closer items `a` and `b` are. Node
+    /// levels are drawn from a fixed seed so that the same corpus always gives the same index.
+    pub fn build(count: usize, cost: impl Fn(usize, usize) -> f64, bar: &ProgressBar) -> Self {
+        let mut rng = StdRng::seed_from_u64(0);
+        let level_factor = 1.0 / (M as f64).ln();
+        let mut index = Hnsw {
+            neighbors: Vec::with_capacity(count),
+            entry: 0,
+        };
+
+        for node in 0..count {
+            bar.inc(1);
+            // In (0, 1] so that the logarithm is finite
+            let uniform = 1.0 - rng.gen::<f64>();
+            let level = (-uniform.ln() * level_factor) as usize;
+            index.neighbors.push(vec![vec![]; level + 1]);
+            if node == 0 {
+                continue;
+            }
+
+            let query_cost = |other: usize| cost(node, other);
+            let top = index.top_layer();
+            let entry = index.entry as usize;
+            let mut entries = vec![Candidate {
+                cost: query_cost(entry),
+                node: index.entry,
+            }];
+            for layer in (level + 1..=top).rev() {
+                entries = index.search_layer(&entries, 1, layer, &query_cost);
+            }
+            for layer in (0..=level.min(top)).rev() {
+                let found = index.search_layer(&entries, EF_CONSTRUCTION, layer, &query_cost);
+                let most = if layer == 0 { M0 } else { M };
+                let selected = select_neighbors(&found, M, &cost);
+                for &neighbor in &selected {
+                    let list = &mut index.neighbors[neighbor as usize][layer];
+                    list.push(node as u32);
+                    if list.len() > most {
+                        let mut candidates = list
+                            .iter()
+                            .map(|&other| Candidate {
+                                cost: cost(neighbor as usize, other as usize),
+                                node: other,
+                            })
+                            .collect::<Vec<_>>();
+                        candidates.sort();
+                        index.neighbors[neighbor as usize][layer] =
+                            select_neighbors(&candidates, most, &cost);
+                    }
+                }
+                index.neighbors[node][layer] = selected;
+                entries = found;
+            }
+            if level > top {
+                index.entry = node as u32;
+            }
+        }
+        index
+    }
+
+    /// Builds the index of `count` items showing a progress bar, and logs how long it took.
+    pub fn index(count: usize, cost: impl Fn(usize, usize) -> f64) -> Self {
+        let started = Instant::now();
+        let bar = progress::bar(count, "documents").with_message("Indexing");
+        let index = Hnsw::build(count, cost, &bar);
+        bar.finish_and_clear();
+        tracing::info!(
+            documents = count,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Built the HNSW index"
+        );
+        index
+    }
+
+    fn top_layer(&self) -> usize {
+        self.neighbors[self.entry as usize].len() - 1
+    }
+
+    /// The `ef` nodes of `layer` closest to the query found from `entries`, closest first.
+    fn search_layer(
+        &self,
+        entries: &[Candidate],
+        ef: usize,
+        layer: usize,
+        query_cost: &impl Fn(usize) -> f64,
+    ) -> Vec<Candidate> {
+        let mut visited = entries
+            .iter()
+            .map(|entry| entry.node)
+            .collect::<HashSet<_>>();
+        let mut candidates = entries
+            .iter()
+            .copied()
+            .map(Reverse)
+            .collect::<BinaryHeap<_>>();
+        let mut found = entries.iter().copied().collect::<BinaryHeap<_>>();
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(Reverse(closest)) = candidates.pop() {
+            let farthest = found.peek().map_or(f64::INFINITY, |farthest| farthest.cost);
+            if closest.cost > farthest && found.len() >= ef {
+                break;
+            }
+            for &neighbor in &self.neighbors[closest.node as usize][layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let candidate = Candidate {
+                    cost: query_cost(neighbor as usize),
+                    node: neighbor,
+                };
+                let farthest = found.peek().map_or(f64::INFINITY, |farthest| farthest.cost);
+                if found.len() < ef || candidate.cost < farthest {
+                    candidates.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    /// The `k` items closest to a query, closest first with their cost, `query_cost(item)`
+    /// being lower the closer the item is to the query. `ef` candidates are kept on the bottom
+    /// layer, at least `k`.
+    pub fn search(
+        &self,
+        query_cost: impl Fn(usize) -> f64,
+        k: usize,
+        ef: usize,
+    ) -> Vec<(usize, f64)> {
+        if self.neighbors.is_empty() {
+            return vec![];
+        }
+        let mut entries = vec![Candidate {
+            cost: query_cost(self.entry as usize),
+            node: self.entry,
+        }];
+        for layer in (1..=self.top_layer()).rev() {
+            entries = self.search_layer(&entries, 1, layer, &query_cost);
+        }
+        self.search_layer(&entries, ef.max(k), 0, &query_cost)
+            .into_iter()
+            .take(k)
+            .map(|candidate| (candidate.node as usize, candidate.cost))
+            .collect()
+    }
+
+    /// The `k` items closest to every item of the index, itself left out, closest first.
+    pub fn neighbors(
+        &self,
+        k: usize,
+        ef: usize,
+        cost: impl Fn(usize, usize) -> f64 + Sync,
+    ) -> Vec<Vec<usize>> {
+        (0..self.neighbors.len())
+            .into_par_iter()
+            .map(|item| {
+                self.search(|other| cost(item, other), k + 1, ef)
+                    .into_iter()
+                    .map(|(other, _)| other)
+                    .filter(|other| *other != item)
+                    .take(k)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Mean fraction of the exact `k` nearest items of every query that the index finds,
+    /// `cost(query, item)` being lower the closer the item is to the query. The queries of
+    /// `self_queries` are items of the index, left out of their own neighbors.
+    pub fn recall(
+        &self,
+        queries: &[usize],
+        k: usize,
+        ef: usize,
+        self_queries: bool,
+        cost: impl Fn(usize, usize) -> f64 + Sync,
+    ) -> f64 {
+        if queries.is_empty() {
+            return 1.0;
+        }
+        let items = self.neighbors.len();
+        let total = queries
+            .par_iter()
+            .map(|&query| {
+                let others = |item: &usize| !self_queries || *item != query;
+                let mut exact = (0..items).filter(others).collect::<Vec<_>>();
+                exact.sort_by(|a, b| cost(query, *a).total_cmp(&cost(query, *b)));
+                exact.truncate(k);
+                let approximate = self
+                    .search(|item| cost(query, item), k + 1, ef)
+                    .into_iter()
+                    .map(|(item, _)| item)
+                    .filter(others)
+                    .take(k);
+                search::recall(
+                    &exact.into_iter().collect::<HashSet<_>>(),
+                    &approximate.collect::<HashSet<_>>(),
+                )
+            })
+            .sum::<f64>();
+        total / queries.len() as f64
+    }
+
+    /// Writes the index, tagged with the `fingerprint` of what it indexes.
+    pub fn save(&self, path: &Path, fingerprint: &str) -> std::io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        let mut push = |value: usize| bytes.extend((value as u32).to_le_bytes());
+        push(fingerprint.len());
+        push(self.entry as usize);
+        push(self.neighbors.len());
+        for layers in &self.neighbors {
+            push(layers.len());
+            for neighbors in layers {
+                push(neighbors.len());
+                neighbors
+                    .iter()
+                    .for_each(|&neighbor| push(neighbor as usize));
+            }
+        }
+        let header = MAGIC.len() + 4;
+        bytes.splice(header..header, fingerprint.bytes());
+        std::fs::write(path, bytes)
+    }
+
+    /// Reads an index of `count` items written by [Hnsw::save], `None` if missing, unreadable,
+    /// of another `fingerprint` or malformed.
+    pub fn load(path: &Path, fingerprint: &str, count: usize) -> Option<Self> {
+        let bytes = std::fs::read(path).ok()?;
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let length = reader.next()?;
+        if reader.take(length)? != fingerprint.as_bytes() {
+            return None;
+        }
+        let entry = reader.next()? as u32;
+        let nodes = reader.next()?;
+        let mut neighbors = Vec::with_capacity(nodes.min(bytes.len()));
+        for _ in 0..nodes {
+            let layers = reader.next()?;
+            let mut node = vec![];
+            for _ in 0..layers {
+                let count = reader.next()?;
+                node.push(
+                    (0..count)
+                        .map(|_| reader.next().map(|neighbor| neighbor as u32))
+                        .collect::<Option<Vec<_>>>()?,
+                );
+            }
+            neighbors.push(node);
+        }
+        let index = Hnsw { neighbors, entry };
+        index.is_valid(count).then_some(index)
+    }
+
+    /// Whether the index has `count` nodes of at least one layer, its entry node is on the top
+    /// layer and every neighbor of a node on a layer is a node of that layer, so that searches
+    /// never read past the index.
+    fn is_valid(&self, count: usize) -> bool {
+        if self.neighbors.len() != count {
+            return false;
+        }
+        if count == 0 {
+            return self.entry == 0;
+        }
+        let Some(entry) = self.neighbors.get(self.entry as usize) else {
+            return false;
+        };
+        self.neighbors.iter().all(|layers| {
+            !layers.is_empty()
+                && layers.len() <= entry.len()
+                && layers.iter().enumerate().all(|(layer, neighbors)| {
+                    neighbors.iter().all(|&neighbor| {
+                        self.neighbors
+                            .get(neighbor as usize)
+                            .is_some_and(|other| other.len() > layer)
+                    })
+                })
+        })
+    }
+}
+
+/// Prints the recall of the HNSW index measured by [Hnsw::recall].
+pub fn print_recall(recall: f64, k: usize, queries: usize) {
+    eprintln!(
+        "HNSW recall@{k} against brute force on {queries} sampled documents: {}",
+        table::format_score(recall)
+    );
+}
+
+/// Reads a saved index front to back.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let taken = self.bytes.get(self.offset..self.offset + length)?;
+        self.offset += length;
+        Some(taken)
+    }
+
+    fn next(&mut self) -> Option<usize> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?) as usize)
+    }
+}
+
+/// Up to `most` of `candidates`, sorted by their cost from a node, to link it to. A candidate
+/// closer to a kept neighbor than to the node is skipped at first, so that links spread in
+/// every direction instead of into a single cluster, then skipped candidates fill the rest.
+fn select_neighbors(
+    candidates: &[Candidate],
+    most: usize,
+    cost: &impl Fn(usize, usize) -> f64,
+) -> Vec<u32> {
+    let mut kept = Vec::<Candidate>::with_capacity(most);
+    let mut skipped = vec![];
+    for candidate in candidates {
+        if kept.len() == most {
+            break;
+        }
+        if kept
+            .iter()
+            .all(|neighbor| cost(candidate.node as usize, neighbor.node as usize) > candidate.cost)
+        {
+            kept.push(*candidate);
+        } else {
+            skipped.push(*candidate);
+        }
+    }
+    kept.extend(skipped.into_iter().take(most - kept.len()));
+    kept.into_iter().map(|candidate| candidate.node).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vectors(count: usize) -> Vec<Vec<f64>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..count)
+            .map(|_| (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect()
+    }
+
+    fn cost(first: &[f64], second: &[f64]) -> f64 {
+        first
+            .iter()
+            .zip(second)
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum()
+    }
+
+    fn build(vectors: &[Vec<f64>]) -> Hnsw {
+        Hnsw::build(
+            vectors.len(),
+            |a, b| cost(&vectors[a], &vectors[b]),
+            &ProgressBar::hidden(),
+        )
+    }
+
+    #[test]
+    fn searches_find_most_exact_neighbors() {
+        let mut vectors = vectors(1100);
+        let queries = vectors.split_off(1000);
+        let index = build(&vectors);
+        let mut found = 0;
+        for query in &queries {
+            let approximate = index
+                .search(|i| cost(query, &vectors[i]), 10, DEFAULT_EF_SEARCH)
+                .into_iter()
+                .map(|(i, _)| i)
+                .collect::<HashSet<_>>();
+            let mut exact = (0..vectors.len()).collect::<Vec<_>>();
+            exact.sort_by(|a, b| cost(query, &vectors[*a]).total_cmp(&cost(query, &vectors[*b])));
+            found += exact[..10]
+                .iter()
+                .filter(|i| approximate.contains(i))
+                .count();
+        }
+        let recall = found as f64 / (queries.len() * 10) as f64;
+        assert!(recall > 0.95, "{recall}");
+    }
+
+    #[test]
+    fn results_are_sorted_and_small_indexes_work() {
+        assert!(build(&[]).search(|_| 0.0, 3, 10).is_empty());
+        let vectors = vectors(5);
+        let results = build(&vectors).search(|i| cost(&vectors[2], &vectors[i]), 10, 10);
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0], (2, 0.0));
+        assert!(results.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+
+    fn temporary_path(test: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "distance-calculator-{}-{test}.hnsw",
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn saved_indexes_load_for_the_same_fingerprint_only() {
+        let index = build(&vectors(300));
+        let path = temporary_path("fingerprint");
+        index.save(&path, "corpus:cosine").unwrap();
+        assert_eq!(Hnsw::load(&path, "corpus:cosine", 300), Some(index));
+        assert_eq!(Hnsw::load(&path, "corpus:l2", 300), None);
+        assert_eq!(Hnsw::load(&path, "corpus:cosine", 299), None);
+        std::fs::write(&path, b"DCHN\x05").unwrap();
+        assert_eq!(Hnsw::load(&path, "corpus:cosine", 300), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_indexes_are_not_loaded() {
+        let path = temporary_path("malformed");
+        let load = |index: Hnsw, count: usize| {
+            index.save(&path, "corpus").unwrap();
+            Hnsw::load(&path, "corpus", count)
+        };
+        let valid = || Hnsw {
+            neighbors: vec![vec![vec![1], vec![]], vec![vec![0]]],
+            entry: 0,
+        };
+        assert_eq!(load(valid(), 2), Some(valid()));
+        assert_eq!(
+            load(
+                Hnsw {
+                    entry: 2,
+                    ..valid()
+                },
+                2
+            ),
+            None,
+            "entry out of the index"
+        );
+        assert_eq!(
+            load(
+                Hnsw {
+                    entry: 1,
+                    ..valid()
+                },
+                2
+            ),
+            None,
+            "entry below the top layer"
+        );
+        let mut index = valid();
+        index.neighbors[1][0] = vec![7];
+        assert_eq!(load(index, 2), None, "neighbor out of the index");
+        let mut index = valid();
+        index.neighbors[0][1] = vec![1];
+        assert_eq!(load(index, 2), None, "neighbor missing from the layer");
+        let mut index = valid();
+        index.neighbors[1].clear();
+        assert_eq!(load(index, 2), None, "node without a layer");
+        let empty = || Hnsw {
+            neighbors: vec![],
+            entry: 0,
+        };
+        assert_eq!(load(empty(), 0), Some(empty()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn neighbors_leave_every_item_out() {
+        let vectors = vectors(50);
+        let cost = |a: usize, b: usize| cost(&vectors[a], &vectors[b]);
+        let neighbors = build(&vectors).neighbors(3, 50, cost);
+        assert_eq!(neighbors.len(), 50);
+        for (item, found) in neighbors.iter().enumerate() {
+            let mut exact = (0..50).filter(|other| *other != item).collect::<Vec<_>>();
+            exact.sort_by(|a, b| cost(item, *a).total_cmp(&cost(item, *b)));
+            assert_eq!(found, &exact[..3], "{item}");
+        }
+    }
+
+    #[test]
+    fn recall_is_measured_against_brute_force() {
+        let vectors = vectors(500);
+        let index = build(&vectors);
+        let cost = |a: usize, b: usize| cost(&vectors[a], &vectors[b]);
+        let queries = (0..500).step_by(10).collect::<Vec<_>>();
+        assert_eq!(index.recall(&queries, 5, 500, true, cost), 1.0);
+        let unlinked = Hnsw {
+            neighbors: vec![vec![vec![]]; 500],
+            entry: 0,
+        };
+        assert_eq!(unlinked.recall(&queries, 5, 500, true, cost), 0.0);
+        assert_eq!(index.recall(&[], 5, 1, true, cost), 1.0);
+        // Every query is its own nearest item unless left out
+        let unfiltered = index.recall(&queries, 1, 500, false, |query, item| {
+            if item == query {
+                0.0
+            } else {
+                1.0 + cost(query, item)
+            }
+        });
+        assert_eq!(unfiltered, 1.0);
+    }
+
+    #[test]
+    fn auto_indexes_large_corpora_unless_exact() {
+        let args = |index: IndexKind, exact: bool| IndexArgs {
+            index,
+            exact,
+            ef_search: DEFAULT_EF_SEARCH,
+            verify_sample: None,
+        };
+        assert!(!args(IndexKind::Auto, false).indexed(HNSW_THRESHOLD - 1));
+        assert!(args(IndexKind::Auto, false).indexed(HNSW_THRESHOLD));
+        assert!(args(IndexKind::Hnsw, false).indexed(10));
+        assert!(!args(IndexKind::Auto, true).indexed(HNSW_THRESHOLD));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{fmt::Display, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+
+/// Media types of the image files that can be embedded, by extension.
+const MEDIA_TYPES: [(&str, &str); 5] = [
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+];
+
+/// What the documents are, and so what the provider embeds for each of them.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Modality {
+    /// Every document is a text
+    Text,
+    /// Every document is the path of an image file
+    Image,
+    /// Documents that are the path of an existing image file are embedded as images, the others
+    /// as texts, in the same space
+    Mixed,
+}
+
+impl Display for Modality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Modality::Text => write!(f, "text"),
+            Modality::Image => write!(f, "image"),
+            Modality::Mixed => write!(f, "mixed"),
+        }
+    }
+}
+
+impl Modality {
+    /// Whether `document` is embedded as an image.
+    pub fn is_image(&self, document: &str) -> bool {
+        match self {
+            Modality::Text => false,
+            Modality::Image => true,
+            Modality::Mixed => media_type(document).is_some() && Path::new(document).is_file(),
+        }
+    }
+}
+
+fn media_type(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    MEDIA_TYPES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, media_type)| *media_type)
+}
+
+/// The image file at `path` as a base64 data URI, the form providers take images in.
+pub fn data_uri(path: &str) -> Result<String, String> {
+    let media_type =
+        media_type(path).ok_or_else(|| format!("{path} isn't a PNG, JPEG, GIF or WebP image"))?;
+    let bytes = std::fs::read(path).map_err(|error| format!("Failed to read {path}: {error}"))?;
+    Ok(format!(
+        "data:{media_type};base64,{}",
+        STANDARD.encode(bytes)
+    ))
+}
+
+/// Checks that every document embedded as an image is a readable image file, before any of
+/// them is sent.
+pub fn check(documents: &[String], modality: Modality) -> Result<(), String> {
+    for document in documents {
+        if !modality.is_image(document) {
+            continue;
+        }
+        if media_type(document).is_none() {
+            return Err(format!(
+                "{document} isn't a PNG, JPEG, GIF or WebP image, which --modality {modality} \
+                 requires of every document"
+            ));
+        }
+        if !Path::new(document).is_file() {
+            return Err(format!("Image {document} doesn't exist"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_encoded_as_data_uris() {
+        let path =
+            std::env::temp_dir().join(format!("distance-calculator-{}.PNG", std::process::id()));
+        std::fs::write(&path, b"\x89PNG").unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(data_uri(path).unwrap(), "data:image/png;base64,iVBORw==");
+        assert!(data_uri("notes.txt").unwrap_err().contains("isn't a PNG"));
+
+        let documents = [path.to_string(), "a photo of a cat".to_string()];
+        assert!(Modality::Mixed.is_image(&documents[0]));
+        assert!(!Modality::Mixed.is_image(&documents[1]));
+        assert!(!Modality::Mixed.is_image("missing.png"));
+        assert_eq!(check(&documents, Modality::Mixed), Ok(()));
+        assert!(check(&documents, Modality::Image).is_err());
+        assert!(check(&["missing.jpg".to_string()], Modality::Image)
+            .unwrap_err()
+            .contains("doesn't exist"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashSet, time::Instant};
+
+use itertools::Itertools;
+use rand::Rng;
+
+use crate::{
+    cluster::kmeans_with_centroids,
+    search::{exact_neighbors, recall, top_k},
+    table, DistanceMetric,
+};
+
+/// An inverted file index: the corpus is partitioned by k-means and a query only scans the
+/// documents of the `nprobe` partitions whose centroids are closest to it.
+pub struct InvertedFile {
+    centroids: Vec<Vec<f64>>,
+    /// Documents of every partition
+    lists: Vec<Vec<usize>>,
+}
+
+impl InvertedFile {
+    pub fn build(vectors: &[Vec<f64>], partitions: usize, rng: &mut impl Rng) -> Self {
+        let (assignments, centroids) = kmeans_with_centroids(vectors, partitions, rng);
+
+        let mut lists = vec![vec![]; centroids.len()];
+        for (document, partition) in assignments.into_iter().enumerate() {
+            lists[partition].push(document);
+        }
+
+        InvertedFile { centroids, lists }
+    }
+
+    /// Documents of the `nprobe` partitions closest to `query`.
+    fn candidates(
+        &self,
+        query: &[f64],
+        nprobe: usize,
+        distance_metric: &DistanceMetric,
+    ) -> Vec<usize> {
+        self.centroids
+            .iter()
+            .map(|centroid| distance_metric.distance(query, centroid))
+            .enumerate()
+            .sorted_by(|(_, a), (_, b)| distance_metric.cmp_closeness(*b, *a))
+            .take(nprobe)
+            .flat_map(|(partition, _)| self.lists[partition].iter().copied())
+            .collect()
+    }
+}
+
+/// Recall, share of the corpus scanned and latency of a search with `nprobe` probes.
+struct Trial {
+    nprobe: usize,
+    recall: f64,
+    scanned: f64,
+    micros_per_query: f64,
+}
+
+fn trial(
+    vectors: &[Vec<f64>],
+    index: &InvertedFile,
+    queries: &[usize],
+    exact: &[HashSet<usize>],
+    nprobe: usize,
+    k: usize,
+    distance_metric: &DistanceMetric,
+) -> Trial {
+    let mut recalls = 0.0;
+    let mut scanned = 0;
+
+    let started = Instant::now();
+    for (&query, exact) in queries.iter().zip(exact) {
+        let candidates = index.candidates(&vectors[query], nprobe, distance_metric);
+        scanned += candidates.len();
+
+        let approximate = top_k(query, candidates, k, distance_metric, |other| {
+            distance_metric.distance(&vectors[query], &vectors[other])
+        });
+        recalls += recall(exact, &approximate);
+    }
+    let elapsed = started.elapsed();
+
+    let count = queries.len() as f64;
+    Trial {
+        nprobe,
+        recall: recalls / count,
+        scanned: scanned as f64 / count / vectors.len() as f64,
+        micros_per_query: elapsed.as_secs_f64() * 1e6 / count,
+    }
+}
+
+/// Searches for the top `k` neighbors of each of `queries` with each of `nprobes` and prints
+/// the recall and cost of each setting next to exhaustive search.
+pub fn print_report(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    index: &InvertedFile,
+    nprobes: &[usize],
+    k: usize,
+    distance_metric: &DistanceMetric,
+) {
+    let started = Instant::now();
+    let exact = exact_neighbors(vectors, queries, k, distance_metric);
+    let exact_micros = started.elapsed().as_secs_f64() * 1e6 / queries.len() as f64;
+
+    let mut table = vec![vec![
+        "nprobe".to_string(),
+        format!("recall@{k}"),
+        "scanned".to_string(),
+        "us per query".to_string(),
+    ]];
+    table.extend(
+        nprobes
+            .iter()
+            .map(|nprobe| trial(vectors, index, queries, &exact, *nprobe, k, distance_metric))
+            .map(|trial| {
+                vec![
+                    trial.nprobe.to_string(),
+                    table::format_score(trial.recall),
+                    format!("{:.1}%", trial.scanned * 100.0),
+                    format!("{:.1}", trial.micros_per_query),
+                ]
+            }),
+    );
+    table.push(vec![
+        "exhaustive".to_string(),
+        "1".to_string(),
+        "100%".to_string(),
+        format!("{exact_micros:.1}"),
+    ]);
+
+    println!(
+        "{} partitions, recall over {} of {} documents",
+        index.lists.len(),
+        queries.len(),
+        vectors.len()
+    );
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// Three groups of vectors around (0, 0), (10, 0) and (0, 10), with jitter.
+    fn vectors() -> Vec<Vec<f64>> {
+        let mut rng = StdRng::seed_from_u64(2);
+        [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)]
+            .iter()
+            .flat_map(|&(x, y)| {
+                (0..20)
+                    .map(|_| vec![x + rng.gen_range(-1.0..1.0), y + rng.gen_range(-1.0..1.0)])
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn documents_are_listed_in_the_partition_of_their_group() {
+        let vectors = vectors();
+        let index = InvertedFile::build(&vectors, 3, &mut StdRng::seed_from_u64(0));
+        let mut lists = index.lists.clone();
+        lists.sort();
+        assert_eq!(
+            lists,
+            [
+                (0..20).collect::<Vec<_>>(),
+                (20..40).collect(),
+                (40..60).collect()
+            ]
+        );
+        let near_origin = index.candidates(&[0.5, 0.5], 1, &DistanceMetric::L2);
+        assert_eq!(near_origin, (0..20).collect::<Vec<_>>());
+        assert_eq!(
+            index.candidates(&[0.5, 0.5], 2, &DistanceMetric::L2).len(),
+            40
+        );
+    }
+
+    #[test]
+    fn probing_every_partition_equals_brute_force() {
+        let vectors = vectors();
+        let index = InvertedFile::build(&vectors, 4, &mut StdRng::seed_from_u64(0));
+        let queries = (0..vectors.len()).collect::<Vec<_>>();
+        for distance_metric in [DistanceMetric::L2, DistanceMetric::Cosine] {
+            let exact = exact_neighbors(&vectors, &queries, 5, &distance_metric);
+            let trial = trial(&vectors, &index, &queries, &exact, 4, 5, &distance_metric);
+            assert_eq!(trial.recall, 1.0, "{distance_metric}");
+            assert_eq!(trial.scanned, 1.0, "{distance_metric}");
+        }
+        let exact = exact_neighbors(&vectors, &queries, 5, &DistanceMetric::L2);
+        let trial = trial(
+            &vectors,
+            &index,
+            &queries,
+            &exact,
+            1,
+            5,
+            &DistanceMetric::L2,
+        );
+        assert!(trial.scanned < 1.0);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{env, fmt::Debug, str::FromStr, sync::OnceLock};
+
+use clap::ValueEnum;
+
+use crate::Provider;
+
+/// Keyring service the API keys are stored under, one entry per provider.
+const KEYRING_SERVICE: &str = "distance-calculator";
+
+/// Keys set once from `--api-key`.
+static API_KEYS: OnceLock<Vec<ApiKey>> = OnceLock::new();
+
+/// Sets the `--api-key`s, at most one per provider and one without a provider.
+pub fn set_api_keys(keys: Vec<ApiKey>) {
+    for (i, key) in keys.iter().enumerate() {
+        assert!(
+            keys[..i].iter().all(|other| other.provider != key.provider),
+            "--api-key given twice for {}",
+            key.provider
+                .as_ref()
+                .map_or("the provider".to_string(), Provider::to_string)
+        );
+    }
+    API_KEYS.set(keys).expect("API keys already set");
+}
+
+/// Fails if an `--api-key` without a provider was given to a run that uses several providers,
+/// as it would send the key of one provider to the others.
+pub fn check_unscoped(providers: &[Provider]) {
+    let unscoped = API_KEYS
+        .get()
+        .is_some_and(|keys| keys.iter().any(|key| key.provider.is_none()));
+    let several = providers.iter().any(|provider| *provider != providers[0]);
+    assert!(
+        !(unscoped && several),
+        "--api-key must name its provider when several are used, e.g. --api-key openai=sk-..."
+    );
+}
+
+/// An API key given on the command line as `provider=key`, or as a bare key for the only
+/// provider of the run, masked whenever it's printed.
+#[derive(Clone)]
+pub struct ApiKey {
+    provider: Option<Provider>,
+    key: String,
+}
+
+impl FromStr for ApiKey {
+    type Err = String;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let scoped = key.split_once('=').and_then(|(provider, key)| {
+            let provider = Provider::from_str(provider, true).ok()?;
+            Some((provider, key))
+        });
+        let (provider, key) = match scoped {
+            Some((provider, key)) => (Some(provider), key),
+            None => (None, key),
+        };
+        if key.is_empty() {
+            return Err("the key is empty".to_string());
+        }
+
+        Ok(Self {
+            provider,
+            key: key.to_string(),
+        })
+    }
+}
+
+impl Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(provider) = &self.provider {
+            write!(f, "{provider}=")?;
+        }
+        write!(f, "{}", mask(&self.key))
+    }
+}
+
+/// The key of `keys` for `provider`: its own, else the one without a provider.
+fn key_for<'a>(keys: &'a [ApiKey], provider: &Provider) -> Option<&'a ApiKey> {
+    keys.iter()
+        .find(|key| key.provider.as_ref() == Some(provider))
+        .or_else(|| keys.iter().find(|key| key.provider.is_none()))
+}
+
+/// Keeps the last four characters of `key`, enough to tell keys apart in logs.
+pub fn mask(key: &str) -> String {
+    let length = key.chars().count();
+    let hidden = length.saturating_sub(4).max(length / 2);
+    let last = key.chars().skip(hidden).collect::<String>();
+    format!("****{last}")
+}
+
+fn environment_variables(provider: &Provider) -> &'static [&'static str] {
+    match provider {
+        Provider::Openai => &["OPENAI_API_KEY"],
+        // COHERE_API_HERE is the misspelled name earlier versions read
+        Provider::Cohere => &["COHERE_API_KEY", "COHERE_API_HERE"],
+        // Mock embeddings need no key
+        Provider::Mock => &[],
+    }
+}
+
+fn keyring_entry(provider: &Provider) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, &provider.to_string())
+}
+
+/// Saves the `--api-key` of `provider` in the OS keyring as its key.
+pub fn store(provider: &Provider) {
+    let key = API_KEYS
+        .get()
+        .and_then(|keys| key_for(keys, provider))
+        .unwrap_or_else(|| panic!("No --api-key for {provider} to store"));
+    keyring_entry(provider)
+        .and_then(|entry| entry.set_password(&key.key))
+        .unwrap_or_else(|error| panic!("Failed to store the {provider} API key: {error}"));
+    eprintln!("Stored {provider} API key {key:?} in the keyring");
+}
+
+/// The API key of `provider`: its `--api-key` if given, else its environment variable, else the
+/// key stored in the OS keyring.
+pub fn resolve(provider: &Provider) -> String {
+    if let Some(key) = API_KEYS.get().and_then(|keys| key_for(keys, provider)) {
+        return key.key.clone();
+    }
+    let variables = environment_variables(provider);
+    if let Some(key) = variables.iter().find_map(|name| env::var(name).ok()) {
+        return key;
+    }
+
+    match keyring_entry(provider).and_then(|entry| entry.get_password()) {
+        Ok(key) => key,
+        Err(keyring::Error::NoEntry) => panic!(
+            "{} not set: pass --api-key, set it or store a key with --store-key",
+            variables[0]
+        ),
+        Err(error) => panic!(
+            "{} not set and the keyring can't be read: {error}",
+            variables[0]
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(argument: &str) -> ApiKey {
+        argument.parse().unwrap()
+    }
+
+    #[test]
+    fn keys_are_masked_to_their_last_four_characters() {
+        assert_eq!(mask("sk-abcdefghijkl"), "****ijkl");
+        // Short keys keep at most half of their characters
+        assert_eq!(mask("abcdef"), "****def");
+        assert_eq!(mask("ab"), "****b");
+        assert_eq!(mask(""), "****");
+        assert_eq!(mask("clé-ünïcödé"), "****cödé");
+    }
+
+    #[test]
+    fn keys_may_name_their_provider() {
+        let openai = key("openai=sk-proj-a=b");
+        assert!(matches!(openai.provider, Some(Provider::Openai)));
+        assert_eq!(openai.key, "sk-proj-a=b");
+        assert_eq!(format!("{openai:?}"), "openai=****-a=b");
+
+        assert!(matches!(key("Cohere=abc").provider, Some(Provider::Cohere)));
+
+        // Anything before `=` that isn't a provider is part of the key
+        let bare = key("base64key==");
+        assert!(bare.provider.is_none());
+        assert_eq!(bare.key, "base64key==");
+
+        assert!("openai=".parse::<ApiKey>().is_err());
+    }
+
+    #[test]
+    fn provider_keys_take_precedence_over_bare_keys() {
+        let keys = [key("shared"), key("cohere=co-key")];
+        assert_eq!(key_for(&keys, &Provider::Cohere).unwrap().key, "co-key");
+        assert_eq!(key_for(&keys, &Provider::Openai).unwrap().key, "shared");
+
+        // A key of another provider is never used
+        let keys = [key("cohere=co-key")];
+        assert!(key_for(&keys, &Provider::Openai).is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::OnceLock;
+
+/// Characters of a text shown by default when it has no sentence to cut it at.
+const DEFAULT_WIDTH: usize = 10;
+
+/// How the texts of documents are shortened into labels.
+#[derive(Debug, Default)]
+pub struct Truncation {
+    /// Characters a label keeps, `None` for the first sentence or [DEFAULT_WIDTH] characters
+    pub width: Option<usize>,
+    /// Cut before the word crossing the width rather than inside it
+    pub word_boundary: bool,
+    /// Show every text in full
+    pub disabled: bool,
+}
+
+/// Set once from `--label-width`, `--label-word-boundary` and `--no-truncate`.
+static TRUNCATION: OnceLock<Truncation> = OnceLock::new();
+
+pub fn set_truncation(truncation: Truncation) {
+    TRUNCATION
+        .set(truncation)
+        .expect("Label truncation already set");
+}
+
+/// The label of a text under the `--label-width` options.
+pub fn label(text: &str) -> String {
+    truncate(text, TRUNCATION.get().unwrap_or(&Truncation::default()))
+}
+
+fn truncate(text: &str, truncation: &Truncation) -> String {
+    if truncation.disabled {
+        return text.to_string();
+    }
+    let width = truncation.width.unwrap_or(DEFAULT_WIDTH);
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    match (truncation.width, text.find('.')) {
+        (None, Some(end)) => format!("{}...", &text[..end]),
+        _ => format!("{}...", cut(text, width, truncation.word_boundary)),
+    }
+}
+
+/// The first `width` characters of `text`, without the word crossing the width when cutting at
+/// word boundaries, unless it's the only word.
+fn cut(text: &str, width: usize, word_boundary: bool) -> &str {
+    let end = text
+        .char_indices()
+        .nth(width)
+        .map_or(text.len(), |(end, _)| end);
+    let head = &text[..end];
+    if !word_boundary || text[end..].starts_with(char::is_whitespace) {
+        return head.trim_end();
+    }
+    match head.rfind(char::is_whitespace) {
+        Some(space) => head[..space].trim_end(),
+        None => head,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn width(width: usize, word_boundary: bool) -> Truncation {
+        Truncation {
+            width: Some(width),
+            word_boundary,
+            disabled: false,
+        }
+    }
+
+    #[test]
+    fn labels_default_to_the_first_sentence_or_ten_characters() {
+        let default = Truncation::default();
+        assert_eq!(truncate("short", &default), "short");
+        assert_eq!(truncate("First one. Second.", &default), "First one...");
+        assert_eq!(truncate("no sentence end here", &default), "no sentenc...");
+    }
+
+    #[test]
+    fn labels_cut_characters_not_bytes() {
+        let default = Truncation::default();
+        assert_eq!(
+            truncate("日本語のテキストです、長い", &default),
+            "日本語のテキストです..."
+        );
+        assert_eq!(truncate("crème brûlée", &width(4, false)), "crèm...");
+        assert_eq!(truncate("Może być.", &default), "Może być.");
+    }
+
+    #[test]
+    fn labels_can_keep_whole_words_or_everything() {
+        let text = "interest rates rose again";
+        assert_eq!(truncate(text, &width(12, false)), "interest rat...");
+        assert_eq!(truncate(text, &width(12, true)), "interest...");
+        assert_eq!(truncate(text, &width(14, true)), "interest rates...");
+        assert_eq!(
+            truncate("antidisestablishment", &width(5, true)),
+            "antid..."
+        );
+        let disabled = Truncation {
+            disabled: true,
+            ..Truncation::default()
+        };
+        assert_eq!(truncate(text, &disabled), text);
+    }
+}
@@ -0,0 +1,171 @@
+use itertools::Itertools;
+use whatlang::{Detector, Lang};
+
+use crate::{pairs::Pair, stats, table, DistanceMetric, EMPTY};
+
+/// Code of the language of a text that isn't detected confidently enough, as in ISO 639-3.
+const UNDETERMINED: &str = "und";
+
+/// Detects the language of every document with whatlang.
+pub struct LanguageDetector {
+    detector: Detector,
+    /// Confidence below which a detected language is `und`
+    min_confidence: f64,
+}
+
+impl LanguageDetector {
+    /// A detector picking among `languages`, or among every language whatlang knows when it's
+    /// empty.
+    pub fn new(languages: &[Lang], min_confidence: f64) -> Self {
+        let detector = if languages.is_empty() {
+            Detector::new()
+        } else {
+            Detector::with_allowlist(languages.to_vec())
+        };
+        LanguageDetector {
+            detector,
+            min_confidence,
+        }
+    }
+
+    /// ISO 639-3 code of the language of `text`, `und` when none is detected with at least the
+    /// minimum confidence.
+    pub fn detect(&self, text: &str) -> &'static str {
+        match self.detector.detect(text) {
+            Some(info) if info.confidence() >= self.min_confidence => info.lang().code(),
+            _ => UNDETERMINED,
+        }
+    }
+
+    /// The language of every text.
+    pub fn detect_all(&self, texts: &[String]) -> Vec<String> {
+        texts
+            .iter()
+            .map(|text| self.detect(text).to_string())
+            .collect()
+    }
+}
+
+/// Parses the ISO 639-3 code of a language whatlang detects.
+pub fn parse_language(code: &str) -> Result<Lang, String> {
+    Lang::from_code(code).ok_or_else(|| {
+        format!("{code} isn't the ISO 639-3 code of a detected language, e.g. eng, fra or cmn")
+    })
+}
+
+/// The languages of `languages`, in the order of their first document, each with the positions
+/// of its documents.
+pub fn groups(languages: &[String]) -> Vec<(&str, Vec<usize>)> {
+    languages
+        .iter()
+        .enumerate()
+        .into_group_map_by(|(_, language)| language.as_str())
+        .into_iter()
+        .map(|(language, documents)| {
+            (
+                language,
+                documents.into_iter().map(|(i, _)| i).collect::<Vec<_>>(),
+            )
+        })
+        .sorted_by_key(|(_, documents)| documents[0])
+        .collect()
+}
+
+/// Prints the mean score of the pairs within every language, on the diagonal, and across every
+/// two languages, then the mean within and across all of them.
+pub fn print_block_summary(pairs: &[Pair], languages: &[String], distance_metric: &DistanceMetric) {
+    let groups = groups(languages);
+    let position = |language: &str| {
+        groups
+            .iter()
+            .position(|(other, _)| *other == language)
+            .unwrap()
+    };
+    let mut blocks = vec![vec![Vec::new(); groups.len()]; groups.len()];
+    let (mut within, mut across) = (Vec::new(), Vec::new());
+    for pair in pairs {
+        let (a, b) = (position(&languages[pair.i]), position(&languages[pair.j]));
+        blocks[a.min(b)][a.max(b)].push(pair.score);
+        if a == b {
+            within.push(pair.score);
+        } else {
+            across.push(pair.score);
+        }
+    }
+
+    let mut rows = vec![std::iter::once(format!("mean {distance_metric}"))
+        .chain(
+            groups
+                .iter()
+                .map(|(language, documents)| format!("{language} ({})", documents.len())),
+        )
+        .collect::<Vec<_>>()];
+    rows.extend(groups.iter().enumerate().map(|(a, (language, _))| {
+        std::iter::once(language.to_string())
+            .chain(
+                (0..groups.len()).map(|b| match &blocks[a.min(b)][a.max(b)] {
+                    scores if scores.is_empty() => EMPTY.to_string(),
+                    scores => table::format_score(stats::mean(scores)),
+                }),
+            )
+            .collect()
+    }));
+    table::print(rows);
+    let describe = |scores: &[f64]| match scores.len() {
+        0 => "no pairs".to_string(),
+        count => format!(
+            "{count} pairs, mean {distance_metric} {}",
+            table::format_score(stats::mean(scores))
+        ),
+    };
+    println!(
+        "within languages: {}; across languages: {}",
+        describe(&within),
+        describe(&across)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_detected_by_whatlang() {
+        let detector = LanguageDetector::new(&[], 0.0);
+        let cases = [
+            ("The cat sat on the mat and it was happy", "eng"),
+            ("Der Hund ist nicht im Haus und schläft", "deu"),
+            ("El perro está en la casa y duerme con el gato", "spa"),
+            ("Привіт, як справи? Їжак", "ukr"),
+            ("Καλημέρα κόσμε", "ell"),
+            ("东京是日本的首都", "cmn"),
+            ("東京は日本の首都です", "jpn"),
+            ("안녕하세요 세계", "kor"),
+            ("1234 !!", "und"),
+        ];
+        for (text, language) in cases {
+            assert_eq!(detector.detect(text), language, "{text}");
+        }
+    }
+
+    #[test]
+    fn the_allowlist_and_confidence_narrow_the_detection() {
+        let dutch = "De kat is niet op het bed maar op de stoel";
+        assert_eq!(LanguageDetector::new(&[], 0.0).detect(dutch), "afr");
+        let allowed = ["eng", "nld"].map(|code| parse_language(code).unwrap());
+        assert_eq!(LanguageDetector::new(&allowed, 0.0).detect(dutch), "nld");
+
+        assert_ne!(LanguageDetector::new(&[], 0.0).detect("apple pie"), "und");
+        assert_eq!(LanguageDetector::new(&[], 0.5).detect("apple pie"), "und");
+        assert!(parse_language("english").is_err());
+    }
+
+    #[test]
+    fn groups_are_in_the_order_of_their_first_document() {
+        let languages = ["fra", "eng", "fra", "und", "eng"].map(String::from);
+        assert_eq!(
+            groups(&languages),
+            [("fra", vec![0, 2]), ("eng", vec![1, 4]), ("und", vec![3])]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use clap::Args;
+use itertools::Itertools;
+use rand::{rngs::StdRng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::{
+    cache::EmbeddingCache,
+    files, format_header,
+    hnsw::{self, Hnsw, IndexArgs},
+    models,
+    providers::Embedding,
+    search, table, DistanceMetric, Provider, ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct LeakageArgs {
+    /// JSON array of the training documents
+    #[arg(long)]
+    train: String,
+    /// JSON array of the test documents
+    #[arg(long)]
+    test: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// Score from which a test document is reported as a probable copy of a training document
+    #[arg(long, default_value_t = 0.95)]
+    threshold: f64,
+    /// Large training sets are searched for the nearest training document of every test
+    /// document with an HNSW index
+    #[command(flatten)]
+    index: IndexArgs,
+}
+
+fn read_documents(path: &str) -> Vec<String> {
+    files::read_documents(path)
+}
+
+/// The nearest training document of every test document with its score, found with the HNSW
+/// `index` of the training documents if given, `None` without training documents.
+fn nearest(
+    train: &[Embedding],
+    test: &[Embedding],
+    index: Option<&Hnsw>,
+    distance_metric: &DistanceMetric,
+    ef_search: usize,
+) -> Vec<Option<(usize, f64)>> {
+    test.par_iter()
+        .map(|test| {
+            let score = |j: usize| distance_metric.distance(&test.vec, &train[j].vec);
+            match index {
+                Some(index) => index
+                    .search(
+                        |j| hnsw::cost(distance_metric, &test.vec, &train[j].vec),
+                        1,
+                        ef_search,
+                    )
+                    .first()
+                    .map(|&(j, _)| (j, score(j))),
+                None => (0..train.len())
+                    .map(|j| (j, score(j)))
+                    .max_by(|(_, a), (_, b)| distance_metric.cmp_closeness(*a, *b)),
+            }
+        })
+        .collect()
+}
+
+/// The test documents whose `nearest` training document scores `threshold` or closer, with that
+/// document and its score, closest first.
+fn leaks(
+    nearest: Vec<Option<(usize, f64)>>,
+    distance_metric: &DistanceMetric,
+    threshold: f64,
+) -> Vec<(usize, usize, f64)> {
+    nearest
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, nearest)| nearest.map(|(j, score)| (i, j, score)))
+        .filter(|(_, _, score)| distance_metric.cmp_closeness(*score, threshold).is_ge())
+        .sorted_by(|(_, _, a), (_, _, b)| distance_metric.cmp_closeness(*b, *a))
+        .collect()
+}
+
+/// The table of the leaks, with the texts of both documents.
+fn leak_table(
+    leaks: &[(usize, usize, f64)],
+    train: &[String],
+    test: &[String],
+    distance_metric: &DistanceMetric,
+) -> Vec<Vec<String>> {
+    let mut table = vec![vec![
+        "test".to_string(),
+        "nearest train".to_string(),
+        distance_metric.to_string(),
+    ]];
+    table.extend(leaks.iter().map(|&(i, j, score)| {
+        vec![
+            format_header(i, &test[i]),
+            format_header(j, &train[j]),
+            table::format_score(score),
+        ]
+    }));
+    table
+}
+
+/// Prints the test documents whose nearest training document reaches the threshold, closest
+/// first.
+pub async fn run(args: LeakageArgs) {
+    let train = read_documents(&args.train);
+    let test = read_documents(&args.test);
+    let input_strings = train.iter().chain(&test).cloned().collect::<Vec<_>>();
+
+    let (embeddings, _) =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &input_strings,
+                args.provider.max_batch_size(),
+            )
+            .await;
+    let (train_embeddings, test_embeddings) = embeddings.split_at(train.len());
+
+    // Costs of the training documents from a test document, or from one another once indexed
+    let cost = |test: &[f64], train: usize| {
+        hnsw::cost(&args.distance_metric, test, &train_embeddings[train].vec)
+    };
+    let index = args
+        .index
+        .indexed(train.len())
+        .then(|| Hnsw::index(train.len(), |a, b| cost(&train_embeddings[a].vec, b)));
+    if let (Some(index), Some(sample)) = (&index, args.index.verify_sample) {
+        let queries = search::queries(test.len(), Some(sample), &mut StdRng::seed_from_u64(0));
+        let recall = index.recall(&queries, 1, args.index.ef_search, false, |query, train| {
+            cost(&test_embeddings[query].vec, train)
+        });
+        hnsw::print_recall(recall, 1, queries.len());
+    }
+
+    let nearest = nearest(
+        train_embeddings,
+        test_embeddings,
+        index.as_ref(),
+        &args.distance_metric,
+        args.index.ef_search,
+    );
+    let leaks = leaks(nearest, &args.distance_metric, args.threshold);
+
+    println!(
+        "{} of {} test documents have a training document scoring {} or closer",
+        leaks.len(),
+        test.len(),
+        table::format_score(args.threshold)
+    );
+    if leaks.is_empty() {
+        return;
+    }
+
+    table::print(leak_table(&leaks, &train, &test, &args.distance_metric));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embeddings(vectors: &[[f64; 2]]) -> Vec<Embedding> {
+        vectors
+            .iter()
+            .map(|vec| Embedding {
+                document: String::new(),
+                vec: vec.to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_test_document_gets_its_nearest_training_document() {
+        let train = embeddings(&[[1.0, 0.0], [0.0, 1.0], [1.0, 1.0]]);
+        let test = embeddings(&[[0.0, 2.0], [3.0, 0.1], [1.0, 0.9]]);
+        let nearest = nearest(&train, &test, None, &DistanceMetric::Cosine, 10);
+        assert_eq!(
+            nearest.iter().map(|n| n.unwrap().0).collect::<Vec<_>>(),
+            [1, 0, 2]
+        );
+        assert!((nearest[0].unwrap().1 - 1.0).abs() < 1e-12);
+
+        let index = Hnsw::build(
+            train.len(),
+            |a, b| hnsw::cost(&DistanceMetric::Cosine, &train[a].vec, &train[b].vec),
+            &indicatif::ProgressBar::hidden(),
+        );
+        let indexed = super::nearest(&train, &test, Some(&index), &DistanceMetric::Cosine, 10);
+        assert_eq!(indexed, nearest);
+
+        assert_eq!(
+            super::nearest(&[], &test, None, &DistanceMetric::Cosine, 10),
+            [None, None, None]
+        );
+    }
+
+    #[test]
+    fn leaks_reach_the_threshold_closest_first() {
+        let nearest = vec![
+            Some((4, 0.96)),
+            Some((2, 0.5)),
+            None,
+            Some((0, 0.99)),
+            Some((1, 0.95)),
+        ];
+        assert_eq!(
+            leaks(nearest.clone(), &DistanceMetric::Cosine, 0.95),
+            [(3, 0, 0.99), (0, 4, 0.96), (4, 1, 0.95)]
+        );
+        assert!(leaks(nearest, &DistanceMetric::Cosine, 0.999).is_empty());
+        // Distances leak below the threshold
+        let nearest = vec![Some((0, 0.3)), Some((1, 0.05)), Some((2, 0.1))];
+        assert_eq!(
+            leaks(nearest, &DistanceMetric::L2, 0.1),
+            [(1, 1, 0.05), (2, 2, 0.1)]
+        );
+    }
+
+    #[test]
+    fn leaks_are_reported_with_both_documents() {
+        let train = ["the cat", "a dog"].map(String::from);
+        let test = ["a dog!", "a bird"].map(String::from);
+        assert_eq!(
+            leak_table(&[(0, 1, 0.5)], &train, &test, &DistanceMetric::Cosine),
+            [
+                vec!["test", "nearest train", "cosine"],
+                vec!["0: a dog!", "1: a dog", "0.5"]
+            ]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use chrono::{Local, NaiveDate};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use crate::{table, warnings, Provider, EMPTY};
+
+const LEDGER_FILE: &str = "usage.jsonl";
+
+#[derive(Subcommand, Debug)]
+pub enum UsageCommand {
+    /// Print tokens and estimated spend per day, provider and model
+    Report {
+        /// Only include usage on or after this date (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<NaiveDate>,
+    },
+}
+
+/// One embedding request as recorded in the ledger.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Entry {
+    date: NaiveDate,
+    provider: String,
+    model: String,
+    tokens: u64,
+    /// Estimated cost in USD, absent for models without a known price
+    cost: Option<f64>,
+}
+
+/// Directory holding the tool's local state, set once from `--data-dir`.
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_data_dir(dir: PathBuf) {
+    DATA_DIR.set(dir).expect("Data directory already set");
+}
+
+/// Directory holding the tool's local state, `~/.distance-calculator` unless `--data-dir` or
+/// `DISTANCE_CALCULATOR_HOME` is set.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = DATA_DIR.get() {
+        return dir.clone();
+    }
+    if let Ok(dir) = env::var("DISTANCE_CALCULATOR_HOME") {
+        return PathBuf::from(dir);
+    }
+
+    let home = env::var("HOME")
+        .or_else(|_| env::var("USERPROFILE"))
+        .expect("HOME not set");
+    PathBuf::from(home).join(".distance-calculator")
+}
+
+fn ledger_path() -> PathBuf {
+    data_dir().join(LEDGER_FILE)
+}
+
+/// Price in USD per million input tokens of the models with published pricing.
+pub fn price_per_million_tokens(provider: &Provider, model: &str) -> Option<f64> {
+    match (provider, model) {
+        (Provider::Openai, "text-embedding-3-small") => Some(0.02),
+        (Provider::Openai, "text-embedding-3-large") => Some(0.13),
+        (Provider::Openai, "text-embedding-ada-002") => Some(0.10),
+        (Provider::Cohere, "embed-english-v3.0")
+        | (Provider::Cohere, "embed-multilingual-v3.0")
+        | (Provider::Cohere, "embed-english-light-v3.0")
+        | (Provider::Cohere, "embed-multilingual-light-v3.0") => Some(0.10),
+        (Provider::Cohere, "embed-v4.0") => Some(0.12),
+        (Provider::Mock, _) => Some(0.0),
+        _ => None,
+    }
+}
+
+/// Appends a request to the ledger. Failing to write it only warns, the run itself succeeded.
+pub fn record(provider: &Provider, model: &str, tokens: u64) {
+    let entry = Entry {
+        date: Local::now().date_naive(),
+        provider: provider.to_string(),
+        model: model.to_string(),
+        tokens,
+        cost: price_per_million_tokens(provider, model)
+            .map(|price| price * tokens as f64 / 1_000_000.0),
+    };
+
+    let path = ledger_path();
+    let result = fs::create_dir_all(data_dir()).and_then(|_| {
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+    });
+
+    if let Err(error) = result {
+        eprintln!("Failed to record usage in {}: {error}", path.display());
+    }
+}
+
+fn entries() -> Vec<Entry> {
+    let path = ledger_path();
+    let Ok(file) = File::open(&path) else {
+        return vec![];
+    };
+    read_entries(BufReader::new(file), &path)
+}
+
+/// The entries of the ledger at `path`, skipping with a warning the lines that aren't one, e.g.
+/// the last line of a run interrupted while writing it.
+fn read_entries(reader: impl BufRead, path: &Path) -> Vec<Entry> {
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            match line
+                .map_err(|error| error.to_string())
+                .and_then(|line| serde_json::from_str(&line).map_err(|error| error.to_string()))
+            {
+                Ok(entry) => Some(entry),
+                Err(error) => {
+                    warnings::warn(format!(
+                        "Skipping line {} of {}: {error}",
+                        i + 1,
+                        path.display()
+                    ));
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn run(command: UsageCommand) {
+    match command {
+        UsageCommand::Report { since } => report(since),
+    }
+}
+
+fn report(since: Option<NaiveDate>) {
+    let mut totals: BTreeMap<(NaiveDate, String, String), (u64, Option<f64>)> = BTreeMap::new();
+    for entry in entries() {
+        if since.is_some_and(|since| entry.date < since) {
+            continue;
+        }
+
+        let (tokens, cost) = totals
+            .entry((entry.date, entry.provider, entry.model))
+            .or_insert((0, Some(0.0)));
+        *tokens += entry.tokens;
+        *cost = cost.zip(entry.cost).map(|(total, cost)| total + cost);
+    }
+
+    let format_cost = |cost: Option<f64>| {
+        cost.map(|cost| format!("${cost:.6}"))
+            .unwrap_or(EMPTY.to_string())
+    };
+
+    let mut table = vec![vec![
+        "date".to_string(),
+        "provider".to_string(),
+        "model".to_string(),
+        "tokens".to_string(),
+        "estimated cost".to_string(),
+    ]];
+    let mut total_tokens = 0;
+    let mut total_cost = 0.0;
+    for ((date, provider, model), (tokens, cost)) in totals {
+        total_tokens += tokens;
+        total_cost += cost.unwrap_or_default();
+        table.push(vec![
+            date.to_string(),
+            provider,
+            model,
+            tokens.to_string(),
+            format_cost(cost),
+        ]);
+    }
+    table.push(vec![
+        "total".to_string(),
+        EMPTY.to_string(),
+        EMPTY.to_string(),
+        total_tokens.to_string(),
+        format_cost(Some(total_cost)),
+    ]);
+
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(tokens: u64) -> Entry {
+        Entry {
+            date: NaiveDate::from_ymd_opt(2024, 11, 5).unwrap(),
+            provider: "openai".to_string(),
+            model: "text-embedding-3-small".to_string(),
+            tokens,
+            cost: Some(tokens as f64 * 0.02 / 1_000_000.0),
+        }
+    }
+
+    #[test]
+    fn corrupt_lines_are_skipped() {
+        let ledger = [
+            serde_json::to_string(&entry(100)).unwrap(),
+            "{\"date\":\"2024-11-05\",\"provider\":\"ope".to_string(),
+            String::new(),
+            serde_json::to_string(&entry(250)).unwrap(),
+        ]
+        .join("\n");
+
+        let entries = read_entries(ledger.as_bytes(), Path::new("usage.jsonl"));
+        assert_eq!(entries, [entry(100), entry(250)]);
+    }
+}
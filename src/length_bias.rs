@@ -0,0 +1,188 @@
+use crate::{stats, DistanceMetric};
+
+/// How strongly the scores of a corpus follow the length of its documents.
+#[derive(Debug)]
+pub struct Bias {
+    /// Correlations between the length of every document and its mean score to the others
+    pub pearson: f64,
+    pub spearman: f64,
+    /// Change of a pair's score when the mean length of its documents doubles
+    pub slope_per_doubling: f64,
+}
+
+/// Length of every document in words, at least one so that it has a logarithm.
+pub fn lengths(input_strings: &[String]) -> Vec<f64> {
+    input_strings
+        .iter()
+        .map(|string| string.split_whitespace().count().max(1) as f64)
+        .collect()
+}
+
+/// Log of the mean length of the documents of every pair, and its mean over the pairs of
+/// distinct documents.
+fn pair_lengths(pairs: &[(usize, usize)], lengths: &[f64]) -> (Vec<f64>, f64) {
+    let logs = pairs
+        .iter()
+        .map(|(i, j)| (lengths[*i].ln() + lengths[*j].ln()) / 2.0)
+        .collect::<Vec<_>>();
+    let distinct = pairs
+        .iter()
+        .zip(&logs)
+        .filter(|((i, j), _)| i != j)
+        .map(|(_, log)| *log)
+        .collect::<Vec<_>>();
+    (logs, stats::mean(&distinct))
+}
+
+/// Least squares slope of the scores of the pairs of distinct documents over the log of their
+/// mean length, 0 when every pair has the same length.
+fn slope(pairs: &[(usize, usize)], scores: &[f64], logs: &[f64], mean_log: f64) -> f64 {
+    let distinct = pairs
+        .iter()
+        .zip(scores.iter().zip(logs))
+        .filter(|((i, j), _)| i != j)
+        .map(|(_, (score, log))| (*score, *log))
+        .collect::<Vec<_>>();
+    let mean_score = distinct.iter().map(|(score, _)| score).sum::<f64>() / distinct.len() as f64;
+    let covariance = distinct
+        .iter()
+        .map(|(score, log)| (score - mean_score) * (log - mean_log))
+        .sum::<f64>();
+    let variance = distinct
+        .iter()
+        .map(|(_, log)| (log - mean_log).powi(2))
+        .sum::<f64>();
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance / variance
+    }
+}
+
+/// Measures the length bias of the `scores` of `pairs` of documents, `None` below three
+/// documents.
+pub fn measure(pairs: &[(usize, usize)], scores: &[f64], lengths: &[f64]) -> Option<Bias> {
+    if lengths.len() < 3 {
+        return None;
+    }
+    let mut totals = vec![0.0; lengths.len()];
+    for ((i, j), score) in pairs.iter().zip(scores) {
+        if i != j {
+            totals[*i] += score;
+            totals[*j] += score;
+        }
+    }
+    let mean_scores = totals
+        .iter()
+        .map(|total| total / (lengths.len() - 1) as f64)
+        .collect::<Vec<_>>();
+    let (logs, mean_log) = pair_lengths(pairs, lengths);
+    Some(Bias {
+        pearson: stats::pearson(lengths, &mean_scores),
+        spearman: stats::spearman(lengths, &mean_scores),
+        slope_per_doubling: slope(pairs, scores, &logs, mean_log) * 2f64.ln(),
+    })
+}
+
+/// Removes the linear trend of the scores of pairs of distinct documents over the log of their
+/// mean length, keeping their mean score. Self-scores are left as they are.
+pub fn correct(pairs: &[(usize, usize)], scores: &mut [f64], lengths: &[f64]) {
+    if lengths.len() < 3 {
+        return;
+    }
+    let (logs, mean_log) = pair_lengths(pairs, lengths);
+    let slope = slope(pairs, scores, &logs, mean_log);
+    for (((i, j), score), log) in pairs.iter().zip(scores.iter_mut()).zip(&logs) {
+        if i != j {
+            *score -= slope * (log - mean_log);
+        }
+    }
+}
+
+/// Prints the length bias of the scores, and what is left of it after `--length-correction`.
+pub fn print_report(
+    bias: Option<&Bias>,
+    corrected: Option<&Bias>,
+    distance_metric: &DistanceMetric,
+) {
+    let Some(bias) = bias else {
+        println!("Length bias needs at least 3 documents");
+        return;
+    };
+    let describe = |bias: &Bias| {
+        format!(
+            "pearson {}, spearman {} between length (words) and mean {distance_metric} score; \
+             {:+} {distance_metric} per doubling of a pair's length",
+            bias.pearson, bias.spearman, bias.slope_per_doubling
+        )
+    };
+    println!("length bias: {}", describe(bias));
+    if let Some(corrected) = corrected {
+        println!("after --length-correction: {}", describe(corrected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every pair of 4 documents including self-pairs, in the order the matrix is scored.
+    fn pairs() -> Vec<(usize, usize)> {
+        (0..4).flat_map(|i| (i..4).map(move |j| (i, j))).collect()
+    }
+
+    /// Scores growing with the mean log length of the pair, plus some noise.
+    fn biased_scores(pairs: &[(usize, usize)], lengths: &[f64]) -> Vec<f64> {
+        pairs
+            .iter()
+            .map(|(i, j)| {
+                if i == j {
+                    1.0
+                } else {
+                    0.2 + 0.1 * (lengths[*i].ln() + lengths[*j].ln()) / 2.0
+                        + 0.01 * ((i + 2 * j) % 3) as f64
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn longer_documents_scoring_higher_are_biased() {
+        let lengths = [1.0, 4.0, 16.0, 64.0];
+        let pairs = pairs();
+        let scores = biased_scores(&pairs, &lengths);
+        let bias = measure(&pairs, &scores, &lengths).unwrap();
+        assert!(bias.spearman > 0.99);
+        assert!((bias.slope_per_doubling - 0.1 * 2f64.ln()).abs() < 0.01);
+        assert!(measure(&pairs[..3], &scores[..3], &lengths[..2]).is_none());
+    }
+
+    #[test]
+    fn corrections_remove_the_trend_and_keep_the_mean() {
+        let lengths = [1.0, 4.0, 16.0, 64.0];
+        let pairs = pairs();
+        let mut scores = biased_scores(&pairs, &lengths);
+        let distinct_mean = |scores: &[f64]| {
+            let distinct = pairs
+                .iter()
+                .zip(scores)
+                .filter(|((i, j), _)| i != j)
+                .map(|(_, score)| *score)
+                .collect::<Vec<_>>();
+            stats::mean(&distinct)
+        };
+        let mean = distinct_mean(&scores);
+
+        correct(&pairs, &mut scores, &lengths);
+        let bias = measure(&pairs, &scores, &lengths).unwrap();
+        assert!(bias.slope_per_doubling.abs() < 1e-12);
+        assert!((distinct_mean(&scores) - mean).abs() < 1e-12);
+        assert_eq!(scores[0], 1.0);
+    }
+
+    #[test]
+    fn lengths_are_words_and_never_zero() {
+        let strings = ["two words", "", "one"].map(String::from);
+        assert_eq!(lengths(&strings), [2.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use clap::ValueEnum;
+
+use crate::{pairs::Pair, stats, table, terms, DistanceMetric};
+
+/// Term frequency saturation of BM25.
+const BM25_K1: f64 = 1.2;
+/// Document length normalization of BM25.
+const BM25_B: f64 = 0.75;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Lexical {
+    /// Okapi BM25 of each document as a query against the other, relative to its score against
+    /// itself, averaged over both directions
+    Bm25,
+    /// Cosine of the TF-IDF vectors of the documents
+    Tfidf,
+    /// Words both documents have over the words of either
+    Jaccard,
+}
+
+impl Display for Lexical {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lexical::Bm25 => write!(f, "bm25"),
+            Lexical::Tfidf => write!(f, "tfidf"),
+            Lexical::Jaccard => write!(f, "jaccard"),
+        }
+    }
+}
+
+/// Parses `--hybrid-weight`, the share of the embedding score in the hybrid score.
+pub fn parse_weight(weight: &str) -> Result<f64, String> {
+    let weight = weight.parse::<f64>().map_err(|error| error.to_string())?;
+    if !(0.0..=1.0).contains(&weight) {
+        return Err("the weight is between 0 and 1".to_string());
+    }
+    Ok(weight)
+}
+
+/// Scores the lexical similarity of the documents of a corpus, between 0 and 1.
+pub struct LexicalScorer {
+    lexical: Lexical,
+    /// Counts of the salient words of every document
+    counts: Vec<HashMap<String, usize>>,
+    /// Inverse document frequency of every word, as the score weighs it
+    idf: HashMap<String, f64>,
+    /// Words of every document
+    lengths: Vec<f64>,
+    mean_length: f64,
+}
+
+impl LexicalScorer {
+    pub fn new(lexical: &Lexical, corpus: &[String]) -> Self {
+        let counts = corpus
+            .iter()
+            .map(|text| {
+                let mut counts = HashMap::<_, usize>::new();
+                for word in terms::salient_words(text) {
+                    *counts.entry(word).or_default() += 1;
+                }
+                counts
+            })
+            .collect::<Vec<_>>();
+        let mut frequencies = HashMap::<_, usize>::new();
+        for word in counts.iter().flat_map(HashMap::keys) {
+            *frequencies.entry(word.clone()).or_default() += 1;
+        }
+        let documents = corpus.len() as f64;
+        let idf = frequencies
+            .into_iter()
+            .map(|(word, frequency)| {
+                let frequency = frequency as f64;
+                let idf = match lexical {
+                    Lexical::Bm25 => (1.0 + (documents - frequency + 0.5) / (frequency + 0.5)).ln(),
+                    // Smoothed like the weights of --shared-terms
+                    _ => ((1.0 + documents) / (1.0 + frequency)).ln() + 1.0,
+                };
+                (word, idf)
+            })
+            .collect();
+        let lengths = counts
+            .iter()
+            .map(|counts| counts.values().sum::<usize>() as f64)
+            .collect::<Vec<_>>();
+        LexicalScorer {
+            lexical: lexical.clone(),
+            mean_length: stats::mean(&lengths),
+            counts,
+            idf,
+            lengths,
+        }
+    }
+
+    /// Lexical similarity of documents `i` and `j`, 0 when either has no salient words.
+    pub fn score(&self, i: usize, j: usize) -> f64 {
+        let score = match self.lexical {
+            Lexical::Bm25 => (self.relative_bm25(i, j) + self.relative_bm25(j, i)) / 2.0,
+            Lexical::Tfidf => {
+                let (first, second) = (self.tfidf(i), self.tfidf(j));
+                let dot = first
+                    .iter()
+                    .filter_map(|(word, weight)| second.get(word).map(|other| weight * other))
+                    .sum::<f64>();
+                let norm = |vector: &HashMap<&String, f64>| {
+                    vector
+                        .values()
+                        .map(|weight| weight * weight)
+                        .sum::<f64>()
+                        .sqrt()
+                };
+                let norms = norm(&first) * norm(&second);
+                if norms == 0.0 {
+                    0.0
+                } else {
+                    dot / norms
+                }
+            }
+            Lexical::Jaccard => {
+                let (first, second) = (
+                    self.counts[i].keys().collect::<HashSet<_>>(),
+                    self.counts[j].keys().collect::<HashSet<_>>(),
+                );
+                let union = first.union(&second).count();
+                if union == 0 {
+                    0.0
+                } else {
+                    first.intersection(&second).count() as f64 / union as f64
+                }
+            }
+        };
+        // Sums of no terms are -0
+        score + 0.0
+    }
+
+    fn tfidf(&self, i: usize) -> HashMap<&String, f64> {
+        self.counts[i]
+            .iter()
+            .map(|(word, count)| (word, *count as f64 * self.idf[word]))
+            .collect()
+    }
+
+    /// BM25 of document `query` against document `document`.
+    fn bm25(&self, query: usize, document: usize) -> f64 {
+        let length = self.lengths[document] / self.mean_length;
+        self.counts[query]
+            .keys()
+            .filter_map(|word| {
+                let frequency = *self.counts[document].get(word)? as f64;
+                Some(
+                    self.idf[word] * frequency * (BM25_K1 + 1.0)
+                        / (frequency + BM25_K1 * (1.0 - BM25_B + BM25_B * length)),
+                )
+            })
+            .sum()
+    }
+
+    /// BM25 of `query` against `document` over its BM25 against itself, so that scores are
+    /// comparable across queries.
+    fn relative_bm25(&self, query: usize, document: usize) -> f64 {
+        let itself = self.bm25(query, query);
+        if itself == 0.0 {
+            0.0
+        } else {
+            self.bm25(query, document) / itself
+        }
+    }
+}
+
+/// Lexical and hybrid columns of the pairs output.
+pub struct LexicalColumns {
+    pub lexical: Vec<f64>,
+    /// `weight × embedding score + (1 - weight) × lexical score`, with `--hybrid-weight`
+    pub hybrid: Option<Vec<f64>>,
+}
+
+impl LexicalColumns {
+    pub fn new(scorer: &LexicalScorer, pairs: &[Pair], hybrid_weight: Option<f64>) -> Self {
+        let lexical = pairs
+            .iter()
+            .map(|pair| scorer.score(pair.i, pair.j))
+            .collect::<Vec<_>>();
+        let hybrid = hybrid_weight.map(|weight| {
+            pairs
+                .iter()
+                .zip(&lexical)
+                .map(|(pair, lexical)| weight * pair.score + (1.0 - weight) * lexical)
+                .collect()
+        });
+        LexicalColumns { lexical, hybrid }
+    }
+
+    /// Named columns of formatted scores, one value per pair.
+    pub fn columns(&self, lexical: &Lexical) -> Vec<(String, Vec<String>)> {
+        let format = |scores: &[f64]| {
+            scores
+                .iter()
+                .map(|score| table::format_score(*score))
+                .collect()
+        };
+        let mut columns = vec![(lexical.to_string(), format(&self.lexical))];
+        if let Some(hybrid) = &self.hybrid {
+            columns.push(("hybrid".to_string(), format(hybrid)));
+        }
+        columns
+    }
+}
+
+/// Prints how closely the embedding scores of the listed pairs follow their lexical scores.
+pub fn print_agreement(
+    pairs: &[Pair],
+    columns: &LexicalColumns,
+    lexical: &Lexical,
+    distance_metric: &DistanceMetric,
+) {
+    if pairs.len() < 3 {
+        return;
+    }
+    let scores = pairs.iter().map(|pair| pair.score).collect::<Vec<_>>();
+    let mut spearman = stats::spearman(&scores, &columns.lexical);
+    if !distance_metric.higher_is_closer() {
+        // Distances fall as lexical similarity rises
+        spearman = -spearman;
+    }
+    println!(
+        "spearman {spearman} between {distance_metric} closeness and {lexical} over the {} pairs \
+         listed",
+        pairs.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> Vec<String> {
+        [
+            "interest rates rise",
+            "interest rates fall again",
+            "the cat sat",
+            "",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    #[test]
+    fn jaccard_compares_the_sets_of_salient_words() {
+        let scorer = LexicalScorer::new(&Lexical::Jaccard, &corpus());
+        assert_eq!(scorer.score(0, 1), 2.0 / 5.0);
+        assert_eq!(scorer.score(0, 2), 0.0);
+        assert_eq!(scorer.score(2, 2), 1.0);
+        assert_eq!(scorer.score(0, 3), 0.0);
+    }
+
+    #[test]
+    fn tfidf_and_bm25_are_symmetric_and_bounded() {
+        for lexical in [Lexical::Tfidf, Lexical::Bm25] {
+            let scorer = LexicalScorer::new(&lexical, &corpus());
+            let (close, far) = (scorer.score(0, 1), scorer.score(0, 2));
+            assert!(close > 0.0 && close < 1.0, "{lexical} {close}");
+            assert_eq!(close, scorer.score(1, 0));
+            assert!(far == 0.0 && far.is_sign_positive());
+            assert!((scorer.score(1, 1) - 1.0).abs() < 1e-12);
+            assert_eq!(scorer.score(3, 0), 0.0);
+        }
+    }
+
+    #[test]
+    fn hybrid_scores_weigh_both_scores() {
+        let scorer = LexicalScorer::new(&Lexical::Jaccard, &corpus());
+        let pairs = [Pair {
+            i: 0,
+            j: 1,
+            score: 0.8,
+        }];
+        let columns = LexicalColumns::new(&scorer, &pairs, Some(0.75));
+        assert_eq!(columns.lexical, [0.4]);
+        assert!((columns.hybrid.unwrap()[0] - (0.75 * 0.8 + 0.25 * 0.4)).abs() < 1e-12);
+    }
+}
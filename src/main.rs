@@ -1,38 +1,224 @@
-use std::{env, fmt::Display, fs::File, io::BufReader};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, IsTerminal},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
 
-use clap::{Parser, ValueEnum};
+use cache::EmbeddingCache;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use indicatif::ParallelProgressIterator;
 use itertools::Itertools;
-use pretty_table::print_table;
-use rig::embeddings::EmbeddingModel;
-use semanticsimilarity_rs::{
-    cosine_similarity, dot_product_distance, euclidean_distance, manhattan_distance,
+use keys::ApiKey;
+use providers::{
+    CohereClient, CohereEmbeddingType, CohereInputType, Embedding, MockClient, OpenaiClient,
 };
+use rand::{rngs::StdRng, SeedableRng};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+mod allowlist;
+mod anchor;
+mod audit;
+mod baseline;
+mod blockwise;
+mod cache;
+mod chunking;
+mod classify;
+mod cluster;
+mod compare;
+mod completions;
+mod config;
+mod crosslingual;
+mod dead_letter;
+mod deadline;
+mod diff;
+mod duplicates;
+mod embedding_file;
+mod estimate;
+mod eval;
+mod explain;
+mod files;
+mod float32;
+mod gen_corpus;
+mod graph;
+mod heatmap;
+mod hierarchy;
+mod history;
+mod hnsw;
+mod http;
+mod images;
+mod ivf;
+mod keys;
+mod labels;
+mod language;
+mod leakage;
+mod ledger;
+mod length_bias;
+mod lexical;
+mod logging;
+mod manifest;
+mod metric_agreement;
+mod metrics;
+mod models;
+mod monitor;
+mod npy;
+mod outliers;
+mod pairs;
+mod paraphrase;
+mod pq;
+mod preprocess;
+mod privacy;
+mod progress;
+mod projection;
+mod providers;
+mod quantization;
+mod query;
+mod regress;
+mod report;
+mod retrieval;
+mod search;
+mod select;
+mod serve;
+mod shared_cache;
+mod split;
+mod sql;
+mod stats;
+mod stream;
+mod table;
+mod template;
+mod tenants;
+mod terms;
+mod truncation;
+mod tui;
+mod vector_store;
+mod warnings;
+mod watch;
+mod whitening;
 
 const EMPTY: &str = "-";
 
 #[derive(Debug, Clone, ValueEnum)]
 enum DistanceMetric {
     Cosine,
+    /// `1 - cosine`, lower is closer like the other distances
+    CosineDistance,
     L2,
     Dot,
     Manhattan,
+    /// Largest difference over all dimensions
+    Chebyshev,
+    /// Generalized L2 with the exponent set by `--minkowski-p`
+    Minkowski,
+    /// Angle between the vectors, scaled to [0, 1]
+    Angular,
+    /// Jaccard distance of the sets of positive dimensions
+    Jaccard,
+}
+
+impl DistanceMetric {
+    fn distance(&self, first: &[f64], second: &[f64]) -> f64 {
+        match self {
+            DistanceMetric::Cosine => metrics::cosine(first, second),
+            DistanceMetric::CosineDistance => 1.0 - metrics::cosine(first, second),
+            DistanceMetric::L2 => metrics::euclidean(first, second),
+            DistanceMetric::Dot => metrics::dot(first, second),
+            DistanceMetric::Manhattan => metrics::manhattan(first, second),
+            DistanceMetric::Chebyshev => metrics::chebyshev_distance(first, second),
+            DistanceMetric::Minkowski => {
+                metrics::minkowski_distance(first, second, metrics::minkowski_p())
+            }
+            DistanceMetric::Angular => metrics::angular_distance(first, second),
+            DistanceMetric::Jaccard => metrics::jaccard_distance(first, second),
+        }
+    }
+
+    /// [Self::distance] of f32 vectors, computed in f32.
+    fn distance_f32(&self, first: &[f32], second: &[f32]) -> f64 {
+        let distance = match self {
+            DistanceMetric::Cosine => float32::cosine(first, second),
+            DistanceMetric::CosineDistance => 1.0 - float32::cosine(first, second),
+            DistanceMetric::L2 => float32::euclidean(first, second),
+            DistanceMetric::Dot => float32::dot(first, second),
+            DistanceMetric::Manhattan => float32::manhattan(first, second),
+            DistanceMetric::Chebyshev => float32::chebyshev(first, second),
+            DistanceMetric::Minkowski => {
+                float32::minkowski(first, second, metrics::minkowski_p() as f32)
+            }
+            DistanceMetric::Angular => float32::angular(first, second),
+            DistanceMetric::Jaccard => float32::jaccard(first, second),
+        };
+        f64::from(distance)
+    }
+
+    /// Score where higher always means more similar, for correlating against gold labels.
+    fn similarity(&self, first: &[f64], second: &[f64]) -> f64 {
+        match self {
+            DistanceMetric::Cosine | DistanceMetric::Dot => self.distance(first, second),
+            _ => -self.distance(first, second),
+        }
+    }
+
+    fn higher_is_closer(&self) -> bool {
+        matches!(self, DistanceMetric::Cosine | DistanceMetric::Dot)
+    }
+
+    /// Orders two scores so that the closer one compares greater.
+    fn cmp_closeness(&self, first: f64, second: f64) -> std::cmp::Ordering {
+        if self.higher_is_closer() {
+            first.total_cmp(&second)
+        } else {
+            second.total_cmp(&first)
+        }
+    }
+
+    /// Score where lower always means more similar, zero for identical unit vectors.
+    fn dissimilarity(&self, first: &[f64], second: &[f64]) -> f64 {
+        match self {
+            DistanceMetric::Cosine => 1.0 - self.distance(first, second),
+            DistanceMetric::Dot => -self.distance(first, second),
+            _ => self.distance(first, second),
+        }
+    }
 }
 
 impl Display for DistanceMetric {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             DistanceMetric::Cosine => write!(f, "cosine"),
+            DistanceMetric::CosineDistance => write!(f, "cosine-distance"),
             DistanceMetric::L2 => write!(f, "l2"),
             DistanceMetric::Dot => write!(f, "dot"),
             DistanceMetric::Manhattan => write!(f, "manhattan"),
+            DistanceMetric::Chebyshev => write!(f, "chebyshev"),
+            DistanceMetric::Minkowski => write!(f, "minkowski"),
+            DistanceMetric::Angular => write!(f, "angular"),
+            DistanceMetric::Jaccard => write!(f, "jaccard"),
         }
     }
 }
 
-#[derive(Debug, Clone, ValueEnum)]
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
 enum Provider {
     Openai,
     Cohere,
+    /// Deterministic pseudo-embeddings or `--mock-fixture` vectors, without any API key
+    Mock,
+}
+
+impl Provider {
+    /// Most documents accepted in one embedding request.
+    fn max_batch_size(&self) -> usize {
+        match self {
+            Provider::Openai => 2048,
+            Provider::Cohere => 96,
+            Provider::Mock => 2048,
+        }
+    }
 }
 
 impl Display for Provider {
@@ -40,95 +226,2132 @@ impl Display for Provider {
         match self {
             Provider::Openai => write!(f, "openai"),
             Provider::Cohere => write!(f, "cohere"),
+            Provider::Mock => write!(f, "mock"),
         }
     }
 }
 
+/// Analyses printed instead of the pairwise scores, which the run then doesn't compute.
+const REPLACING_ANALYSES: [&str; 13] = [
+    "clusters",
+    "labels",
+    "outliers",
+    "shared_terms",
+    "explain",
+    "pairs",
+    "dendrogram",
+    "project",
+    "pq",
+    "ivf",
+    "truncate_dims",
+    "quantize",
+    "pairs_out",
+];
+
 #[derive(Parser, Debug)]
 #[command(name = "Distance Calculator")]
 #[command(version = "1.0")]
 #[command(about = "Calculates distance between multiple vectors", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(short)]
-    input_file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Exponent of the `minkowski` distance metric
+    #[arg(long, global = true, default_value_t = metrics::DEFAULT_MINKOWSKI_P)]
+    minkowski_p: f64,
+    /// JSON array of a weight per dimension, multiplying its terms in every distance metric:
+    /// 0 masks a dimension out, 1 keeps it as it is
+    #[arg(long)]
+    dim_weights: Option<String>,
+    /// Never call a provider: fail if a document isn't already cached instead of paying for it
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Stop embedding once this much time (e.g. `10m`) has passed since the start, across every
+    /// batch and provider, and finish the run with the documents embedded by then
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        conflicts_with_all = ["interval", "watch"]
+    )]
+    deadline: Option<std::time::Duration>,
+    /// TOML file of default options [default: distance-calculator.toml if present]
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// File of environment variables such as API keys to load [default: .env if present]
+    #[arg(long, global = true)]
+    env_file: Option<String>,
+    /// Directory of the embedding cache and usage ledger [default: ~/.distance-calculator]
+    #[arg(long, global = true, env = "DISTANCE_CALCULATOR_HOME")]
+    data_dir: Option<String>,
+    /// Embedding cache shared between machines: a Redis server (`redis://cache:6379/`) or an S3
+    /// bucket and prefix (`s3://bucket/embeddings`)
+    #[arg(long, global = true, env = "DISTANCE_CALCULATOR_SHARED_CACHE")]
+    shared_cache: Option<String>,
+    /// Log what the run does to stderr: -v for requests, cache lookups and timings, -vv and -vvv
+    /// for more detail
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    #[arg(long, global = true, default_value_t = logging::LogFormat::Text)]
+    log_format: logging::LogFormat,
+    /// Append-only log of every embedding request, with document hashes but never their text
+    /// [default: audit.jsonl in the data directory]
+    #[arg(long, global = true)]
+    audit_log: Option<String>,
+    /// Append every batch the provider fails to embed, with its documents, the error and the
+    /// provider's request id, to this JSON lines file
+    #[arg(long, global = true)]
+    dead_letter: Option<String>,
+    /// Fail the run once it's done if it raised any non-fatal warning, e.g. so that CI catches
+    /// stale cache entries or rescaled vectors
+    #[arg(long, global = true)]
+    deny_warnings: bool,
+    /// Hosts the tool may contact, e.g. `api.openai.com,*.internal.example.com`: any other
+    /// provider, cache or database host fails the run [default: any host]
+    #[arg(long, global = true, value_delimiter = ',')]
+    allowed_hosts: Option<Vec<String>>,
+    /// Seconds an HTTP request to a provider or service may take before failing [default: no
+    /// limit]
+    #[arg(long, global = true, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+    /// Proxy of every HTTP request, e.g. `http://proxy.example.com:3128` [default: the
+    /// `HTTPS_PROXY` and `HTTP_PROXY` variables]
+    #[arg(long, global = true, value_parser = http::parse_proxy)]
+    proxy: Option<String>,
+    /// Characters of a document's text its label keeps in tables [default: its first sentence,
+    /// or 10 characters]
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    label_width: Option<u16>,
+    /// Cut labels before the word crossing `--label-width` rather than inside it
+    #[arg(long, global = true)]
+    label_word_boundary: bool,
+    /// Label documents with their full texts
+    #[arg(long, global = true, conflicts_with_all = ["label_width", "label_word_boundary"])]
+    no_truncate: bool,
+    /// API key of a provider, instead of its environment variable or the keyring: `openai=sk-...`,
+    /// repeated for each provider, or a bare key when the run uses a single provider
+    #[arg(long, global = true)]
+    api_key: Vec<ApiKey>,
+    /// Save `--api-key` in the OS keyring as the key of this provider
+    #[arg(long, global = true, requires = "api_key")]
+    store_key: Option<Provider>,
+    /// JSON array of the documents, or a directory of text files, one document per file named
+    /// by its file name. Repeat to read several inputs, whose documents are then identified by
+    /// their input, e.g. `a.json:0`
+    #[arg(short, required_unless_present_any = ["input_sql", "embeddings", "store_key"])]
+    input_file: Vec<String>,
+    /// Read documents from the last column of this SQL query instead of an input file
+    #[arg(long, requires = "db", conflicts_with = "input_file")]
+    input_sql: Option<String>,
+    /// Database URL (`postgres://`, `mysql://` or `sqlite://`), or SQLite file, for
+    /// `--input-sql`, `--write-results` and `--history`
+    #[arg(long, value_parser = sql::parse_database_url)]
+    db: Option<String>,
+    /// Write the results into this table of the `--db` database, keyed by document id
+    #[arg(
+        long,
+        requires = "db",
+        value_parser = sql::parse_table_name,
+        conflicts_with = "dry_run",
+        conflicts_with_all = REPLACING_ANALYSES
+    )]
+    write_results: Option<String>,
+    #[arg(long, default_value_t = sql::WriteMode::Pairs)]
+    write_mode: sql::WriteMode,
+    /// Append the run, its documents and the score of every pair to the run history tables of
+    /// the `--db` database
+    #[arg(
+        long,
+        requires = "db",
+        conflicts_with_all = ["interval", "dry_run"],
+        conflicts_with_all = REPLACING_ANALYSES
+    )]
+    history: bool,
     #[arg(short, long, default_value_t = Provider::Openai)]
     provider: Provider,
-    #[arg(short, long)]
-    embedding_model: String,
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["embeddings", "store_key"],
+        value_parser = models::parse_model
+    )]
+    embedding_model: Option<String>,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    /// Read the documents and their vectors from a file written by `--save-embeddings` instead
+    /// of embedding an input
+    #[arg(long, conflicts_with_all = ["input_file", "input_sql", "interval"])]
+    embeddings: Option<String>,
+    /// Documents embedded per request, the provider's maximum by default
+    #[arg(long)]
+    batch_size: Option<usize>,
+    /// Keep the embeddings of this run in this file, saved after every batch, and resume from
+    /// it when rerun, instead of the shared embedding cache
+    #[arg(long)]
+    checkpoint: Option<String>,
+    /// Embed every document again, even those already cached
+    #[arg(long, conflicts_with = "embeddings")]
+    refresh: bool,
+    /// Transform the documents before embedding them, stage by stage (e.g.
+    /// `strip_html,lowercase,collapse_ws,truncate:512`)
+    #[arg(long, value_delimiter = ',', value_parser = preprocess::parse_stage)]
+    preprocess: Vec<Arc<dyn preprocess::Stage>>,
+    /// Embed every text as this template, `{}` standing for the text, e.g. `query: {}` for the
+    /// instruction prefixes of e5 and bge models. Texts keep their own name in the output
+    #[arg(long, value_parser = template::parse, conflicts_with = "embeddings")]
+    template: Option<template::Template>,
+    /// Template of the texts of one `-i` input, overriding `--template` for them, e.g.
+    /// `queries.json=query: {}` (repeat for several inputs)
+    #[arg(long, value_parser = template::parse_input, requires = "input_file")]
+    input_template: Vec<(String, template::Template)>,
+    /// Split documents longer than this many words into chunks, embedded separately
+    #[arg(long, conflicts_with_all = ["embeddings", "interval"])]
+    chunk_size: Option<usize>,
+    /// Words every chunk repeats from the end of the previous one
+    #[arg(long, requires = "chunk_size", default_value_t = 0)]
+    chunk_overlap: usize,
+    /// How the chunks of a document combine. `max-sim` only applies to the pairwise scores, so
+    /// it can't be given with the analyses that replace them
+    #[arg(
+        long,
+        requires = "chunk_size",
+        default_value_t = chunking::Aggregation::Mean,
+        conflicts_with_all = REPLACING_ANALYSES
+    )]
+    chunk_aggregation: chunking::Aggregation,
+    /// Print the tokens and estimated cost of embedding the uncached documents, without
+    /// embedding them
+    #[arg(long, conflicts_with_all = ["embeddings", "interval"])]
+    dry_run: bool,
+    /// Report the documents duplicating an earlier one on stderr before embedding them
+    #[arg(long, conflicts_with_all = ["embeddings", "chunk_size"])]
+    report_duplicates: bool,
+    /// Embed each group of duplicate documents once, giving every document of the group the
+    /// embedding of its first one. Exact duplicates are always embedded once
+    #[arg(long, conflicts_with_all = ["embeddings", "chunk_size"])]
+    dedupe_inputs: bool,
+    /// What documents may differ by and still be duplicates for `--report-duplicates` and
+    /// `--dedupe-inputs`, comma-separated [default: nothing]
+    #[arg(long, value_delimiter = ',')]
+    duplicate_normalization: Vec<duplicates::Normalization>,
+    /// Save the documents and their vectors to this file for later runs with `--embeddings`
+    #[arg(long)]
+    save_embeddings: Option<String>,
+    /// Write the provider, model, dimensions, metric, vector settings, input hashes, tool version
+    /// and timestamps of the run to this JSON file, to reproduce or audit its results
+    #[arg(long, conflicts_with = "interval")]
+    manifest: Option<String>,
+    /// Float type of the vectors saved by `--save-embeddings`
+    #[arg(long, requires = "save_embeddings", default_value_t = embedding_file::StorageType::F64)]
+    storage_type: embedding_file::StorageType,
+    /// Add Gaussian noise giving the saved vectors this differential privacy budget, after
+    /// clipping them to unit length, and save the positions of the documents instead of their
+    /// texts
+    #[arg(long, requires = "save_embeddings")]
+    privacy_epsilon: Option<f64>,
+    /// Probability that the `--privacy-epsilon` guarantee doesn't hold
+    #[arg(long, requires = "privacy_epsilon", default_value_t = 1e-5)]
+    privacy_delta: f64,
     #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
     distance_metric: DistanceMetric,
+    /// Scale every vector to unit length before comparing them
+    #[arg(long)]
+    normalize: bool,
+    /// Transform fitted on the vectors of the documents and applied to them, and to their
+    /// chunks, before comparing them
+    #[arg(long, default_value_t = whitening::VectorTransform::None)]
+    vector_transform: whitening::VectorTransform,
+    /// Principal components `--vector-transform whiten` keeps
+    #[arg(long, default_value_t = whitening::DEFAULT_WHITEN_DIMENSIONS, value_parser = clap::value_parser!(u16).range(1..))]
+    whiten_dims: u16,
+    /// Use the vectorized cosine, dot and L2 kernels, on by default from 500 documents or 4096
+    /// dimensions
+    #[arg(long)]
+    fast: bool,
+    /// Precision the vectors are kept and the pairwise scores computed in once the vectors are
+    /// ready: `f32` halves their memory for large corpora
+    #[arg(
+        long,
+        default_value_t = float32::VectorPrecision::F64,
+        conflicts_with_all = [
+            "clusters", "labels", "outliers", "shared_terms", "explain", "pairs", "dendrogram",
+            "project", "pq", "ivf", "truncate_dims", "quantize", "pairs_out", "interval",
+            "dim_weights"
+        ]
+    )]
+    vector_precision: float32::VectorPrecision,
+    /// Print to stderr how long embedding the documents and computing the scores took
+    #[arg(long)]
+    timings: bool,
+    /// Run k-means with this many clusters and print assignments instead of the distance matrix
+    #[arg(long)]
+    clusters: Option<usize>,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Score how well the embeddings separate known categories, a JSON array of the label of
+    /// every input document (or of `{"label"}` objects, like a labeled corpus), instead of the
+    /// distance matrix
+    #[arg(long, conflicts_with_all = ["clusters", "deadline"])]
+    labels: Option<String>,
+    /// Print every document's score against the centroid of the corpus, farthest first, and
+    /// flag this many farthest documents as outliers, instead of the distance matrix
+    #[arg(long)]
+    outliers: Option<usize>,
+    /// Print the score of two documents (ids, texts or positions, e.g. `0,3`) and the salient
+    /// terms and bigrams they share instead of the distance matrix
+    #[arg(long, value_parser = terms::parse_pair)]
+    shared_terms: Option<(String, String)>,
+    /// Print the score of two documents (ids, texts or positions, e.g. `0,3`) and the dimensions
+    /// contributing most to their dot product or cosine, with their values, instead of the
+    /// distance matrix
+    #[arg(long, value_parser = terms::parse_pair)]
+    explain: Option<(String, String)>,
+    /// Only score these pairs of documents instead of every pair: a JSON array of `[first,
+    /// second]` documents, by position or by id or text, e.g. `[[0, 3], ["doc-a", "doc-b"]]`
+    #[arg(long, conflicts_with_all = ["anchor", "report_template", "group_by", "pairs_out"])]
+    pairs: Option<String>,
+    /// Run agglomerative clustering and print an ASCII dendrogram instead of the distance matrix
+    #[arg(long)]
+    dendrogram: bool,
+    #[arg(long, default_value_t = hierarchy::Linkage::Average)]
+    linkage: hierarchy::Linkage,
+    /// Also write the dendrogram tree to this file
+    #[arg(long, requires = "dendrogram")]
+    tree_out: Option<String>,
+    #[arg(long, default_value_t = hierarchy::TreeFormat::Newick)]
+    tree_format: hierarchy::TreeFormat,
+    /// Reduce the embeddings to 2D and write `index,label,x,y` CSV instead of the distance matrix
+    #[arg(long)]
+    project: Option<projection::Projection>,
+    /// File to write the projected points to, stdout if not set
+    #[arg(long, requires = "project")]
+    project_out: Option<String>,
+    /// Train a product quantizer with this many subspaces and report the recall of PQ search
+    /// instead of the distance matrix
+    #[arg(long)]
+    pq: Option<usize>,
+    /// Centroids per PQ subspace
+    #[arg(long, default_value_t = 256, requires = "pq")]
+    pq_centroids: usize,
+    /// Simulate an IVF index with this many k-means partitions and report its recall and cost
+    /// for every `--nprobe` instead of the distance matrix
+    #[arg(long, conflicts_with = "pq")]
+    ivf: Option<usize>,
+    /// Numbers of partitions searched per query by the `--ivf` simulation
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1,2,4,8,16",
+        requires = "ivf"
+    )]
+    nprobe: Vec<usize>,
+    /// Truncate the vectors to each of these dimensions (e.g. `256,512,1024`) and report how
+    /// far the scores and neighbors drift from the full vectors instead of the distance matrix
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["pq", "ivf"])]
+    truncate_dims: Option<Vec<usize>>,
+    /// Quantize the vectors with each of these schemes and report how far the scores and
+    /// neighbors drift from float32 instead of the distance matrix
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["pq", "ivf", "truncate_dims"])]
+    quantize: Option<Vec<quantization::Quantization>>,
+    /// Number of neighbors compared between exact and approximate search
+    #[arg(long, default_value_t = 10)]
+    recall_k: usize,
+    /// Measure the recall of `--pq`, `--ivf`, `--truncate-dims` and `--quantize` against brute
+    /// force on a random sample of this many documents instead of all of them
+    #[arg(long)]
+    verify_sample: Option<usize>,
+    /// Also print summary statistics of the pairwise scores
+    #[arg(long)]
+    stats: bool,
+    /// Resample the pairs this many times for 95% confidence intervals of the `--stats` mean and
+    /// median
+    #[arg(long, requires = "stats", value_parser = clap::value_parser!(u32).range(1..))]
+    bootstrap: Option<u32>,
+    /// Also print how strongly the scores follow the length of the documents: the correlation
+    /// between every document's length and its mean score, and the trend of the pair scores
+    #[arg(long, conflicts_with_all = ["interval", "watch", "pairs_out"])]
+    length_bias: bool,
+    /// Remove the linear trend of the pair scores over the log of the documents' length before
+    /// printing or checking them
+    #[arg(long, conflicts_with_all = ["interval", "watch", "pairs_out"])]
+    length_correction: bool,
+    /// Also score the pairs with each of these comma-separated metrics (every metric when none
+    /// is given) and print the Spearman and Kendall correlations between the rankings of the
+    /// pairs by every two of them, to see whether the choice of metric matters for the corpus
+    #[arg(
+        long,
+        num_args = 0..,
+        value_delimiter = ',',
+        conflicts_with_all = ["interval", "watch", "pairs_out", "chunk_size"]
+    )]
+    metric_agreement: Option<Vec<DistanceMetric>>,
+    /// Also render the distance matrix as a PNG heatmap to this file
+    #[arg(long)]
+    heatmap: Option<String>,
+    /// Also write the full distance matrix to this NumPy `.npy` file, and the documents of its
+    /// rows to a `.labels.json` file next to it
+    #[arg(long, conflicts_with_all = ["interval", "pairs_out"])]
+    matrix_out: Option<String>,
+    /// Precision of the `--matrix-out` values
+    #[arg(long, requires = "matrix_out", default_value_t = npy::Dtype::F64)]
+    matrix_dtype: npy::Dtype,
+    /// Also write the similarity graph of the documents to this file: the documents are its
+    /// nodes and the pairs scoring at least as close as `--threshold` its weighted edges
+    #[arg(long, requires = "threshold", conflicts_with_all = ["interval", "pairs_out"])]
+    graph_out: Option<String>,
+    /// Score from which a pair is an edge of the `--graph-out` graph
+    #[arg(long, requires = "graph_out", allow_negative_numbers = true)]
+    threshold: Option<f64>,
+    #[arg(long, requires = "graph_out", default_value_t = graph::GraphFormat::Csv)]
+    graph_format: graph::GraphFormat,
+    /// Re-read the input on this schedule (e.g. `1h`, `30m`) and append a summary of every run
+    /// to the results log
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        conflicts_with_all = [
+            "clusters", "labels", "outliers", "shared_terms", "explain", "pairs", "dendrogram",
+            "project", "pq", "ivf", "truncate_dims", "quantize"
+        ]
+    )]
+    interval: Option<std::time::Duration>,
+    /// JSON lines file the `--interval` summaries are appended to
+    #[arg(long, default_value = "results.jsonl")]
+    results_log: String,
+    /// Run again every time the input file changes, only embedding the edited documents
+    #[arg(
+        long,
+        requires = "input_file",
+        conflicts_with_all = ["input_sql", "embeddings", "interval", "dry_run"]
+    )]
+    watch: bool,
+    /// Write every pair to this CSV file block by block instead of building the distance matrix
+    /// in memory, for corpora too large for the table
+    #[arg(long, conflicts_with = "interval")]
+    pairs_out: Option<String>,
+    /// Rows of the matrix scored at a time by `--pairs-out`
+    #[arg(long, default_value_t = 256, requires = "pairs_out")]
+    block_size: usize,
+    /// Print the result as a distance matrix or as one row per pair
+    #[arg(long, default_value_t = pairs::OutputShape::Matrix)]
+    output_shape: pairs::OutputShape,
+    /// Half of the symmetric matrix that is printed, or both halves
+    #[arg(long, default_value_t = pairs::MatrixShape::Upper)]
+    matrix_shape: pairs::MatrixShape,
+    /// Print the matrix or pairs as a terminal table, as Markdown to paste into an issue or as
+    /// HTML shaded by score for a report. `parquet` writes every pair to stdout, which has to be
+    /// redirected to a file, and `scalar` only the score of every pair, one per line
+    #[arg(long, default_value_t = table::OutputFormat::Table)]
+    output_format: table::OutputFormat,
+    /// Decimals of the printed scores, in every command [default: every digit, 2 for
+    /// percentages]
+    #[arg(long, global = true)]
+    precision: Option<usize>,
+    /// Notation of the printed scores, in every output format but Parquet: `percent` shows
+    /// cosine similarities as percentages
+    #[arg(long, default_value_t = table::Notation::Plain)]
+    number_format: table::Notation,
+    /// Only print the score of every other document against this one, closest first: the id,
+    /// text or position of a reference document to rank candidates against
+    #[arg(long, conflicts_with_all = ["output_shape", "report_template", "interval", "pairs_out"])]
+    anchor: Option<String>,
+    /// Render the results with this Handlebars template instead of printing them, e.g. a
+    /// house-style Markdown or HTML report
+    #[arg(long, conflicts_with_all = ["output_format", "output_shape", "interval", "pairs_out"])]
+    report_template: Option<String>,
+    /// Browse the matrix in a scrollable view instead of printing it, for more documents than a
+    /// table fits: arrows move, `s` sorts by the selected row, `/` searches the documents and
+    /// Enter shows the full texts of the selected pair
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "output_format", "output_shape", "anchor", "report_template", "interval", "watch",
+            "pairs_out"
+        ]
+    )]
+    tui: bool,
+    /// Order of the pairs output [default: closest first]
+    #[arg(long)]
+    sort: Option<pairs::Sort>,
+    /// Also print a lexical similarity of every pair of the pairs output, between 0 and 1, to
+    /// see where it and the embedding score diverge
+    #[arg(long)]
+    lexical: Option<lexical::Lexical>,
+    /// Also print a hybrid score of every pair: this weight times the cosine similarity plus
+    /// the rest times the `--lexical` score
+    #[arg(long, requires = "lexical", value_parser = lexical::parse_weight)]
+    hybrid_weight: Option<f64>,
+    /// Group the pairs output by the input file of the documents, pairs within every file
+    /// before pairs across files (requires `--output-shape pairs`), or the output by the
+    /// detected language of the documents, with a summary of the scores across languages
+    #[arg(
+        long,
+        requires = "input_file",
+        conflicts_with_all = ["anchor", "report_template", "interval", "pairs_out"]
+    )]
+    group_by: Option<pairs::GroupBy>,
+    /// Languages `--group-by language` picks among, as comma-separated ISO 639-3 codes such as
+    /// `eng,fra,deu` [default: every language whatlang detects]
+    #[arg(long, value_delimiter = ',', value_parser = language::parse_language)]
+    languages: Vec<whatlang::Lang>,
+    /// Confidence between 0 and 1 below which `--group-by language` puts a document in the `und`
+    /// group instead of the language detected, as short texts are often guessed wrong
+    #[arg(long, default_value_t = 0.0, value_parser = lexical::parse_weight)]
+    min_language_confidence: f64,
+    /// Only print this many pairs (requires `--output-shape pairs`), documents with `--anchor`,
+    /// terms with `--shared-terms` or dimensions with `--explain`
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Corpus size above which only the closest `--limit` pairs (20 by default) and the
+    /// `--stats` summary are printed instead of every pair
+    #[arg(long, default_value_t = 50)]
+    max_table_documents: usize,
+    /// Print every pair however large the corpus
+    #[arg(long)]
+    full_table: bool,
+    /// Color very close pairs green and very far pairs red
+    #[arg(long, default_value_t = table::ColorChoice::Auto)]
+    color: table::ColorChoice,
+    /// Score from which a pair is colored as very close [default: closest quarter of pairs]
+    #[arg(long)]
+    close_threshold: Option<f64>,
+    /// Score from which a pair is colored as very far [default: farthest quarter of pairs]
+    #[arg(long)]
+    far_threshold: Option<f64>,
+    /// Exit with status 1 if any pair scores above this, e.g. a cosine similarity of 0.95 to
+    /// catch near-duplicates in CI
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        conflicts_with_all = ["interval", "watch", "pairs_out"]
+    )]
+    fail_if_above: Option<f64>,
+    /// Exit with status 1 if any pair scores below this
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        conflicts_with_all = ["interval", "watch", "pairs_out"]
+    )]
+    fail_if_below: Option<f64>,
+}
+
+/// Pairs printed for corpora above `--max-table-documents` when no `--limit` is given.
+const DEFAULT_TOP_PAIRS: usize = 20;
+
+/// Pairs failing `--fail-if-above` or `--fail-if-below` listed before exiting.
+const MAX_REPORTED_FAILURES: usize = 10;
+
+/// Provider-specific request options, shared by every command that embeds documents.
+#[derive(clap::Args, Debug, Clone)]
+struct ProviderArgs {
+    /// OpenAI organization to bill requests to
+    #[arg(long, env = "OPENAI_ORG_ID")]
+    openai_org: Option<String>,
+    /// OpenAI project to bill requests to
+    #[arg(long, env = "OPENAI_PROJECT_ID")]
+    openai_project: Option<String>,
+    /// Dimensions of the returned embeddings, for models that can shorten them (OpenAI
+    /// text-embedding-3 models) and `--provider mock` [default: 256 for mock]
+    #[arg(long)]
+    dimensions: Option<usize>,
+    /// What Cohere should embed the texts for
+    #[arg(long, default_value_t = CohereInputType::SearchDocument)]
+    cohere_input_type: CohereInputType,
+    /// Type of the embeddings Cohere returns [default: float]
+    #[arg(long)]
+    cohere_embedding_type: Option<CohereEmbeddingType>,
+    /// What the documents are: texts, or paths of image files embedded by a multimodal model
+    /// (Cohere embed-v4.0)
+    #[arg(long, default_value_t = images::Modality::Text)]
+    modality: images::Modality,
+    /// JSON object of texts and their vectors, which `--provider mock` embeds every text as
+    /// instead of pseudo-random vectors
+    #[arg(long)]
+    mock_fixture: Option<String>,
+}
+
+impl ProviderArgs {
+    /// Name the cache of `embedding_model` is stored under, distinct for every option that
+    /// changes the vectors.
+    fn cache_name(&self, embedding_model: &str) -> String {
+        let mut name = embedding_model.to_string();
+        if let Some(dimensions) = self.dimensions {
+            name += &format!("@{dimensions}");
+        }
+        if self.cohere_input_type != CohereInputType::SearchDocument {
+            name += &format!("@{}", self.cohere_input_type);
+        }
+        if let Some(embedding_type) = self
+            .cohere_embedding_type
+            .filter(|embedding_type| *embedding_type != CohereEmbeddingType::Float)
+        {
+            name += &format!("@{embedding_type}");
+        }
+        if self.modality != images::Modality::Text {
+            name += &format!("@{}", self.modality);
+        }
+        if let Some(mock_fixture) = &self.mock_fixture {
+            // Named by its contents, so that editing the fixture doesn't serve stale vectors
+            let contents = files::read_text(mock_fixture)
+                .unwrap_or_else(|error| panic!("Failed to read {mock_fixture}: {error}"));
+            name += &format!("@fixture-{:.12x}", Sha256::digest(contents));
+        }
+        name
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Save the pair scores of a corpus, or check that they haven't drifted since
+    Baseline {
+        #[command(subcommand)]
+        command: baseline::BaselineCommand,
+    },
+    /// Classify documents by the label of the nearest centroid of labeled training documents
+    Classify(classify::ClassifyArgs),
+    /// Print the completion script of a shell
+    Completions(completions::CompletionsArgs),
+    /// Score every pair of documents with several embedding models side by side
+    Compare(compare::CompareArgs),
+    /// Measure how closely a multilingual model aligns the translations of a parallel corpus
+    Crosslingual(crosslingual::CrosslingualArgs),
+    /// Compare the pair scores of two exported runs, e.g. to catch a silently updated model
+    Diff(diff::DiffArgs),
+    /// Evaluate embedding models against gold similarity scores
+    Eval(eval::EvalArgs),
+    /// Write a synthetic labeled corpus of template sentences across topics, for benchmarks
+    GenCorpus(gen_corpus::GenCorpusArgs),
+    /// Report test documents that are near-copies of training documents
+    Leakage(leakage::LeakageArgs),
+    /// Print the man page of the tool, or write one per command
+    Man(completions::ManArgs),
+    /// List the known embedding models of every provider, with their dimensions, token limit and
+    /// price
+    Models(models::ModelsArgs),
+    /// Score paraphrase sets within and across sets, flagging the sets the model splits up
+    Paraphrase(paraphrase::ParaphraseArgs),
+    /// Search the closest documents of a saved corpus for one or more queries
+    Query(query::QueryArgs),
+    /// Score current texts against stored reference texts and fail the items that drifted
+    Regress(regress::RegressArgs),
+    /// Evaluate embedding models on queries with known relevant documents
+    Retrieval(retrieval::RetrievalArgs),
+    /// Select documents both relevant to a query and diverse, by maximal marginal relevance
+    Select(select::SelectArgs),
+    /// Serve `POST /compare` and `POST /query` over HTTP for other services
+    Serve(serve::ServeArgs),
+    /// Split a labeled corpus into stratified folds that keep near-duplicates together
+    Split(split::SplitArgs),
+    /// Experimental: publish nearest-neighbor events for documents read from a Redis stream or Kafka topic
+    Stream(stream::StreamArgs),
+    /// Inspect the local ledger of tokens and estimated spend
+    Usage {
+        #[command(subcommand)]
+        command: ledger::UsageCommand,
+    },
 }
 
 impl Args {
-    fn input_strings(&self) -> Vec<String> {
-        let file = File::open(self.input_file.clone()).unwrap();
-        let reader = BufReader::new(file);
+    fn language_detector(&self) -> language::LanguageDetector {
+        language::LanguageDetector::new(&self.languages, self.min_language_confidence)
+    }
+
+    /// Rejects `--stats` with the output formats that can't hold it, as clap does conflicting
+    /// arguments: clap can't declare a conflict with one value of an argument.
+    fn validate(&self) -> Result<(), clap::Error> {
+        if self.command.is_some() && (self.history || self.write_results.is_some()) {
+            let argument = if self.history {
+                "--history"
+            } else {
+                "--write-results"
+            };
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!("the argument '{argument}' cannot be used with a subcommand"),
+            ));
+        }
+        // Checked here as global arguments can't conflict with those of the main command only
+        if self.deny_warnings && (self.interval.is_some() || self.watch) {
+            let argument = if self.watch { "--watch" } else { "--interval" };
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!("the argument '--deny-warnings' cannot be used with '{argument}'"),
+            ));
+        }
+        if let Some(Command::Compare(compare_args)) = &self.command {
+            compare_args.validate().map_err(|message| {
+                Args::command().error(ErrorKind::WrongNumberOfValues, message)
+            })?;
+        }
+        let format = &self.output_format;
+        if self.stats
+            && matches!(
+                format,
+                table::OutputFormat::Parquet | table::OutputFormat::Scalar
+            )
+        {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!("the argument '--stats' cannot be used with '--output-format {format}'"),
+            ));
+        }
+        if self.length_bias
+            && matches!(
+                format,
+                table::OutputFormat::Parquet | table::OutputFormat::Scalar
+            )
+        {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "the argument '--length-bias' cannot be used with '--output-format {format}'"
+                ),
+            ));
+        }
+        let finds_duplicates = self.report_duplicates || self.dedupe_inputs;
+        if !finds_duplicates && !self.duplicate_normalization.is_empty() {
+            return Err(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "the argument '--duplicate-normalization' requires '--report-duplicates' or \
+                 '--dedupe-inputs'",
+            ));
+        }
+        if matches!(format, table::OutputFormat::Parquet) && std::io::stdout().is_terminal() {
+            return Err(Args::command().error(
+                ErrorKind::InvalidValue,
+                "the argument '--output-format parquet' writes a binary file: redirect stdout to \
+                 it",
+            ));
+        }
+        if self.tui && !std::io::stdout().is_terminal() {
+            return Err(Args::command().error(
+                ErrorKind::InvalidValue,
+                "the argument '--tui' requires stdout to be a terminal",
+            ));
+        }
+        if let Some(metrics) = &self.metric_agreement {
+            if matches!(
+                format,
+                table::OutputFormat::Parquet | table::OutputFormat::Scalar
+            ) {
+                return Err(Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the argument '--metric-agreement' cannot be used with '--output-format \
+                         {format}'"
+                    ),
+                ));
+            }
+            if metric_agreement::selected(metrics).len() < 2 {
+                return Err(Args::command().error(
+                    ErrorKind::TooFewValues,
+                    "'--metric-agreement' needs at least 2 metrics to compare",
+                ));
+            }
+        }
+        if self.number_format == table::Notation::Percent
+            && !matches!(self.distance_metric, DistanceMetric::Cosine)
+        {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "the argument '--number-format percent' cannot be used with \
+                     '--distance-metric {}'",
+                    self.distance_metric
+                ),
+            ));
+        }
+        let detects_languages = matches!(self.group_by, Some(pairs::GroupBy::Language));
+        if !detects_languages && !self.languages.is_empty() {
+            return Err(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "the argument '--languages' requires '--group-by language'",
+            ));
+        }
+        if !detects_languages && self.min_language_confidence > 0.0 {
+            return Err(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "the argument '--min-language-confidence' requires '--group-by language'",
+            ));
+        }
+        if let Some(group_by) = &self.group_by {
+            if matches!(group_by, pairs::GroupBy::File)
+                && !matches!(self.output_shape, pairs::OutputShape::Pairs)
+            {
+                return Err(Args::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!("the argument '--group-by {group_by}' requires '--output-shape pairs'"),
+                ));
+            }
+            if matches!(
+                format,
+                table::OutputFormat::Parquet | table::OutputFormat::Scalar
+            ) {
+                return Err(Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the argument '--group-by' cannot be used with '--output-format {format}'"
+                    ),
+                ));
+            }
+        }
+        if let Some(lexical) = &self.lexical {
+            if self.pairs.is_none() && !matches!(self.output_shape, pairs::OutputShape::Pairs) {
+                return Err(Args::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!(
+                        "the argument '--lexical {lexical}' requires '--output-shape pairs' or \
+                         '--pairs'"
+                    ),
+                ));
+            }
+            if matches!(
+                format,
+                table::OutputFormat::Parquet | table::OutputFormat::Scalar
+            ) {
+                return Err(Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the argument '--lexical' cannot be used with '--output-format {format}'"
+                    ),
+                ));
+            }
+        }
+        if self.hybrid_weight.is_some() && !matches!(self.distance_metric, DistanceMetric::Cosine) {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "the argument '--hybrid-weight' cannot be used with '--distance-metric {}'",
+                    self.distance_metric
+                ),
+            ));
+        }
+        if self.dim_weights.is_some() && self.vector_transform == whitening::VectorTransform::Whiten
+        {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--dim-weights' cannot be used with '--vector-transform whiten', \
+                 whose dimensions are principal components",
+            ));
+        }
+        if self.explain.is_some() && !explain::explains(&self.distance_metric) {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "the argument '--explain' cannot be used with '--distance-metric {}', which \
+                     isn't a sum over dimensions",
+                    self.distance_metric
+                ),
+            ));
+        }
+        if self.vector_precision == float32::VectorPrecision::F32
+            && self.chunk_aggregation == chunking::Aggregation::MaxSim
+        {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--vector-precision f32' cannot be used with '--chunk-aggregation \
+                 max-sim'",
+            ));
+        }
+        let modality = self.provider_args.modality;
+        if modality != images::Modality::Text {
+            if self.provider == Provider::Openai {
+                return Err(Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the argument '--modality {modality}' cannot be used with '--provider \
+                         openai', which only embeds texts"
+                    ),
+                ));
+            }
+            let text_argument = if self.chunk_size.is_some() {
+                Some("--chunk-size")
+            } else if !self.preprocess.is_empty() {
+                Some("--preprocess")
+            } else if self.template.is_some() || !self.input_template.is_empty() {
+                Some("--template")
+            } else {
+                self.lexical.as_ref().map(|_| "--lexical")
+            };
+            if let Some(text_argument) = text_argument {
+                return Err(Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the argument '--modality {modality}' cannot be used with \
+                         '{text_argument}', which applies to texts"
+                    ),
+                ));
+            }
+        }
+        if self.provider_args.mock_fixture.is_some() && self.provider != Provider::Mock {
+            return Err(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "the argument '--mock-fixture' requires '--provider mock'",
+            ));
+        }
+        if let Some((input, _)) = self
+            .input_template
+            .iter()
+            .find(|(input, _)| !self.input_file.contains(input))
+        {
+            return Err(Args::command().error(
+                ErrorKind::InvalidValue,
+                format!("the argument '--input-template' names {input}, which isn't an '-i' input"),
+            ));
+        }
+        if self.watch && self.input_file.len() > 1 {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--watch' cannot be used with several '-i' inputs",
+            ));
+        }
+        if self.anchor.is_some() && matches!(format, table::OutputFormat::Parquet) {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--anchor' cannot be used with '--output-format parquet'",
+            ));
+        }
+        Ok(())
+    }
+
+    /// `texts` as embedded, by the `--input-template` of their input among `inputs` or the
+    /// `--template`, `None` without templates.
+    fn templated(&self, texts: &[String], inputs: &[&str]) -> Option<Vec<String>> {
+        (self.template.is_some() || !self.input_template.is_empty())
+            .then(|| template::apply(texts, inputs, self.template.as_ref(), &self.input_template))
+    }
 
-        serde_json::from_reader(reader).unwrap()
+    fn embedding_cache(&self) -> EmbeddingCache {
+        let cache = match &self.checkpoint {
+            Some(checkpoint) => EmbeddingCache::open_at(checkpoint.into()),
+            None => EmbeddingCache::open(
+                &self.provider,
+                &self.provider_args,
+                self.embedding_model.as_ref().unwrap(),
+            ),
+        };
+        cache.with_refresh(self.refresh)
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+            .unwrap_or_else(|| self.provider.max_batch_size())
+    }
+
+    /// Ids, texts, tenants and input files of the input documents. Documents from a JSON file
+    /// are identified by position and those of a directory by file name, prefixed by their input
+    /// when there are several. Only JSON input files record tenants, and SQL queries have no
+    /// input files.
+    async fn input_documents(&self) -> InputDocuments {
+        let (input_ids, input_strings, tenants, sources) = match (&self.input_sql, &self.db) {
+            (Some(input_sql), Some(db)) => {
+                let (input_ids, input_strings) =
+                    sql::read_documents(db, input_sql).await.into_iter().unzip();
+                (input_ids, input_strings, vec![], vec![])
+            }
+            _ => {
+                let (mut input_ids, mut input_strings, mut tenants, mut sources) =
+                    (vec![], vec![], vec![], vec![]);
+                for input_file in &self.input_file {
+                    let (ids, strings, file_tenants) = read_input_file(input_file);
+                    sources.extend(std::iter::repeat_n(input_file.clone(), ids.len()));
+                    input_ids.extend(ids.into_iter().map(|id| match self.input_file.len() {
+                        1 => id,
+                        _ => format!("{input_file}:{id}"),
+                    }));
+                    input_strings.extend(strings);
+                    tenants.extend(file_tenants);
+                }
+                (input_ids, input_strings, tenants, sources)
+            }
+        };
+        (
+            input_ids,
+            preprocess::apply(&self.preprocess, input_strings),
+            tenants,
+            sources,
+        )
     }
 }
 
-#[tokio::main]
-async fn main() {
-    // Parse command-line arguments
-    let args = Args::parse();
+/// Prints the pairs output of `pairs`, with the `--lexical` and `--hybrid-weight` columns.
+fn print_pair_rows(
+    args: &Args,
+    input_strings: &[String],
+    pairs: &[pairs::Pair],
+    sources: Option<&[String]>,
+) {
+    let Some(lexical) = &args.lexical else {
+        pairs::print_pairs(
+            input_strings,
+            pairs,
+            sources,
+            &[],
+            &args.distance_metric,
+            &args.output_format,
+        );
+        return;
+    };
+    let scorer = lexical::LexicalScorer::new(lexical, input_strings);
+    let columns = lexical::LexicalColumns::new(&scorer, pairs, args.hybrid_weight);
+    pairs::print_pairs(
+        input_strings,
+        pairs,
+        sources,
+        &columns.columns(lexical),
+        &args.distance_metric,
+        &args.output_format,
+    );
+    lexical::print_agreement(pairs, &columns, lexical, &args.distance_metric);
+}
+
+/// Ids, texts, tenants and input files of documents.
+type InputDocuments = (Vec<String>, Vec<String>, Vec<Option<String>>, Vec<String>);
+
+/// Ids, texts and tenants of the documents of an input file or directory.
+fn read_input_file(input_file: &str) -> (Vec<String>, Vec<String>, Vec<Option<String>>) {
+    if Path::new(input_file).is_dir() {
+        let (input_ids, input_strings): (Vec<_>, Vec<_>) =
+            files::read_directory(input_file).into_iter().unzip();
+        let tenants = vec![None; input_ids.len()];
+        return (input_ids, input_strings, tenants);
+    }
+    let (input_strings, tenants): (Vec<_>, _) = files::read_records(input_file).into_iter().unzip();
+    (
+        (0..input_strings.len()).map(|i| i.to_string()).collect(),
+        input_strings,
+        tenants,
+    )
+}
 
-    let input_strings = args.input_strings();
+async fn embed(
+    provider: &Provider,
+    provider_args: &ProviderArgs,
+    embedding_model: &str,
+    input_strings: Vec<String>,
+) -> Vec<Embedding> {
+    embed_versioned(provider, provider_args, embedding_model, input_strings)
+        .await
+        .0
+}
 
-    let documents = match args.provider {
+/// Embeds `input_strings` and returns the snapshot of the model that embedded them, when the
+/// provider reports it.
+async fn embed_versioned(
+    provider: &Provider,
+    provider_args: &ProviderArgs,
+    embedding_model: &str,
+    input_strings: Vec<String>,
+) -> (Vec<Embedding>, Option<String>) {
+    if providers::offline() {
+        eprintln!(
+            "{} documents aren't cached for {provider} model {embedding_model} and --offline \
+             forbids embedding them",
+            input_strings.len()
+        );
+        fail("--offline forbids embedding documents");
+    }
+
+    let started = Instant::now();
+    let document_hashes = audit::hashes(&input_strings);
+    tracing::info!(
+        %provider,
+        model = embedding_model,
+        documents = input_strings.len(),
+        "Embedding batch"
+    );
+    // Only cloned when failed batches are written to the dead-letter file
+    let batch = dead_letter::enabled().then(|| input_strings.clone());
+    let embeddings = match provider {
+        Provider::Openai if provider_args.modality != images::Modality::Text => {
+            eprintln!(
+                "--modality {} requires --provider cohere, OpenAI only embeds texts",
+                provider_args.modality
+            );
+            fail("OpenAI only embeds texts");
+        }
         Provider::Openai => {
-            let openai_api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
-            let openai_client = rig::providers::openai::Client::new(&openai_api_key);
+            let openai_api_key = keys::resolve(provider);
+            let openai_client = OpenaiClient::new(&openai_api_key)
+                .with_organization(provider_args.openai_org.clone())
+                .with_project(provider_args.openai_project.clone())
+                .with_dimensions(provider_args.dimensions);
 
-            let model = openai_client.embedding_model(&args.embedding_model);
-            model
-                .embed_documents(input_strings.clone().into_iter())
+            openai_client
+                .embed_documents(embedding_model, input_strings)
                 .await
         }
+        Provider::Mock => MockClient::new(provider_args.dimensions)
+            .with_fixture(provider_args.mock_fixture.as_deref().map(files::read_json))
+            .embed_documents(embedding_model, input_strings),
         Provider::Cohere => {
-            let cohere_api_key = env::var("COHERE_API_HERE").expect("COHERE_API_HERE not set");
-            let cohere_client = rig::providers::cohere::Client::new(&cohere_api_key);
+            let cohere_api_key = keys::resolve(provider);
+            let cohere_client = CohereClient::new(&cohere_api_key)
+                .with_embedding_type(provider_args.cohere_embedding_type)
+                .with_modality(provider_args.modality);
 
-            let model = cohere_client.embedding_model(&args.embedding_model, "search_document");
-            model
-                .embed_documents(input_strings.clone().into_iter())
+            cohere_client
+                .embed_documents(
+                    embedding_model,
+                    provider_args.cohere_input_type,
+                    input_strings,
+                )
                 .await
         }
+    };
+
+    let response = match embeddings {
+        Ok(response) => response,
+        Err(error) => {
+            let message = error.to_string();
+            audit::record(
+                provider,
+                embedding_model,
+                document_hashes,
+                None,
+                Some(message),
+            );
+            tracing::error!(
+                "Failed to embed documents with {provider} model {embedding_model}: {error}"
+            );
+            if let Some(batch) = batch {
+                dead_letter::record(provider, embedding_model, batch, &error);
+            }
+            fail(&error.to_string());
+        }
+    };
+    tracing::debug!(
+        %provider,
+        model = embedding_model,
+        tokens = response.tokens,
+        model_version = response.model_version,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Embedded batch"
+    );
+
+    ledger::record(provider, embedding_model, response.tokens);
+    audit::record(
+        provider,
+        embedding_model,
+        document_hashes,
+        Some(response.tokens),
+        None,
+    );
+    (response.embeddings, response.model_version)
+}
+
+/// Ends the run after a failure that was already reported, or only fails the request being
+/// answered by `serve`.
+fn fail(message: &str) -> ! {
+    if serve::serving() {
+        panic!("{message}");
+    }
+    std::process::exit(1);
+}
+
+/// Scales every vector to unit length, warning about those that weren't.
+fn normalize_documents(documents: &mut [Embedding]) {
+    let mut rescaled = 0;
+    for document in documents {
+        let norm = document.vec.iter().map(|value| value * value).sum::<f64>();
+        if (norm - 1.0).abs() > 1e-9 {
+            rescaled += 1;
+        }
+        metrics::normalize(&mut document.vec);
+    }
+
+    if rescaled > 0 {
+        warnings::warn(format!(
+            "--normalize rescaled {rescaled} vectors to unit length"
+        ));
+    }
+}
+
+/// Fits `--vector-transform` on the documents and applies it to them and to `chunks`.
+fn transform_documents<'a>(
+    args: &Args,
+    documents: &mut [Embedding],
+    chunks: impl IntoIterator<Item = &'a mut Embedding>,
+) {
+    if args.vector_transform == whitening::VectorTransform::None || documents.is_empty() {
+        return;
+    }
+    let vectors = documents
+        .iter()
+        .map(|document| document.vec.as_slice())
+        .collect::<Vec<_>>();
+    let fitted = whitening::Fitted::new(&args.vector_transform, &vectors, args.whiten_dims.into());
+    for embedding in documents.iter_mut() {
+        fitted.apply(&mut embedding.vec);
+    }
+    for embedding in chunks {
+        fitted.apply(&mut embedding.vec);
+    }
+    if let Some(warning) = whitening::collapse_warning(
+        &args.vector_transform,
+        args.whiten_dims.into(),
+        documents.len(),
+    ) {
+        warnings::warn(warning);
+    }
+    tracing::info!(
+        transform = %args.vector_transform,
+        dimensions = fitted.dimensions(),
+        "Vectors transformed"
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    // Parse command-line arguments
+    let args = Args::parse_from(config::args());
+    if let Err(error) = args.validate() {
+        error.exit();
+    }
+    logging::init(args.verbose, args.log_format);
+    metrics::set_minkowski_p(args.minkowski_p);
+    if let Some(dim_weights) = &args.dim_weights {
+        metrics::set_dim_weights(files::read_json(dim_weights));
+    }
+    table::set_number_format(args.precision, args.number_format.clone());
+    labels::set_truncation(labels::Truncation {
+        width: args.label_width.map(usize::from),
+        word_boundary: args.label_word_boundary,
+        disabled: args.no_truncate,
+    });
+    providers::set_offline(args.offline);
+    if let Some(data_dir) = &args.data_dir {
+        ledger::set_data_dir(data_dir.into());
+    }
+    if let Some(shared_cache) = &args.shared_cache {
+        shared_cache::set_url(shared_cache.clone());
+    }
+    if let Some(audit_log) = &args.audit_log {
+        audit::set_audit_log(audit_log.into());
+    }
+    if let Some(dead_letter) = &args.dead_letter {
+        dead_letter::set_dead_letter(dead_letter.into());
+    }
+    if let Some(allowed_hosts) = &args.allowed_hosts {
+        allowlist::set_allowed_hosts(allowed_hosts.clone());
+    }
+    http::set_http_options(http::HttpOptions {
+        timeout: args.timeout.map(std::time::Duration::from_secs),
+        proxy: args.proxy.clone(),
+    });
+    if !args.api_key.is_empty() {
+        keys::set_api_keys(args.api_key.clone());
+        if let Some(provider) = &args.store_key {
+            keys::store(provider);
+        }
+    }
+
+    let deny_warnings = args.deny_warnings;
+    if let Some(command) = args.command {
+        match command {
+            Command::Baseline { command } => baseline::run(command).await,
+            Command::Classify(classify_args) => classify::run(classify_args).await,
+            Command::Completions(completions_args) => completions::run(completions_args),
+            Command::Compare(compare_args) => compare::run(compare_args).await,
+            Command::Crosslingual(crosslingual_args) => crosslingual::run(crosslingual_args).await,
+            Command::Diff(diff_args) => diff::run(diff_args),
+            Command::Eval(eval_args) => eval::run(eval_args).await,
+            Command::GenCorpus(gen_corpus_args) => gen_corpus::run(gen_corpus_args),
+            Command::Leakage(leakage_args) => leakage::run(leakage_args).await,
+            Command::Man(man_args) => completions::run_man(man_args),
+            Command::Models(models_args) => models::run(models_args),
+            Command::Paraphrase(paraphrase_args) => paraphrase::run(paraphrase_args).await,
+            Command::Query(query_args) => query::run(query_args).await,
+            Command::Regress(regress_args) => regress::run(regress_args).await,
+            Command::Retrieval(retrieval_args) => retrieval::run(retrieval_args).await,
+            Command::Select(select_args) => select::run(select_args).await,
+            Command::Serve(serve_args) => serve::run(serve_args).await,
+            Command::Split(split_args) => split::run(split_args).await,
+            Command::Stream(stream_args) => stream::run(stream_args).await,
+            Command::Usage { command } => ledger::run(command),
+        }
+        if deny_warnings {
+            warnings::deny();
+        }
+        return;
+    }
+
+    if args.input_file.is_empty() && args.input_sql.is_none() && args.embeddings.is_none() {
+        return;
     }
-    .unwrap();
+
+    if let Some(interval) = args.interval {
+        monitor::run(&args, interval).await;
+        return;
+    }
+    if args.watch {
+        watch::run(&args).await;
+        return;
+    }
+
+    if let Some(budget) = args.deadline {
+        deadline::set_deadline(budget);
+    }
+    analyze(&args).await;
+    if args.deny_warnings {
+        warnings::deny();
+    }
+}
+
+/// Embeds or loads the documents and runs the analysis selected by `args` on them, by default
+/// printing the distance matrix, then what the run cost each tenant of the documents.
+async fn analyze(args: &Args) {
+    let mut tenant_costs = None;
+    analyze_documents(args, &mut tenant_costs).await;
+    if let Some(tenant_costs) = tenant_costs {
+        let stdout_holds_results = args.report_template.is_some()
+            || matches!(
+                args.output_format,
+                table::OutputFormat::Parquet | table::OutputFormat::Scalar
+            );
+        tenants::print_report(&tenant_costs, stdout_holds_results);
+    }
+}
+
+/// [analyze], setting `tenant_costs` once the documents are embedded if they have tenants.
+async fn analyze_documents(args: &Args, tenant_costs: &mut Option<Vec<tenants::TenantCost>>) {
+    let started_at = chrono::Utc::now();
+    let started = Instant::now();
+    // The chunks of every document, kept to score pairs of documents by their closest chunks
+    let mut chunk_embeddings = None::<Vec<Vec<Embedding>>>;
+    // Snapshot of the model that embedded the documents, when the provider reports it
+    let mut model_version = None::<String>;
+    let (input_ids, input_strings, input_sources, mut documents) = match &args.embeddings {
+        Some(embeddings) => {
+            let documents = embedding_file::load(embeddings);
+            let input_ids = (0..documents.len()).map(|i| i.to_string()).collect();
+            let input_strings = documents
+                .iter()
+                .map(|document| document.document.clone())
+                .collect();
+            (input_ids, input_strings, vec![], documents)
+        }
+        None => {
+            let (input_ids, input_strings, tenants, input_sources) = args.input_documents().await;
+            if let Err(error) = images::check(&input_strings, args.provider_args.modality) {
+                eprintln!("{error}");
+                fail(&error);
+            }
+            let embedding_model = args.embedding_model.as_ref().unwrap();
+            let chunked = args
+                .chunk_size
+                .map(|size| chunking::split(&input_strings, size, args.chunk_overlap));
+            let texts = chunked
+                .as_ref()
+                .map_or(&input_strings, |(chunks, _)| chunks);
+            let inputs = match &chunked {
+                Some((_, ranges)) => ranges
+                    .iter()
+                    .zip(&input_sources)
+                    .flat_map(|(range, input)| std::iter::repeat_n(input.as_str(), range.len()))
+                    .collect(),
+                None => input_sources.iter().map(String::as_str).collect::<Vec<_>>(),
+            };
+            // Embedded as their template, but still named by their text
+            let templated = args.templated(texts, &inputs);
+            let embedded_texts = templated.as_ref().unwrap_or(texts);
+            let duplicate_groups = (args.report_duplicates || args.dedupe_inputs)
+                .then(|| duplicates::groups(embedded_texts, &args.duplicate_normalization));
+            if let (true, Some(groups)) = (args.report_duplicates, &duplicate_groups) {
+                duplicates::print_report(groups, texts, &args.duplicate_normalization);
+            }
+            // Embedded as the first document of their group, but still named by their text
+            let deduplicated = duplicate_groups
+                .filter(|_| args.dedupe_inputs)
+                .map(|groups| duplicates::representatives(embedded_texts, &groups));
+            let embedded_texts = deduplicated.as_ref().unwrap_or(embedded_texts);
+
+            let mut cache = args.embedding_cache();
+            if args.dry_run {
+                estimate::print_report(&[estimate::Estimate {
+                    provider: args.provider.clone(),
+                    model: embedding_model.clone(),
+                    documents: embedded_texts.len(),
+                    uncached: cache.uncached(embedded_texts),
+                }]);
+                return;
+            }
+
+            // Checked before embedding, to charge the embedded texts to the tenants having them
+            let uncached = tenants
+                .iter()
+                .any(Option::is_some)
+                .then(|| cache.uncached(embedded_texts));
+            let mut embeddings = cache
+                .embed_by_deadline(
+                    &args.provider,
+                    &args.provider_args,
+                    embedding_model,
+                    embedded_texts,
+                    args.batch_size(),
+                )
+                .await;
+            if let Some(mut uncached) = uncached {
+                // Texts the deadline left unembedded weren't paid for
+                let embedded = embeddings
+                    .iter()
+                    .flatten()
+                    .map(|embedding| &embedding.document)
+                    .collect::<HashSet<_>>();
+                uncached.retain(|text| embedded.contains(text));
+                let documents = (0..input_strings.len())
+                    .map(|i| match &chunked {
+                        Some((_, ranges)) => &embedded_texts[ranges[i].clone()],
+                        None => &embedded_texts[i..=i],
+                    })
+                    .collect::<Vec<_>>();
+                *tenant_costs = Some(tenants::attribute(
+                    &args.provider,
+                    embedding_model,
+                    &tenants,
+                    &documents,
+                    &uncached,
+                ));
+            }
+            model_version = cache.latest_version().map(str::to_string);
+            if templated.is_some() || deduplicated.is_some() {
+                for (embedding, text) in embeddings.iter_mut().zip(texts) {
+                    if let Some(embedding) = embedding {
+                        embedding.document = text.clone();
+                    }
+                }
+            }
+
+            let ranges = match &chunked {
+                Some((_, ranges)) => ranges.clone(),
+                None => (0..input_strings.len()).map(|i| i..i + 1).collect(),
+            };
+            let (kept, embeddings, ranges) = deadline::complete_documents(&ranges, embeddings);
+            let (input_ids, input_strings, input_sources) = if kept.len() < input_strings.len() {
+                warnings::warn(format!(
+                    "--deadline reached: {} of {} documents weren't embedded and are left out",
+                    input_strings.len() - kept.len(),
+                    input_strings.len()
+                ));
+                let keep = |values: &[String]| {
+                    kept.iter()
+                        .filter_map(|i| values.get(*i).cloned())
+                        .collect::<Vec<_>>()
+                };
+                (keep(&input_ids), keep(&input_strings), keep(&input_sources))
+            } else {
+                (input_ids, input_strings, input_sources)
+            };
+            let documents = match &chunked {
+                Some(_) => {
+                    if args.chunk_aggregation == chunking::Aggregation::MaxSim {
+                        let chunks = ranges
+                            .iter()
+                            .map(|range| embeddings[range.clone()].to_vec());
+                        chunk_embeddings = Some(chunks.collect());
+                    }
+                    chunking::mean_pool(&input_strings, &embeddings, &ranges)
+                }
+                None => embeddings,
+            };
+            (input_ids, input_strings, input_sources, documents)
+        }
+    };
+    tracing::info!(
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Embedded documents"
+    );
+    if args.timings {
+        eprintln!("Embedding: {:.2?}", started.elapsed());
+    }
+
+    if let Some(save_embeddings) = &args.save_embeddings {
+        let (exported, metadata) = match args.privacy_epsilon {
+            Some(epsilon) => {
+                let mut noisy = documents.clone();
+                let metadata = privacy::add_noise(&mut noisy, epsilon, args.privacy_delta);
+                (Cow::Owned(noisy), metadata)
+            }
+            None => (Cow::Borrowed(documents.as_slice()), HashMap::new()),
+        };
+        embedding_file::save(save_embeddings, &exported, args.storage_type, metadata)
+            .unwrap_or_else(|error| panic!("Failed to write {save_embeddings}: {error}"));
+        if args.storage_type != embedding_file::StorageType::F64 {
+            embedding_file::report_storage_error(
+                &documents,
+                args.storage_type,
+                &args.distance_metric,
+            );
+        }
+    }
+
+    if args.normalize {
+        normalize_documents(&mut documents);
+        for chunk in chunk_embeddings.iter_mut().flatten().flatten() {
+            metrics::normalize(&mut chunk.vec);
+        }
+    }
+    transform_documents(
+        args,
+        &mut documents,
+        chunk_embeddings.iter_mut().flatten().flatten(),
+    );
+    let dimensions = embedding_file::dimensions(&documents);
+    tracing::info!(documents = documents.len(), dimensions, "Embeddings ready");
+    metrics::set_fast(args.fast || metrics::fast_by_default(documents.len(), dimensions));
+    metrics::check_dim_weights(dimensions);
+    if let Some(manifest) = &args.manifest {
+        manifest::Manifest::new(
+            args,
+            started_at,
+            model_version.as_deref(),
+            &input_strings,
+            dimensions,
+        )
+        .write(manifest);
+    }
+
+    if let Some(k) = args.clusters {
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let assignments = cluster::kmeans(&vectors, k, &mut rng);
+
+        cluster::print_clusters(
+            &input_strings,
+            &vectors,
+            &assignments,
+            &args.distance_metric,
+        );
+        return;
+    }
+
+    if let Some(labels) = &args.labels {
+        let labels = cluster::read_labels(labels);
+        assert_eq!(
+            labels.len(),
+            input_strings.len(),
+            "--labels must list one label per input document"
+        );
+        let names = labels.iter().unique().cloned().collect::<Vec<_>>();
+        let assignments = labels
+            .iter()
+            .map(|label| names.iter().position(|name| name == label).unwrap())
+            .collect::<Vec<_>>();
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        cluster::print_quality(&names, &vectors, &assignments, &args.distance_metric);
+        return;
+    }
+
+    if let Some(count) = args.outliers {
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let scores = outliers::centroid_scores(&vectors, &args.distance_metric);
+        outliers::print_outliers(&input_strings, &scores, count, &args.distance_metric);
+        return;
+    }
+
+    if let Some(pair) = &args.shared_terms {
+        let (i, j) = terms::resolve("--shared-terms", pair, &input_ids, &input_strings);
+        let score = args
+            .distance_metric
+            .distance(&documents[i].vec, &documents[j].vec);
+        terms::print_shared_terms(
+            (i, j),
+            &input_strings,
+            score,
+            &args.distance_metric,
+            args.limit.unwrap_or(terms::DEFAULT_SHARED_TERMS),
+        );
+        return;
+    }
+
+    if let Some(pair) = &args.explain {
+        let (i, j) = terms::resolve("--explain", pair, &input_ids, &input_strings);
+        explain::print_explanation(
+            (i, j),
+            (&documents[i].vec, &documents[j].vec),
+            &input_strings,
+            &args.distance_metric,
+            args.limit.unwrap_or(explain::DEFAULT_EXPLAINED_DIMENSIONS),
+        );
+        return;
+    }
+
+    if let Some(pairs_file) = &args.pairs {
+        let listed = pairs::read_pairs(pairs_file, &input_ids, &input_strings);
+        let mut scored = pairs::score_pairs(&listed, &documents, &args.distance_metric);
+        // Listed order for `scalar` unless sorted, so that a script knows which line is which
+        // pair, closest first otherwise
+        if args.sort.is_some() || !matches!(args.output_format, table::OutputFormat::Scalar) {
+            pairs::sort_pairs(&mut scored, &args.distance_metric, args.sort.as_ref());
+        }
+        if let Some(limit) = args.limit {
+            scored.truncate(limit);
+        }
+        match args.output_format {
+            table::OutputFormat::Parquet => pairs::write_parquet(
+                BufWriter::new(std::io::stdout()),
+                &scored,
+                &args.distance_metric,
+            )
+            .unwrap_or_else(|error| panic!("Failed to write Parquet: {error}")),
+            table::OutputFormat::Scalar => {
+                for pair in scored {
+                    println!("{}", table::format_score(pair.score));
+                }
+            }
+            _ => print_pair_rows(args, &input_strings, &scored, None),
+        }
+        return;
+    }
+
+    if args.dendrogram {
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let Some(tree) = hierarchy::agglomerate(&vectors, &args.distance_metric, &args.linkage)
+        else {
+            println!("No documents to cluster");
+            return;
+        };
+
+        println!("{}", hierarchy::dendrogram(&tree, &input_strings));
+        if let Some(tree_out) = &args.tree_out {
+            let contents = match args.tree_format {
+                hierarchy::TreeFormat::Newick => hierarchy::newick(&tree, &input_strings),
+                hierarchy::TreeFormat::Json => hierarchy::json(&tree, &input_strings).to_string(),
+            };
+            std::fs::write(files::long_path(tree_out), contents).unwrap();
+        }
+        return;
+    }
+
+    if let Some(projection) = &args.project {
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let points = projection.project(&vectors, &mut rng);
+
+        match &args.project_out {
+            Some(project_out) => {
+                let file = File::create(files::long_path(project_out)).unwrap();
+                projection::write_points(file, &input_strings, &points)
+            }
+            None => projection::write_points(std::io::stdout(), &input_strings, &points),
+        }
+        return;
+    }
+
+    if let Some(subspaces) = args.pq {
+        assert!(
+            documents.len() > 1,
+            "PQ analysis needs at least two documents"
+        );
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let quantizer =
+            pq::ProductQuantizer::train(&vectors, subspaces, args.pq_centroids, &mut rng);
+        let queries = search::queries(vectors.len(), args.verify_sample, &mut rng);
+
+        pq::print_report(
+            &vectors,
+            &queries,
+            &quantizer,
+            args.recall_k,
+            &args.distance_metric,
+        );
+        return;
+    }
+
+    if let Some(partitions) = args.ivf {
+        assert!(
+            documents.len() > 1,
+            "IVF simulation needs at least two documents"
+        );
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let index = ivf::InvertedFile::build(&vectors, partitions, &mut rng);
+        let queries = search::queries(vectors.len(), args.verify_sample, &mut rng);
+
+        ivf::print_report(
+            &vectors,
+            &queries,
+            &index,
+            &args.nprobe,
+            args.recall_k,
+            &args.distance_metric,
+        );
+        return;
+    }
+
+    if let Some(dimensions) = &args.truncate_dims {
+        assert!(
+            documents.len() > 1,
+            "Truncation analysis needs at least two documents"
+        );
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let queries = search::queries(vectors.len(), args.verify_sample, &mut rng);
+
+        truncation::print_report(
+            &vectors,
+            &queries,
+            dimensions,
+            args.normalize,
+            args.recall_k,
+            &args.distance_metric,
+        );
+        return;
+    }
+
+    if let Some(quantizations) = &args.quantize {
+        assert!(
+            documents.len() > 1,
+            "Quantization analysis needs at least two documents"
+        );
+        let vectors = documents
+            .into_iter()
+            .map(|document| document.vec)
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let queries = search::queries(vectors.len(), args.verify_sample, &mut rng);
+
+        quantization::print_report(
+            &vectors,
+            &queries,
+            quantizations,
+            args.recall_k,
+            &args.distance_metric,
+        );
+        return;
+    }
+
+    if let Some(pairs_out) = &args.pairs_out {
+        let file = File::create(files::long_path(pairs_out))
+            .unwrap_or_else(|error| panic!("Failed to create {pairs_out}: {error}"));
+        blockwise::write_pairs(
+            BufWriter::new(file),
+            &input_ids,
+            &documents,
+            &args.distance_metric,
+            args.block_size,
+        )
+        .unwrap_or_else(|error| panic!("Failed to write {pairs_out}: {error}"));
+        return;
+    }
+
+    let agreements = args
+        .metric_agreement
+        .as_ref()
+        .map(|metrics| metric_agreement::measure(&documents, &metric_agreement::selected(metrics)));
+    let vectors32 = (args.vector_precision == float32::VectorPrecision::F32).then(|| {
+        let f64_bytes = float32::f64_bytes(&documents);
+        let vectors = float32::Vectors32::take(&mut documents, dimensions);
+        if args.timings {
+            eprintln!(
+                "Vectors: {} in f32 instead of {} in f64, {} saved",
+                float32::format_bytes(vectors.bytes()),
+                float32::format_bytes(f64_bytes),
+                float32::format_bytes(f64_bytes - vectors.bytes())
+            );
+        }
+        vectors
+    });
 
     let mut dataframe = DataFrame::set_headers(input_strings.clone());
+    let mut matrix = vec![vec![0.0; input_strings.len()]; input_strings.len()];
 
-    documents
-        .into_iter()
-        .enumerate()
-        .combinations_with_replacement(2)
-        .map(|mut combination| {
-            combination.sort_by(|(i, _), (j, _)| Ord::cmp(i, j)); // Sort the pair to make (1, 2) and (2, 1) identical
-            combination
+    // Score the pairs in parallel, then fill the table sequentially in the original row order
+    let pairs = (0..documents.len())
+        .flat_map(|i| (i..documents.len()).map(move |j| (i, j)))
+        .collect::<Vec<_>>();
+    let started = Instant::now();
+    let bar = progress::bar(pairs.len(), "pairs").with_message("Scoring");
+    let mut distances = pairs
+        .par_iter()
+        .progress_with(bar.clone())
+        .map(|&(i, j)| match &chunk_embeddings {
+            Some(chunks) => chunking::max_sim(&chunks[i], &chunks[j], &args.distance_metric),
+            None => match &vectors32 {
+                Some(vectors) => args
+                    .distance_metric
+                    .distance_f32(vectors.row(i), vectors.row(j)),
+                None => args
+                    .distance_metric
+                    .distance(&documents[i].vec, &documents[j].vec),
+            },
         })
-        .unique_by(|pair| {
-            let (i, _) = pair.first().unwrap();
-            let (j, _) = pair.last().unwrap();
+        .collect::<Vec<_>>();
+    bar.finish_and_clear();
+    tracing::info!(
+        pairs = pairs.len(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Scored pairs"
+    );
+    if args.timings {
+        eprintln!("Scoring {} pairs: {:.2?}", pairs.len(), started.elapsed());
+    }
+    let lengths =
+        (args.length_bias || args.length_correction).then(|| length_bias::lengths(&input_strings));
+    let bias = lengths
+        .as_ref()
+        .filter(|_| args.length_bias)
+        .map(|lengths| length_bias::measure(&pairs, &distances, lengths));
+    let mut corrected_bias = None;
+    if let (true, Some(lengths)) = (args.length_correction, &lengths) {
+        length_bias::correct(&pairs, &mut distances, lengths);
+        if args.length_bias {
+            corrected_bias = length_bias::measure(&pairs, &distances, lengths);
+        }
+    }
 
-            (*i, *j)
-        })
-        .for_each(|pair| {
-            let (i, first) = pair.first().unwrap();
-            let (j, second) = pair.last().unwrap();
-
-            let distance = match args.distance_metric {
-                DistanceMetric::Cosine => cosine_similarity(&first.vec, &second.vec, false),
-                DistanceMetric::L2 => euclidean_distance(&first.vec, &second.vec),
-                DistanceMetric::Dot => dot_product_distance(&first.vec, &second.vec),
-                DistanceMetric::Manhattan => manhattan_distance(&first.vec, &second.vec),
+    // Pairs come row by row, so that every row of either half is filled from left to right
+    pairs.iter().zip(distances).for_each(|((i, j), distance)| {
+        for (row, column) in [(i, j), (j, i)].into_iter().dedup() {
+            if args.matrix_shape.shows(*row, *column) {
+                dataframe.add_row_header(row, &documents[*row].document);
+                dataframe.add_row_distances(row, column, distance);
+            }
+        }
+        matrix[*i][*j] = distance;
+        matrix[*j][*i] = distance;
+    });
+
+    let printed = args.report_template.is_none()
+        && args.anchor.is_none()
+        && !args.tui
+        && matches!(
+            args.output_format,
+            table::OutputFormat::Table | table::OutputFormat::Markdown | table::OutputFormat::Html
+        );
+    let capped = printed && !args.full_table && input_strings.len() > args.max_table_documents;
+    if capped {
+        warnings::warn(format!(
+            "{} documents exceed --max-table-documents {}: printing the top pairs and a summary \
+             (--full-table prints every pair)",
+            input_strings.len(),
+            args.max_table_documents
+        ));
+    }
+
+    match (&args.output_format, &args.output_shape) {
+        _ if args.tui => tui::run(&input_strings, &matrix, &args.distance_metric)
+            .unwrap_or_else(|error| panic!("Failed to run the TUI: {error}")),
+        _ if args.anchor.is_some() => {
+            let anchor = anchor::resolve(args.anchor.as_ref().unwrap(), &input_ids, &input_strings);
+            let mut scores =
+                anchor::scores(anchor, &matrix, &args.distance_metric, args.sort.as_ref());
+            if let Some(limit) = args.limit {
+                scores.truncate(limit);
+            }
+            anchor::print_scores(
+                anchor,
+                &input_strings,
+                &scores,
+                &args.distance_metric,
+                &args.output_format,
+            );
+        }
+        _ if args.report_template.is_some() => {
+            let template = args.report_template.as_ref().unwrap();
+            let mut pairs = pairs::sorted_pairs(&matrix, &args.distance_metric, args.sort.as_ref());
+            let scores = pairs.iter().map(|pair| pair.score).collect::<Vec<_>>();
+            if let Some(limit) = args.limit {
+                pairs.truncate(limit);
+            }
+            let report = report::Report {
+                metric: args.distance_metric.to_string(),
+                higher_is_closer: args.distance_metric.higher_is_closer(),
+                provider: args.embeddings.is_none().then(|| args.provider.to_string()),
+                model: args.embedding_model.as_deref(),
+                generated_at: started_at.to_rfc3339(),
+                documents: input_ids
+                    .iter()
+                    .zip(&input_strings)
+                    .enumerate()
+                    .map(|(index, (id, text))| report::ReportDocument { index, id, text })
+                    .collect(),
+                pairs: pairs
+                    .iter()
+                    .map(|pair| report::ReportPair::new(pair, &input_strings))
+                    .collect(),
+                matrix: &matrix,
+                summary: report::Summary::new(&scores),
+            };
+            let rendered = report::render(template, &report)
+                .unwrap_or_else(|error| panic!("Failed to render {template}: {error}"));
+            print!("{rendered}");
+        }
+        (table::OutputFormat::Parquet, _) => {
+            let mut pairs = pairs::sorted_pairs(&matrix, &args.distance_metric, args.sort.as_ref());
+            if let Some(limit) = args.limit {
+                pairs.truncate(limit);
+            }
+            pairs::write_parquet(
+                BufWriter::new(std::io::stdout()),
+                &pairs,
+                &args.distance_metric,
+            )
+            .unwrap_or_else(|error| panic!("Failed to write Parquet: {error}"));
+        }
+        (table::OutputFormat::Scalar, _) => {
+            // Input order unless sorted, so that a script knows which line is which pair
+            let mut pairs = match &args.sort {
+                Some(sort) => pairs::sorted_pairs(&matrix, &args.distance_metric, Some(sort)),
+                None => pairs::input_order(&matrix).collect(),
+            };
+            if let Some(limit) = args.limit {
+                pairs.truncate(limit);
+            }
+            for pair in pairs {
+                println!("{}", table::format_score(pair.score));
+            }
+        }
+        (_, pairs::OutputShape::Matrix) if !capped => match &args.group_by {
+            Some(pairs::GroupBy::Language) => {
+                let languages = args.language_detector().detect_all(&input_strings);
+                for (language, documents) in language::groups(&languages) {
+                    match documents.len() {
+                        1 => println!("{language} (1 document)"),
+                        count => println!("{language} ({count} documents)"),
+                    }
+                    let submatrix = documents
+                        .iter()
+                        .map(|i| documents.iter().map(|j| matrix[*i][*j]).collect())
+                        .collect::<Vec<_>>();
+                    print_matrix(args, &dataframe.select(&documents), &submatrix);
+                }
+                let pairs = pairs::input_order(&matrix).collect::<Vec<_>>();
+                language::print_block_summary(&pairs, &languages, &args.distance_metric);
+            }
+            _ => print_matrix(args, &dataframe, &matrix),
+        },
+        _ => {
+            let pairs = pairs::sorted_pairs(&matrix, &args.distance_metric, args.sort.as_ref());
+            let limit = match (args.limit, capped) {
+                (None, true) => Some(DEFAULT_TOP_PAIRS),
+                (limit, _) => limit,
+            };
+            // Grouped pairs are limited per group
+            let groups = args.group_by.as_ref().map(|group_by| match group_by {
+                pairs::GroupBy::File => input_sources.clone(),
+                pairs::GroupBy::Language => args.language_detector().detect_all(&input_strings),
+            });
+            let sources = groups.as_deref();
+            let listed = match sources {
+                Some(sources) => pairs::group_by_source(&pairs, sources, limit),
+                None => pairs
+                    .iter()
+                    .copied()
+                    .take(limit.unwrap_or(usize::MAX))
+                    .collect(),
             };
+            print_pair_rows(args, &input_strings, &listed, sources);
+            match (&args.group_by, sources) {
+                (Some(pairs::GroupBy::Language), Some(languages)) => {
+                    language::print_block_summary(&pairs, languages, &args.distance_metric)
+                }
+                (_, Some(sources)) => {
+                    pairs::print_group_summary(&pairs, sources, &args.distance_metric)
+                }
+                _ => {}
+            }
+        }
+    }
 
-            dataframe.add_row_header(i, &second.document);
-            dataframe.add_row_distances(i, j, distance);
-        });
+    if args.stats || capped {
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        stats::print_summary(
+            &input_strings,
+            dimensions,
+            &matrix,
+            &args.distance_metric,
+            args.bootstrap.map(|rounds| (rounds, &mut rng)),
+        );
+    }
+    if let Some(bias) = &bias {
+        length_bias::print_report(
+            bias.as_ref(),
+            corrected_bias.as_ref(),
+            &args.distance_metric,
+        );
+    }
+    if let Some(agreements) = &agreements {
+        metric_agreement::print_report(agreements, input_strings.len());
+    }
 
-    print_table!(dataframe.as_dataframe());
+    if let Some(heatmap) = &args.heatmap {
+        if input_strings.is_empty() {
+            warnings::warn(format!("No documents to render, {heatmap} was not written"));
+        } else {
+            heatmap::render(heatmap, &input_strings, &matrix, &args.distance_metric).unwrap();
+        }
+    }
+
+    if let Some(matrix_out) = &args.matrix_out {
+        npy::write(matrix_out, &input_strings, &matrix, &args.matrix_dtype)
+            .unwrap_or_else(|error| panic!("Failed to write {matrix_out}: {error}"));
+    }
+
+    if let (Some(graph_out), Some(threshold)) = (&args.graph_out, args.threshold) {
+        let edges = graph::edges(&matrix, &args.distance_metric, threshold);
+        let file = File::create(files::long_path(graph_out))
+            .unwrap_or_else(|error| panic!("Failed to create {graph_out}: {error}"));
+        graph::write(
+            BufWriter::new(file),
+            &args.graph_format,
+            &input_ids,
+            &input_strings,
+            &edges,
+        )
+        .unwrap_or_else(|error| panic!("Failed to write {graph_out}: {error}"));
+    }
+
+    if let (Some(table), Some(db)) = (&args.write_results, &args.db) {
+        let rows = match args.write_mode {
+            sql::WriteMode::Pairs => (0..input_ids.len())
+                .tuple_combinations()
+                .map(|(i, j)| sql::ResultRow {
+                    source_id: &input_ids[i],
+                    target_id: &input_ids[j],
+                    score: matrix[i][j],
+                })
+                .collect::<Vec<_>>(),
+            sql::WriteMode::Neighbors => (0..input_ids.len())
+                .filter_map(|i| {
+                    let closest = (0..input_ids.len()).filter(|j| *j != i).max_by(|a, b| {
+                        args.distance_metric
+                            .cmp_closeness(matrix[i][*a], matrix[i][*b])
+                    })?;
+
+                    Some(sql::ResultRow {
+                        source_id: &input_ids[i],
+                        target_id: &input_ids[closest],
+                        score: matrix[i][closest],
+                    })
+                })
+                .collect(),
+        };
+
+        sql::write_results(db, table, &args.distance_metric.to_string(), &rows).await;
+    }
+
+    if let (true, Some(db)) = (args.history, &args.db) {
+        let run = history::Run {
+            started_at,
+            provider: args.embeddings.is_none().then(|| args.provider.to_string()),
+            model: args.embedding_model.as_deref(),
+            model_version: model_version.as_deref(),
+            metric: args.distance_metric.to_string(),
+        };
+        let documents = input_ids
+            .iter()
+            .zip(&input_strings)
+            .map(|(id, document)| (id.as_str(), document.as_str()))
+            .collect::<Vec<_>>();
+        history::record(db, &run, &documents, &matrix).await;
+    }
+
+    if args.fail_if_above.is_some() || args.fail_if_below.is_some() {
+        check_thresholds(args, &input_strings, &matrix);
+    }
+}
+
+/// Exits with status 1, listing the offending pairs, if any pair scores above `--fail-if-above`
+/// or below `--fail-if-below`.
+fn check_thresholds(args: &Args, input_strings: &[String], matrix: &[Vec<f64>]) {
+    let failures = pairs::sorted_pairs(matrix, &args.distance_metric, None)
+        .into_iter()
+        .filter_map(|pair| {
+            let bound = match (args.fail_if_above, args.fail_if_below) {
+                (Some(above), _) if pair.score > above => format!("above --fail-if-above {above}"),
+                (_, Some(below)) if pair.score < below => format!("below --fail-if-below {below}"),
+                _ => return None,
+            };
+            Some((pair, bound))
+        })
+        .collect::<Vec<_>>();
+    if failures.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "{} {} failed the score thresholds:",
+        failures.len(),
+        if failures.len() == 1 { "pair" } else { "pairs" }
+    );
+    for (pair, bound) in failures.iter().take(MAX_REPORTED_FAILURES) {
+        eprintln!(
+            "  {} / {}: {} {bound}",
+            format_header(pair.i, &input_strings[pair.i]),
+            format_header(pair.j, &input_strings[pair.j]),
+            pair.score
+        );
+    }
+    if failures.len() > MAX_REPORTED_FAILURES {
+        eprintln!("  and {} more", failures.len() - MAX_REPORTED_FAILURES);
+    }
+    std::process::exit(1);
+}
+
+fn print_matrix(args: &Args, dataframe: &DataFrame, matrix: &[Vec<f64>]) {
+    let higher_is_closer = args.distance_metric.higher_is_closer();
+    let scores = matrix
+        .iter()
+        .enumerate()
+        .flat_map(|(i, row)| row[i + 1..].iter().copied())
+        .collect::<Vec<_>>();
+    // Column 0 holds the row labels and self-distances are not worth highlighting
+    let highlighted = |i: usize, column: usize| {
+        column > 0 && column != i + 1 && args.matrix_shape.shows(i, column - 1)
+    };
+
+    match args.output_format {
+        table::OutputFormat::Markdown => table::print_markdown(&dataframe.as_dataframe()),
+        table::OutputFormat::Html => {
+            let closeness = table::closeness(&scores, higher_is_closer);
+            table::print_html(&dataframe.as_dataframe(), |i, column| {
+                highlighted(i, column).then(|| closeness(matrix[i][column - 1]))
+            });
+        }
+        table::OutputFormat::Table if args.color.enabled() => {
+            let defaults = table::Thresholds::quartiles(&scores, higher_is_closer);
+            let thresholds = table::Thresholds {
+                close: args.close_threshold.unwrap_or(defaults.close),
+                far: args.far_threshold.unwrap_or(defaults.far),
+                higher_is_closer,
+            };
+
+            table::print_colored(dataframe.as_dataframe(), |i, column| {
+                highlighted(i, column)
+                    .then(|| thresholds.color(matrix[i][column - 1]))
+                    .flatten()
+            });
+        }
+        table::OutputFormat::Table => table::print(dataframe.as_dataframe()),
+        table::OutputFormat::Parquet | table::OutputFormat::Scalar => {
+            unreachable!("{} output doesn't print a table", args.output_format)
+        }
+    }
 }
 
 struct DataFrame {
@@ -149,7 +2372,7 @@ impl DataFrame {
                     if i == 0 {
                         "".to_string()
                     } else {
-                        format_header(i-1, string)
+                        format_header(i - 1, string)
                     }
                 })
                 .collect::<Vec<_>>(),
@@ -173,33 +2396,50 @@ impl DataFrame {
         let row_i = self.get_row(i);
 
         if row_i.len() == *j + 1 {
-            row_i.push(distance.to_string());
+            row_i.push(table::format_score(distance));
         } else {
             while row_i.len() < *j + 1 {
                 row_i.push(EMPTY.to_string());
             }
-            row_i.push(distance.to_string());
+            row_i.push(table::format_score(distance));
+        }
+    }
+
+    /// The rows and columns of the documents at `positions`, keeping their headers.
+    fn select(&self, positions: &[usize]) -> Self {
+        let cell = |row: &[String], column: usize| {
+            row.get(column)
+                .cloned()
+                .unwrap_or_else(|| EMPTY.to_string())
+        };
+        DataFrame {
+            headers: std::iter::once(String::new())
+                .chain(positions.iter().map(|i| self.headers[i + 1].clone()))
+                .collect(),
+            data: positions
+                .iter()
+                .map(|i| {
+                    let row = &self.data[*i];
+                    std::iter::once(cell(row, 0))
+                        .chain(positions.iter().map(|j| cell(row, j + 1)))
+                        .collect()
+                })
+                .collect(),
         }
     }
 
     fn as_dataframe(&self) -> Vec<Vec<String>> {
         let mut data = vec![self.headers.clone()];
-        data.extend(self.data.clone());
+        // Rows of the lower half end at the diagonal
+        data.extend(self.data.iter().map(|row| {
+            let mut row = row.clone();
+            row.resize(self.headers.len(), EMPTY.to_string());
+            row
+        }));
         data
     }
 }
 
 fn format_header(i: usize, string: &str) -> String {
-    format!(
-        "{}: {}",
-        i,
-        match string.len() {
-            0..=10 => string.to_string(),
-            _ => if let Some(index) = string.find('.') {
-                format!("{}...", &string[..index])
-            } else {
-                format!("{}...", &string[..10])
-            }
-        }
-    )
+    format!("{}: {}", i, labels::label(string))
 }
@@ -0,0 +1,187 @@
+use std::{collections::BTreeMap, fs::File, io::BufWriter, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    audit, files, metrics, template::Template, whitening::VectorTransform, Args, DistanceMetric,
+};
+
+/// What a run compared and how, written by `--manifest` to reproduce or audit its results.
+#[derive(Serialize)]
+pub struct Manifest<'a> {
+    tool: &'static str,
+    version: &'static str,
+    started_at: DateTime<Utc>,
+    /// When the vectors were ready, before the analysis ran
+    embedded_at: DateTime<Utc>,
+    /// Absent for runs on saved `--embeddings`
+    provider: Option<String>,
+    model: Option<&'a str>,
+    /// Snapshot of the model the provider reported, if any
+    model_version: Option<&'a str>,
+    /// Dimensions asked of the provider with `--dimensions`
+    requested_dimensions: Option<usize>,
+    /// Dimensions of the vectors compared, after `--vector-transform`
+    dimensions: usize,
+    metric: String,
+    /// Exponent of the `minkowski` metric
+    minkowski_p: Option<f64>,
+    normalize: bool,
+    vector_transform: String,
+    /// Components kept by `--vector-transform whiten`
+    whiten_dims: Option<u16>,
+    dim_weights: Option<Input<'a>>,
+    /// `--template` the texts were embedded as
+    template: Option<&'a str>,
+    /// `--input-template` of every input having one
+    input_templates: BTreeMap<&'a str, &'a str>,
+    /// Input files and directories, `--embeddings` file or `--input-sql` query
+    inputs: Vec<Input<'a>>,
+    documents: usize,
+    /// SHA-256 of the SHA-256 of every document, in order, after `--preprocess` but before the
+    /// templates
+    corpus_sha256: String,
+}
+
+/// A file, directory or query the run read, with the SHA-256 of its contents.
+#[derive(Serialize)]
+struct Input<'a> {
+    source: &'a str,
+    sha256: String,
+}
+
+impl<'a> Input<'a> {
+    fn new(path: &'a str) -> Self {
+        Input {
+            source: path,
+            sha256: hash_path(path),
+        }
+    }
+}
+
+/// SHA-256 of a file, or of the names and contents of the files of a directory in the order
+/// they are read.
+fn hash_path(path: &str) -> String {
+    let mut hasher = Sha256::new();
+    if Path::new(path).is_dir() {
+        for (name, contents) in files::read_directory(path) {
+            for part in [name, contents] {
+                hasher.update((part.len() as u64).to_le_bytes());
+                hasher.update(part);
+            }
+        }
+    } else {
+        let bytes = std::fs::read(files::long_path(path))
+            .unwrap_or_else(|error| panic!("Failed to read {path}: {error}"));
+        hasher.update(bytes);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// SHA-256 of the documents, as the hash of their audit log hashes.
+fn hash_corpus(documents: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for hash in audit::hashes(documents) {
+        hasher.update(hash);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+impl<'a> Manifest<'a> {
+    pub fn new(
+        args: &'a Args,
+        started_at: DateTime<Utc>,
+        model_version: Option<&'a str>,
+        input_strings: &[String],
+        dimensions: usize,
+    ) -> Self {
+        let mut inputs = args
+            .input_file
+            .iter()
+            .map(|path| Input::new(path))
+            .collect::<Vec<_>>();
+        if let Some(embeddings) = &args.embeddings {
+            inputs.push(Input::new(embeddings));
+        }
+        if let Some(input_sql) = &args.input_sql {
+            inputs.push(Input {
+                source: input_sql,
+                sha256: format!("{:x}", Sha256::digest(input_sql)),
+            });
+        }
+        let embedded = args.embeddings.is_none();
+        Manifest {
+            tool: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            started_at,
+            embedded_at: Utc::now(),
+            provider: embedded.then(|| args.provider.to_string()),
+            model: args.embedding_model.as_deref().filter(|_| embedded),
+            model_version,
+            requested_dimensions: args.provider_args.dimensions.filter(|_| embedded),
+            dimensions,
+            metric: args.distance_metric.to_string(),
+            minkowski_p: matches!(args.distance_metric, DistanceMetric::Minkowski)
+                .then(metrics::minkowski_p),
+            normalize: args.normalize,
+            vector_transform: args.vector_transform.to_string(),
+            whiten_dims: (args.vector_transform == VectorTransform::Whiten)
+                .then_some(args.whiten_dims),
+            dim_weights: args.dim_weights.as_deref().map(Input::new),
+            template: args.template.as_ref().map(Template::as_str),
+            input_templates: args
+                .input_template
+                .iter()
+                .map(|(input, template)| (input.as_str(), template.as_str()))
+                .collect(),
+            inputs,
+            documents: input_strings.len(),
+            corpus_sha256: hash_corpus(input_strings),
+        }
+    }
+
+    pub fn write(&self, path: &str) {
+        let file = File::create(files::long_path(path))
+            .unwrap_or_else(|error| panic!("Failed to create {path}: {error}"));
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .unwrap_or_else(|error| panic!("Failed to write {path}: {error}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_hashes_depend_on_the_texts_and_their_order() {
+        let corpus = ["first".to_string(), "second".to_string()];
+        let swapped = [corpus[1].clone(), corpus[0].clone()];
+        assert_eq!(hash_corpus(&corpus), hash_corpus(&corpus.clone()));
+        assert_ne!(hash_corpus(&corpus), hash_corpus(&swapped));
+        // Documents are hashed apart, so moving text between them changes the hash
+        let merged = ["firsts".to_string(), "econd".to_string()];
+        assert_ne!(hash_corpus(&corpus), hash_corpus(&merged));
+    }
+
+    #[test]
+    fn directories_hash_their_file_names_and_contents() {
+        let directory = std::env::temp_dir().join(format!(
+            "distance-calculator-manifest-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("a.txt"), "apple").unwrap();
+        let path = directory.to_str().unwrap();
+        let before = hash_path(path);
+        std::fs::write(directory.join("b.txt"), "banana").unwrap();
+        let after = hash_path(path);
+        assert_ne!(before, after);
+        assert_eq!(
+            hash_path(directory.join("a.txt").to_str().unwrap()),
+            format!("{:x}", Sha256::digest("apple"))
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
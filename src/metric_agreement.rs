@@ -0,0 +1,129 @@
+use clap::ValueEnum;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::{providers::Embedding, stats, table, DistanceMetric};
+
+/// How similarly two metrics rank the pairs of documents.
+#[derive(Debug)]
+pub struct Agreement {
+    pub first: DistanceMetric,
+    pub second: DistanceMetric,
+    pub spearman: f64,
+    pub kendall: f64,
+}
+
+/// The metrics of `--metric-agreement`, every metric when none is given.
+pub fn selected(metrics: &[DistanceMetric]) -> Vec<DistanceMetric> {
+    if metrics.is_empty() {
+        DistanceMetric::value_variants().to_vec()
+    } else {
+        metrics.to_vec()
+    }
+}
+
+/// Correlates the rankings of the pairs of distinct documents by every two of `metrics`, with
+/// the scores of every metric turned into similarities so that the correlations of agreeing
+/// distances and similarities are positive.
+pub fn measure(documents: &[Embedding], metrics: &[DistanceMetric]) -> Vec<Agreement> {
+    let pairs = (0..documents.len())
+        .tuple_combinations::<(_, _)>()
+        .collect::<Vec<_>>();
+    let similarities = metrics
+        .iter()
+        .map(|metric| {
+            pairs
+                .par_iter()
+                .map(|&(i, j)| metric.similarity(&documents[i].vec, &documents[j].vec))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    (0..metrics.len())
+        .tuple_combinations()
+        .map(|(a, b)| Agreement {
+            first: metrics[a].clone(),
+            second: metrics[b].clone(),
+            spearman: stats::spearman(&similarities[a], &similarities[b]),
+            kendall: stats::kendall(&similarities[a], &similarities[b]),
+        })
+        .collect()
+}
+
+/// Prints the agreement of every two metrics, and the two that disagree most.
+pub fn print_report(agreements: &[Agreement], documents: usize) {
+    if documents < 3 {
+        println!("Metric agreement needs at least 3 documents");
+        return;
+    }
+    let mut rows = vec![vec![
+        "metric".to_string(),
+        "other metric".to_string(),
+        "spearman".to_string(),
+        "kendall".to_string(),
+    ]];
+    rows.extend(agreements.iter().map(|agreement| {
+        vec![
+            agreement.first.to_string(),
+            agreement.second.to_string(),
+            table::format_score(agreement.spearman),
+            table::format_score(agreement.kendall),
+        ]
+    }));
+    table::print(rows);
+    if let Some(least) = agreements
+        .iter()
+        .min_by(|a, b| a.kendall.total_cmp(&b.kendall))
+    {
+        println!(
+            "{} and {} disagree most on the ranking of the {} pairs (kendall {})",
+            least.first,
+            least.second,
+            documents * (documents - 1) / 2,
+            table::format_score(least.kendall)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documents() -> Vec<Embedding> {
+        [
+            vec![1.0, 0.0, 0.0],
+            vec![0.9, 0.1, 0.0],
+            vec![0.0, 3.0, 0.2],
+            vec![0.1, 0.2, 0.9],
+        ]
+        .map(|vec| Embedding {
+            document: String::new(),
+            vec,
+        })
+        .to_vec()
+    }
+
+    #[test]
+    fn a_distance_agrees_with_its_similarity() {
+        let metrics = [
+            DistanceMetric::Cosine,
+            DistanceMetric::CosineDistance,
+            DistanceMetric::Angular,
+        ];
+        let agreements = measure(&documents(), &metrics);
+        assert_eq!(agreements.len(), 3);
+        for agreement in &agreements {
+            assert!((agreement.spearman - 1.0).abs() < 1e-12, "{agreement:?}");
+            assert!((agreement.kendall - 1.0).abs() < 1e-12, "{agreement:?}");
+        }
+    }
+
+    #[test]
+    fn every_metric_is_selected_by_default() {
+        assert_eq!(selected(&[]).len(), DistanceMetric::value_variants().len());
+        assert!(matches!(
+            selected(&[DistanceMetric::L2])[..],
+            [DistanceMetric::L2]
+        ));
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    f64::consts::PI,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use semanticsimilarity_rs::{
+    cosine_similarity, dot_product_distance, euclidean_distance, manhattan_distance,
+};
+
+/// Exponent of the Minkowski distance, set once from `--minkowski-p`.
+static MINKOWSKI_P: OnceLock<f64> = OnceLock::new();
+
+pub const DEFAULT_MINKOWSKI_P: f64 = 3.0;
+
+pub fn set_minkowski_p(p: f64) {
+    assert!(p >= 1.0, "--minkowski-p must be at least 1");
+    MINKOWSKI_P.set(p).expect("Minkowski p already set");
+}
+
+pub fn minkowski_p() -> f64 {
+    *MINKOWSKI_P.get().unwrap_or(&DEFAULT_MINKOWSKI_P)
+}
+
+/// Weight of every dimension in the distances, set once from `--dim-weights`.
+static DIM_WEIGHTS: OnceLock<Vec<f64>> = OnceLock::new();
+
+pub fn set_dim_weights(weights: Vec<f64>) {
+    assert!(
+        weights
+            .iter()
+            .all(|weight| weight.is_finite() && *weight >= 0.0),
+        "--dim-weights must be finite and non-negative"
+    );
+    assert!(
+        weights.iter().any(|weight| *weight > 0.0),
+        "--dim-weights must weigh at least one dimension"
+    );
+    DIM_WEIGHTS
+        .set(weights)
+        .expect("Dimension weights already set");
+}
+
+pub fn dim_weights() -> Option<&'static [f64]> {
+    DIM_WEIGHTS.get().map(Vec::as_slice)
+}
+
+/// Fails when `--dim-weights` doesn't have a weight for each of the `dimensions`.
+pub fn check_dim_weights(dimensions: usize) {
+    if let Some(weights) = dim_weights() {
+        assert_eq!(
+            weights.len(),
+            dimensions,
+            "--dim-weights has {} weights for vectors of {dimensions} dimensions",
+            weights.len()
+        );
+    }
+}
+
+/// Weight of dimension `i`, 1 without weights.
+fn weight(weights: Option<&[f64]>, i: usize) -> f64 {
+    weights.map_or(1.0, |weights| weights[i])
+}
+
+/// Whether cosine, dot and L2 use the kernels below instead of semanticsimilarity_rs, set from
+/// `--fast` or the size of the corpus.
+static FAST: AtomicBool = AtomicBool::new(false);
+
+/// Corpus size from which the fast kernels are used even without `--fast`.
+pub const FAST_THRESHOLD: usize = 500;
+
+/// Dimensions from which the fast kernels are used even without `--fast`: semanticsimilarity_rs
+/// splits every pair of such vectors into rayon jobs, nested in the parallel loop over pairs.
+pub const HIGH_DIMENSIONS: usize = 4096;
+
+/// Independent accumulators per kernel, enough for the compiler to fill AVX registers.
+const LANES: usize = 8;
+
+pub fn set_fast(enabled: bool) {
+    FAST.store(enabled, Ordering::Relaxed);
+}
+
+pub fn fast() -> bool {
+    FAST.load(Ordering::Relaxed)
+}
+
+/// Whether the fast kernels are used without `--fast` for `documents` vectors of `dimensions`.
+pub fn fast_by_default(documents: usize, dimensions: usize) -> bool {
+    documents >= FAST_THRESHOLD || dimensions >= HIGH_DIMENSIONS
+}
+
+/// Sums `term` over the dimensions in `LANES` interleaved partial sums.
+///
+/// semanticsimilarity_rs spawns a rayon job per call, which costs far more than the arithmetic
+/// for a single pair of vectors. Plain loops over fixed-size chunks are auto-vectorized instead.
+/// The summation order differs, so results can change in the last bits.
+#[inline(always)]
+fn lane_sum(first: &[f64], second: &[f64], term: impl Fn(f64, f64) -> f64) -> f64 {
+    let mut sums = [0.0; LANES];
+    let first_chunks = first.chunks_exact(LANES);
+    let second_chunks = second.chunks_exact(LANES);
+    let remainder = first_chunks
+        .remainder()
+        .iter()
+        .zip(second_chunks.remainder())
+        .map(|(a, b)| term(*a, *b))
+        .sum::<f64>();
+
+    for (a, b) in first_chunks.zip(second_chunks) {
+        for lane in 0..LANES {
+            sums[lane] += term(a[lane], b[lane]);
+        }
+    }
+    sums.iter().sum::<f64>() + remainder
+}
+
+pub fn fast_dot(first: &[f64], second: &[f64]) -> f64 {
+    lane_sum(first, second, |a, b| a * b)
+}
+
+pub fn fast_euclidean(first: &[f64], second: &[f64]) -> f64 {
+    lane_sum(first, second, |a, b| (a - b) * (a - b)).sqrt()
+}
+
+pub fn fast_cosine(first: &[f64], second: &[f64]) -> f64 {
+    let norms = fast_dot(first, first).sqrt() * fast_dot(second, second).sqrt();
+    fast_dot(first, second) / norms
+}
+
+/// Sums `term` over the dimensions, each times its weight.
+fn weighted_sum(
+    first: &[f64],
+    second: &[f64],
+    weights: &[f64],
+    term: impl Fn(f64, f64) -> f64,
+) -> f64 {
+    first
+        .iter()
+        .zip(second)
+        .zip(weights)
+        .map(|((a, b), weight)| weight * term(*a, *b))
+        .sum()
+}
+
+pub fn weighted_dot(first: &[f64], second: &[f64], weights: &[f64]) -> f64 {
+    weighted_sum(first, second, weights, |a, b| a * b)
+}
+
+pub fn weighted_euclidean(first: &[f64], second: &[f64], weights: &[f64]) -> f64 {
+    weighted_sum(first, second, weights, |a, b| (a - b) * (a - b)).sqrt()
+}
+
+/// Cosine under the inner product weighting every dimension.
+pub fn weighted_cosine(first: &[f64], second: &[f64], weights: &[f64]) -> f64 {
+    let norms =
+        weighted_dot(first, first, weights).sqrt() * weighted_dot(second, second, weights).sqrt();
+    weighted_dot(first, second, weights) / norms
+}
+
+pub fn weighted_manhattan(first: &[f64], second: &[f64], weights: &[f64]) -> f64 {
+    weighted_sum(first, second, weights, |a, b| (a - b).abs())
+}
+
+pub fn cosine(first: &[f64], second: &[f64]) -> f64 {
+    match dim_weights() {
+        Some(weights) => weighted_cosine(first, second, weights),
+        None if fast() => fast_cosine(first, second),
+        None => cosine_similarity(first, second, false),
+    }
+}
+
+pub fn euclidean(first: &[f64], second: &[f64]) -> f64 {
+    match dim_weights() {
+        Some(weights) => weighted_euclidean(first, second, weights),
+        None if fast() => fast_euclidean(first, second),
+        None => euclidean_distance(first, second),
+    }
+}
+
+pub fn dot(first: &[f64], second: &[f64]) -> f64 {
+    match dim_weights() {
+        Some(weights) => weighted_dot(first, second, weights),
+        None if fast() => fast_dot(first, second),
+        None => dot_product_distance(first, second),
+    }
+}
+
+pub fn manhattan(first: &[f64], second: &[f64]) -> f64 {
+    match dim_weights() {
+        Some(weights) => weighted_manhattan(first, second, weights),
+        None => manhattan_distance(first, second),
+    }
+}
+
+/// Largest absolute difference over all dimensions.
+pub fn chebyshev_distance(first: &[f64], second: &[f64]) -> f64 {
+    chebyshev(first, second, dim_weights())
+}
+
+/// Largest weighted absolute difference over all dimensions.
+fn chebyshev(first: &[f64], second: &[f64], weights: Option<&[f64]>) -> f64 {
+    first
+        .iter()
+        .zip(second)
+        .enumerate()
+        .map(|(i, (a, b))| weight(weights, i) * (a - b).abs())
+        .fold(0.0, f64::max)
+}
+
+/// Generalization of the Manhattan (`p = 1`) and L2 (`p = 2`) distances.
+pub fn minkowski_distance(first: &[f64], second: &[f64], p: f64) -> f64 {
+    minkowski(first, second, p, dim_weights())
+}
+
+fn minkowski(first: &[f64], second: &[f64], p: f64, weights: Option<&[f64]>) -> f64 {
+    first
+        .iter()
+        .zip(second)
+        .enumerate()
+        .map(|(i, (a, b))| weight(weights, i) * (a - b).abs().powf(p))
+        .sum::<f64>()
+        .powf(1.0 / p)
+}
+
+/// Angle between the vectors scaled to `[0, 1]`, a proper metric unlike `1 - cosine`.
+pub fn angular_distance(first: &[f64], second: &[f64]) -> f64 {
+    // Rounding can push the cosine of (anti)parallel vectors slightly outside of [-1, 1]
+    cosine(first, second).clamp(-1.0, 1.0).acos() / PI
+}
+
+/// Jaccard distance between the sets of positive dimensions of each vector.
+pub fn jaccard_distance(first: &[f64], second: &[f64]) -> f64 {
+    jaccard(first, second, dim_weights())
+}
+
+/// Jaccard distance of the sets of positive dimensions, every dimension counting its weight.
+fn jaccard(first: &[f64], second: &[f64], weights: Option<&[f64]>) -> f64 {
+    let (intersection, union) = first.iter().zip(second).enumerate().fold(
+        (0.0, 0.0),
+        |(intersection, union), (i, (a, b))| {
+            let (a, b) = (*a > 0.0, *b > 0.0);
+            let weight = weight(weights, i);
+            (
+                intersection + if a && b { weight } else { 0.0 },
+                union + if a || b { weight } else { 0.0 },
+            )
+        },
+    );
+
+    if union == 0.0 {
+        0.0
+    } else {
+        1.0 - intersection / union
+    }
+}
+
+/// Scales `vector` to unit L2 norm, leaving zero vectors untouched.
+pub fn normalize(vector: &mut [f64]) {
+    let norm = vector.iter().map(|value| value * value).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    fn vectors() -> (Vec<f64>, Vec<f64>) {
+        // Not a multiple of the lane count, to cover the remainder
+        let first = (0..37).map(|i| (i as f64 * 0.37).sin()).collect();
+        let second = (0..37).map(|i| (i as f64 * 0.11).cos() - 0.5).collect();
+        (first, second)
+    }
+
+    #[test]
+    fn fast_kernels_match_semanticsimilarity() {
+        let (first, second) = vectors();
+        assert_close(
+            fast_dot(&first, &second),
+            semanticsimilarity_rs::dot_product_distance(&first, &second),
+        );
+        assert_close(
+            fast_euclidean(&first, &second),
+            euclidean_distance(&first, &second),
+        );
+        assert_close(
+            fast_cosine(&first, &second),
+            cosine_similarity(&first, &second, false),
+        );
+    }
+
+    #[test]
+    fn fast_kernels_handle_short_vectors() {
+        assert_close(fast_dot(&[2.0, 3.0], &[4.0, 5.0]), 23.0);
+        assert_close(fast_euclidean(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn chebyshev_is_the_largest_difference() {
+        assert_close(chebyshev_distance(&[1.0, -2.0, 3.0], &[2.0, 2.0, 2.5]), 4.0);
+        assert_close(chebyshev_distance(&[1.0, 2.0], &[1.0, 2.0]), 0.0);
+    }
+
+    #[test]
+    fn minkowski_generalizes_manhattan_and_l2() {
+        let (first, second) = ([0.0, 0.0], [3.0, 4.0]);
+        assert_close(minkowski_distance(&first, &second, 1.0), 7.0);
+        assert_close(minkowski_distance(&first, &second, 2.0), 5.0);
+        assert_close(
+            minkowski_distance(&first, &second, 3.0),
+            91.0_f64.powf(1.0 / 3.0),
+        );
+    }
+
+    #[test]
+    fn angular_distance_spans_zero_to_one() {
+        assert_close(angular_distance(&[1.0, 0.0], &[2.0, 0.0]), 0.0);
+        assert_close(angular_distance(&[1.0, 0.0], &[0.0, 1.0]), 0.5);
+        assert_close(angular_distance(&[1.0, 0.0], &[-1.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    fn angular_distance_of_parallel_vectors_is_not_nan() {
+        let vector = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7];
+        assert!(!angular_distance(&vector, &vector).is_nan());
+    }
+
+    #[test]
+    fn jaccard_binarizes_by_sign() {
+        // Positive dimensions are {0, 1} and {1, 2}
+        assert_close(
+            jaccard_distance(&[0.5, 0.1, -0.3, -1.0], &[-0.5, 2.0, 0.4, 0.0]),
+            1.0 - 1.0 / 3.0,
+        );
+        assert_close(jaccard_distance(&[1.0, -1.0], &[3.0, -2.0]), 0.0);
+    }
+
+    #[test]
+    fn normalize_scales_to_unit_norm() {
+        let mut vector = [3.0, -4.0];
+        normalize(&mut vector);
+        assert_close(vector[0], 0.6);
+        assert_close(vector[1], -0.8);
+
+        let mut zero = [0.0, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn jaccard_of_vectors_without_positive_dimensions_is_zero() {
+        assert_close(jaccard_distance(&[-1.0, 0.0], &[0.0, -2.0]), 0.0);
+    }
+
+    #[test]
+    fn weights_scale_the_dimensions() {
+        let (first, second) = vectors();
+        let ones = vec![1.0; first.len()];
+        assert_close(
+            weighted_dot(&first, &second, &ones),
+            fast_dot(&first, &second),
+        );
+        assert_close(
+            weighted_cosine(&first, &second, &ones),
+            cosine_similarity(&first, &second, false),
+        );
+        assert_close(
+            weighted_manhattan(&first, &second, &ones),
+            manhattan_distance(&first, &second),
+        );
+
+        // Weighting a dimension by 4 is scaling it by 2 for L2
+        let (first, second, weights) = ([1.0, 0.0], [0.0, 1.0], [4.0, 1.0]);
+        assert_close(weighted_euclidean(&first, &second, &weights), 5f64.sqrt());
+        assert_close(
+            weighted_euclidean(&first, &second, &weights),
+            euclidean_distance(&[2.0, 0.0], &[0.0, 1.0]),
+        );
+        assert_close(chebyshev(&first, &second, Some(&weights)), 4.0);
+        assert_close(minkowski(&first, &second, 3.0, Some(&weights)), 5f64.cbrt());
+    }
+
+    #[test]
+    fn zero_weights_mask_dimensions() {
+        let mask = [1.0, 0.0, 1.0];
+        let (first, second) = ([1.0, 5.0, 2.0], [1.0, -5.0, 2.0]);
+        assert_close(weighted_euclidean(&first, &second, &mask), 0.0);
+        assert_close(weighted_cosine(&first, &second, &mask), 1.0);
+        assert_close(chebyshev(&first, &second, Some(&mask)), 0.0);
+        // Only dimension 1 differs in sign, and it is masked
+        assert_close(jaccard(&first, &second, Some(&mask)), 0.0);
+        assert_close(jaccard(&first, &second, None), 1.0 - 2.0 / 3.0);
+    }
+}
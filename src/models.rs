@@ -0,0 +1,184 @@
+use clap::Args;
+
+use crate::{ledger::price_per_million_tokens, table, Provider, EMPTY};
+
+/// Most edits between a model name and a known model for it to be taken as a typo of it.
+const MAX_TYPO_DISTANCE: usize = 3;
+
+#[derive(Args, Debug)]
+pub struct ModelsArgs {
+    /// Only list the models of this provider
+    #[arg(short, long)]
+    provider: Option<Provider>,
+}
+
+/// An embedding model with published limits.
+#[derive(Debug)]
+pub struct Model {
+    pub provider: Provider,
+    pub name: &'static str,
+    /// Dimensions of its vectors, before `--dimensions` shortens them
+    pub dimensions: usize,
+    /// Most tokens of a document it embeds
+    pub max_tokens: usize,
+}
+
+/// The embedding models known to the tool, by provider.
+pub const CATALOG: [Model; 8] = [
+    Model {
+        provider: Provider::Openai,
+        name: "text-embedding-3-small",
+        dimensions: 1536,
+        max_tokens: 8191,
+    },
+    Model {
+        provider: Provider::Openai,
+        name: "text-embedding-3-large",
+        dimensions: 3072,
+        max_tokens: 8191,
+    },
+    Model {
+        provider: Provider::Openai,
+        name: "text-embedding-ada-002",
+        dimensions: 1536,
+        max_tokens: 8191,
+    },
+    Model {
+        provider: Provider::Cohere,
+        name: "embed-english-v3.0",
+        dimensions: 1024,
+        max_tokens: 512,
+    },
+    Model {
+        provider: Provider::Cohere,
+        name: "embed-multilingual-v3.0",
+        dimensions: 1024,
+        max_tokens: 512,
+    },
+    Model {
+        provider: Provider::Cohere,
+        name: "embed-english-light-v3.0",
+        dimensions: 384,
+        max_tokens: 512,
+    },
+    Model {
+        provider: Provider::Cohere,
+        name: "embed-multilingual-light-v3.0",
+        dimensions: 384,
+        max_tokens: 512,
+    },
+    Model {
+        provider: Provider::Cohere,
+        name: "embed-v4.0",
+        dimensions: 1536,
+        max_tokens: 128_000,
+    },
+];
+
+/// Number of single character insertions, deletions and substitutions turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(a != *b))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The known model a name not in the catalog is most likely a typo of.
+fn suggestion(name: &str) -> Option<&'static Model> {
+    if CATALOG.iter().any(|model| model.name == name) {
+        return None;
+    }
+    CATALOG
+        .iter()
+        .map(|model| (edit_distance(name, model.name), model))
+        .filter(|(distance, _)| *distance <= MAX_TYPO_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, model)| model)
+}
+
+/// Parses `--embedding-model`, rejecting names a few edits away from a known model. Other names
+/// are left to the provider, which may serve models newer than the catalog.
+pub fn parse_model(name: &str) -> Result<String, String> {
+    match suggestion(name) {
+        Some(model) => Err(format!(
+            "unknown model, did you mean {} model `{}`? (see `{} models`)",
+            model.provider,
+            model.name,
+            env!("CARGO_BIN_NAME")
+        )),
+        None => Ok(name.to_string()),
+    }
+}
+
+/// Prints the known models, their dimensions, token limit and price.
+pub fn run(args: ModelsArgs) {
+    let mut table = vec![[
+        "provider",
+        "model",
+        "dimensions",
+        "max tokens",
+        "USD per 1k tokens",
+    ]
+    .map(String::from)
+    .to_vec()];
+    for model in CATALOG.iter().filter(|model| {
+        args.provider
+            .as_ref()
+            .is_none_or(|provider| *provider == model.provider)
+    }) {
+        table.push(vec![
+            model.provider.to_string(),
+            model.name.to_string(),
+            model.dimensions.to_string(),
+            model.max_tokens.to_string(),
+            price_per_million_tokens(&model.provider, model.name)
+                .map_or(EMPTY.to_string(), |price| format!("{:.5}", price / 1000.0)),
+        ]);
+    }
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances_count_characters() {
+        assert_eq!(edit_distance("text", "text"), 0);
+        assert_eq!(edit_distance("txt", "text"), 1);
+        assert_eq!(edit_distance("tetx", "text"), 2);
+        assert_eq!(edit_distance("crème", "creme"), 1);
+    }
+
+    #[test]
+    fn typos_of_known_models_are_rejected_with_a_suggestion() {
+        let error = parse_model("text-embeding-3-small").unwrap_err();
+        assert!(error.contains("did you mean openai model `text-embedding-3-small`"));
+        let error = parse_model("embed-english-v3").unwrap_err();
+        assert!(error.contains("`embed-english-v3.0`"));
+    }
+
+    #[test]
+    fn known_and_unrelated_models_are_accepted() {
+        assert_eq!(
+            parse_model("text-embedding-3-large").unwrap(),
+            "text-embedding-3-large"
+        );
+        assert_eq!(parse_model("my-fine-tune").unwrap(), "my-fine-tune");
+    }
+
+    #[test]
+    fn every_catalog_model_has_a_price() {
+        for model in &CATALOG {
+            assert!(price_per_million_tokens(&model.provider, model.name).is_some());
+        }
+    }
+}
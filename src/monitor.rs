@@ -0,0 +1,188 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    embedding_file, metrics, normalize_documents, providers::Embedding, transform_documents,
+    warnings, Args, DistanceMetric,
+};
+
+/// One line of the results log.
+#[derive(Serialize)]
+struct Summary {
+    timestamp: DateTime<Local>,
+    documents: usize,
+    /// Documents that were not in the cache yet and had to be embedded
+    embedded: usize,
+    /// Dimensions of the embeddings, which scoring time and memory grow with
+    dimensions: usize,
+    metric: String,
+    mean: Option<f64>,
+    closest: Option<Neighbors>,
+    farthest: Option<Neighbors>,
+    /// Non-fatal warnings raised during the run
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Neighbors {
+    source_id: String,
+    target_id: String,
+    score: f64,
+}
+
+/// The summary of the pairwise scores of `documents`, `embedded` of which were not cached.
+fn summarize(
+    ids: &[String],
+    documents: &[Embedding],
+    embedded: usize,
+    distance_metric: &DistanceMetric,
+    warnings: Vec<String>,
+) -> Summary {
+    let scores = documents
+        .iter()
+        .enumerate()
+        .tuple_combinations()
+        .map(|((i, a), (j, b))| (i, j, distance_metric.distance(&a.vec, &b.vec)))
+        .collect::<Vec<_>>();
+    let neighbors = |(i, j, score): &(usize, usize, f64)| Neighbors {
+        source_id: ids[*i].clone(),
+        target_id: ids[*j].clone(),
+        score: *score,
+    };
+
+    Summary {
+        timestamp: Local::now(),
+        documents: documents.len(),
+        embedded,
+        dimensions: embedding_file::dimensions(documents),
+        metric: distance_metric.to_string(),
+        mean: (!scores.is_empty())
+            .then(|| scores.iter().map(|(_, _, score)| score).sum::<f64>() / scores.len() as f64),
+        closest: scores
+            .iter()
+            .max_by(|(_, _, a), (_, _, b)| distance_metric.cmp_closeness(*a, *b))
+            .map(neighbors),
+        farthest: scores
+            .iter()
+            .min_by(|(_, _, a), (_, _, b)| distance_metric.cmp_closeness(*a, *b))
+            .map(neighbors),
+        warnings,
+    }
+}
+
+/// Re-reads the input every `interval` and appends a summary of the pairwise scores to
+/// `--results-log`, only embedding the documents that were added since the previous run.
+pub async fn run(args: &Args, interval: Duration) {
+    let results_log = &args.results_log;
+    let embedding_model = args.embedding_model.as_ref().unwrap();
+    let mut cache = args.embedding_cache();
+
+    loop {
+        let started = Instant::now();
+        let (input_ids, input_strings, _, input_sources) = args.input_documents().await;
+        let inputs = input_sources.iter().map(String::as_str).collect::<Vec<_>>();
+        let templated = args.templated(&input_strings, &inputs);
+        let (mut documents, embedded) = cache
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                embedding_model,
+                templated.as_ref().unwrap_or(&input_strings),
+                args.batch_size(),
+            )
+            .await;
+        if templated.is_some() {
+            for (document, text) in documents.iter_mut().zip(&input_strings) {
+                document.document = text.clone();
+            }
+        }
+        if args.normalize {
+            normalize_documents(&mut documents);
+        }
+        transform_documents(args, &mut documents, []);
+        let dimensions = embedding_file::dimensions(&documents);
+        metrics::set_fast(args.fast || metrics::fast_by_default(documents.len(), dimensions));
+        metrics::check_dim_weights(dimensions);
+
+        let summary = summarize(
+            &input_ids,
+            &documents,
+            embedded,
+            &args.distance_metric,
+            warnings::take(),
+        );
+
+        let line = serde_json::to_string(&summary).unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(results_log)
+            .unwrap_or_else(|error| panic!("Failed to open results log {results_log}: {error}"));
+        writeln!(file, "{line}").unwrap();
+        println!("{line}");
+
+        tokio::time::sleep(interval.saturating_sub(started.elapsed())).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn documents(vectors: &[[f64; 2]]) -> (Vec<String>, Vec<Embedding>) {
+        let ids = (0..vectors.len()).map(|i| format!("doc-{i}")).collect();
+        let documents = vectors
+            .iter()
+            .map(|vec| Embedding {
+                document: String::new(),
+                vec: vec.to_vec(),
+            })
+            .collect();
+        (ids, documents)
+    }
+
+    #[test]
+    fn summaries_hold_the_closest_and_farthest_pairs() {
+        let (ids, documents) = documents(&[[0.0, 0.0], [3.0, 4.0], [0.0, 1.0]]);
+        let summary = summarize(&ids, &documents, 1, &DistanceMetric::L2, vec![]);
+        let line = serde_json::to_value(&summary).unwrap();
+        assert_eq!(line["documents"], 3);
+        assert_eq!(line["embedded"], 1);
+        assert_eq!(line["dimensions"], 2);
+        assert_eq!(line["metric"], "l2");
+        let mean = (5.0 + 1.0 + 18f64.sqrt()) / 3.0;
+        assert!((line["mean"].as_f64().unwrap() - mean).abs() < 1e-12);
+        assert_eq!(line["closest"]["source_id"], "doc-0");
+        assert_eq!(line["closest"]["target_id"], "doc-2");
+        assert_eq!(line["closest"]["score"], 1.0);
+        assert_eq!(line["farthest"]["target_id"], "doc-1");
+        assert_eq!(line["farthest"]["score"], 5.0);
+
+        // The closest pair of a similarity scores highest
+        let summary = summarize(&ids[1..], &documents[1..], 0, &DistanceMetric::Dot, vec![]);
+        let line = serde_json::to_value(&summary).unwrap();
+        assert_eq!(line["closest"]["score"], 4.0);
+        assert_eq!(line["closest"], line["farthest"]);
+    }
+
+    #[test]
+    fn a_single_document_has_no_pairs() {
+        let (ids, documents) = documents(&[[1.0, 0.0]]);
+        let warnings = vec!["Input file changed while reading".to_string()];
+        let summary = summarize(&ids, &documents, 0, &DistanceMetric::Cosine, warnings);
+        let line = serde_json::to_value(&summary).unwrap();
+        assert_eq!(line["mean"], Value::Null);
+        assert_eq!(line["closest"], Value::Null);
+        assert_eq!(line["farthest"], Value::Null);
+        assert_eq!(line["warnings"][0], "Input file changed while reading");
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use clap::ValueEnum;
+
+use crate::files;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Total header length .npy files are padded to a multiple of, so that the data is aligned.
+const ALIGNMENT: usize = 64;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Dtype {
+    F64,
+    /// Half the size, with about 7 significant digits
+    F32,
+}
+
+impl Display for Dtype {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dtype::F64 => write!(f, "f64"),
+            Dtype::F32 => write!(f, "f32"),
+        }
+    }
+}
+
+impl Dtype {
+    /// NumPy's name of the little-endian type.
+    fn descr(&self) -> &'static str {
+        match self {
+            Dtype::F64 => "<f8",
+            Dtype::F32 => "<f4",
+        }
+    }
+}
+
+/// Labels file written next to the matrix file `path`: `matrix.npy` gets `matrix.labels.json`.
+pub fn labels_path(path: &str) -> PathBuf {
+    PathBuf::from(path).with_extension("labels.json")
+}
+
+/// Writes the square `matrix` to `path` as a row-major `.npy` array of `dtype`, which
+/// `numpy.load` reads as is, and the documents of its rows and columns to `labels_path(path)`
+/// as a JSON array.
+pub fn write(
+    path: &str,
+    input_strings: &[String],
+    matrix: &[Vec<f64>],
+    dtype: &Dtype,
+) -> std::io::Result<()> {
+    let n = matrix.len();
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({n}, {n}), }}",
+        dtype.descr()
+    );
+    // Magic, version and header length come first, and the header ends with a newline
+    let unpadded = MAGIC.len() + 2 + 2 + header.len() + 1;
+    header += &" ".repeat(unpadded.next_multiple_of(ALIGNMENT) - unpadded);
+    header.push('\n');
+
+    let mut writer = BufWriter::new(File::create(files::long_path(path))?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in matrix.iter().flatten() {
+        match dtype {
+            Dtype::F64 => writer.write_all(&value.to_le_bytes())?,
+            Dtype::F32 => writer.write_all(&(*value as f32).to_le_bytes())?,
+        }
+    }
+    writer.flush()?;
+
+    let labels = serde_json::to_string_pretty(input_strings)?;
+    std::fs::write(files::long_path(labels_path(path)), labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header and data of the `.npy` file at `path`.
+    fn read(path: &str) -> (String, Vec<u8>) {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+        assert_eq!(&bytes[6..8], &[1, 0]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let data_start = 10 + header_len;
+        assert_eq!(data_start % ALIGNMENT, 0);
+
+        let header = String::from_utf8(bytes[10..data_start].to_vec()).unwrap();
+        assert!(header.ends_with('\n'));
+        (header.trim_end().to_string(), bytes[data_start..].to_vec())
+    }
+
+    fn matrix() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 0.25, -0.5],
+            vec![0.25, 1.0, 0.1],
+            vec![-0.5, 0.1, 1.0],
+        ]
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-npy-{}-{name}",
+            std::process::id()
+        ));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn f64_matrices_round_trip_with_their_labels() {
+        let path = temp_path("matrix.npy");
+        let documents = ["one".to_string(), "two".to_string(), "three".to_string()];
+        write(&path, &documents, &matrix(), &Dtype::F64).unwrap();
+
+        let (header, data) = read(&path);
+        assert_eq!(
+            header,
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (3, 3), }"
+        );
+        let values = data
+            .chunks_exact(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(values, matrix().concat());
+
+        let labels = std::fs::read_to_string(labels_path(&path)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<String>>(&labels).unwrap(),
+            documents
+        );
+    }
+
+    #[test]
+    fn f32_matrices_are_rounded_to_single_precision() {
+        let path = temp_path("matrix-f32.npy");
+        let documents = ["a".to_string(), "b".to_string(), "c".to_string()];
+        write(&path, &documents, &matrix(), &Dtype::F32).unwrap();
+
+        let (header, data) = read(&path);
+        assert!(header.starts_with("{'descr': '<f4',"));
+        let values = data
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let expected = matrix()
+            .concat()
+            .into_iter()
+            .map(|value| value as f32)
+            .collect::<Vec<_>>();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn labels_sit_next_to_the_matrix() {
+        assert_eq!(
+            labels_path("out/matrix.npy"),
+            PathBuf::from("out/matrix.labels.json")
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{cluster, format_header, table, DistanceMetric, EMPTY};
+
+/// Score of every document against the centroid of the corpus, farthest first, with the
+/// positions of the documents.
+pub fn centroid_scores(
+    vectors: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+) -> Vec<(usize, f64)> {
+    if vectors.is_empty() {
+        return vec![];
+    }
+    let centroid = cluster::mean(&vectors.iter().collect::<Vec<_>>());
+    let mut scores = vectors
+        .iter()
+        .map(|vector| distance_metric.distance(vector, &centroid))
+        .enumerate()
+        .collect::<Vec<_>>();
+    scores.sort_by(|(_, a), (_, b)| distance_metric.cmp_closeness(*a, *b));
+    scores
+}
+
+/// Prints every document and its score against the centroid, farthest first, flagging the
+/// `count` farthest as outliers.
+pub fn print_outliers(
+    input_strings: &[String],
+    scores: &[(usize, f64)],
+    count: usize,
+    distance_metric: &DistanceMetric,
+) {
+    let mut table = vec![vec![
+        "document".to_string(),
+        format!("{distance_metric} to centroid"),
+        "outlier".to_string(),
+    ]];
+    table.extend(scores.iter().enumerate().map(|(rank, (i, score))| {
+        vec![
+            format_header(*i, &input_strings[*i]),
+            table::format_score(*score),
+            if rank < count { "yes" } else { EMPTY }.to_string(),
+        ]
+    }));
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_far_from_the_centroid_come_first() {
+        let vectors = vec![
+            vec![1.0, 0.0],
+            vec![1.0, 0.1],
+            vec![0.9, 0.0],
+            vec![-1.0, 1.0],
+        ];
+        let order = |distance_metric| {
+            centroid_scores(&vectors, &distance_metric)
+                .into_iter()
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order(DistanceMetric::L2)[0], 3);
+        assert_eq!(order(DistanceMetric::Cosine)[0], 3);
+    }
+
+    #[test]
+    fn the_centroid_is_the_mean() {
+        let vectors = vec![vec![0.0, 0.0], vec![2.0, 0.0], vec![0.0, 4.0]];
+        let scores = centroid_scores(&vectors, &DistanceMetric::L2);
+        // The centroid is (2/3, 4/3)
+        let expected = |x: f64, y: f64| ((x - 2.0 / 3.0).powi(2) + (y - 4.0 / 3.0).powi(2)).sqrt();
+        assert_eq!(scores[0].0, 2);
+        assert!((scores[0].1 - expected(0.0, 4.0)).abs() < 1e-12);
+        assert!(centroid_scores(&[], &DistanceMetric::L2).is_empty());
+    }
+}
@@ -0,0 +1,516 @@
+use std::{fmt::Display, io::Write, sync::Arc};
+
+use arrow_array::{DictionaryArray, Float64Array, RecordBatch, UInt64Array};
+use arrow_schema::{DataType, Field, Schema};
+use clap::ValueEnum;
+use itertools::Itertools;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::{anchor, files, format_header, providers::Embedding, stats, table, DistanceMetric};
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum OutputShape {
+    /// Triangular distance matrix
+    Matrix,
+    /// One row per pair of documents
+    Pairs,
+}
+
+impl Display for OutputShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputShape::Matrix => write!(f, "matrix"),
+            OutputShape::Pairs => write!(f, "pairs"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum MatrixShape {
+    /// Scores above the diagonal, `-` below it
+    Upper,
+    /// Scores below the diagonal, `-` above it
+    Lower,
+    /// Every score, mirrored across the diagonal
+    Full,
+}
+
+impl Display for MatrixShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatrixShape::Upper => write!(f, "upper"),
+            MatrixShape::Lower => write!(f, "lower"),
+            MatrixShape::Full => write!(f, "full"),
+        }
+    }
+}
+
+impl MatrixShape {
+    /// Whether the cell of row `i` and column `j` of the matrix holds a score, the diagonal of
+    /// self-scores included.
+    pub fn shows(&self, i: usize, j: usize) -> bool {
+        match self {
+            MatrixShape::Upper => i <= j,
+            MatrixShape::Lower => i >= j,
+            MatrixShape::Full => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Sort {
+    /// Lowest score first
+    Asc,
+    /// Highest score first
+    Desc,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum GroupBy {
+    /// The input files of the documents: pairs within every file, then pairs across files
+    File,
+    /// The detected language of every document: a matrix per language, then the mean score
+    /// within and across every two languages
+    Language,
+}
+
+impl Display for GroupBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GroupBy::File => write!(f, "file"),
+            GroupBy::Language => write!(f, "language"),
+        }
+    }
+}
+
+/// Score of two distinct documents, `i < j`.
+#[derive(Debug, Clone, Copy)]
+pub struct Pair {
+    pub i: usize,
+    pub j: usize,
+    pub score: f64,
+}
+
+/// Every pair of distinct documents in `matrix`, in input order: `(0, 1)`, `(0, 2)`, ...
+pub fn input_order(matrix: &[Vec<f64>]) -> impl Iterator<Item = Pair> + '_ {
+    (0..matrix.len()).tuple_combinations().map(|(i, j)| Pair {
+        i,
+        j,
+        score: matrix[i][j],
+    })
+}
+
+/// Every pair of distinct documents in `matrix`, ordered by `sort` or closest first.
+pub fn sorted_pairs(
+    matrix: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+    sort: Option<&Sort>,
+) -> Vec<Pair> {
+    let mut pairs = input_order(matrix).collect::<Vec<_>>();
+    sort_pairs(&mut pairs, distance_metric, sort);
+    pairs
+}
+
+/// Orders `pairs` by `sort` or closest first.
+pub fn sort_pairs(pairs: &mut [Pair], distance_metric: &DistanceMetric, sort: Option<&Sort>) {
+    match sort {
+        Some(Sort::Asc) => pairs.sort_by(|a, b| a.score.total_cmp(&b.score)),
+        Some(Sort::Desc) => pairs.sort_by(|a, b| b.score.total_cmp(&a.score)),
+        None => pairs.sort_by(|a, b| distance_metric.cmp_closeness(b.score, a.score)),
+    }
+}
+
+/// A document of `--pairs`: its position, or its id, text or position as a string.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Reference {
+    Position(usize),
+    Label(String),
+}
+
+/// Reads the `--pairs` file, a JSON array of `[first, second]` documents, into the positions of
+/// its pairs with the first document first. Pairs listed twice are only kept once.
+pub fn read_pairs(
+    path: &str,
+    input_ids: &[String],
+    input_strings: &[String],
+) -> Vec<(usize, usize)> {
+    resolve_pairs(files::read_json(path), input_ids, input_strings)
+}
+
+fn resolve_pairs(
+    pairs: Vec<(Reference, Reference)>,
+    input_ids: &[String],
+    input_strings: &[String],
+) -> Vec<(usize, usize)> {
+    let find = |reference: &Reference| match reference {
+        Reference::Position(i) => Some(*i)
+            .filter(|i| *i < input_strings.len())
+            .unwrap_or_else(|| {
+                panic!(
+                    "--pairs position {i} is past the {} input documents",
+                    input_strings.len()
+                )
+            }),
+        Reference::Label(label) => {
+            anchor::find(label, input_ids, input_strings).unwrap_or_else(|| {
+                panic!("--pairs {label} is neither the id, text nor position of an input document")
+            })
+        }
+    };
+    pairs
+        .iter()
+        .map(|(first, second)| {
+            let (i, j) = (find(first), find(second));
+            assert_ne!(i, j, "--pairs lists document {i} with itself");
+            (i.min(j), i.max(j))
+        })
+        .unique()
+        .collect()
+}
+
+/// Scores the `pairs` of documents, without the rest of the matrix.
+pub fn score_pairs(
+    pairs: &[(usize, usize)],
+    documents: &[Embedding],
+    distance_metric: &DistanceMetric,
+) -> Vec<Pair> {
+    pairs
+        .par_iter()
+        .map(|(i, j)| Pair {
+            i: *i,
+            j: *j,
+            score: distance_metric.distance(&documents[*i].vec, &documents[*j].vec),
+        })
+        .collect()
+}
+
+/// Position of the first document of every input file among `sources`, ordering the files as
+/// they were given.
+fn source_rank(sources: &[String], source: &str) -> usize {
+    sources.iter().position(|other| other == source).unwrap()
+}
+
+/// `pairs` grouped by the input files of their documents: the pairs within every file first, in
+/// the order of the files, then the pairs across every two files. Pairs keep their order within
+/// a group, of which only the first `limit` are kept.
+pub fn group_by_source(pairs: &[Pair], sources: &[String], limit: Option<usize>) -> Vec<Pair> {
+    let groups = pairs.iter().copied().into_group_map_by(|pair| {
+        let (i, j) = (
+            source_rank(sources, &sources[pair.i]),
+            source_rank(sources, &sources[pair.j]),
+        );
+        (i != j, i.min(j), i.max(j))
+    });
+    groups
+        .into_iter()
+        .sorted_by_key(|(key, _)| *key)
+        .flat_map(|(_, pairs)| pairs.into_iter().take(limit.unwrap_or(usize::MAX)))
+        .collect()
+}
+
+/// Label of the group of a pair: the input file of both documents, or both files.
+fn group(pair: &Pair, sources: &[String]) -> String {
+    let (i, j) = (&sources[pair.i], &sources[pair.j]);
+    if i == j {
+        i.clone()
+    } else if source_rank(sources, i) < source_rank(sources, j) {
+        format!("{i} × {j}")
+    } else {
+        format!("{j} × {i}")
+    }
+}
+
+/// Prints the number of pairs and mean score within and across input files.
+pub fn print_group_summary(pairs: &[Pair], sources: &[String], distance_metric: &DistanceMetric) {
+    let (within, across): (Vec<_>, Vec<_>) = pairs
+        .iter()
+        .partition(|pair| sources[pair.i] == sources[pair.j]);
+    let describe = |pairs: &[&Pair]| {
+        let scores = pairs.iter().map(|pair| pair.score).collect::<Vec<_>>();
+        match scores.len() {
+            0 => "no pairs".to_string(),
+            count => format!(
+                "{count} pairs, mean {distance_metric} {}",
+                table::format_score(stats::mean(&scores))
+            ),
+        }
+    };
+    println!(
+        "within files: {}; across files: {}",
+        describe(&within),
+        describe(&across)
+    );
+}
+
+/// Prints one row per pair, after a column of the group of every pair when `sources` gives the
+/// input file of every document, and before the named `columns` of one value per pair.
+pub fn print_pairs(
+    input_strings: &[String],
+    pairs: &[Pair],
+    sources: Option<&[String]>,
+    columns: &[(String, Vec<String>)],
+    distance_metric: &DistanceMetric,
+    output_format: &table::OutputFormat,
+) {
+    let mut header = vec![
+        "doc_i".to_string(),
+        "doc_j".to_string(),
+        "metric".to_string(),
+        "score".to_string(),
+    ];
+    if sources.is_some() {
+        header.insert(0, "group".to_string());
+    }
+    let score_column = header.len() - 1;
+    header.extend(columns.iter().map(|(name, _)| name.clone()));
+    let mut table = vec![header];
+    table.extend(pairs.iter().enumerate().map(|(row, pair)| {
+        let mut cells = vec![
+            format_header(pair.i, &input_strings[pair.i]),
+            format_header(pair.j, &input_strings[pair.j]),
+            distance_metric.to_string(),
+            table::format_score(pair.score),
+        ];
+        if let Some(sources) = sources {
+            cells.insert(0, group(pair, sources));
+        }
+        cells.extend(columns.iter().map(|(_, values)| values[row].clone()));
+        cells
+    }));
+
+    match output_format {
+        table::OutputFormat::Table => table::print(table),
+        table::OutputFormat::Parquet | table::OutputFormat::Scalar => {
+            unreachable!("{output_format} output doesn't print a table")
+        }
+        table::OutputFormat::Markdown => table::print_markdown(&table),
+        table::OutputFormat::Html => {
+            let scores = pairs.iter().map(|pair| pair.score).collect::<Vec<_>>();
+            let closeness = table::closeness(&scores, distance_metric.higher_is_closer());
+            // Only the score column is shaded
+            table::print_html(&table, |row, column| {
+                (column == score_column).then(|| closeness(pairs[row].score))
+            });
+        }
+    }
+}
+
+/// Pairs written to Parquet per record batch.
+const PARQUET_BATCH: usize = 65_536;
+
+/// Writes `pairs` as a zstd-compressed Parquet file of `doc_i`, `doc_j` (the positions of the
+/// documents in the input), `metric` and `score` columns.
+pub fn write_parquet(
+    writer: impl Write + Send,
+    pairs: &[Pair],
+    distance_metric: &DistanceMetric,
+) -> parquet::errors::Result<()> {
+    let metric_type = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("doc_i", DataType::UInt64, false),
+        Field::new("doc_j", DataType::UInt64, false),
+        Field::new("metric", metric_type, false),
+        Field::new("score", DataType::Float64, false),
+    ]));
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(Default::default()))
+        .build();
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+
+    let metric = distance_metric.to_string();
+    for batch in pairs.chunks(PARQUET_BATCH) {
+        let columns: Vec<Arc<dyn arrow_array::Array>> = vec![
+            Arc::new(UInt64Array::from_iter_values(
+                batch.iter().map(|pair| pair.i as u64),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                batch.iter().map(|pair| pair.j as u64),
+            )),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|_| metric.as_str())
+                    .collect::<DictionaryArray<arrow_array::types::Int32Type>>(),
+            ),
+            Arc::new(Float64Array::from_iter_values(
+                batch.iter().map(|pair| pair.score),
+            )),
+        ];
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+    }
+
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int32Type, UInt64Type},
+    };
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+
+    fn matrix() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 0.2, 0.9],
+            vec![0.2, 1.0, 0.5],
+            vec![0.9, 0.5, 1.0],
+        ]
+    }
+
+    fn indices(pairs: &[Pair]) -> Vec<(usize, usize)> {
+        pairs.iter().map(|pair| (pair.i, pair.j)).collect()
+    }
+
+    #[test]
+    fn input_order_lists_every_distinct_pair_once() {
+        let pairs = input_order(&matrix()).collect::<Vec<_>>();
+        assert_eq!(indices(&pairs), [(0, 1), (0, 2), (1, 2)]);
+        assert_eq!(
+            pairs.iter().map(|pair| pair.score).collect::<Vec<_>>(),
+            [0.2, 0.9, 0.5]
+        );
+
+        assert_eq!(input_order(&[vec![1.0]]).count(), 0);
+        assert_eq!(input_order(&[]).count(), 0);
+    }
+
+    #[test]
+    fn pairs_are_sorted_closest_first_by_default() {
+        let matrix = matrix();
+        let sorted = |metric, sort| indices(&sorted_pairs(&matrix, &metric, sort));
+        assert_eq!(
+            sorted(DistanceMetric::Cosine, None),
+            [(0, 2), (1, 2), (0, 1)]
+        );
+        // Lower distances are closer
+        assert_eq!(sorted(DistanceMetric::L2, None), [(0, 1), (1, 2), (0, 2)]);
+        assert_eq!(
+            sorted(DistanceMetric::Cosine, Some(&Sort::Asc)),
+            [(0, 1), (1, 2), (0, 2)]
+        );
+        assert_eq!(
+            sorted(DistanceMetric::L2, Some(&Sort::Desc)),
+            [(0, 2), (1, 2), (0, 1)]
+        );
+    }
+
+    #[test]
+    fn parquet_files_hold_the_pairs_in_long_format() {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-pairs-{}.parquet",
+            std::process::id()
+        ));
+        let pairs = input_order(&matrix()).collect::<Vec<_>>();
+        write_parquet(
+            std::fs::File::create(&path).unwrap(),
+            &pairs,
+            &DistanceMetric::Cosine,
+        )
+        .unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        let names = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["doc_i", "doc_j", "metric", "score"]);
+
+        let column = |i: usize| batch.column(i).as_primitive::<UInt64Type>();
+        assert_eq!(column(0).values(), &[0, 0, 1]);
+        assert_eq!(column(1).values(), &[1, 2, 2]);
+        let metric = batch.column(2).as_dictionary::<Int32Type>();
+        assert_eq!(metric.len(), 3);
+        assert_eq!(metric.values().as_string::<i32>().value(0), "cosine");
+        let scores = batch.column(3).as_primitive::<Float64Type>();
+        assert_eq!(scores.values(), &[0.2, 0.9, 0.5]);
+    }
+
+    #[test]
+    fn matrix_shapes_show_their_half_and_the_diagonal() {
+        let shown = |shape: MatrixShape| {
+            (0..3)
+                .flat_map(|i| (0..3).map(move |j| (i, j)))
+                .filter(|(i, j)| shape.shows(*i, *j))
+                .count()
+        };
+        assert_eq!(shown(MatrixShape::Upper), 6);
+        assert_eq!(shown(MatrixShape::Lower), 6);
+        assert_eq!(shown(MatrixShape::Full), 9);
+        assert!(MatrixShape::Upper.shows(0, 2) && !MatrixShape::Upper.shows(2, 0));
+        assert!(MatrixShape::Lower.shows(2, 0) && !MatrixShape::Lower.shows(0, 2));
+    }
+
+    #[test]
+    fn pairs_are_grouped_within_then_across_files() {
+        // Documents 0 and 2 come from b.json, given first, and 1 and 3 from a.json
+        let sources = ["b.json", "a.json", "b.json", "a.json"].map(String::from);
+        let matrix = vec![vec![0.0; 4]; 4];
+        let pairs = input_order(&matrix).collect::<Vec<_>>();
+        let grouped = group_by_source(&pairs, &sources, None);
+        assert_eq!(
+            indices(&grouped),
+            [(0, 2), (1, 3), (0, 1), (0, 3), (1, 2), (2, 3)]
+        );
+        assert_eq!(
+            indices(&group_by_source(&pairs, &sources, Some(1))),
+            [(0, 2), (1, 3), (0, 1)]
+        );
+        let pairs = grouped;
+        assert_eq!(group(&pairs[0], &sources), "b.json");
+        assert_eq!(group(&pairs[1], &sources), "a.json");
+        assert_eq!(group(&pairs[4], &sources), "b.json × a.json");
+    }
+
+    #[test]
+    fn listed_pairs_resolve_positions_ids_and_texts() {
+        let ids = ["a", "b", "c"].map(String::from);
+        let strings = ["first", "second", "third"].map(String::from);
+        let pairs = vec![
+            (Reference::Position(2), Reference::Position(0)),
+            (
+                Reference::Label("b".to_string()),
+                Reference::Label("third".to_string()),
+            ),
+            (
+                Reference::Label("0".to_string()),
+                Reference::Label("c".to_string()),
+            ),
+        ];
+        assert_eq!(resolve_pairs(pairs, &ids, &strings), [(0, 2), (1, 2)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "lists document 1 with itself")]
+    fn listed_pairs_have_two_documents() {
+        let ids = ["a", "b"].map(String::from);
+        let pairs = vec![(Reference::Label("b".to_string()), Reference::Position(1))];
+        resolve_pairs(pairs, &ids, &ids);
+    }
+
+    #[test]
+    fn only_listed_pairs_are_scored() {
+        let documents = [vec![1.0, 0.0], vec![0.0, 1.0], vec![3.0, 4.0]].map(|vec| Embedding {
+            document: String::new(),
+            vec,
+        });
+        let pairs = score_pairs(&[(0, 2)], &documents, &DistanceMetric::L2);
+        assert_eq!(indices(&pairs), [(0, 2)]);
+        assert!((pairs[0].score - 20f64.sqrt()).abs() < 1e-12);
+    }
+}
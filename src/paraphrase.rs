@@ -0,0 +1,184 @@
+use clap::Args;
+
+use crate::{
+    cache::EmbeddingCache, files, format_header, models, providers::Embedding, stats, table,
+    DistanceMetric, Provider, ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct ParaphraseArgs {
+    /// JSON array of paraphrase sets, each an array of documents meaning the same thing
+    #[arg(short)]
+    input_file: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+}
+
+impl ParaphraseArgs {
+    fn sets(&self) -> Vec<Vec<String>> {
+        files::read_json(&self.input_file)
+    }
+}
+
+/// The scores of the pairs of a paraphrase set's documents, and of its documents with those of
+/// the other sets.
+#[derive(Debug)]
+struct SetScores {
+    within: Vec<f64>,
+    across: Vec<f64>,
+    /// Documents whose nearest neighbor belongs to another set
+    strays: usize,
+}
+
+/// The set of every document of `sets`, in order.
+fn set_of(sets: &[Vec<String>]) -> Vec<usize> {
+    sets.iter()
+        .enumerate()
+        .flat_map(|(set, documents)| std::iter::repeat_n(set, documents.len()))
+        .collect()
+}
+
+/// The scores of every set, with `set_of` the set of every embedding.
+fn set_scores(
+    embeddings: &[Embedding],
+    set_of: &[usize],
+    distance_metric: &DistanceMetric,
+) -> Vec<SetScores> {
+    let sets = set_of.iter().max().map_or(0, |last| last + 1);
+    (0..sets)
+        .map(|set| {
+            let mut scores = SetScores {
+                within: vec![],
+                across: vec![],
+                strays: 0,
+            };
+            for i in (0..embeddings.len()).filter(|&i| set_of[i] == set) {
+                let mut nearest = None::<(usize, f64)>;
+                for j in (0..embeddings.len()).filter(|&j| j != i) {
+                    let score = distance_metric.distance(&embeddings[i].vec, &embeddings[j].vec);
+                    if set_of[j] == set {
+                        scores.within.push(score);
+                    } else {
+                        scores.across.push(score);
+                    }
+                    if nearest.is_none_or(|(_, closest)| {
+                        distance_metric.cmp_closeness(score, closest).is_gt()
+                    }) {
+                        nearest = Some((j, score));
+                    }
+                }
+                if nearest.is_some_and(|(j, _)| set_of[j] != set) {
+                    scores.strays += 1;
+                }
+            }
+            scores
+        })
+        .collect()
+}
+
+/// Prints the mean score within every paraphrase set and between its documents and those of the
+/// other sets, flagging the sets with a document whose nearest neighbor belongs to another set.
+pub async fn run(args: ParaphraseArgs) {
+    let sets = args.sets();
+    assert!(sets.len() > 1, "Expected at least two paraphrase sets");
+    assert!(
+        sets.iter().all(|set| !set.is_empty()),
+        "Paraphrase sets can't be empty"
+    );
+    let input_strings = sets.iter().flatten().cloned().collect::<Vec<_>>();
+
+    let (embeddings, _) =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &input_strings,
+                args.provider.max_batch_size(),
+            )
+            .await;
+
+    let set_scores = set_scores(&embeddings, &set_of(&sets), &args.distance_metric);
+
+    let mut table = vec![vec![
+        "set".to_string(),
+        "documents".to_string(),
+        "within".to_string(),
+        "across".to_string(),
+        "strays".to_string(),
+    ]];
+    for (set, (documents, scores)) in sets.iter().zip(&set_scores).enumerate() {
+        table.push(vec![
+            format_header(set, &documents[0]),
+            documents.len().to_string(),
+            if scores.within.is_empty() {
+                "-".to_string()
+            } else {
+                table::format_score(stats::mean(&scores.within))
+            },
+            table::format_score(stats::mean(&scores.across)),
+            scores.strays.to_string(),
+        ]);
+    }
+
+    println!(
+        "{} of {} sets have documents closer to another set than to their own",
+        set_scores.iter().filter(|scores| scores.strays > 0).count(),
+        sets.len()
+    );
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embeddings(vectors: &[[f64; 2]]) -> Vec<Embedding> {
+        vectors
+            .iter()
+            .map(|vec| Embedding {
+                document: String::new(),
+                vec: vec.to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_document_belongs_to_its_set() {
+        let sets = vec![
+            vec!["a".to_string(), "b".to_string()],
+            vec!["c".to_string()],
+            vec!["d".to_string(), "e".to_string(), "f".to_string()],
+        ];
+        assert_eq!(set_of(&sets), [0, 0, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn scores_are_split_within_and_across_sets() {
+        let embeddings = embeddings(&[[0.0, 0.0], [1.0, 0.0], [5.0, 0.0], [1.5, 0.0]]);
+        let scores = set_scores(&embeddings, &[0, 0, 1, 1], &DistanceMetric::L2);
+        assert_eq!(scores.len(), 2);
+        // Every ordered pair is scored once from each side
+        assert_eq!(scores[0].within, [1.0, 1.0]);
+        assert_eq!(scores[0].across, [5.0, 1.5, 4.0, 0.5]);
+        assert_eq!(scores[1].within, [3.5, 3.5]);
+        assert_eq!(scores[1].across, [5.0, 4.0, 1.5, 0.5]);
+        // 1 is nearest 3 and 3 nearest 1, across the sets; 0 is nearest 1
+        assert_eq!((scores[0].strays, scores[1].strays), (1, 1));
+    }
+
+    #[test]
+    fn a_lone_document_has_no_score_within_its_set() {
+        let embeddings = embeddings(&[[1.0, 0.0], [0.9, 0.1], [0.0, 1.0]]);
+        let scores = set_scores(&embeddings, &[0, 0, 1], &DistanceMetric::Cosine);
+        assert!(scores[1].within.is_empty());
+        assert_eq!(scores[1].across.len(), 2);
+        assert_eq!((scores[0].strays, scores[1].strays), (0, 1));
+    }
+}
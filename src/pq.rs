@@ -0,0 +1,237 @@
+use itertools::Itertools;
+use rand::Rng;
+
+use crate::{
+    cluster::{kmeans_with_centroids, nearest_centroid},
+    search::{exact_neighbors, recall, top_k},
+    table, DistanceMetric,
+};
+
+/// A product quantizer: the dimensions are split into `subspaces` contiguous chunks and the
+/// chunk of every vector is replaced by the nearest of the centroids learned for that chunk.
+pub struct ProductQuantizer {
+    /// Start of every subspace, followed by the number of dimensions
+    bounds: Vec<usize>,
+    /// Centroids of every subspace
+    codebooks: Vec<Vec<Vec<f64>>>,
+}
+
+impl ProductQuantizer {
+    pub fn train(
+        vectors: &[Vec<f64>],
+        subspaces: usize,
+        centroids: usize,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let dimensions = vectors[0].len();
+        let subspaces = subspaces.clamp(1, dimensions);
+        let bounds = (0..=subspaces)
+            .map(|subspace| subspace * dimensions / subspaces)
+            .collect::<Vec<_>>();
+
+        let codebooks = bounds
+            .iter()
+            .tuple_windows()
+            .map(|(&start, &end)| {
+                let chunks = vectors
+                    .iter()
+                    .map(|vector| vector[start..end].to_vec())
+                    .collect::<Vec<_>>();
+                kmeans_with_centroids(&chunks, centroids, rng).1
+            })
+            .collect();
+
+        ProductQuantizer { bounds, codebooks }
+    }
+
+    /// Index of the nearest centroid of every subspace.
+    pub fn encode(&self, vector: &[f64]) -> Vec<usize> {
+        self.bounds
+            .iter()
+            .tuple_windows()
+            .zip(&self.codebooks)
+            .map(|((&start, &end), codebook)| nearest_centroid(&vector[start..end], codebook))
+            .collect()
+    }
+
+    /// The vector approximated by `codes`.
+    pub fn decode(&self, codes: &[usize]) -> Vec<f64> {
+        codes
+            .iter()
+            .zip(&self.codebooks)
+            .flat_map(|(code, codebook)| codebook[*code].iter().copied())
+            .collect()
+    }
+
+    /// Bytes needed to store the codes of one vector.
+    pub fn code_size(&self) -> usize {
+        self.codebooks
+            .iter()
+            .map(|codebook| (codebook.len().max(2) - 1).ilog2() as usize / 8 + 1)
+            .sum()
+    }
+}
+
+/// Mean fraction of the exact top `k` neighbors of each of `queries` that a search over the PQ
+/// reconstructions of the other documents also returns.
+pub fn recall_at_k(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    quantizer: &ProductQuantizer,
+    k: usize,
+    distance_metric: &DistanceMetric,
+) -> f64 {
+    let reconstructions = vectors
+        .iter()
+        .map(|vector| quantizer.decode(&quantizer.encode(vector)))
+        .collect::<Vec<_>>();
+    let exact = exact_neighbors(vectors, queries, k, distance_metric);
+
+    let recalls = queries.iter().zip(&exact).map(|(&query, exact)| {
+        // Asymmetric search: the query stays exact, only the indexed documents are quantized
+        let approximate = top_k(query, 0..vectors.len(), k, distance_metric, |other| {
+            distance_metric.distance(&vectors[query], &reconstructions[other])
+        });
+        recall(exact, &approximate)
+    });
+
+    recalls.sum::<f64>() / queries.len() as f64
+}
+
+pub fn print_report(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    quantizer: &ProductQuantizer,
+    k: usize,
+    distance_metric: &DistanceMetric,
+) {
+    let code_size = quantizer.code_size();
+    let full_size = vectors[0].len() * std::mem::size_of::<f64>();
+
+    let table = vec![
+        vec![
+            "subspaces".to_string(),
+            "centroids".to_string(),
+            "bytes per vector".to_string(),
+            "compression".to_string(),
+            format!("recall@{k}"),
+            "queries".to_string(),
+        ],
+        vec![
+            quantizer.codebooks.len().to_string(),
+            quantizer
+                .codebooks
+                .iter()
+                .map(|codebook| codebook.len())
+                .max()
+                .unwrap_or(0)
+                .to_string(),
+            code_size.to_string(),
+            format!("{}x", full_size / code_size),
+            table::format_score(recall_at_k(vectors, queries, quantizer, k, distance_metric)),
+            queries.len().to_string(),
+        ],
+    ];
+
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// Two tight groups of vectors, far apart in both halves of their dimensions.
+    fn vectors() -> Vec<Vec<f64>> {
+        vec![
+            vec![0.0, 0.0, 5.0, 5.0],
+            vec![0.2, 0.0, 5.0, 5.2],
+            vec![10.0, 10.0, -5.0, -5.0],
+            vec![10.2, 10.0, -5.0, -5.2],
+        ]
+    }
+
+    fn squared_error(first: &[f64], second: &[f64]) -> f64 {
+        first.iter().zip(second).map(|(a, b)| (a - b).powi(2)).sum()
+    }
+
+    #[test]
+    fn codebooks_split_the_dimensions_into_subspaces() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let vectors = vec![vec![0.0; 6], vec![1.0; 6], vec![2.0; 6]];
+        let quantizer = ProductQuantizer::train(&vectors, 4, 2, &mut rng);
+        assert_eq!(quantizer.bounds, [0, 1, 3, 4, 6]);
+        assert_eq!(quantizer.codebooks.len(), 4);
+        for (codebook, (start, end)) in
+            quantizer
+                .codebooks
+                .iter()
+                .zip([(0, 1), (1, 3), (3, 4), (4, 6)])
+        {
+            assert_eq!(codebook.len(), 2);
+            assert!(codebook
+                .iter()
+                .all(|centroid| centroid.len() == end - start));
+        }
+
+        let clamped = ProductQuantizer::train(&vectors, 10, 5, &mut rng);
+        assert_eq!(
+            clamped.bounds,
+            [0, 1, 2, 3, 4, 5, 6],
+            "one dimension per subspace"
+        );
+        assert!(
+            clamped.codebooks.iter().all(|codebook| codebook.len() == 3),
+            "no more centroids than vectors"
+        );
+    }
+
+    #[test]
+    fn vectors_round_trip_through_their_codes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let vectors = vectors();
+
+        let exact = ProductQuantizer::train(&vectors, 2, 4, &mut rng);
+        for vector in &vectors {
+            assert_eq!(&exact.decode(&exact.encode(vector)), vector);
+        }
+
+        // One centroid per group: every vector comes back as the mean of its group
+        let quantizer = ProductQuantizer::train(&vectors, 2, 2, &mut rng);
+        let codes = vectors
+            .iter()
+            .map(|vector| quantizer.encode(vector))
+            .collect::<Vec<_>>();
+        assert_eq!(codes[0], codes[1]);
+        assert_eq!(codes[2], codes[3]);
+        assert_ne!(codes[0], codes[2]);
+        let error = vectors
+            .iter()
+            .zip(&codes)
+            .map(|(vector, codes)| squared_error(vector, &quantizer.decode(codes)))
+            .sum::<f64>()
+            / vectors.len() as f64;
+        assert!((error - 0.02).abs() < 1e-9, "{error}");
+    }
+
+    #[test]
+    fn codes_take_a_byte_per_256_centroids() {
+        let quantizer = |centroids: usize| ProductQuantizer {
+            bounds: vec![0, 1, 2],
+            codebooks: vec![vec![vec![0.0]; centroids]; 2],
+        };
+        assert_eq!(quantizer(1).code_size(), 2);
+        assert_eq!(quantizer(256).code_size(), 2);
+        assert_eq!(quantizer(257).code_size(), 4);
+    }
+
+    #[test]
+    fn lossless_codes_find_every_exact_neighbor() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let vectors = vectors();
+        let quantizer = ProductQuantizer::train(&vectors, 2, 4, &mut rng);
+        let recall = recall_at_k(&vectors, &[0, 1, 2, 3], &quantizer, 1, &DistanceMetric::L2);
+        assert_eq!(recall, 1.0);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, LazyLock},
+};
+
+use regex::{Captures, Regex};
+
+/// One transformation of the text of every document before it is embedded.
+pub trait Stage: Debug + Send + Sync {
+    fn apply(&self, text: String) -> String;
+
+    /// Number of spans of `text` the stage redacts, reported per document.
+    fn redactions(&self, _text: &str) -> usize {
+        0
+    }
+}
+
+/// Removes HTML tags and decodes the common character entities.
+#[derive(Debug)]
+struct StripHtml;
+
+impl Stage for StripHtml {
+    fn apply(&self, text: String) -> String {
+        let mut stripped = String::with_capacity(text.len());
+        let mut in_tag = false;
+        for character in text.chars() {
+            match character {
+                '<' => in_tag = true,
+                '>' if in_tag => in_tag = false,
+                _ if !in_tag => stripped.push(character),
+                _ => {}
+            }
+        }
+
+        [
+            ("&nbsp;", " "),
+            ("&lt;", "<"),
+            ("&gt;", ">"),
+            ("&quot;", "\""),
+            ("&#39;", "'"),
+            ("&amp;", "&"),
+        ]
+        .into_iter()
+        .fold(stripped, |text, (entity, character)| {
+            text.replace(entity, character)
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Lowercase;
+
+impl Stage for Lowercase {
+    fn apply(&self, text: String) -> String {
+        text.to_lowercase()
+    }
+}
+
+/// Replaces every run of whitespace by a single space and trims both ends.
+#[derive(Debug)]
+struct CollapseWhitespace;
+
+impl Stage for CollapseWhitespace {
+    fn apply(&self, text: String) -> String {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Keeps the first characters of the text.
+#[derive(Debug)]
+struct Truncate(usize);
+
+impl Stage for Truncate {
+    fn apply(&self, text: String) -> String {
+        match text.char_indices().nth(self.0) {
+            Some((end, _)) => text[..end].to_string(),
+            None => text,
+        }
+    }
+}
+
+static EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap());
+/// 13 to 19 digits, optionally grouped by spaces or dashes
+static CARD_NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").unwrap());
+/// An optional country code, an area code (in parentheses or followed by a separator) and two
+/// groups of 3 or 4 digits, e.g. `(555) 123-4567` or `+44 20 7946 0958`
+static PHONE_NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{2,4}\)[\s.-]?|\b\d{2,4}[\s.-])\d{3,4}[\s.-]?\d{3,4}\b",
+    )
+    .unwrap()
+});
+
+/// Replaces email addresses, card numbers passing the Luhn check and phone numbers by
+/// placeholders, so that they never reach a provider.
+#[derive(Debug)]
+struct RedactPii;
+
+impl RedactPii {
+    /// The redacted text and the number of redacted spans.
+    fn redact(text: &str) -> (String, usize) {
+        let mut count = 0;
+        let text = EMAIL.replace_all(text, |_: &Captures| {
+            count += 1;
+            "[EMAIL]"
+        });
+        let text = CARD_NUMBER.replace_all(&text, |captures: &Captures| {
+            if passes_luhn(&captures[0]) {
+                count += 1;
+                "[CARD]".to_string()
+            } else {
+                captures[0].to_string()
+            }
+        });
+        let text = PHONE_NUMBER.replace_all(&text, |_: &Captures| {
+            count += 1;
+            "[PHONE]"
+        });
+        (text.into_owned(), count)
+    }
+}
+
+impl Stage for RedactPii {
+    fn apply(&self, text: String) -> String {
+        Self::redact(&text).0
+    }
+
+    fn redactions(&self, text: &str) -> usize {
+        Self::redact(text).1
+    }
+}
+
+/// Whether the digits of `number` end with a valid Luhn check digit, as card numbers do.
+fn passes_luhn(number: &str) -> bool {
+    let sum = number
+        .chars()
+        .filter_map(|character| character.to_digit(10))
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum::<u32>();
+    sum % 10 == 0
+}
+
+/// Parses one stage of `--preprocess`, e.g. `lowercase` or `truncate:512`.
+pub fn parse_stage(stage: &str) -> Result<Arc<dyn Stage>, String> {
+    let (name, parameter) = match stage.split_once(':') {
+        Some((name, parameter)) => (name, Some(parameter)),
+        None => (stage, None),
+    };
+
+    match (name, parameter) {
+        ("strip_html", None) => Ok(Arc::new(StripHtml)),
+        ("lowercase", None) => Ok(Arc::new(Lowercase)),
+        ("collapse_ws", None) => Ok(Arc::new(CollapseWhitespace)),
+        ("redact_pii", None) => Ok(Arc::new(RedactPii)),
+        ("truncate", Some(characters)) => characters
+            .parse()
+            .map(|characters| Arc::new(Truncate(characters)) as Arc<dyn Stage>)
+            .map_err(|_| format!("invalid number of characters {characters:?}")),
+        ("truncate", None) => {
+            Err("truncate needs a number of characters, e.g. truncate:512".into())
+        }
+        _ => Err(format!(
+            "unknown stage {stage:?} (expected strip_html, lowercase, collapse_ws, redact_pii or \
+             truncate:N)"
+        )),
+    }
+}
+
+/// Runs every text through the stages in order, printing to stderr how many spans were redacted
+/// in each text that had any.
+pub fn apply(stages: &[Arc<dyn Stage>], texts: Vec<String>) -> Vec<String> {
+    let mut redacted_texts = 0;
+    let mut total_redactions = 0;
+    let texts = texts
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let mut redactions = 0;
+            let text = stages.iter().fold(text, |text, stage| {
+                redactions += stage.redactions(&text);
+                stage.apply(text)
+            });
+            if redactions > 0 {
+                eprintln!("Redactions in document {i}: {redactions}");
+                redacted_texts += 1;
+                total_redactions += redactions;
+            }
+            text
+        })
+        .collect();
+
+    if redacted_texts > 0 {
+        eprintln!("Redactions: {total_redactions} in {redacted_texts} documents");
+    }
+    texts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(stages: &str) -> Vec<Arc<dyn Stage>> {
+        stages
+            .split(',')
+            .map(|stage| parse_stage(stage).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn stages_run_in_order() {
+        let texts = vec!["<p>Fish &amp;\n\n  <b>Chips</b></p>".to_string()];
+        assert_eq!(
+            apply(&pipeline("strip_html,lowercase,collapse_ws"), texts.clone()),
+            ["fish & chips"]
+        );
+        // Truncating first counts the characters of the tags
+        assert_eq!(apply(&pipeline("truncate:4,strip_html"), texts), ["F"]);
+    }
+
+    #[test]
+    fn truncate_counts_characters() {
+        assert_eq!(Truncate(3).apply("héllo".to_string()), "hél");
+        assert_eq!(Truncate(10).apply("short".to_string()), "short");
+        assert_eq!(Truncate(0).apply("gone".to_string()), "");
+    }
+
+    #[test]
+    fn invalid_stages_are_rejected() {
+        for stage in ["uppercase", "truncate", "truncate:many", "lowercase:1"] {
+            assert!(parse_stage(stage).is_err(), "{stage}");
+        }
+    }
+
+    #[test]
+    fn luhn_check_digits() {
+        assert!(passes_luhn("4111 1111 1111 1111"));
+        assert!(passes_luhn("5500-0000-0000-0004"));
+        assert!(passes_luhn("378282246310005"));
+        assert!(!passes_luhn("4111 1111 1111 1112"));
+        assert!(!passes_luhn("1234567890123"));
+    }
+
+    #[test]
+    fn pii_is_redacted_and_counted() {
+        let (text, count) = RedactPii::redact(
+            "Mail jane.doe+news@example.co.uk or call (555) 123-4567, card 4111-1111-1111-1111",
+        );
+        assert_eq!(text, "Mail [EMAIL] or call [PHONE], card [CARD]");
+        assert_eq!(count, 3);
+
+        let (text, count) = RedactPii::redact("Call +44 20 7946 0958 today");
+        assert_eq!(text, "Call [PHONE] today");
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn numbers_failing_luhn_are_not_cards() {
+        // An order number of card length, which isn't a phone number either
+        let (text, count) = RedactPii::redact("Order 1234567890123456 shipped");
+        assert_eq!(text, "Order 1234567890123456 shipped");
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn text_without_pii_is_unchanged() {
+        let text = "Version 3.2 ships in 2024 with 12 fixes";
+        assert_eq!(RedactPii::redact(text), (text.to_string(), 0));
+    }
+}
@@ -0,0 +1,12 @@
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// A progress bar on stderr counting `items` towards `length`, hidden when stderr isn't a
+/// terminal so that logs and pipes stay clean.
+pub fn bar(length: usize, items: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template(&format!(
+        "{{msg}} [{{bar:40}}] {{pos}}/{{len}} {items} ({{elapsed}}, {{eta}} left)"
+    ))
+    .unwrap()
+    .progress_chars("=> ");
+    ProgressBar::new(length as u64).with_style(style)
+}
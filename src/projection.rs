@@ -0,0 +1,339 @@
+use std::{fmt::Display, io::Write};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use rand::Rng;
+use semanticsimilarity_rs::euclidean_distance;
+
+const POWER_ITERATIONS: usize = 200;
+const UMAP_NEIGHBORS: usize = 15;
+const UMAP_EPOCHS: usize = 200;
+const UMAP_NEGATIVE_SAMPLES: usize = 5;
+/// Curve parameters of the low-dimensional similarity `1 / (1 + a * d^(2b))` for `min_dist = 0.1`.
+const UMAP_A: f64 = 1.577;
+const UMAP_B: f64 = 0.895;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Projection {
+    Pca,
+    Umap,
+}
+
+impl Display for Projection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Projection::Pca => write!(f, "pca"),
+            Projection::Umap => write!(f, "umap"),
+        }
+    }
+}
+
+impl Projection {
+    pub fn project(&self, vectors: &[Vec<f64>], rng: &mut impl Rng) -> Vec<[f64; 2]> {
+        match self {
+            Projection::Pca => pca(vectors, rng),
+            Projection::Umap => umap(vectors, rng),
+        }
+    }
+}
+
+fn dot(first: &[f64], second: &[f64]) -> f64 {
+    first.iter().zip(second).map(|(a, b)| a * b).sum()
+}
+
+fn normalize(vector: &mut [f64]) {
+    let norm = dot(vector, vector).sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+/// Projects onto the two principal components, found by power iteration on the implicit
+/// covariance matrix so that no `d x d` matrix is ever built.
+pub fn pca(vectors: &[Vec<f64>], rng: &mut impl Rng) -> Vec<[f64; 2]> {
+    if vectors.is_empty() {
+        return vec![];
+    }
+
+    let dimensions = vectors[0].len();
+    let mut mean = vec![0.0; dimensions];
+    for vector in vectors {
+        for (total, value) in mean.iter_mut().zip(vector) {
+            *total += value / vectors.len() as f64;
+        }
+    }
+    let centered = vectors
+        .iter()
+        .map(|vector| {
+            vector
+                .iter()
+                .zip(&mean)
+                .map(|(value, mean)| value - mean)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut components: Vec<Vec<f64>> = vec![];
+    for _ in 0..2 {
+        let mut component = (0..dimensions)
+            .map(|_| rng.gen_range(-1.0..1.0))
+            .collect::<Vec<_>>();
+
+        for _ in 0..POWER_ITERATIONS {
+            let mut next = vec![0.0; dimensions];
+            for row in &centered {
+                let projection = dot(row, &component);
+                for (total, value) in next.iter_mut().zip(row) {
+                    *total += projection * value;
+                }
+            }
+
+            // Deflate against the components found so far
+            for previous in &components {
+                let overlap = dot(&next, previous);
+                for (value, previous) in next.iter_mut().zip(previous) {
+                    *value -= overlap * previous;
+                }
+            }
+
+            normalize(&mut next);
+            component = next;
+        }
+
+        components.push(component);
+    }
+
+    centered
+        .iter()
+        .map(|row| [dot(row, &components[0]), dot(row, &components[1])])
+        .collect()
+}
+
+/// A compact UMAP: fuzzy k-nearest-neighbor graph in the embedding space, laid out in 2D by
+/// stochastic gradient descent with negative sampling, starting from the PCA projection.
+pub fn umap(vectors: &[Vec<f64>], rng: &mut impl Rng) -> Vec<[f64; 2]> {
+    let n = vectors.len();
+    let neighbors = UMAP_NEIGHBORS.min(n.saturating_sub(1));
+    if neighbors == 0 {
+        return pca(vectors, rng);
+    }
+
+    // Fuzzy membership strengths of each point's k nearest neighbors
+    let mut weights = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        let nearest = (0..n)
+            .filter(|j| *j != i)
+            .map(|j| (j, euclidean_distance(&vectors[i], &vectors[j])))
+            .sorted_by(|(_, a), (_, b)| a.total_cmp(b))
+            .take(neighbors)
+            .collect::<Vec<_>>();
+
+        let rho = nearest[0].1;
+        let sigma = smooth_knn_sigma(&nearest, rho, (neighbors as f64).log2());
+        for (j, distance) in nearest {
+            weights[i][j] = (-(distance - rho).max(0.0) / sigma).exp();
+        }
+    }
+
+    // Fuzzy union of the directed graph with its transpose
+    let edges = (0..n)
+        .tuple_combinations()
+        .filter_map(|(i, j)| {
+            let (a, b) = (weights[i][j], weights[j][i]);
+            let weight = a + b - a * b;
+            (weight > 0.0).then_some((i, j, weight))
+        })
+        .collect::<Vec<_>>();
+    let max_weight = edges
+        .iter()
+        .map(|(_, _, weight)| *weight)
+        .fold(0.0, f64::max);
+
+    let mut embedding = pca(vectors, rng);
+    let scale = embedding
+        .iter()
+        .flat_map(|point| point.iter().map(|value| value.abs()))
+        .fold(0.0, f64::max);
+    if scale > 0.0 {
+        for point in embedding.iter_mut() {
+            point.iter_mut().for_each(|value| *value *= 10.0 / scale);
+        }
+    }
+
+    for epoch in 0..UMAP_EPOCHS {
+        let learning_rate = 1.0 - epoch as f64 / UMAP_EPOCHS as f64;
+
+        for (i, j, weight) in &edges {
+            // Sample edges proportionally to their weight
+            if rng.gen::<f64>() > weight / max_weight {
+                continue;
+            }
+
+            let gradient = attraction(&embedding[*i], &embedding[*j]);
+            for axis in 0..2 {
+                embedding[*i][axis] += learning_rate * gradient[axis];
+                embedding[*j][axis] -= learning_rate * gradient[axis];
+            }
+
+            for _ in 0..UMAP_NEGATIVE_SAMPLES {
+                let k = rng.gen_range(0..n);
+                if k == *i {
+                    continue;
+                }
+
+                let gradient = repulsion(&embedding[*i], &embedding[k]);
+                for axis in 0..2 {
+                    embedding[*i][axis] += learning_rate * gradient[axis];
+                }
+            }
+        }
+    }
+
+    embedding
+}
+
+/// Binary search for the bandwidth that makes the neighbor memberships sum to `target`.
+fn smooth_knn_sigma(nearest: &[(usize, f64)], rho: f64, target: f64) -> f64 {
+    let (mut low, mut high, mut sigma) = (0.0, f64::INFINITY, 1.0);
+
+    for _ in 0..64 {
+        let total = nearest
+            .iter()
+            .map(|(_, distance)| (-(distance - rho).max(0.0) / sigma).exp())
+            .sum::<f64>();
+
+        if (total - target).abs() < 1e-5 {
+            break;
+        }
+
+        if total > target {
+            high = sigma;
+            sigma = (low + high) / 2.0;
+        } else {
+            low = sigma;
+            sigma = if high.is_infinite() {
+                sigma * 2.0
+            } else {
+                (low + high) / 2.0
+            };
+        }
+    }
+
+    sigma.max(1e-3)
+}
+
+fn squared_distance(first: &[f64; 2], second: &[f64; 2]) -> f64 {
+    (first[0] - second[0]).powi(2) + (first[1] - second[1]).powi(2)
+}
+
+fn clip(value: f64) -> f64 {
+    value.clamp(-4.0, 4.0)
+}
+
+fn attraction(first: &[f64; 2], second: &[f64; 2]) -> [f64; 2] {
+    let distance = squared_distance(first, second);
+    if distance == 0.0 {
+        return [0.0; 2];
+    }
+
+    let coefficient = -2.0 * UMAP_A * UMAP_B * distance.powf(UMAP_B - 1.0)
+        / (1.0 + UMAP_A * distance.powf(UMAP_B));
+    [
+        clip(coefficient * (first[0] - second[0])),
+        clip(coefficient * (first[1] - second[1])),
+    ]
+}
+
+fn repulsion(first: &[f64; 2], second: &[f64; 2]) -> [f64; 2] {
+    let distance = squared_distance(first, second);
+    let coefficient = 2.0 * UMAP_B / ((0.001 + distance) * (1.0 + UMAP_A * distance.powf(UMAP_B)));
+    [
+        clip(coefficient * (first[0] - second[0])),
+        clip(coefficient * (first[1] - second[1])),
+    ]
+}
+
+/// Writes one `index,label,x,y` row per document.
+pub fn write_points(writer: impl Write, input_strings: &[String], points: &[[f64; 2]]) {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["index", "label", "x", "y"]).unwrap();
+    for (i, (string, [x, y])) in input_strings.iter().zip(points).enumerate() {
+        writer
+            .write_record([i.to_string(), string.clone(), x.to_string(), y.to_string()])
+            .unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn line(n: usize) -> Vec<Vec<f64>> {
+        (0..n).map(|i| vec![i as f64, 0.5, -1.0]).collect()
+    }
+
+    #[test]
+    fn projections_have_one_point_per_vector() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for projection in [Projection::Pca, Projection::Umap] {
+            for n in [1, 2, 20] {
+                assert_eq!(projection.project(&line(n), &mut rng).len(), n);
+            }
+        }
+    }
+
+    #[test]
+    fn no_vectors_project_to_no_points() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(pca(&[], &mut rng).is_empty());
+        assert!(umap(&[], &mut rng).is_empty());
+    }
+
+    #[test]
+    fn pca_keeps_the_order_along_the_main_axis() {
+        let points = pca(&line(5), &mut StdRng::seed_from_u64(0));
+
+        // The first component is the direction of the line, up to its sign
+        let first = points.iter().map(|point| point[0]).collect::<Vec<_>>();
+        let increasing = first.windows(2).all(|pair| pair[0] < pair[1]);
+        let decreasing = first.windows(2).all(|pair| pair[0] > pair[1]);
+        assert!(increasing || decreasing, "{first:?}");
+        for (point, expected) in first.iter().zip([-2.0, -1.0, 0.0, 1.0, 2.0]) {
+            assert!((point.abs() - f64::abs(expected)).abs() < 1e-6);
+        }
+        assert!(points.iter().all(|point| point[1].abs() < 1e-6));
+    }
+
+    #[test]
+    fn umap_separates_distant_clusters() {
+        let vectors = (0..20)
+            .map(|i| {
+                let offset = if i < 10 { 0.0 } else { 100.0 };
+                vec![offset + (i % 10) as f64 * 0.1, offset]
+            })
+            .collect::<Vec<_>>();
+        let points = umap(&vectors, &mut StdRng::seed_from_u64(0));
+
+        let centroid = |points: &[[f64; 2]]| {
+            let n = points.len() as f64;
+            [
+                points.iter().map(|point| point[0]).sum::<f64>() / n,
+                points.iter().map(|point| point[1]).sum::<f64>() / n,
+            ]
+        };
+        let spread = |points: &[[f64; 2]], center: [f64; 2]| {
+            points
+                .iter()
+                .map(|point| squared_distance(point, &center).sqrt())
+                .fold(0.0, f64::max)
+        };
+        let (first, second) = points.split_at(10);
+        let (first_center, second_center) = (centroid(first), centroid(second));
+        let gap = squared_distance(&first_center, &second_center).sqrt();
+        assert!(gap > spread(first, first_center));
+        assert!(gap > spread(second, second_center));
+    }
+}
@@ -0,0 +1,643 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+use reqwest::{header::HeaderMap, Response};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    allowlist,
+    images::{self, Modality},
+};
+
+const OPENAI_API_BASE_URL: &str = "https://api.openai.com";
+const COHERE_API_BASE_URL: &str = "https://api.cohere.ai";
+
+/// Dimensions of the vectors of `--provider mock` without `--dimensions`.
+pub const MOCK_DIMENSIONS: usize = 256;
+
+/// Response header carrying the provider's identifier for a request.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Whether embedding requests are forbidden, set from `--offline`.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// A document and its embedding vector.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Embedding {
+    pub document: String,
+    pub vec: Vec<f64>,
+}
+
+/// Embeddings returned for one request, with the number of tokens the provider billed for it.
+pub struct EmbeddingResponse {
+    pub embeddings: Vec<Embedding>,
+    pub tokens: u64,
+    /// Snapshot of the model that served the request, for providers that report it
+    pub model_version: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmbeddingError {
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("provider error{}: {message}", request_id_suffix(.request_id))]
+    ProviderError {
+        message: String,
+        /// Identifier the provider assigned to the failed request, to quote in support tickets
+        request_id: Option<String>,
+    },
+
+    #[error("{0}")]
+    UnreadableImage(String),
+}
+
+impl EmbeddingError {
+    /// Identifier the provider assigned to the failed request, if it answered.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            EmbeddingError::HttpError(_) | EmbeddingError::UnreadableImage(_) => None,
+            EmbeddingError::ProviderError { request_id, .. } => request_id.as_deref(),
+        }
+    }
+}
+
+fn request_id_suffix(request_id: &Option<String>) -> String {
+    match request_id {
+        Some(request_id) => format!(" (request id {request_id})"),
+        None => String::new(),
+    }
+}
+
+fn request_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+#[derive(Deserialize)]
+struct OpenaiErrorResponse {
+    error: OpenaiError,
+}
+
+#[derive(Deserialize)]
+struct OpenaiError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct CohereErrorResponse {
+    message: String,
+}
+
+/// Turns a non-success response into an [EmbeddingError], keeping the provider's request id.
+async fn provider_error(
+    response: Response,
+    parse_message: fn(&str) -> Option<String>,
+) -> EmbeddingError {
+    let request_id = request_id(response.headers());
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+
+    EmbeddingError::ProviderError {
+        message: parse_message(&body).unwrap_or_else(|| format!("{status}: {body}")),
+        request_id,
+    }
+}
+
+/// Parses a successful response, read chunk by chunk into a buffer sized from its
+/// `Content-Length`. A batch of 4k-8k dimensional embeddings is hundreds of megabytes of JSON,
+/// which is then held once instead of as its chunks plus their concatenation.
+async fn read_json<T: DeserializeOwned>(
+    mut response: Response,
+    request_id: &Option<String>,
+) -> Result<T, EmbeddingError> {
+    let length = response.content_length().unwrap_or_default();
+    let mut body = Vec::with_capacity(length as usize);
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+    }
+    tracing::trace!(bytes = body.len(), "Read response");
+
+    serde_json::from_slice(&body).map_err(|error| EmbeddingError::ProviderError {
+        message: format!("invalid response: {error}"),
+        request_id: request_id.clone(),
+    })
+}
+
+#[derive(Deserialize)]
+struct OpenaiEmbeddingResponse {
+    data: Vec<OpenaiEmbeddingData>,
+    usage: OpenaiUsage,
+    #[serde(default)]
+    model: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenaiUsage {
+    total_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct OpenaiEmbeddingData {
+    embedding: Vec<f64>,
+}
+
+pub struct OpenaiClient {
+    http_client: reqwest::Client,
+    api_key: String,
+    organization: Option<String>,
+    project: Option<String>,
+    dimensions: Option<usize>,
+}
+
+impl OpenaiClient {
+    pub fn new(api_key: &str) -> Self {
+        OpenaiClient {
+            http_client: allowlist::http_client(),
+            api_key: api_key.to_string(),
+            organization: None,
+            project: None,
+            dimensions: None,
+        }
+    }
+
+    /// Bills requests to this organization (`OpenAI-Organization` header).
+    pub fn with_organization(mut self, organization: Option<String>) -> Self {
+        self.organization = organization;
+        self
+    }
+
+    /// Bills requests to this project (`OpenAI-Project` header).
+    pub fn with_project(mut self, project: Option<String>) -> Self {
+        self.project = project;
+        self
+    }
+
+    /// Asks for embeddings shortened to this many dimensions (`dimensions` parameter).
+    pub fn with_dimensions(mut self, dimensions: Option<usize>) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    pub async fn embed_documents(
+        &self,
+        model: &str,
+        documents: Vec<String>,
+    ) -> Result<EmbeddingResponse, EmbeddingError> {
+        let url = format!("{OPENAI_API_BASE_URL}/v1/embeddings");
+        allowlist::check(&url);
+        let mut request = self.http_client.post(url).bearer_auth(&self.api_key);
+        if let Some(organization) = &self.organization {
+            request = request.header("OpenAI-Organization", organization);
+        }
+        if let Some(project) = &self.project {
+            request = request.header("OpenAI-Project", project);
+        }
+
+        let mut body = json!({
+            "model": model,
+            "input": documents,
+        });
+        if let Some(dimensions) = self.dimensions {
+            body["dimensions"] = json!(dimensions);
+        }
+
+        let response = request.json(&body).send().await?;
+
+        if !response.status().is_success() {
+            return Err(provider_error(response, |body| {
+                serde_json::from_str::<OpenaiErrorResponse>(body)
+                    .ok()
+                    .map(|response| response.error.message)
+            })
+            .await);
+        }
+
+        let request_id = request_id(response.headers());
+        let response = read_json::<OpenaiEmbeddingResponse>(response, &request_id).await?;
+
+        Ok(EmbeddingResponse {
+            embeddings: zip_embeddings(
+                documents,
+                response
+                    .data
+                    .into_iter()
+                    .map(|data| data.embedding)
+                    .collect(),
+                request_id,
+            )?,
+            tokens: response.usage.total_tokens,
+            model_version: response.model,
+        })
+    }
+}
+
+/// What the embedded texts are for; Cohere embeds queries and documents differently.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum CohereInputType {
+    #[value(name = "search_document")]
+    SearchDocument,
+    #[value(name = "search_query")]
+    SearchQuery,
+    Classification,
+    Clustering,
+}
+
+impl Display for CohereInputType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CohereInputType::SearchDocument => write!(f, "search_document"),
+            CohereInputType::SearchQuery => write!(f, "search_query"),
+            CohereInputType::Classification => write!(f, "classification"),
+            CohereInputType::Clustering => write!(f, "clustering"),
+        }
+    }
+}
+
+/// Type of the values of the embeddings Cohere returns.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum CohereEmbeddingType {
+    Float,
+    Int8,
+    Uint8,
+    /// Signs packed 8 per signed byte, unpacked to -1 or 1 per dimension
+    Binary,
+    /// Signs packed 8 per unsigned byte, unpacked to -1 or 1 per dimension
+    Ubinary,
+}
+
+impl Display for CohereEmbeddingType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CohereEmbeddingType::Float => write!(f, "float"),
+            CohereEmbeddingType::Int8 => write!(f, "int8"),
+            CohereEmbeddingType::Uint8 => write!(f, "uint8"),
+            CohereEmbeddingType::Binary => write!(f, "binary"),
+            CohereEmbeddingType::Ubinary => write!(f, "ubinary"),
+        }
+    }
+}
+
+impl CohereEmbeddingType {
+    /// The vector an embedding of this type stands for.
+    fn unpack(&self, values: Vec<f64>) -> Vec<f64> {
+        let bits = |byte: u8| (0..8).rev().map(move |bit| (byte >> bit) & 1);
+        match self {
+            CohereEmbeddingType::Binary => values
+                .into_iter()
+                .flat_map(|value| bits(value as i8 as u8))
+                .map(|bit| if bit == 1 { 1.0 } else { -1.0 })
+                .collect(),
+            CohereEmbeddingType::Ubinary => values
+                .into_iter()
+                .flat_map(|value| bits(value as u8))
+                .map(|bit| if bit == 1 { 1.0 } else { -1.0 })
+                .collect(),
+            _ => values,
+        }
+    }
+}
+
+/// Embeddings as a plain list of float vectors, or by type when `embedding_types` is requested.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CohereEmbeddings {
+    Float(Vec<Vec<f64>>),
+    ByType(HashMap<String, Vec<Vec<f64>>>),
+}
+
+#[derive(Deserialize)]
+struct CohereEmbeddingResponse {
+    embeddings: CohereEmbeddings,
+    #[serde(default)]
+    meta: Option<CohereMeta>,
+}
+
+#[derive(Deserialize)]
+struct CohereMeta {
+    billed_units: CohereBilledUnits,
+}
+
+#[derive(Deserialize)]
+struct CohereBilledUnits {
+    #[serde(default)]
+    input_tokens: u64,
+}
+
+pub struct CohereClient {
+    http_client: reqwest::Client,
+    api_key: String,
+    embedding_type: Option<CohereEmbeddingType>,
+    modality: Modality,
+}
+
+impl CohereClient {
+    pub fn new(api_key: &str) -> Self {
+        CohereClient {
+            http_client: allowlist::http_client(),
+            api_key: api_key.to_string(),
+            embedding_type: None,
+            modality: Modality::Text,
+        }
+    }
+
+    /// Asks for embeddings of this type (`embedding_types` parameter) instead of floats.
+    pub fn with_embedding_type(mut self, embedding_type: Option<CohereEmbeddingType>) -> Self {
+        self.embedding_type = embedding_type;
+        self
+    }
+
+    /// Embeds the documents `modality` takes as images from their files.
+    pub fn with_modality(mut self, modality: Modality) -> Self {
+        self.modality = modality;
+        self
+    }
+
+    /// The v2 inputs of `documents`, texts or images read from their files.
+    fn inputs(&self, documents: &[String]) -> Result<Vec<serde_json::Value>, EmbeddingError> {
+        documents
+            .iter()
+            .map(|document| {
+                let content = if self.modality.is_image(document) {
+                    let url =
+                        images::data_uri(document).map_err(EmbeddingError::UnreadableImage)?;
+                    json!({"type": "image_url", "image_url": {"url": url}})
+                } else {
+                    json!({"type": "text", "text": document})
+                };
+                Ok(json!({"content": [content]}))
+            })
+            .collect()
+    }
+
+    pub async fn embed_documents(
+        &self,
+        model: &str,
+        input_type: CohereInputType,
+        documents: Vec<String>,
+    ) -> Result<EmbeddingResponse, EmbeddingError> {
+        let (url, mut body) = match self.modality {
+            Modality::Text => (
+                format!("{COHERE_API_BASE_URL}/v1/embed"),
+                json!({
+                    "model": model,
+                    "texts": documents,
+                    "input_type": input_type.to_string(),
+                }),
+            ),
+            // Only the v2 API embeds images, taking texts and images alike as inputs
+            Modality::Image | Modality::Mixed => (
+                format!("{COHERE_API_BASE_URL}/v2/embed"),
+                json!({
+                    "model": model,
+                    "inputs": self.inputs(&documents)?,
+                    "input_type": input_type.to_string(),
+                    "embedding_types": [CohereEmbeddingType::Float.to_string()],
+                }),
+            ),
+        };
+        if let Some(embedding_type) = self.embedding_type {
+            body["embedding_types"] = json!([embedding_type.to_string()]);
+        }
+
+        allowlist::check(&url);
+        let response = self
+            .http_client
+            .post(url)
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(provider_error(response, |body| {
+                serde_json::from_str::<CohereErrorResponse>(body)
+                    .ok()
+                    .map(|response| response.message)
+            })
+            .await);
+        }
+
+        let request_id = request_id(response.headers());
+        let response = read_json::<CohereEmbeddingResponse>(response, &request_id).await?;
+
+        let vectors = match (response.embeddings, self.embedding_type) {
+            (CohereEmbeddings::Float(vectors), _) => vectors,
+            (CohereEmbeddings::ByType(mut by_type), embedding_type) => {
+                let embedding_type = embedding_type.unwrap_or(CohereEmbeddingType::Float);
+                let vectors = by_type.remove(&embedding_type.to_string()).ok_or_else(|| {
+                    EmbeddingError::ProviderError {
+                        message: format!("response has no {embedding_type} embeddings"),
+                        request_id: request_id.clone(),
+                    }
+                })?;
+                vectors
+                    .into_iter()
+                    .map(|vector| embedding_type.unpack(vector))
+                    .collect()
+            }
+        };
+
+        Ok(EmbeddingResponse {
+            embeddings: zip_embeddings(documents, vectors, request_id)?,
+            tokens: response
+                .meta
+                .map(|meta| meta.billed_units.input_tokens)
+                .unwrap_or_default(),
+            model_version: None,
+        })
+    }
+}
+
+/// Embeds without any provider, for tests and demos: every word gets a pseudo-random unit vector
+/// drawn from the SHA-256 of the model and the word, and texts the mean of the vectors of their
+/// words, so that texts sharing words are closer and every run on every machine gives the same
+/// vectors. Texts of a fixture get its vectors instead.
+pub struct MockClient {
+    dimensions: usize,
+    fixture: Option<HashMap<String, Vec<f64>>>,
+}
+
+impl MockClient {
+    pub fn new(dimensions: Option<usize>) -> Self {
+        MockClient {
+            dimensions: dimensions.unwrap_or(MOCK_DIMENSIONS),
+            fixture: None,
+        }
+    }
+
+    /// Embeds every text as its vector in `fixture`, failing on the texts missing from it.
+    pub fn with_fixture(mut self, fixture: Option<HashMap<String, Vec<f64>>>) -> Self {
+        self.fixture = fixture;
+        self
+    }
+
+    pub fn embed_documents(
+        &self,
+        model: &str,
+        documents: Vec<String>,
+    ) -> Result<EmbeddingResponse, EmbeddingError> {
+        let vectors =
+            documents
+                .iter()
+                .map(|document| match &self.fixture {
+                    Some(fixture) => fixture.get(document).cloned().ok_or_else(|| {
+                        EmbeddingError::ProviderError {
+                            message: format!("the fixture has no vector for {document:?}"),
+                            request_id: None,
+                        }
+                    }),
+                    None => Ok(pseudo_embedding(model, document, self.dimensions)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+        Ok(EmbeddingResponse {
+            embeddings: zip_embeddings(documents, vectors, None)?,
+            tokens: 0,
+            model_version: None,
+        })
+    }
+}
+
+/// Unit vector drawn from the SHA-256 of `model` and `word`, 32 bits per dimension.
+fn pseudo_vector(model: &str, word: &str, dimensions: usize) -> Vec<f64> {
+    let seed = Sha256::new()
+        .chain_update(model)
+        .chain_update([0])
+        .chain_update(word)
+        .finalize();
+    let vector = (0..dimensions.div_ceil(8) as u64)
+        .flat_map(|block| {
+            let hash = Sha256::new()
+                .chain_update(seed)
+                .chain_update(block.to_le_bytes())
+                .finalize();
+            (0..8)
+                .map(|i| {
+                    let bits = u32::from_le_bytes(hash[i * 4..i * 4 + 4].try_into().unwrap());
+                    f64::from(bits) / f64::from(u32::MAX) * 2.0 - 1.0
+                })
+                .collect::<Vec<_>>()
+        })
+        .take(dimensions)
+        .collect::<Vec<_>>();
+    unit(vector)
+}
+
+fn unit(vector: Vec<f64>) -> Vec<f64> {
+    let norm = vector.iter().map(|value| value * value).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return vector;
+    }
+    vector.into_iter().map(|value| value / norm).collect()
+}
+
+/// The mean of the pseudo-random vectors of the lowercase words of `text`, as a unit vector, or
+/// of the whole text when it has no words.
+fn pseudo_embedding(model: &str, text: &str, dimensions: usize) -> Vec<f64> {
+    let words = text
+        .split(|character: char| !character.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return pseudo_vector(model, text, dimensions);
+    }
+    let mut sum = vec![0.0; dimensions];
+    for word in &words {
+        for (total, value) in sum.iter_mut().zip(pseudo_vector(model, word, dimensions)) {
+            *total += value;
+        }
+    }
+    unit(sum)
+}
+
+fn zip_embeddings(
+    documents: Vec<String>,
+    vectors: Vec<Vec<f64>>,
+    request_id: Option<String>,
+) -> Result<Vec<Embedding>, EmbeddingError> {
+    if vectors.len() != documents.len() {
+        return Err(EmbeddingError::ProviderError {
+            message: format!(
+                "received {} embeddings for {} documents",
+                vectors.len(),
+                documents.len()
+            ),
+            request_id,
+        });
+    }
+
+    Ok(documents
+        .into_iter()
+        .zip(vectors)
+        .map(|(document, vec)| Embedding { document, vec })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cosine(first: &[f64], second: &[f64]) -> f64 {
+        first.iter().zip(second).map(|(a, b)| a * b).sum()
+    }
+
+    #[test]
+    fn mock_embeddings_are_deterministic_unit_vectors() {
+        let client = MockClient::new(Some(12));
+        let texts = ["The cat sat", "the CAT sat!", "tax returns", "the cat ran"];
+        let response = client
+            .embed_documents("hash", texts.map(str::to_string).to_vec())
+            .unwrap();
+        let vectors = response
+            .embeddings
+            .iter()
+            .map(|embedding| embedding.vec.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(vectors[0].len(), 12);
+        assert!((cosine(&vectors[0], &vectors[0]) - 1.0).abs() < 1e-12);
+        // Words are compared by their lowercase letters and digits
+        assert_eq!(vectors[0], vectors[1]);
+        assert!(cosine(&vectors[0], &vectors[3]) > cosine(&vectors[0], &vectors[2]));
+        assert_eq!(vectors[0], pseudo_embedding("hash", "the cat sat", 12));
+        assert_ne!(vectors[0], pseudo_embedding("other", "the cat sat", 12));
+        assert_eq!(
+            MockClient::new(None)
+                .embed_documents("hash", vec!["!?".to_string()])
+                .unwrap()
+                .embeddings[0]
+                .vec
+                .len(),
+            MOCK_DIMENSIONS
+        );
+    }
+
+    #[test]
+    fn mock_fixtures_give_their_vectors() {
+        let fixture = HashMap::from([("cat".to_string(), vec![1.0, 0.0])]);
+        let client = MockClient::new(None).with_fixture(Some(fixture));
+        let response = client.embed_documents("hash", vec!["cat".to_string()]);
+        assert_eq!(response.unwrap().embeddings[0].vec, [1.0, 0.0]);
+        let error = client
+            .embed_documents("hash", vec!["dog".to_string()])
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("no vector for \"dog\""));
+    }
+}
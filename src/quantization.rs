@@ -0,0 +1,227 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+use crate::{
+    search::exact_neighbors,
+    table,
+    truncation::{pair_scores, Drift},
+    DistanceMetric,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Quantization {
+    /// 8-bit integers spanning the range of every dimension over the corpus
+    Int8,
+    /// One sign bit per dimension
+    Binary,
+}
+
+impl Display for Quantization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quantization::Int8 => write!(f, "int8"),
+            Quantization::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+impl Quantization {
+    fn bytes_per_vector(&self, dimensions: usize) -> usize {
+        match self {
+            Quantization::Int8 => dimensions,
+            Quantization::Binary => dimensions.div_ceil(8),
+        }
+    }
+
+    /// The vectors as scored after quantization: int8 codes mapped back into the range of each
+    /// dimension, bits as -1 or 1.
+    fn quantize(&self, vectors: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        match self {
+            Quantization::Int8 => {
+                let dimensions = vectors[0].len();
+                let (minimums, maximums) = (0..dimensions)
+                    .map(|d| {
+                        vectors
+                            .iter()
+                            .fold((f64::MAX, f64::MIN), |(min, max), vector| {
+                                (min.min(vector[d]), max.max(vector[d]))
+                            })
+                    })
+                    .unzip::<_, _, Vec<_>, Vec<_>>();
+
+                vectors
+                    .iter()
+                    .map(|vector| {
+                        vector
+                            .iter()
+                            .zip(minimums.iter().zip(&maximums))
+                            .map(|(value, (min, max))| {
+                                let step = (max - min) / 255.0;
+                                if step == 0.0 {
+                                    return *min;
+                                }
+                                min + ((value - min) / step).round() * step
+                            })
+                            .collect()
+                    })
+                    .collect()
+            }
+            Quantization::Binary => vectors
+                .iter()
+                .map(|vector| {
+                    vector
+                        .iter()
+                        .map(|value| if *value > 0.0 { 1.0 } else { -1.0 })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The vectors as stored in float32, the reference of the quantized ones.
+fn float32(vectors: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    vectors
+        .iter()
+        .map(|vector| vector.iter().map(|value| *value as f32 as f64).collect())
+        .collect()
+}
+
+/// The drift of the vectors stored with each of `quantizations` from the float32 ones.
+fn drifts(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    quantizations: &[Quantization],
+    k: usize,
+    distance_metric: &DistanceMetric,
+) -> Vec<Drift> {
+    let float32 = float32(vectors);
+    let float32_scores = pair_scores(&float32, distance_metric);
+    let float32_neighbors = exact_neighbors(&float32, queries, k, distance_metric);
+    quantizations
+        .iter()
+        .map(|quantization| {
+            Drift::measure(
+                &quantization.quantize(&float32),
+                &float32_scores,
+                &float32_neighbors,
+                queries,
+                k,
+                distance_metric,
+            )
+        })
+        .collect()
+}
+
+/// Prints how far the pairwise scores and the top `k` neighbors of `queries` drift when the
+/// vectors are stored with each of `quantizations` rather than as float32.
+///
+/// Binary vectors are scored as vectors of -1 and 1, for which cosine is a linear function of
+/// the Hamming distance; their drift is only meaningful for cosine, their rank correlation for
+/// every metric.
+pub fn print_report(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    quantizations: &[Quantization],
+    k: usize,
+    distance_metric: &DistanceMetric,
+) {
+    let dimensions = vectors[0].len();
+    let mut table = vec![
+        vec![
+            "storage".to_string(),
+            "bytes per vector".to_string(),
+            "mean drift".to_string(),
+            "max drift".to_string(),
+            "spearman".to_string(),
+            format!("recall@{k}"),
+        ],
+        vec![
+            "float32".to_string(),
+            (dimensions * std::mem::size_of::<f32>()).to_string(),
+            "0".to_string(),
+            "0".to_string(),
+            "1".to_string(),
+            "1".to_string(),
+        ],
+    ];
+    let drifts = drifts(vectors, queries, quantizations, k, distance_metric);
+    for (quantization, drift) in quantizations.iter().zip(drifts) {
+        table.push(
+            [
+                quantization.to_string(),
+                quantization.bytes_per_vector(dimensions).to_string(),
+            ]
+            .into_iter()
+            .chain(drift.cells())
+            .collect(),
+        );
+    }
+
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vectors() -> Vec<Vec<f64>> {
+        vec![
+            vec![0.1, -0.5, 0.3],
+            vec![0.9, 0.2, 0.3],
+            vec![-0.4, 0.7, 0.3],
+            vec![0.5, -0.1, 0.3],
+        ]
+    }
+
+    #[test]
+    fn int8_codes_stay_within_half_a_step() {
+        let vectors = vectors();
+        let quantized = Quantization::Int8.quantize(&vectors);
+        let steps = [1.3 / 255.0, 1.2 / 255.0];
+        for (vector, quantized) in vectors.iter().zip(&quantized) {
+            for d in 0..2 {
+                assert!((vector[d] - quantized[d]).abs() <= steps[d] / 2.0 + 1e-12);
+            }
+            // A constant dimension is kept as is
+            assert_eq!(quantized[2], 0.3);
+        }
+        // The extremes of every dimension are codes 0 and 255
+        assert!((quantized[1][0] - 0.9).abs() < 1e-12);
+        assert!((quantized[2][0] + 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn binary_vectors_keep_the_signs() {
+        assert_eq!(
+            Quantization::Binary.quantize(&vectors()[..2]),
+            [vec![1.0, -1.0, 1.0], vec![1.0, 1.0, 1.0]]
+        );
+        assert_eq!(Quantization::Binary.quantize(&[vec![0.0]]), [vec![-1.0]]);
+    }
+
+    #[test]
+    fn quantized_vectors_take_fewer_bytes() {
+        assert_eq!(Quantization::Int8.bytes_per_vector(1536), 1536);
+        assert_eq!(Quantization::Binary.bytes_per_vector(1536), 192);
+        assert_eq!(Quantization::Binary.bytes_per_vector(10), 2);
+    }
+
+    #[test]
+    fn binary_drifts_further_than_int8() {
+        let queries = [0, 1, 2, 3];
+        let drifts = drifts(
+            &vectors(),
+            &queries,
+            &[Quantization::Int8, Quantization::Binary],
+            1,
+            &DistanceMetric::Cosine,
+        );
+        let (int8, binary) = (&drifts[0], &drifts[1]);
+        assert!(int8.max < 0.01, "{int8:?}");
+        assert_eq!(int8.recall, 1.0);
+        assert!(binary.mean > int8.mean && binary.max > 0.1, "{binary:?}");
+        assert!(binary.max >= binary.mean);
+    }
+}
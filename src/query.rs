@@ -0,0 +1,435 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::{Instant, UNIX_EPOCH},
+};
+
+use clap::{ArgGroup, Args};
+use itertools::Itertools;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    embed,
+    embedding_file::{self, Matrix},
+    format_header,
+    hnsw::{self, Hnsw, IndexArgs},
+    metrics, models, search, table,
+    template::{self, Template},
+    vector_store::{Collection, VectorStore},
+    warnings, DistanceMetric, Provider, ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("corpus").required(true).args(["embeddings", "store"])))]
+pub struct QueryArgs {
+    /// Corpus to search, written by `--save-embeddings` with the same model
+    #[arg(long)]
+    embeddings: Option<String>,
+    /// Search the vectors already indexed in this vector store instead of a saved corpus
+    #[arg(long, requires = "collection", conflicts_with_all = ["index", "verify_sample"])]
+    store: Option<VectorStore>,
+    /// Collection of the `--store` to search, embedded with the same model
+    #[arg(long, requires = "store")]
+    collection: Option<String>,
+    /// URL of the `--store` [default: `QDRANT_URL` or http://localhost:6333, `LANCEDB_URL`]
+    #[arg(long, requires = "store")]
+    store_url: Option<String>,
+    /// Named vector (Qdrant) or vector column (LanceDB) to search
+    #[arg(long, requires = "store")]
+    store_vector: Option<String>,
+    /// Payload field (Qdrant) or column (LanceDB) holding the text of every point
+    #[arg(long, default_value = "text", requires = "store")]
+    store_text_field: String,
+    /// Query text (repeat for several queries)
+    #[arg(short, long, required = true)]
+    query: Vec<String>,
+    /// Embed every query as this template, `{}` standing for the query, e.g. `query: {}` for e5
+    /// models whose corpus was embedded with `--template "passage: {}"`
+    #[arg(long, value_parser = template::parse)]
+    template: Option<Template>,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// Number of results per query
+    #[arg(short = 'k', long, default_value_t = 10)]
+    top_k: usize,
+    /// `--embeddings` corpora are searched with an HNSW index built once and saved next to them
+    /// as `<embeddings>.hnsw`
+    #[command(flatten)]
+    index: IndexArgs,
+    /// Append one JSON line per query with its timings and the scores of every document
+    #[arg(long)]
+    trace: Option<String>,
+}
+
+/// One line of the `--trace` file.
+#[derive(Serialize)]
+struct Trace<'a> {
+    query: &'a str,
+    embed_ms: f64,
+    search_ms: f64,
+    /// Score of every corpus document, in corpus order, or of every point returned by `--store`
+    /// or the HNSW index
+    scores: &'a [f64],
+    /// Non-fatal warnings raised while answering the query
+    warnings: Vec<String>,
+}
+
+/// Documents searched by the queries.
+enum Corpus<'a> {
+    Saved(Matrix),
+    Indexed(Matrix, Hnsw),
+    Store(Collection<'a>),
+}
+
+/// What the HNSW index of an embeddings file of `length` bytes last modified at `modified`
+/// indexes, so that it's built again when the file or the metric change.
+fn fingerprint(length: u64, modified: u128, distance_metric: &DistanceMetric, p: f64) -> String {
+    match distance_metric {
+        DistanceMetric::Minkowski => format!("{length}:{modified}:{distance_metric}:{p}"),
+        _ => format!("{length}:{modified}:{distance_metric}"),
+    }
+}
+
+/// The HNSW index of `corpus` saved next to the `embeddings` file, built and saved first if
+/// missing or of an older version of the file.
+fn index(embeddings: &str, corpus: &Matrix, distance_metric: &DistanceMetric) -> Hnsw {
+    let metadata = std::fs::metadata(embeddings)
+        .unwrap_or_else(|error| panic!("Failed to read {embeddings}: {error}"));
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    let fingerprint = fingerprint(
+        metadata.len(),
+        modified,
+        distance_metric,
+        metrics::minkowski_p(),
+    );
+    let path = PathBuf::from(format!("{embeddings}.hnsw"));
+    if let Some(index) = Hnsw::load(&path, &fingerprint, corpus.rows()) {
+        return index;
+    }
+
+    let index = Hnsw::index(corpus.rows(), |a, b| {
+        hnsw::cost(distance_metric, corpus.row(a), corpus.row(b))
+    });
+    if let Err(error) = index.save(&path, &fingerprint) {
+        warnings::warn(format!(
+            "Failed to save the HNSW index to {}, it will be built again: {error}",
+            path.display()
+        ));
+    }
+    index
+}
+
+/// The labels and scores of the documents of a saved `corpus` for the query `vector`: of every
+/// document, in corpus order, or of the `--top-k` that the HNSW `index` finds.
+fn search_saved(
+    corpus: &Matrix,
+    index: Option<&Hnsw>,
+    vector: &[f64],
+    args: &QueryArgs,
+) -> (Vec<String>, Vec<f64>) {
+    let rows = match index {
+        Some(index) => index
+            .search(
+                |row| hnsw::cost(&args.distance_metric, vector, corpus.row(row)),
+                args.top_k,
+                args.index.ef_search,
+            )
+            .into_iter()
+            .map(|(row, _)| row)
+            .collect(),
+        None => (0..corpus.rows()).collect::<Vec<_>>(),
+    };
+    let scores = rows
+        .iter()
+        .map(|row| args.distance_metric.distance(vector, corpus.row(*row)))
+        .collect();
+    let labels = rows
+        .iter()
+        .map(|row| format_header(*row, &corpus.documents[*row]))
+        .collect();
+    (labels, scores)
+}
+
+/// Positions of the `top_k` closest of `scores`, closest first.
+fn ranking(scores: &[f64], distance_metric: &DistanceMetric, top_k: usize) -> Vec<usize> {
+    (0..scores.len())
+        .sorted_by(|a, b| distance_metric.cmp_closeness(scores[*b], scores[*a]))
+        .take(top_k)
+        .collect()
+}
+
+/// Embeds every query and prints its closest documents of the corpus.
+pub async fn run(args: QueryArgs) {
+    let corpus = match &args.store {
+        Some(store) => Corpus::Store(Collection {
+            store,
+            url: args
+                .store_url
+                .clone()
+                .unwrap_or_else(|| store.default_url()),
+            name: args
+                .collection
+                .as_ref()
+                .expect("--store requires --collection"),
+            vector_name: args.store_vector.as_deref(),
+            text_field: &args.store_text_field,
+        }),
+        None => {
+            let embeddings = args
+                .embeddings
+                .as_ref()
+                .expect("--embeddings is required without --store");
+            let corpus = embedding_file::load_matrix(embeddings);
+            // Saved corpora are scored pair by pair, too slowly with semanticsimilarity_rs
+            metrics::set_fast(metrics::fast_by_default(corpus.rows(), corpus.dimensions()));
+            if args.index.indexed(corpus.rows()) {
+                let index = index(embeddings, &corpus, &args.distance_metric);
+                if let Some(sample) = args.index.verify_sample {
+                    let mut rng = StdRng::seed_from_u64(0);
+                    let queries = search::queries(corpus.rows(), Some(sample), &mut rng);
+                    let recall =
+                        index.recall(&queries, args.top_k, args.index.ef_search, true, |a, b| {
+                            hnsw::cost(&args.distance_metric, corpus.row(a), corpus.row(b))
+                        });
+                    hnsw::print_recall(recall, args.top_k, queries.len());
+                }
+                Corpus::Indexed(corpus, index)
+            } else {
+                Corpus::Saved(corpus)
+            }
+        }
+    };
+    let mut trace = args.trace.as_ref().map(|path| {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|error| panic!("Failed to open trace file {path}: {error}"));
+        BufWriter::new(file)
+    });
+
+    for query in &args.query {
+        let started = Instant::now();
+        let vector = embed(
+            &args.provider,
+            &args.provider_args,
+            &args.embedding_model,
+            vec![args
+                .template
+                .as_ref()
+                .map_or_else(|| query.clone(), |template| template.apply(query))],
+        )
+        .await
+        .remove(0)
+        .vec;
+        let embed_ms = started.elapsed().as_secs_f64() * 1e3;
+
+        let started = Instant::now();
+        let (labels, scores) = match &corpus {
+            Corpus::Saved(corpus) => search_saved(corpus, None, &vector, &args),
+            Corpus::Indexed(corpus, index) => search_saved(corpus, Some(index), &vector, &args),
+            // The store ranks its points with its own index and distance, rescored locally
+            Corpus::Store(collection) => {
+                let hits = collection.search(&vector, args.top_k).await;
+                let scores = hits
+                    .iter()
+                    .map(|hit| args.distance_metric.distance(&vector, &hit.vector))
+                    .collect::<Vec<_>>();
+                let labels = hits
+                    .iter()
+                    .map(|hit| format!("{} ({})", hit.document, hit.id))
+                    .collect::<Vec<_>>();
+                (labels, scores)
+            }
+        };
+        let results = ranking(&scores, &args.distance_metric, args.top_k);
+        let search_ms = started.elapsed().as_secs_f64() * 1e3;
+
+        println!("{query}");
+        let mut table = vec![vec![
+            "document".to_string(),
+            args.distance_metric.to_string(),
+        ]];
+        table.extend(
+            results
+                .iter()
+                .map(|i| vec![labels[*i].clone(), table::format_score(scores[*i])]),
+        );
+        table::print(table);
+
+        if let Some(trace) = &mut trace {
+            let line = Trace {
+                query,
+                embed_ms,
+                search_ms,
+                scores: &scores,
+                warnings: warnings::take(),
+            };
+            writeln!(trace, "{}", serde_json::to_string(&line).unwrap()).unwrap();
+        }
+    }
+
+    if let Some(trace) = &mut trace {
+        trace.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::{embedding_file::StorageType, hnsw::IndexKind, providers::Embedding};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    fn parse(args: &[&str]) -> Result<QueryArgs, clap::Error> {
+        Cli::try_parse_from(
+            ["query", "-e", "text-embedding-3-small", "-q", "cat"]
+                .iter()
+                .chain(args),
+        )
+        .map(|cli| cli.query)
+    }
+
+    /// A corpus saved to a temporary embeddings file, of the points 0 to 9 of a line in 2D.
+    fn corpus(test: &str) -> (String, Matrix) {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "distance-calculator-query-{}-{test}.edcm",
+                std::process::id()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let embeddings = (0..10)
+            .map(|i| Embedding {
+                document: format!("point {i}"),
+                vec: vec![i as f64, 1.0],
+            })
+            .collect::<Vec<_>>();
+        embedding_file::save(&path, &embeddings, StorageType::F64, HashMap::new()).unwrap();
+        let matrix = embedding_file::load_matrix(&path);
+        (path, matrix)
+    }
+
+    #[test]
+    fn queries_search_a_saved_corpus_or_a_store() {
+        let args = parse(&["--embeddings", "corpus.edcm", "-q", "dog"]).unwrap();
+        assert_eq!(args.query, ["cat", "dog"]);
+        assert_eq!(args.top_k, 10);
+        assert_eq!(args.index.index, IndexKind::Auto);
+        assert_eq!(args.index.ef_search, hnsw::DEFAULT_EF_SEARCH);
+        assert!(!args.index.indexed(100));
+
+        assert!(parse(&[]).is_err(), "a corpus is required");
+        assert!(parse(&["--embeddings", "corpus.edcm", "--index", "hnsw", "--exact"]).is_err());
+        assert!(parse(&[
+            "--store",
+            "qdrant",
+            "--collection",
+            "docs",
+            "--index",
+            "hnsw"
+        ])
+        .is_err());
+        assert!(
+            parse(&["--store", "qdrant"]).is_err(),
+            "--store requires --collection"
+        );
+    }
+
+    #[test]
+    fn rankings_put_the_closest_scores_first() {
+        let scores = [0.2, 0.9, -0.5, 0.4];
+        assert_eq!(ranking(&scores, &DistanceMetric::Cosine, 3), [1, 3, 0]);
+        assert_eq!(ranking(&scores, &DistanceMetric::L2, 10), [2, 0, 3, 1]);
+        assert!(ranking(&[], &DistanceMetric::L2, 3).is_empty());
+    }
+
+    #[test]
+    fn brute_force_scores_every_document_in_corpus_order() {
+        let (path, corpus) = corpus("brute-force");
+        let args = parse(&["--embeddings", &path, "-d", "l2", "-k", "2"]).unwrap();
+        let (labels, scores) = search_saved(&corpus, None, &[3.0, 1.0], &args);
+        assert_eq!(labels.len(), 10);
+        assert_eq!(labels[4], "4: point 4");
+        assert_eq!(scores[..5], [3.0, 2.0, 1.0, 0.0, 1.0]);
+        let closest = ranking(&scores, &args.distance_metric, args.top_k);
+        assert_eq!(closest[0], 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_index_finds_the_brute_force_neighbors_and_is_saved() {
+        let (path, corpus) = corpus("index");
+        let args = parse(&[
+            "--embeddings",
+            &path,
+            "-d",
+            "l2",
+            "-k",
+            "3",
+            "--index",
+            "hnsw",
+        ])
+        .unwrap();
+        let built = index(&path, &corpus, &args.distance_metric);
+        let saved = PathBuf::from(format!("{path}.hnsw"));
+        assert!(saved.exists());
+        assert_eq!(
+            index(&path, &corpus, &args.distance_metric),
+            built,
+            "loaded once saved"
+        );
+
+        let query = [6.2, 1.0];
+        let (labels, scores) = search_saved(&corpus, Some(&built), &query, &args);
+        let (all_labels, all_scores) = search_saved(&corpus, None, &query, &args);
+        let found = ranking(&scores, &args.distance_metric, 3)
+            .into_iter()
+            .map(|i| labels[i].clone())
+            .collect::<Vec<_>>();
+        let exact = ranking(&all_scores, &args.distance_metric, 3)
+            .into_iter()
+            .map(|i| all_labels[i].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(found, ["6: point 6", "7: point 7", "5: point 5"]);
+        assert_eq!(found, exact);
+        std::fs::remove_file(saved).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn indexes_are_fingerprinted_by_the_minkowski_exponent() {
+        let minkowski = |p| fingerprint(10, 20, &DistanceMetric::Minkowski, p);
+        assert_eq!(minkowski(3.0), "10:20:minkowski:3");
+        assert_ne!(minkowski(3.0), minkowski(4.0));
+        assert_eq!(
+            fingerprint(10, 20, &DistanceMetric::Cosine, 3.0),
+            fingerprint(10, 20, &DistanceMetric::Cosine, 4.0)
+        );
+        assert_ne!(
+            fingerprint(10, 21, &DistanceMetric::Cosine, 3.0),
+            fingerprint(10, 20, &DistanceMetric::Cosine, 3.0)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+
+use clap::Args;
+use serde::Deserialize;
+
+use crate::{
+    cache::EmbeddingCache, files, format_header, models, table, DistanceMetric, Provider,
+    ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct RegressArgs {
+    /// JSON file of the reference texts: an array, or an object of texts by item id
+    #[arg(long)]
+    reference: String,
+    /// JSON file of the current texts, in the shape of `--reference`
+    #[arg(long)]
+    current: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// Score from which a current text still matches its reference
+    #[arg(long, default_value_t = 0.9, allow_negative_numbers = true)]
+    threshold: f64,
+}
+
+/// Texts of a reference or current file: by position for arrays, by id for objects.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Items {
+    List(Vec<String>),
+    ById(BTreeMap<String, String>),
+}
+
+impl Items {
+    /// `(id, text)` of every item, in file order for arrays and in id order for objects.
+    fn read(path: &str) -> Vec<(String, String)> {
+        match files::read_json(path) {
+            Items::List(texts) => texts
+                .into_iter()
+                .enumerate()
+                .map(|(i, text)| (i.to_string(), text))
+                .collect(),
+            Items::ById(texts) => texts.into_iter().collect(),
+        }
+    }
+}
+
+/// An item of either file and its texts.
+#[derive(Debug, PartialEq)]
+struct Item<'a> {
+    id: &'a str,
+    reference: Option<&'a str>,
+    current: Option<&'a str>,
+}
+
+/// Every item of `reference`, in order, then those only in `current`.
+fn items<'a>(reference: &'a [(String, String)], current: &'a [(String, String)]) -> Vec<Item<'a>> {
+    let find = |items: &'a [(String, String)], id: &str| {
+        items
+            .iter()
+            .find(|(other, _)| other == id)
+            .map(|(_, text)| text.as_str())
+    };
+    reference
+        .iter()
+        .chain(
+            current
+                .iter()
+                .filter(|(id, _)| find(reference, id).is_none()),
+        )
+        .map(|(id, _)| Item {
+            id,
+            reference: find(reference, id),
+            current: find(current, id),
+        })
+        .collect()
+}
+
+/// Whether `score` is at least as close as `threshold` under `distance_metric`.
+fn passes(score: f64, threshold: f64, distance_metric: &DistanceMetric) -> bool {
+    distance_metric.cmp_closeness(score, threshold).is_ge()
+}
+
+/// Prints the score of every current text against its reference and whether it reaches the
+/// threshold, then exits with status 1 if any item fails or is missing from either file.
+pub async fn run(args: RegressArgs) {
+    let reference = Items::read(&args.reference);
+    let current = Items::read(&args.current);
+    let items = items(&reference, &current);
+
+    let texts = items
+        .iter()
+        .filter_map(|item| item.reference.zip(item.current))
+        .flat_map(|(reference, current)| [reference.to_string(), current.to_string()])
+        .collect::<Vec<_>>();
+    let (embeddings, _) =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &texts,
+                args.provider.max_batch_size(),
+            )
+            .await;
+
+    let mut table = vec![vec![
+        "item".to_string(),
+        "text".to_string(),
+        args.distance_metric.to_string(),
+        "result".to_string(),
+    ]];
+    let mut failures = 0;
+    let mut pairs = embeddings.chunks(2);
+    for (i, item) in items.iter().enumerate() {
+        let (score, result) = match (item.reference, item.current) {
+            (Some(_), Some(_)) => {
+                let pair = pairs.next().unwrap();
+                let score = args.distance_metric.distance(&pair[0].vec, &pair[1].vec);
+                let passed = passes(score, args.threshold, &args.distance_metric);
+                (
+                    table::format_score(score),
+                    if passed { "pass" } else { "FAIL" },
+                )
+            }
+            (Some(_), None) => ("-".to_string(), "missing from current"),
+            (None, _) => ("-".to_string(), "missing from reference"),
+        };
+        if result != "pass" {
+            failures += 1;
+        }
+        let text = item.reference.or(item.current).unwrap_or_default();
+        table.push(vec![
+            item.id.to_string(),
+            format_header(i, text),
+            score,
+            result.to_string(),
+        ]);
+    }
+
+    table::print(table);
+    println!(
+        "{} of {} items score {} {} or closer to their reference",
+        items.len() - failures,
+        items.len(),
+        args.distance_metric,
+        args.threshold
+    );
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(id, text)| (id.to_string(), text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn items_are_matched_by_id() {
+        let reference = texts(&[("faq-1", "old answer"), ("faq-2", "dropped")]);
+        let current = texts(&[("faq-1", "new answer"), ("faq-3", "added")]);
+        assert_eq!(
+            items(&reference, &current),
+            [
+                Item {
+                    id: "faq-1",
+                    reference: Some("old answer"),
+                    current: Some("new answer"),
+                },
+                Item {
+                    id: "faq-2",
+                    reference: Some("dropped"),
+                    current: None,
+                },
+                Item {
+                    id: "faq-3",
+                    reference: None,
+                    current: Some("added"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_are_identified_by_position_and_objects_by_id() {
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!(
+                "distance-calculator-regress-{name}-{}.json",
+                std::process::id()
+            ))
+        };
+        std::fs::write(path("list"), r#"["first", "second"]"#).unwrap();
+        std::fs::write(path("object"), r#"{"b": "second", "a": "first"}"#).unwrap();
+
+        assert_eq!(
+            Items::read(path("list").to_str().unwrap()),
+            texts(&[("0", "first"), ("1", "second")])
+        );
+        assert_eq!(
+            Items::read(path("object").to_str().unwrap()),
+            texts(&[("a", "first"), ("b", "second")])
+        );
+    }
+
+    #[test]
+    fn thresholds_follow_the_direction_of_the_metric() {
+        assert!(passes(0.95, 0.9, &DistanceMetric::Cosine));
+        assert!(passes(0.9, 0.9, &DistanceMetric::Cosine));
+        assert!(!passes(0.85, 0.9, &DistanceMetric::Cosine));
+        assert!(passes(0.1, 0.2, &DistanceMetric::L2));
+        assert!(!passes(0.3, 0.2, &DistanceMetric::L2));
+    }
+}
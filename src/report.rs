@@ -0,0 +1,201 @@
+use std::path::Path;
+
+use handlebars::{handlebars_helper, no_escape, Handlebars, RenderErrorReason, TemplateError};
+use serde::Serialize;
+
+use crate::{files, pairs::Pair, stats};
+
+/// Everything a run computed, as the model of `--report-template` templates.
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub metric: String,
+    /// Whether higher scores mean closer documents under `metric`
+    pub higher_is_closer: bool,
+    /// Absent for runs on saved `--embeddings`
+    pub provider: Option<String>,
+    pub model: Option<&'a str>,
+    pub generated_at: String,
+    pub documents: Vec<ReportDocument<'a>>,
+    /// Pairs in the order and number of the pairs output (`--sort`, `--limit`)
+    pub pairs: Vec<ReportPair<'a>>,
+    /// Score of every pair of documents, in input order
+    pub matrix: &'a [Vec<f64>],
+    /// Distribution of the scores of every pair, absent with fewer than two documents
+    pub summary: Option<Summary>,
+}
+
+#[derive(Serialize)]
+pub struct ReportDocument<'a> {
+    pub index: usize,
+    pub id: &'a str,
+    pub text: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct ReportPair<'a> {
+    pub i: usize,
+    pub j: usize,
+    pub doc_i: &'a str,
+    pub doc_j: &'a str,
+    pub score: f64,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub pairs: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub std: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl<'a> ReportPair<'a> {
+    pub fn new(pair: &Pair, input_strings: &'a [String]) -> Self {
+        ReportPair {
+            i: pair.i,
+            j: pair.j,
+            doc_i: &input_strings[pair.i],
+            doc_j: &input_strings[pair.j],
+            score: pair.score,
+        }
+    }
+}
+
+impl Summary {
+    /// Summary of the pairwise `scores`, none if there are none.
+    pub fn new(scores: &[f64]) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+        Some(Summary {
+            pairs: scores.len(),
+            mean: stats::mean(scores),
+            median: stats::median(scores),
+            std: stats::std_dev(scores),
+            min: scores.iter().copied().fold(f64::INFINITY, f64::min),
+            max: scores.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        })
+    }
+}
+
+// `{{fixed score 3}}` prints a number with 3 decimals
+handlebars_helper!(fixed: |value: f64, decimals: u64| format!("{value:.*}", decimals as usize));
+// `{{percent score}}` prints a number as a percentage with 1 decimal
+handlebars_helper!(percent: |value: f64| format!("{:.1}%", value * 100.0));
+
+/// Renders `report` with the Handlebars template of the file at `path`.
+///
+/// Templates are strict: a misspelled field fails instead of printing nothing. Values are only
+/// HTML-escaped in `.html` and `.htm` templates.
+pub fn render(path: &str, report: &Report) -> Result<String, String> {
+    let template = files::read_text(path).map_err(|error| error.to_string())?;
+    let html = Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension == "html" || extension == "htm");
+    render_template(&template, html, report)
+}
+
+fn render_template(template: &str, html: bool, report: &Report) -> Result<String, String> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    if !html {
+        handlebars.register_escape_fn(no_escape);
+    }
+    handlebars.register_helper("fixed", Box::new(fixed));
+    handlebars.register_helper("percent", Box::new(percent));
+    handlebars
+        .register_template_string("report", template)
+        .map_err(|error: TemplateError| error.to_string())?;
+
+    handlebars
+        .render("report", report)
+        .map_err(|error| match error.reason() {
+            RenderErrorReason::MissingVariable(Some(variable)) => {
+                format!("the template uses {variable}, which reports don't have")
+            }
+            _ => error.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report<'a>(input_strings: &'a [String], matrix: &'a [Vec<f64>]) -> Report<'a> {
+        let pairs = [Pair {
+            i: 0,
+            j: 1,
+            score: 0.8125,
+        }];
+        Report {
+            metric: "cosine".to_string(),
+            higher_is_closer: true,
+            provider: Some("openai".to_string()),
+            model: Some("text-embedding-3-small"),
+            generated_at: "2024-11-05T10:00:00+00:00".to_string(),
+            documents: input_strings
+                .iter()
+                .enumerate()
+                .map(|(index, text)| ReportDocument {
+                    index,
+                    id: "id",
+                    text,
+                })
+                .collect(),
+            pairs: pairs
+                .iter()
+                .map(|pair| ReportPair::new(pair, input_strings))
+                .collect(),
+            matrix,
+            summary: Summary::new(&[0.8125]),
+        }
+    }
+
+    #[test]
+    fn templates_render_the_report() {
+        let input_strings = ["cats & dogs".to_string(), "kittens".to_string()];
+        let matrix = [vec![1.0, 0.8125], vec![0.8125, 1.0]];
+        let template = "# {{model}} ({{metric}})\n\
+                        {{#each pairs}}- {{doc_i}} / {{doc_j}}: {{fixed score 2}} ({{percent score}})\n{{/each}}\
+                        mean {{summary.mean}} over {{summary.pairs}} pair";
+
+        assert_eq!(
+            render_template(template, false, &report(&input_strings, &matrix)).unwrap(),
+            "# text-embedding-3-small (cosine)\n\
+             - cats & dogs / kittens: 0.81 (81.2%)\n\
+             mean 0.8125 over 1 pair"
+        );
+    }
+
+    #[test]
+    fn html_templates_escape_the_documents() {
+        let input_strings = ["cats & dogs".to_string(), "<b>kittens</b>".to_string()];
+        let matrix = [vec![1.0, 0.8125], vec![0.8125, 1.0]];
+        let template = "{{#each documents}}<li>{{text}}</li>{{/each}}";
+
+        assert_eq!(
+            render_template(template, true, &report(&input_strings, &matrix)).unwrap(),
+            "<li>cats &amp; dogs</li><li>&lt;b&gt;kittens&lt;/b&gt;</li>"
+        );
+    }
+
+    #[test]
+    fn unknown_fields_fail() {
+        let input_strings = ["a".to_string(), "b".to_string()];
+        let matrix = [vec![1.0, 0.5], vec![0.5, 1.0]];
+        let error = render_template("{{modle}}", false, &report(&input_strings, &matrix));
+        assert!(error.unwrap_err().contains("modle"));
+
+        let error = render_template("{{#each pairs}}", false, &report(&input_strings, &matrix));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn summaries_need_scores() {
+        assert!(Summary::new(&[]).is_none());
+        let summary = Summary::new(&[0.2, 0.4, 0.9]).unwrap();
+        assert_eq!((summary.pairs, summary.median), (3, 0.4));
+        assert_eq!((summary.min, summary.max), (0.2, 0.9));
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+
+use clap::Args;
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{
+    cache::EmbeddingCache,
+    files, models, table,
+    template::{self, Template},
+    DistanceMetric, Provider, ProviderArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct RetrievalArgs {
+    /// JSON file of `{"documents": [...], "queries": [{"query", "relevant"}]}`, `relevant` being
+    /// the positions of the documents relevant to the query
+    #[arg(short, long)]
+    relevance_file: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    /// Embedding model to evaluate (repeat to compare models)
+    #[arg(short, long, required = true, value_parser = models::parse_model)]
+    embedding_model: Vec<String>,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// Number of retrieved documents recall and nDCG are measured on
+    #[arg(short = 'k', long, default_value_t = 10)]
+    top_k: usize,
+    /// Embed every query as this template, `{}` standing for the query, e.g. `query: {}`
+    #[arg(long, value_parser = template::parse)]
+    query_template: Option<Template>,
+    /// Embed every document as this template, `{}` standing for the document, e.g.
+    /// `passage: {}`
+    #[arg(long, value_parser = template::parse)]
+    document_template: Option<Template>,
+}
+
+#[derive(Deserialize)]
+struct RelevanceFile {
+    documents: Vec<String>,
+    queries: Vec<LabeledQuery>,
+}
+
+#[derive(Deserialize)]
+struct LabeledQuery {
+    query: String,
+    relevant: HashSet<usize>,
+}
+
+impl RetrievalArgs {
+    fn relevance(&self) -> RelevanceFile {
+        files::read_json(&self.relevance_file)
+    }
+}
+
+/// Recall@k, reciprocal rank of the first relevant document and nDCG@k of one ranking.
+fn query_metrics(ranking: &[usize], relevant: &HashSet<usize>, k: usize) -> [f64; 3] {
+    let found = ranking
+        .iter()
+        .take(k)
+        .filter(|document| relevant.contains(document))
+        .count();
+    let reciprocal_rank = ranking
+        .iter()
+        .position(|document| relevant.contains(document))
+        .map_or(0.0, |rank| 1.0 / (rank + 1) as f64);
+
+    let discount = |rank: usize| 1.0 / (rank as f64 + 2.0).log2();
+    let dcg = ranking
+        .iter()
+        .take(k)
+        .enumerate()
+        .filter(|(_, document)| relevant.contains(document))
+        .map(|(rank, _)| discount(rank))
+        .sum::<f64>();
+    let ideal_dcg = (0..relevant.len().min(k)).map(discount).sum::<f64>();
+
+    [
+        found as f64 / relevant.len() as f64,
+        reciprocal_rank,
+        dcg / ideal_dcg,
+    ]
+}
+
+/// Ranks the documents for every query with each model and prints the mean recall@k, MRR and
+/// nDCG@k of each model.
+pub async fn run(args: RetrievalArgs) {
+    let relevance = args.relevance();
+    for labeled in &relevance.queries {
+        assert!(
+            !labeled.relevant.is_empty(),
+            "Query {:?} has no relevant documents",
+            labeled.query
+        );
+        assert!(
+            labeled
+                .relevant
+                .iter()
+                .all(|document| *document < relevance.documents.len()),
+            "Query {:?} has relevant documents past the end of the corpus",
+            labeled.query
+        );
+    }
+
+    let templated = |template: &Option<Template>, text: &String| {
+        template
+            .as_ref()
+            .map_or_else(|| text.clone(), |template| template.apply(text))
+    };
+    let input_strings = relevance
+        .documents
+        .iter()
+        .map(|document| templated(&args.document_template, document))
+        .chain(
+            relevance
+                .queries
+                .iter()
+                .map(|labeled| templated(&args.query_template, &labeled.query)),
+        )
+        .collect::<Vec<_>>();
+
+    let k = args.top_k;
+    let mut table = vec![vec![
+        "model".to_string(),
+        format!("recall@{k}"),
+        "mrr".to_string(),
+        format!("ndcg@{k}"),
+    ]];
+    for model in &args.embedding_model {
+        let (embeddings, _) = EmbeddingCache::open(&args.provider, &args.provider_args, model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                model,
+                &input_strings,
+                args.provider.max_batch_size(),
+            )
+            .await;
+        let (documents, queries) = embeddings.split_at(relevance.documents.len());
+
+        let mut totals = [0.0; 3];
+        for (query, labeled) in queries.iter().zip(&relevance.queries) {
+            let scores = documents
+                .iter()
+                .map(|document| args.distance_metric.distance(&query.vec, &document.vec))
+                .collect::<Vec<_>>();
+            let ranking = (0..documents.len())
+                .sorted_by(|a, b| args.distance_metric.cmp_closeness(scores[*b], scores[*a]))
+                .collect::<Vec<_>>();
+
+            let metrics = query_metrics(&ranking, &labeled.relevant, k);
+            for (total, metric) in totals.iter_mut().zip(metrics) {
+                *total += metric;
+            }
+        }
+
+        let count = relevance.queries.len() as f64;
+        table.push(
+            std::iter::once(model.clone())
+                .chain(totals.iter().map(|total| (total / count).to_string()))
+                .collect(),
+        );
+    }
+
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relevant(documents: &[usize]) -> HashSet<usize> {
+        documents.iter().copied().collect()
+    }
+
+    fn assert_metrics(actual: [f64; 3], expected: [f64; 3]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+        }
+    }
+
+    #[test]
+    fn a_perfect_ranking_scores_one() {
+        let metrics = query_metrics(&[2, 0, 1, 3], &relevant(&[2, 0]), 2);
+        assert_metrics(metrics, [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn relevant_documents_past_k_only_count_for_mrr() {
+        let metrics = query_metrics(&[0, 1, 2, 3], &relevant(&[3]), 2);
+        assert_metrics(metrics, [0.0, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn lower_ranks_are_discounted() {
+        // Found at ranks 2 and 3 of 3, ideally at ranks 1 and 2
+        let metrics = query_metrics(&[0, 1, 2], &relevant(&[1, 2]), 3);
+        let dcg = 1.0 / 3f64.log2() + 1.0 / 4f64.log2();
+        let ideal_dcg = 1.0 + 1.0 / 3f64.log2();
+        assert_metrics(metrics, [1.0, 0.5, dcg / ideal_dcg]);
+    }
+
+    #[test]
+    fn recall_is_over_every_relevant_document() {
+        // Three relevant documents can't all be in the top 2, but nDCG@2 is still perfect
+        let metrics = query_metrics(&[0, 1, 2], &relevant(&[0, 1, 2]), 2);
+        assert_metrics(metrics, [2.0 / 3.0, 1.0, 1.0]);
+    }
+}
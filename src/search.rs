@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use rand::{seq::index::sample, Rng};
+
+use crate::DistanceMetric;
+
+/// Documents used as queries to measure recall: a random sample of `sample_size` of them, or
+/// all of them.
+pub fn queries(count: usize, sample_size: Option<usize>, rng: &mut impl Rng) -> Vec<usize> {
+    match sample_size {
+        Some(sample_size) if sample_size < count => {
+            let mut queries = sample(rng, count, sample_size).into_vec();
+            queries.sort();
+            queries
+        }
+        _ => (0..count).collect(),
+    }
+}
+
+/// The `k` candidates closest to `query` by `score`, the query itself excluded.
+pub fn top_k(
+    query: usize,
+    candidates: impl IntoIterator<Item = usize>,
+    k: usize,
+    distance_metric: &DistanceMetric,
+    score: impl Fn(usize) -> f64,
+) -> HashSet<usize> {
+    candidates
+        .into_iter()
+        .filter(|other| *other != query)
+        .map(|other| (other, score(other)))
+        .sorted_by(|(_, a), (_, b)| distance_metric.cmp_closeness(*b, *a))
+        .take(k)
+        .map(|(other, _)| other)
+        .collect()
+}
+
+/// The exact top `k` neighbors among all vectors of each of `queries`, found by brute force.
+pub fn exact_neighbors(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    k: usize,
+    distance_metric: &DistanceMetric,
+) -> Vec<HashSet<usize>> {
+    queries
+        .iter()
+        .map(|&query| {
+            top_k(query, 0..vectors.len(), k, distance_metric, |other| {
+                distance_metric.distance(&vectors[query], &vectors[other])
+            })
+        })
+        .collect()
+}
+
+/// Fraction of `exact` that `approximate` found.
+pub fn recall(exact: &HashSet<usize>, approximate: &HashSet<usize>) -> f64 {
+    if exact.is_empty() {
+        return 1.0;
+    }
+    exact.intersection(approximate).count() as f64 / exact.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn vectors() -> Vec<Vec<f64>> {
+        [0.0, 1.0, 3.0, 6.0, 10.0].map(|x| vec![x, 1.0]).to_vec()
+    }
+
+    #[test]
+    fn top_k_leaves_the_query_out_and_follows_the_metric() {
+        let vectors = vectors();
+        let l2 = |query: usize| {
+            let vectors = &vectors;
+            move |other: usize| DistanceMetric::L2.distance(&vectors[query], &vectors[other])
+        };
+        assert_eq!(
+            top_k(3, 0..5, 2, &DistanceMetric::L2, l2(3)),
+            HashSet::from([2, 4])
+        );
+        // Higher dot products are closer
+        let dot = |other: usize| DistanceMetric::Dot.distance(&vectors[4], &vectors[other]);
+        assert_eq!(
+            top_k(4, 0..5, 2, &DistanceMetric::Dot, dot),
+            HashSet::from([2, 3])
+        );
+        assert_eq!(
+            top_k(2, [0, 4], 1, &DistanceMetric::L2, l2(2)),
+            HashSet::from([0]),
+            "only the candidates are searched"
+        );
+    }
+
+    #[test]
+    fn exact_neighbors_are_the_brute_force_top_k() {
+        let vectors = vectors();
+        assert_eq!(
+            exact_neighbors(&vectors, &[0, 4], 2, &DistanceMetric::L2),
+            [HashSet::from([1, 2]), HashSet::from([3, 2])]
+        );
+        // k beyond the corpus returns every other document
+        assert_eq!(
+            exact_neighbors(&vectors, &[1], 10, &DistanceMetric::L2),
+            [HashSet::from([0, 2, 3, 4])]
+        );
+        assert!(exact_neighbors(&vectors, &[], 2, &DistanceMetric::L2).is_empty());
+    }
+
+    #[test]
+    fn recall_is_the_share_of_exact_neighbors_found() {
+        let exact = HashSet::from([1, 2, 3, 4]);
+        assert_eq!(recall(&exact, &HashSet::from([1, 2, 3, 4])), 1.0);
+        assert_eq!(recall(&exact, &HashSet::from([2, 9])), 0.25);
+        assert_eq!(recall(&HashSet::new(), &HashSet::from([2])), 1.0);
+    }
+
+    #[test]
+    fn queries_sample_the_corpus_without_repeats() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let sampled = queries(100, Some(10), &mut rng);
+        assert_eq!(sampled.len(), 10);
+        assert!(sampled.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(sampled.iter().all(|query| *query < 100));
+        assert_eq!(queries(5, Some(10), &mut rng), [0, 1, 2, 3, 4]);
+        assert_eq!(queries(3, None, &mut rng), [0, 1, 2]);
+    }
+}
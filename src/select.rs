@@ -0,0 +1,189 @@
+use clap::Args;
+
+use crate::{
+    cache::EmbeddingCache, cluster, files, format_header, models, table, DistanceMetric, Provider,
+    ProviderArgs, EMPTY,
+};
+
+#[derive(Args, Debug)]
+pub struct SelectArgs {
+    /// JSON array of the candidate documents
+    #[arg(short)]
+    input_file: String,
+    /// Number of documents to select
+    #[arg(long)]
+    n: usize,
+    /// Weight of the relevance against the diversity of every selected document: 1 only picks
+    /// by relevance, 0 only by diversity
+    #[arg(long, default_value_t = 0.5, value_parser = parse_lambda)]
+    lambda: f64,
+    /// Text the selected documents should be relevant to [default: the centroid of the
+    /// candidates, i.e. representative documents]
+    #[arg(long)]
+    query: Option<String>,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+}
+
+fn parse_lambda(lambda: &str) -> Result<f64, String> {
+    let lambda = lambda.parse::<f64>().map_err(|error| error.to_string())?;
+    if !(0.0..=1.0).contains(&lambda) {
+        return Err("lambda is between 0 and 1".to_string());
+    }
+    Ok(lambda)
+}
+
+/// A selected document, its similarity to the query and its highest similarity to the documents
+/// selected before it.
+#[derive(Debug, PartialEq)]
+struct Selected {
+    index: usize,
+    relevance: f64,
+    redundancy: Option<f64>,
+}
+
+/// Picks `n` of the `vectors` by maximal marginal relevance: every step selects the document
+/// maximizing `lambda * relevance - (1 - lambda) * redundancy`, where relevance is its
+/// similarity to `query` and redundancy its highest similarity to the documents already chosen.
+fn mmr(
+    vectors: &[Vec<f64>],
+    query: &[f64],
+    n: usize,
+    lambda: f64,
+    distance_metric: &DistanceMetric,
+) -> Vec<Selected> {
+    let relevance = vectors
+        .iter()
+        .map(|vector| distance_metric.similarity(vector, query))
+        .collect::<Vec<_>>();
+    // Highest similarity of every document to the selected ones
+    let mut redundancy = vec![None::<f64>; vectors.len()];
+    let mut selected = vec![false; vectors.len()];
+    let mut selection = vec![];
+
+    for _ in 0..n.min(vectors.len()) {
+        let score =
+            |i: usize| lambda * relevance[i] - (1.0 - lambda) * redundancy[i].unwrap_or(0.0);
+        let Some(best) = (0..vectors.len())
+            .filter(|i| !selected[*i])
+            .max_by(|a, b| score(*a).total_cmp(&score(*b)).then(b.cmp(a)))
+        else {
+            break;
+        };
+        selected[best] = true;
+        selection.push(Selected {
+            index: best,
+            relevance: relevance[best],
+            redundancy: redundancy[best],
+        });
+        for (i, vector) in vectors.iter().enumerate() {
+            let similarity = distance_metric.similarity(vector, &vectors[best]);
+            redundancy[i] = Some(redundancy[i].map_or(similarity, |max| max.max(similarity)));
+        }
+    }
+    selection
+}
+
+/// Prints the selected documents in the order they were picked.
+pub async fn run(args: SelectArgs) {
+    let documents = files::read_documents(&args.input_file);
+    let mut texts = documents.clone();
+    texts.extend(args.query.clone());
+
+    let (embeddings, _) =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &texts,
+                args.provider.max_batch_size(),
+            )
+            .await;
+    let mut vectors = embeddings
+        .into_iter()
+        .map(|embedding| embedding.vec)
+        .collect::<Vec<_>>();
+    let query = match args.query {
+        Some(_) => vectors.pop().unwrap(),
+        None if vectors.is_empty() => vec![],
+        None => cluster::mean(&vectors.iter().collect::<Vec<_>>()),
+    };
+
+    let selection = mmr(&vectors, &query, args.n, args.lambda, &args.distance_metric);
+    let target = if args.query.is_some() {
+        "query"
+    } else {
+        "centroid"
+    };
+    let mut table = vec![vec![
+        "rank".to_string(),
+        "document".to_string(),
+        format!("{} to {target}", args.distance_metric),
+        format!("closest selected ({})", args.distance_metric),
+    ]];
+    // Similarities are scores negated for metrics where lower is closer
+    let score = |similarity: f64| {
+        if args.distance_metric.higher_is_closer() {
+            similarity
+        } else {
+            -similarity
+        }
+    };
+    for (rank, selected) in selection.iter().enumerate() {
+        table.push(vec![
+            (rank + 1).to_string(),
+            format_header(selected.index, &documents[selected.index]),
+            table::format_score(score(selected.relevance)),
+            selected.redundancy.map_or(EMPTY.to_string(), |redundancy| {
+                table::format_score(score(redundancy))
+            }),
+        ]);
+    }
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two near-duplicates close to the query and one document in another direction.
+    fn vectors() -> Vec<Vec<f64>> {
+        vec![vec![1.0, 0.0], vec![0.99, 0.01], vec![0.6, 0.8]]
+    }
+
+    fn indices(selection: &[Selected]) -> Vec<usize> {
+        selection.iter().map(|selected| selected.index).collect()
+    }
+
+    #[test]
+    fn relevance_only_picks_the_closest_documents() {
+        let selection = mmr(&vectors(), &[1.0, 0.0], 2, 1.0, &DistanceMetric::Cosine);
+        assert_eq!(indices(&selection), [0, 1]);
+        assert_eq!(selection[0].redundancy, None);
+        assert!(selection[1].redundancy.unwrap() > 0.99);
+    }
+
+    #[test]
+    fn diversity_skips_near_duplicates() {
+        let selection = mmr(&vectors(), &[0.95, 0.3], 2, 0.5, &DistanceMetric::Cosine);
+        assert_eq!(indices(&selection), [1, 2]);
+        let selection = mmr(&vectors(), &[0.95, 0.3], 2, 0.5, &DistanceMetric::L2);
+        assert_eq!(indices(&selection), [1, 2]);
+    }
+
+    #[test]
+    fn selections_stop_at_the_candidates() {
+        let selection = mmr(&vectors(), &[1.0, 0.0], 5, 0.5, &DistanceMetric::Cosine);
+        assert_eq!(selection.len(), 3);
+        assert!(mmr(&[], &[], 2, 0.5, &DistanceMetric::Cosine).is_empty());
+        assert!(parse_lambda("1.5").is_err());
+        assert_eq!(parse_lambda("0.3"), Ok(0.3));
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use clap::{Args, ValueEnum};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::Mutex};
+
+use crate::{cache::EmbeddingCache, models, DistanceMetric, Provider, ProviderArgs};
+
+/// Whether the tool is serving requests, in which a failed embedding only fails its request.
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+pub fn serving() -> bool {
+    SERVING.load(Ordering::Relaxed)
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    bind: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    /// Metric of requests that don't name one
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+}
+
+struct Server {
+    args: ServeArgs,
+    /// Embeddings of every text seen so far, shared by all requests
+    cache: Mutex<EmbeddingCache>,
+}
+
+/// Status and JSON body of a failed request.
+type ApiError = (StatusCode, Json<ErrorBody>);
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+fn error(status: StatusCode, message: String) -> ApiError {
+    (status, Json(ErrorBody { error: message }))
+}
+
+#[derive(Deserialize)]
+struct CompareRequest {
+    texts: Vec<String>,
+    metric: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CompareResponse {
+    metric: String,
+    /// Score of every pair of texts, in request order
+    matrix: Vec<Vec<f64>>,
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    query: String,
+    corpus: Vec<String>,
+    top_k: Option<usize>,
+    metric: Option<String>,
+}
+
+#[derive(Serialize)]
+struct QueryResponse {
+    metric: String,
+    /// The closest documents of the corpus, closest first
+    results: Vec<QueryResult>,
+}
+
+#[derive(Serialize)]
+struct QueryResult {
+    /// Position of the document in the corpus
+    index: usize,
+    document: String,
+    score: f64,
+}
+
+/// Serves `POST /compare` and `POST /query` until the process is stopped.
+pub async fn run(args: ServeArgs) {
+    SERVING.store(true, Ordering::Relaxed);
+    let listener = TcpListener::bind(&args.bind)
+        .await
+        .unwrap_or_else(|error| panic!("Failed to listen on {}: {error}", args.bind));
+    let cache = EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model);
+    let server = Arc::new(Server {
+        args,
+        cache: Mutex::new(cache),
+    });
+
+    let app = router(server);
+    eprintln!("Listening on http://{}", listener.local_addr().unwrap());
+    axum::serve(listener, app).await.expect("Server failed");
+}
+
+fn router(server: Arc<Server>) -> Router {
+    Router::new()
+        .route("/compare", post(compare))
+        .route("/query", post(query))
+        .with_state(server)
+}
+
+impl Server {
+    fn metric(&self, name: Option<&str>) -> Result<DistanceMetric, ApiError> {
+        match name {
+            Some(name) => DistanceMetric::from_str(name, true).map_err(|message| {
+                error(
+                    StatusCode::BAD_REQUEST,
+                    format!("unknown metric: {message}"),
+                )
+            }),
+            None => Ok(self.args.distance_metric.clone()),
+        }
+    }
+
+    /// Vectors of `texts`, embedding those that aren't cached yet. The cache is unlocked while
+    /// the provider embeds them, so other requests are served meanwhile.
+    ///
+    /// Embedding runs in a task of its own so that a provider failure, which ends a command-line
+    /// run, only fails the request.
+    async fn embed(self: &Arc<Self>, texts: Vec<String>) -> Result<Vec<Vec<f64>>, ApiError> {
+        let server = self.clone();
+        let task = tokio::spawn(async move {
+            let args = &server.args;
+            EmbeddingCache::embed_locked(
+                &server.cache,
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &texts,
+                args.provider.max_batch_size(),
+            )
+            .await
+            .into_iter()
+            .map(|embedding| embedding.vec)
+            .collect()
+        });
+
+        task.await.map_err(|failure| {
+            let message = failure
+                .try_into_panic()
+                .map_or_else(|failure| failure.to_string(), panic_message);
+            error(StatusCode::BAD_GATEWAY, message)
+        })
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or_else(
+            || "embedding failed".to_string(),
+            |message| message.to_string(),
+        ),
+    }
+}
+
+async fn compare(
+    State(server): State<Arc<Server>>,
+    Json(request): Json<CompareRequest>,
+) -> Result<Json<CompareResponse>, ApiError> {
+    let metric = server.metric(request.metric.as_deref())?;
+    let vectors = server.embed(request.texts).await?;
+
+    let mut matrix = vec![vec![0.0; vectors.len()]; vectors.len()];
+    for ((i, a), (j, b)) in vectors.iter().enumerate().tuple_combinations() {
+        let score = metric.distance(a, b);
+        matrix[i][j] = score;
+        matrix[j][i] = score;
+    }
+    for (i, vector) in vectors.iter().enumerate() {
+        matrix[i][i] = metric.distance(vector, vector);
+    }
+
+    Ok(Json(CompareResponse {
+        metric: metric.to_string(),
+        matrix,
+    }))
+}
+
+async fn query(
+    State(server): State<Arc<Server>>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<QueryResponse>, ApiError> {
+    let metric = server.metric(request.metric.as_deref())?;
+    let mut texts = vec![request.query];
+    texts.extend(request.corpus.iter().cloned());
+    let mut vectors = server.embed(texts).await?;
+    let query = vectors.remove(0);
+
+    let scores = vectors
+        .iter()
+        .map(|vector| metric.distance(&query, vector))
+        .collect::<Vec<_>>();
+    let results = (0..vectors.len())
+        .sorted_by(|a, b| metric.cmp_closeness(scores[*b], scores[*a]))
+        .take(request.top_k.unwrap_or(10))
+        .map(|index| QueryResult {
+            index,
+            document: request.corpus[index].clone(),
+            score: scores[index],
+        })
+        .collect();
+
+    Ok(Json(QueryResponse {
+        metric: metric.to_string(),
+        results,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use clap::Parser;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::providers::Embedding;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        serve: ServeArgs,
+    }
+
+    /// A server whose cache already holds the vectors of `texts`, so no request reaches the
+    /// provider.
+    fn app(test: &str, texts: &[(&str, Vec<f64>)]) -> Router {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-serve-{test}-{}.zst",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut cache = EmbeddingCache::open_at(path);
+        let embeddings = texts
+            .iter()
+            .map(|(document, vec)| Embedding {
+                document: document.to_string(),
+                vec: vec.clone(),
+            })
+            .collect();
+        cache.add(embeddings, None);
+
+        let args = Cli::parse_from(["serve", "-e", "text-embedding-3-small"]).serve;
+        router(Arc::new(Server {
+            args,
+            cache: Mutex::new(cache),
+        }))
+    }
+
+    async fn post_json(app: Router, uri: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn texts() -> Vec<(&'static str, Vec<f64>)> {
+        vec![
+            ("cat", vec![1.0, 0.0]),
+            ("kitten", vec![0.8, 0.6]),
+            ("car", vec![0.0, 1.0]),
+        ]
+    }
+
+    #[tokio::test]
+    async fn compare_scores_every_pair() {
+        let (status, body) = post_json(
+            app("compare", &texts()),
+            "/compare",
+            json!({ "texts": ["cat", "kitten", "car"] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["metric"], "cosine");
+
+        let matrix = serde_json::from_value::<Vec<Vec<f64>>>(body["matrix"].clone()).unwrap();
+        let expected = [[1.0, 0.8, 0.0], [0.8, 1.0, 0.6], [0.0, 0.6, 1.0]];
+        for (row, expected) in matrix.iter().zip(expected) {
+            for (score, expected) in row.iter().zip(expected) {
+                assert!((score - expected).abs() < 1e-9, "{matrix:?}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn query_ranks_the_corpus_closest_first() {
+        let (status, body) = post_json(
+            app("query", &texts()),
+            "/query",
+            json!({ "query": "cat", "corpus": ["car", "kitten"], "top_k": 1, "metric": "l2" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["metric"], "l2");
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["index"], 1);
+        assert_eq!(results[0]["document"], "kitten");
+        assert!((results[0]["score"].as_f64().unwrap() - 0.4f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn unknown_metrics_are_bad_requests() {
+        let (status, body) = post_json(
+            app("metric", &texts()),
+            "/compare",
+            json!({ "texts": ["cat"], "metric": "closeness" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("unknown metric"));
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_are_rejected() {
+        let (status, _) = post_json(
+            app("malformed", &texts()),
+            "/query",
+            json!({ "corpus": ["cat"] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,467 @@
+use std::sync::OnceLock;
+
+use itertools::Itertools;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use s3::{creds::Credentials, error::S3Error, Bucket, Region};
+use sha2::{Digest, Sha256};
+use tokio::task::JoinSet;
+
+use crate::{allowlist, providers::Embedding, warnings};
+
+/// Documents looked up in Redis per request.
+const REDIS_CHUNK_SIZE: usize = 1000;
+/// Objects requested from S3 at the same time.
+const S3_CONCURRENCY: usize = 32;
+
+/// Cache shared between machines, set once from `--shared-cache`.
+static SHARED_CACHE_URL: OnceLock<String> = OnceLock::new();
+
+pub fn set_url(url: String) {
+    assert!(
+        url.starts_with("redis://") || url.starts_with("rediss://") || url.starts_with("s3://"),
+        "Unsupported shared cache {url}: expected a redis:// or s3:// URL"
+    );
+    SHARED_CACHE_URL
+        .set(url)
+        .expect("Shared cache URL already set");
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SharedCacheError {
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("S3 error: {0}")]
+    S3(#[from] S3Error),
+}
+
+/// The vectors of one provider and model in the shared cache.
+pub enum SharedCache {
+    /// One hash keyed by document
+    Redis {
+        connection: MultiplexedConnection,
+        key: String,
+    },
+    /// One object per document, named by the SHA-256 of its text under `prefix`
+    S3 { bucket: Box<Bucket>, prefix: String },
+}
+
+impl SharedCache {
+    /// Connects to the shared cache of `--shared-cache`, if any, warning when it can't be reached.
+    pub async fn connect(provider: &str, cache_name: &str) -> Option<Self> {
+        let url = SHARED_CACHE_URL.get()?;
+        Self::connect_to(url, provider, cache_name)
+            .await
+            .inspect_err(|error| {
+                warnings::warn(format!(
+                    "Failed to connect to the shared cache {url}: {error}"
+                ))
+            })
+            .ok()
+    }
+
+    async fn connect_to(
+        url: &str,
+        provider: &str,
+        cache_name: &str,
+    ) -> Result<Self, SharedCacheError> {
+        let Some(location) = url.strip_prefix("s3://") else {
+            allowlist::check(url);
+            let connection = redis::Client::open(url)?
+                .get_multiplexed_async_connection()
+                .await?;
+            return Ok(SharedCache::Redis {
+                connection,
+                key: format!("distance-calculator:cache:{provider}:{cache_name}"),
+            });
+        };
+
+        let (name, prefix) = location.split_once('/').unwrap_or((location, ""));
+        // AWS_ENDPOINT points to S3-compatible stores such as MinIO, which expect path-style URLs
+        let custom_endpoint = std::env::var_os("AWS_ENDPOINT").is_some();
+        let region = Region::from_default_env().unwrap_or(Region::UsEast1);
+        let mut bucket = Bucket::new(name, region, Credentials::default().map_err(S3Error::from)?)?;
+        if custom_endpoint {
+            bucket = bucket.with_path_style();
+        }
+        allowlist::check(&bucket.url());
+
+        Ok(SharedCache::S3 {
+            bucket,
+            prefix: s3_prefix(prefix, provider, cache_name),
+        })
+    }
+
+    /// The version and vector of each of `documents`, if shared.
+    pub async fn fetch(
+        &mut self,
+        documents: &[String],
+    ) -> Result<Vec<Option<(Option<String>, Vec<f64>)>>, SharedCacheError> {
+        let mut entries = Vec::with_capacity(documents.len());
+        match self {
+            SharedCache::Redis { connection, key } => {
+                for chunk in documents.chunks(REDIS_CHUNK_SIZE) {
+                    let values: Vec<Option<Vec<u8>>> = redis::cmd("HMGET")
+                        .arg(&*key)
+                        .arg(chunk)
+                        .query_async(connection)
+                        .await?;
+                    entries.extend(values.iter().map(|value| value.as_deref().and_then(decode)));
+                }
+            }
+            SharedCache::S3 { bucket, prefix } => {
+                for chunk in documents.chunks(S3_CONCURRENCY) {
+                    let mut requests = JoinSet::new();
+                    for (i, document) in chunk.iter().enumerate() {
+                        let (bucket, path) = (bucket.clone(), object_path(prefix, document));
+                        requests.spawn(async move { (i, bucket.get_object(path).await) });
+                    }
+
+                    let mut chunk_entries = vec![None; chunk.len()];
+                    while let Some(request) = requests.join_next().await {
+                        let (i, response) = request.expect("S3 request panicked");
+                        let response = response?;
+                        match response.status_code() {
+                            200 => chunk_entries[i] = decode(response.bytes()),
+                            404 => {}
+                            status => return Err(http_error(status, response.bytes()).into()),
+                        }
+                    }
+                    entries.extend(chunk_entries);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Shares `embeddings`, embedded by the model `version`.
+    pub async fn store(
+        &mut self,
+        embeddings: &[Embedding],
+        version: Option<&str>,
+    ) -> Result<(), SharedCacheError> {
+        if embeddings.is_empty() {
+            return Ok(());
+        }
+
+        match self {
+            SharedCache::Redis { connection, key } => {
+                let entries = embeddings
+                    .iter()
+                    .map(|embedding| (embedding.document.as_str(), encode(version, &embedding.vec)))
+                    .collect::<Vec<_>>();
+                connection.hset_multiple(&*key, &entries).await?
+            }
+            SharedCache::S3 { bucket, prefix } => {
+                for chunk in embeddings.chunks(S3_CONCURRENCY) {
+                    let mut requests = JoinSet::new();
+                    for embedding in chunk {
+                        let bucket = bucket.clone();
+                        let path = object_path(prefix, &embedding.document);
+                        let value = encode(version, &embedding.vec);
+                        requests.spawn(async move { bucket.put_object(path, &value).await });
+                    }
+
+                    while let Some(request) = requests.join_next().await {
+                        let response = request.expect("S3 request panicked")?;
+                        if response.status_code() != 200 {
+                            return Err(http_error(response.status_code(), response.bytes()).into());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prefix of the objects of one provider and model under the prefix of the `--shared-cache` URL.
+fn s3_prefix(prefix: &str, provider: &str, cache_name: &str) -> String {
+    [prefix.trim_matches('/'), provider, cache_name]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .join("/")
+}
+
+fn object_path(prefix: &str, document: &str) -> String {
+    format!("{prefix}/{:x}", Sha256::digest(document))
+}
+
+fn http_error(status: u16, body: &[u8]) -> S3Error {
+    S3Error::HttpFailWithBody(status, String::from_utf8_lossy(body).into_owned())
+}
+
+/// Value of a document in the shared cache: `version length (u32) | version (UTF-8) | vector
+/// (f64 each)`, little-endian, the version being empty when unknown.
+fn encode(version: Option<&str>, vector: &[f64]) -> Vec<u8> {
+    let version = version.unwrap_or_default();
+    let mut value = Vec::with_capacity(4 + version.len() + vector.len() * 8);
+    value.extend((version.len() as u32).to_le_bytes());
+    value.extend(version.as_bytes());
+    for component in vector {
+        value.extend(component.to_le_bytes());
+    }
+    value
+}
+
+fn decode(value: &[u8]) -> Option<(Option<String>, Vec<f64>)> {
+    let length = u32::from_le_bytes(value.get(..4)?.try_into().ok()?) as usize;
+    let version = String::from_utf8(value.get(4..4 + length)?.to_vec()).ok()?;
+    let vector = value.get(4 + length..)?;
+    if vector.len() % 8 != 0 {
+        return None;
+    }
+
+    let vector = vector
+        .chunks_exact(8)
+        .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    Some((Some(version).filter(|version| !version.is_empty()), vector))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{Method, StatusCode, Uri},
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::Mutex,
+    };
+
+    use super::*;
+
+    fn embedding(document: &str, vec: Vec<f64>) -> Embedding {
+        Embedding {
+            document: document.to_string(),
+            vec,
+        }
+    }
+
+    /// Hashes of the fake Redis server, by key.
+    type Hashes = Arc<Mutex<HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>>>>;
+
+    /// Reads one command of the Redis protocol, an array of bulk strings.
+    async fn read_command(
+        reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    ) -> Option<Vec<Vec<u8>>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok().filter(|&n| n > 0)?;
+        let count = line.trim_end().strip_prefix('*')?.parse::<usize>().ok()?;
+        let mut arguments = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            let length = line.trim_end().strip_prefix('$')?.parse::<usize>().ok()?;
+            let mut argument = vec![0; length + 2];
+            reader.read_exact(&mut argument).await.ok()?;
+            argument.truncate(length);
+            arguments.push(argument);
+        }
+        Some(arguments)
+    }
+
+    /// Answers HMGET and HMSET from hashes kept in memory, and everything else with OK.
+    async fn fake_redis() -> (
+        String,
+        Arc<Mutex<HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>>>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let hashes = Hashes::default();
+        let server_hashes = hashes.clone();
+        tokio::spawn(async move {
+            while let Ok((connection, _)) = listener.accept().await {
+                let hashes = server_hashes.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = connection.into_split();
+                    let mut reader = BufReader::new(reader);
+                    while let Some(command) = read_command(&mut reader).await {
+                        let mut hashes = hashes.lock().await;
+                        let reply = match command[0].to_ascii_uppercase().as_slice() {
+                            b"HMGET" => {
+                                let hash = hashes.get(&command[1]);
+                                let mut reply = format!("*{}\r\n", command.len() - 2).into_bytes();
+                                for field in &command[2..] {
+                                    match hash.and_then(|hash| hash.get(field)) {
+                                        Some(value) => {
+                                            reply.extend(format!("${}\r\n", value.len()).bytes());
+                                            reply.extend(value);
+                                            reply.extend(b"\r\n");
+                                        }
+                                        None => reply.extend(b"$-1\r\n"),
+                                    }
+                                }
+                                reply
+                            }
+                            b"HMSET" | b"HSET" => {
+                                let hash = hashes.entry(command[1].clone()).or_default();
+                                for pair in command[2..].chunks(2) {
+                                    hash.insert(pair[0].clone(), pair[1].clone());
+                                }
+                                b"+OK\r\n".to_vec()
+                            }
+                            _ => b"+OK\r\n".to_vec(),
+                        };
+                        writer.write_all(&reply).await.unwrap();
+                    }
+                });
+            }
+        });
+        (url, hashes)
+    }
+
+    #[test]
+    fn values_round_trip_with_their_version() {
+        let vector = [0.25, -1.5, f64::MIN_POSITIVE];
+        let value = encode(Some("text-embedding-3-small"), &vector);
+        assert_eq!(value.len(), 4 + 22 + 3 * 8);
+        assert_eq!(
+            decode(&value),
+            Some((Some("text-embedding-3-small".to_string()), vector.to_vec()))
+        );
+        assert_eq!(
+            decode(&encode(None, &vector)),
+            Some((None, vector.to_vec()))
+        );
+    }
+
+    #[test]
+    fn malformed_values_are_misses() {
+        let value = encode(Some("v1"), &[1.0, 2.0]);
+        assert_eq!(decode(&value[..3]), None);
+        assert_eq!(decode(&value[..value.len() - 1]), None);
+        assert_eq!(decode(&[9, 0, 0, 0, b'v']), None);
+    }
+
+    #[tokio::test]
+    async fn redis_hashes_are_keyed_by_provider_and_model() {
+        let (url, hashes) = fake_redis().await;
+        let mut cache = SharedCache::connect_to(&url, "openai", "text-embedding-3-small")
+            .await
+            .unwrap();
+        let SharedCache::Redis { key, .. } = &cache else {
+            panic!("expected a Redis cache");
+        };
+        assert_eq!(
+            key,
+            "distance-calculator:cache:openai:text-embedding-3-small"
+        );
+
+        assert_eq!(
+            cache
+                .fetch(&["cat".to_string(), "dog".to_string()])
+                .await
+                .unwrap(),
+            [None, None]
+        );
+        cache
+            .store(&[embedding("cat", vec![1.0, 0.0])], Some("v1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            cache
+                .fetch(&["cat".to_string(), "dog".to_string()])
+                .await
+                .unwrap(),
+            [Some((Some("v1".to_string()), vec![1.0, 0.0])), None]
+        );
+
+        // Another model shares nothing with the first
+        let mut other = SharedCache::connect_to(&url, "openai", "text-embedding-3-large")
+            .await
+            .unwrap();
+        assert_eq!(other.fetch(&["cat".to_string()]).await.unwrap(), [None]);
+        assert_eq!(hashes.lock().await.len(), 1);
+    }
+
+    /// Objects of the fake S3 store, by path.
+    type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Serves and stores objects in memory, by path, and answers 404 for the others.
+    async fn fake_s3() -> (String, Objects) {
+        let objects = Objects::default();
+        let app = axum::Router::new()
+            .fallback(
+                |State(objects): State<Objects>, method: Method, uri: Uri, body: Bytes| async move {
+                    let mut objects = objects.lock().await;
+                    match method {
+                        Method::PUT => {
+                            objects.insert(uri.path().to_string(), body.to_vec());
+                            (StatusCode::OK, vec![])
+                        }
+                        _ => match objects.get(uri.path()) {
+                            Some(object) => (StatusCode::OK, object.clone()),
+                            None => (StatusCode::NOT_FOUND, vec![]),
+                        },
+                    }
+                },
+            )
+            .with_state(objects.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (endpoint, objects)
+    }
+
+    #[test]
+    fn s3_objects_are_named_by_provider_model_and_text_hash() {
+        assert_eq!(s3_prefix("", "openai", "small"), "openai/small");
+        assert_eq!(
+            s3_prefix("/team/cache/", "openai", "small"),
+            "team/cache/openai/small"
+        );
+        assert_eq!(
+            object_path("openai/small", "hello"),
+            "openai/small/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[tokio::test]
+    async fn s3_objects_are_hits_once_stored() {
+        let (endpoint, objects) = fake_s3().await;
+        let region = Region::Custom {
+            region: "us-east-1".to_string(),
+            endpoint,
+        };
+        let credentials = Credentials::new(Some("key"), Some("secret"), None, None, None).unwrap();
+        let mut cache = SharedCache::S3 {
+            bucket: Bucket::new("embeddings", region, credentials)
+                .unwrap()
+                .with_path_style(),
+            prefix: s3_prefix("team", "cohere", "embed-english-v3.0"),
+        };
+
+        let documents = ["cat".to_string(), "dog".to_string()];
+        assert_eq!(cache.fetch(&documents).await.unwrap(), [None, None]);
+        cache
+            .store(&[embedding("dog", vec![0.5, 0.5])], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.fetch(&documents).await.unwrap(),
+            [None, Some((None, vec![0.5, 0.5]))]
+        );
+        assert_eq!(
+            objects.lock().await.keys().collect::<Vec<_>>(),
+            [&format!(
+                "/embeddings/{}",
+                object_path("team/cohere/embed-english-v3.0", "dog")
+            )]
+        );
+    }
+
+    #[test]
+    fn only_redis_and_s3_urls_are_shared_caches() {
+        let error = std::panic::catch_unwind(|| set_url("memcached://cache:11211".to_string()))
+            .unwrap_err();
+        assert!(error
+            .downcast_ref::<String>()
+            .unwrap()
+            .contains("expected a redis:// or s3:// URL"));
+    }
+}
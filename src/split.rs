@@ -0,0 +1,351 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::BufWriter,
+};
+
+use clap::Args;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::EmbeddingCache,
+    cluster, files,
+    hnsw::{self, Hnsw, IndexArgs},
+    models, search, table, DistanceMetric, Provider, ProviderArgs,
+};
+
+/// Nearest documents of every document looked for near-duplicates with the HNSW index. Chains
+/// of near-duplicates still join documents with more of them into one group.
+const DUPLICATE_CANDIDATES: usize = 10;
+
+#[derive(Args, Debug)]
+pub struct SplitArgs {
+    /// JSON array of `{"text", "label"}` objects (or of plain strings when stratifying by cluster)
+    #[arg(short)]
+    input_file: String,
+    /// JSON file the documents are written to, each with its fold
+    #[arg(short, long)]
+    output: String,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    #[arg(long, default_value_t = 5)]
+    folds: usize,
+    /// Stratify by this many k-means clusters instead of by label
+    #[arg(long)]
+    clusters: Option<usize>,
+    /// Score from which two documents are near-duplicates and kept in the same fold
+    #[arg(long, default_value_t = 0.95)]
+    duplicate_threshold: f64,
+    /// Near-duplicates of large corpora are looked for among the nearest documents found by an
+    /// HNSW index
+    #[command(flatten)]
+    index: IndexArgs,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InputDocument {
+    Text(String),
+    Labeled { text: String, label: Option<String> },
+}
+
+#[derive(Serialize)]
+struct FoldDocument<'a> {
+    text: &'a str,
+    label: Option<&'a str>,
+    fold: usize,
+}
+
+impl SplitArgs {
+    fn documents(&self) -> Vec<(String, Option<String>)> {
+        files::read_json::<Vec<InputDocument>>(&self.input_file)
+            .into_iter()
+            .map(|document| match document {
+                InputDocument::Text(text) => (text, None),
+                InputDocument::Labeled { text, label } => (text, label),
+            })
+            .collect()
+    }
+}
+
+fn find(parents: &mut [usize], mut document: usize) -> usize {
+    while parents[document] != document {
+        parents[document] = parents[parents[document]];
+        document = parents[document];
+    }
+    document
+}
+
+/// Groups of documents linked by chains of near-duplicate pairs, looked for among the
+/// `DUPLICATE_CANDIDATES` nearest documents of every document if `index` is given, among all
+/// of them otherwise.
+fn duplicate_groups(
+    vectors: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+    threshold: f64,
+    index: Option<(&Hnsw, usize)>,
+) -> Vec<Vec<usize>> {
+    let is_duplicate = |i: usize, j: usize| {
+        let score = distance_metric.distance(&vectors[i], &vectors[j]);
+        distance_metric.cmp_closeness(score, threshold).is_ge()
+    };
+    let duplicates = match index {
+        Some((index, ef)) => index
+            .neighbors(DUPLICATE_CANDIDATES, ef, |a, b| {
+                hnsw::cost(distance_metric, &vectors[a], &vectors[b])
+            })
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, neighbors)| neighbors.into_iter().map(move |j| (i, j)))
+            .filter(|&(i, j)| is_duplicate(i, j))
+            .collect::<Vec<_>>(),
+        None => (0..vectors.len())
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                (i + 1..vectors.len())
+                    .filter(move |&j| is_duplicate(i, j))
+                    .map(move |j| (i, j))
+            })
+            .collect::<Vec<_>>(),
+    };
+
+    let mut parents = (0..vectors.len()).collect::<Vec<_>>();
+    for (i, j) in duplicates {
+        let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+        parents[root_i.max(root_j)] = root_i.min(root_j);
+    }
+
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for document in 0..vectors.len() {
+        groups
+            .entry(find(&mut parents, document))
+            .or_default()
+            .push(document);
+    }
+    groups.into_values().collect()
+}
+
+/// Assigns every group of near-duplicates to a single fold, spreading the groups of each stratum
+/// so that every fold gets about the same number of its documents.
+fn assign_folds(
+    groups: Vec<Vec<usize>>,
+    strata: &[String],
+    folds: usize,
+    rng: &mut StdRng,
+) -> Vec<usize> {
+    let mut strata_groups = BTreeMap::<_, Vec<_>>::new();
+    for group in groups {
+        strata_groups
+            .entry(strata[group[0]].clone())
+            .or_default()
+            .push(group);
+    }
+
+    let mut assignments = vec![0; strata.len()];
+    for mut groups in strata_groups.into_values() {
+        // Largest groups first, ties in random order, each to the fold that has the fewest
+        // documents of the stratum so far
+        groups.shuffle(rng);
+        groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+
+        let mut sizes = vec![0; folds];
+        for group in groups {
+            let fold = (0..folds).min_by_key(|&fold| sizes[fold]).unwrap();
+            sizes[fold] += group.len();
+            for document in group {
+                assignments[document] = fold;
+            }
+        }
+    }
+    assignments
+}
+
+/// Partitions a labeled corpus into folds stratified by label or by cluster, keeping
+/// near-duplicates together so that no fold can be tested on documents it was trained on.
+pub async fn run(args: SplitArgs) {
+    assert!(args.folds >= 2, "--folds must be at least 2");
+    let documents = args.documents();
+    let input_strings = documents
+        .iter()
+        .map(|(text, _)| text.clone())
+        .collect::<Vec<_>>();
+
+    let (embeddings, _) =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model)
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &input_strings,
+                args.provider.max_batch_size(),
+            )
+            .await;
+    let vectors = embeddings
+        .into_iter()
+        .map(|embedding| embedding.vec)
+        .collect::<Vec<_>>();
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let strata = match args.clusters {
+        Some(k) => cluster::kmeans(&vectors, k, &mut rng)
+            .into_iter()
+            .map(|cluster| format!("cluster {cluster}"))
+            .collect::<Vec<_>>(),
+        None => documents
+            .iter()
+            .map(|(_, label)| label.clone().unwrap_or_else(|| "unlabeled".to_string()))
+            .collect(),
+    };
+
+    let cost = |a: usize, b: usize| hnsw::cost(&args.distance_metric, &vectors[a], &vectors[b]);
+    let index = args
+        .index
+        .indexed(vectors.len())
+        .then(|| Hnsw::index(vectors.len(), cost));
+    if let (Some(index), Some(sample)) = (&index, args.index.verify_sample) {
+        let queries = search::queries(vectors.len(), Some(sample), &mut rng);
+        let recall = index.recall(
+            &queries,
+            DUPLICATE_CANDIDATES,
+            args.index.ef_search,
+            true,
+            cost,
+        );
+        hnsw::print_recall(recall, DUPLICATE_CANDIDATES, queries.len());
+    }
+    let groups = duplicate_groups(
+        &vectors,
+        &args.distance_metric,
+        args.duplicate_threshold,
+        index.as_ref().map(|index| (index, args.index.ef_search)),
+    );
+    let duplicates = groups.iter().filter(|group| group.len() > 1).count();
+    let folds = assign_folds(groups, &strata, args.folds, &mut rng);
+
+    let output = documents
+        .iter()
+        .zip(&folds)
+        .map(|((text, label), &fold)| FoldDocument {
+            text,
+            label: label.as_deref(),
+            fold,
+        })
+        .collect::<Vec<_>>();
+    let file = File::create(files::long_path(&args.output)).expect("Failed to create output file");
+    serde_json::to_writer_pretty(BufWriter::new(file), &output).expect("Failed to write folds");
+
+    let names = strata.iter().cloned().collect::<BTreeSet<_>>();
+    let mut table = vec![["fold".to_string(), "documents".to_string()]
+        .into_iter()
+        .chain(names.iter().cloned())
+        .collect::<Vec<_>>()];
+    for fold in 0..args.folds {
+        let mut row = vec![
+            fold.to_string(),
+            folds
+                .iter()
+                .filter(|&&other| other == fold)
+                .count()
+                .to_string(),
+        ];
+        row.extend(names.iter().map(|name| {
+            folds
+                .iter()
+                .zip(&strata)
+                .filter(|(&other, stratum)| other == fold && *stratum == name)
+                .count()
+                .to_string()
+        }));
+        table.push(row);
+    }
+
+    println!("{duplicates} groups of near-duplicates kept within a single fold");
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two pairs of near-duplicates, a chain of three and a document apart.
+    fn vectors() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.99, 0.1, 0.0],
+            vec![0.0, 0.0, 1.0],
+            vec![0.0, 0.99, 0.14],
+            vec![0.0, 0.97, 0.25],
+            vec![-1.0, 0.0, 0.0],
+        ]
+    }
+
+    #[test]
+    fn near_duplicates_are_grouped_through_chains() {
+        assert_eq!(
+            duplicate_groups(&vectors(), &DistanceMetric::Cosine, 0.99, None),
+            [vec![0, 2], vec![1, 4, 5], vec![3], vec![6]]
+        );
+        // Only identical directions at 1, every document at -1
+        assert_eq!(
+            duplicate_groups(&vectors(), &DistanceMetric::Cosine, 1.0, None).len(),
+            7
+        );
+        assert_eq!(
+            duplicate_groups(&vectors(), &DistanceMetric::Cosine, -1.0, None),
+            [(0..7).collect::<Vec<_>>()]
+        );
+        assert!(duplicate_groups(&[], &DistanceMetric::Cosine, 0.9, None).is_empty());
+    }
+
+    #[test]
+    fn the_index_finds_the_same_near_duplicates() {
+        let vectors = vectors();
+        let index = Hnsw::build(
+            vectors.len(),
+            |a, b| hnsw::cost(&DistanceMetric::Cosine, &vectors[a], &vectors[b]),
+            &indicatif::ProgressBar::hidden(),
+        );
+        assert_eq!(
+            duplicate_groups(&vectors, &DistanceMetric::Cosine, 0.99, Some((&index, 10))),
+            duplicate_groups(&vectors, &DistanceMetric::Cosine, 0.99, None)
+        );
+    }
+
+    #[test]
+    fn groups_stay_in_one_fold_and_strata_spread_evenly() {
+        let groups = vec![vec![0, 1, 2], vec![3], vec![4], vec![5], vec![6, 7]];
+        let strata = ["a", "a", "a", "a", "a", "a", "b", "b"].map(String::from);
+        let folds = assign_folds(groups, &strata, 2, &mut StdRng::seed_from_u64(0));
+        assert_eq!(folds[0], folds[1]);
+        assert_eq!(folds[1], folds[2]);
+        assert_eq!(folds[6], folds[7]);
+        let size = |fold: usize| (0..6).filter(|i| folds[*i] == fold).count();
+        assert_eq!((size(0), size(1)), (3, 3), "{folds:?}");
+        assert!(assign_folds(vec![], &[], 3, &mut StdRng::seed_from_u64(0)).is_empty());
+    }
+
+    #[test]
+    fn folds_are_determined_by_the_seed() {
+        let groups = || (0..20).map(|i| vec![i]).collect::<Vec<_>>();
+        let strata = vec!["a".to_string(); 20];
+        let folds =
+            |seed: u64| assign_folds(groups(), &strata, 4, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(folds(1), folds(1));
+        assert_ne!(folds(1), folds(2));
+        let fold_sizes = (0..4)
+            .map(|fold| folds(1).iter().filter(|other| **other == fold).count())
+            .collect::<Vec<_>>();
+        assert_eq!(fold_sizes, [5, 5, 5, 5]);
+    }
+}
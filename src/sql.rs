@@ -0,0 +1,253 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use sqlx::{any::AnyRow, AnyPool, Row};
+
+use crate::allowlist;
+
+/// SQLite's historical limit on the placeholders of a statement, which inserts stay below.
+const MAX_PLACEHOLDERS: usize = 999;
+
+/// Columns of the results table.
+const RESULT_COLUMNS: &[&str] = &["source_id", "target_id", "metric", "score"];
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum WriteMode {
+    /// One row per pair of documents
+    Pairs,
+    /// One row per document with its nearest neighbor
+    Neighbors,
+}
+
+impl Display for WriteMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteMode::Pairs => write!(f, "pairs"),
+            WriteMode::Neighbors => write!(f, "neighbors"),
+        }
+    }
+}
+
+/// A row written back to the results table.
+pub struct ResultRow<'a> {
+    pub source_id: &'a str,
+    pub target_id: &'a str,
+    pub score: f64,
+}
+
+/// Parses `--write-results`: a table name, optionally qualified by its schema, made of ASCII
+/// letters, digits and underscores so that it can be spliced into SQL for every database.
+pub fn parse_table_name(table: &str) -> Result<String, String> {
+    let is_identifier = |part: &str| {
+        part.starts_with(|character: char| character.is_ascii_alphabetic() || character == '_')
+            && part
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || character == '_')
+    };
+    let parts = table.split('.').collect::<Vec<_>>();
+    if parts.len() > 2 || !parts.into_iter().all(is_identifier) {
+        return Err(
+            "must be a table name, or schema.table, of letters, digits and underscores".to_string(),
+        );
+    }
+    Ok(table.to_string())
+}
+
+/// Parses `--db`: a database URL, or the path of a SQLite file, created if missing.
+pub fn parse_database_url(db: &str) -> Result<String, String> {
+    if db.is_empty() {
+        return Err("must be a database URL or a SQLite file".to_string());
+    }
+    if db.contains("://") {
+        return Ok(db.to_string());
+    }
+    Ok(format!("sqlite://{db}?mode=rwc"))
+}
+
+pub async fn connect(url: &str) -> AnyPool {
+    sqlx::any::install_default_drivers();
+    allowlist::check(url);
+
+    AnyPool::connect(url)
+        .await
+        .unwrap_or_else(|error| panic!("Failed to connect to {url}: {error}"))
+}
+
+/// Runs `query` against the database at `url` (`postgres://`, `mysql://` or `sqlite://`) and
+/// returns the `(id, document)` of every row: the first column is the id and the last column
+/// is the document.
+///
+/// This lets queries like `select id, body from articles` be used unchanged. Single-column
+/// queries are identified by their row number.
+pub async fn read_documents(url: &str, query: &str) -> Vec<(String, String)> {
+    let pool = connect(url).await;
+    let rows = sqlx::query(query)
+        .fetch_all(&pool)
+        .await
+        .unwrap_or_else(|error| panic!("Failed to run input query: {error}"));
+
+    rows.iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let id = if row.len() > 1 {
+                id(row)
+            } else {
+                i.to_string()
+            };
+            (id, document(row))
+        })
+        .collect()
+}
+
+fn id(row: &AnyRow) -> String {
+    row.try_get::<String, _>(0)
+        .or_else(|_| row.try_get::<i64, _>(0).map(|id| id.to_string()))
+        .or_else(|_| row.try_get::<i32, _>(0).map(|id| id.to_string()))
+        .expect("First column of the input query must be a text or integer id")
+}
+
+fn document(row: &AnyRow) -> String {
+    let column = row
+        .len()
+        .checked_sub(1)
+        .expect("Input query must return at least one column");
+
+    row.try_get(column)
+        .expect("Last column of the input query must be text")
+}
+
+/// Creates `table` if needed and inserts `rows` into it in a single transaction, a batch of
+/// rows per statement.
+pub async fn write_results(url: &str, table: &str, metric: &str, rows: &[ResultRow<'_>]) {
+    let pool = connect(url).await;
+
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS {table} \
+         (source_id TEXT NOT NULL, target_id TEXT NOT NULL, metric TEXT NOT NULL, score DOUBLE PRECISION NOT NULL)"
+    ))
+    .execute(&pool)
+    .await
+    .unwrap_or_else(|error| panic!("Failed to create results table {table}: {error}"));
+
+    let mut transaction = pool.begin().await.unwrap();
+    for batch in rows.chunks(batch_rows(RESULT_COLUMNS)) {
+        let statement = insert_statement(url, table, RESULT_COLUMNS, batch.len());
+        let mut query = sqlx::query(&statement);
+        for row in batch {
+            query = query
+                .bind(row.source_id)
+                .bind(row.target_id)
+                .bind(metric)
+                .bind(row.score);
+        }
+        query
+            .execute(&mut *transaction)
+            .await
+            .unwrap_or_else(|error| panic!("Failed to write results to {table}: {error}"));
+    }
+    transaction.commit().await.unwrap();
+}
+
+/// Rows of `columns` inserted by one statement.
+pub fn batch_rows(columns: &[&str]) -> usize {
+    MAX_PLACEHOLDERS / columns.len()
+}
+
+/// Statement inserting `rows` rows of `columns` into `table`.
+pub fn insert_statement(url: &str, table: &str, columns: &[&str], rows: usize) -> String {
+    // The Any driver passes placeholders through untouched, and Postgres numbers them
+    let values = (0..rows)
+        .map(|row| {
+            let placeholders = (1..=columns.len())
+                .map(|column| match url.starts_with("postgres") {
+                    true => format!("${}", row * columns.len() + column),
+                    false => "?".to_string(),
+                })
+                .collect::<Vec<_>>();
+            format!("({})", placeholders.join(", "))
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "INSERT INTO {table} ({}) VALUES {}",
+        columns.join(", "),
+        values.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_names_are_plain_identifiers() {
+        for table in ["results", "_results_2", "analytics.Results"] {
+            assert_eq!(parse_table_name(table), Ok(table.to_string()));
+        }
+        for table in [
+            "",
+            "2results",
+            "results; DROP TABLE articles",
+            "a.b.c",
+            "results.",
+            "\"results\"",
+            "résultats",
+        ] {
+            assert!(parse_table_name(table).is_err(), "{table}");
+        }
+    }
+
+    #[test]
+    fn paths_are_sqlite_databases() {
+        assert_eq!(
+            parse_database_url("results.sqlite"),
+            Ok("sqlite://results.sqlite?mode=rwc".to_string())
+        );
+        assert_eq!(
+            parse_database_url("postgres://localhost/analytics"),
+            Ok("postgres://localhost/analytics".to_string())
+        );
+        assert!(parse_database_url("").is_err());
+    }
+
+    #[test]
+    fn postgres_placeholders_are_numbered_across_rows() {
+        assert_eq!(
+            insert_statement("postgres://localhost/db", "results", RESULT_COLUMNS, 2),
+            "INSERT INTO results (source_id, target_id, metric, score) \
+             VALUES ($1, $2, $3, $4), ($5, $6, $7, $8)"
+        );
+        assert_eq!(
+            insert_statement("sqlite://results.db", "results", RESULT_COLUMNS, 1),
+            "INSERT INTO results (source_id, target_id, metric, score) VALUES (?, ?, ?, ?)"
+        );
+    }
+
+    #[tokio::test]
+    async fn results_are_written_in_batches() {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-results-{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+
+        let ids = (0..batch_rows(RESULT_COLUMNS) + 1)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        let rows = ids
+            .iter()
+            .map(|id| ResultRow {
+                source_id: id,
+                target_id: "0",
+                score: 0.5,
+            })
+            .collect::<Vec<_>>();
+        write_results(&url, "results", "cosine", &rows).await;
+
+        let written = read_documents(&url, "select source_id, metric from results").await;
+        assert_eq!(written.len(), rows.len());
+        let last = batch_rows(RESULT_COLUMNS);
+        assert_eq!(written[last].0, last.to_string());
+        assert!(written.iter().all(|(_, metric)| metric == "cosine"));
+    }
+}
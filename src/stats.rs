@@ -0,0 +1,368 @@
+use rand::{rngs::StdRng, Rng};
+use semanticsimilarity_rs::pearson_correlation;
+
+use crate::{
+    format_header,
+    pairs::{sorted_pairs, Pair},
+    table, DistanceMetric,
+};
+
+/// Fractional ranks of `values` (1-based), where tied values share their average rank.
+pub fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order = (0..values.len()).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| values[i].total_cmp(&values[j]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end + 1 < order.len() && values[order[end + 1]] == values[order[start]] {
+            end += 1;
+        }
+
+        let rank = (start + end) as f64 / 2.0 + 1.0;
+        for &index in &order[start..=end] {
+            ranks[index] = rank;
+        }
+        start = end + 1;
+    }
+
+    ranks
+}
+
+pub fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    pearson_correlation(x, y)
+}
+
+pub fn spearman(x: &[f64], y: &[f64]) -> f64 {
+    pearson(&ranks(x), &ranks(y))
+}
+
+/// Kendall's tau-b, which accounts for ties, in O(n log n) by counting the swaps of a merge
+/// sort (Knight's algorithm).
+pub fn kendall(x: &[f64], y: &[f64]) -> f64 {
+    let mut order = (0..x.len()).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| x[i].total_cmp(&x[j]).then(y[i].total_cmp(&y[j])));
+    let pairs = |n: usize| (n * n.saturating_sub(1) / 2) as f64;
+    let tied = |order: &[usize], same: &dyn Fn(usize, usize) -> bool| {
+        order
+            .chunk_by(|&i, &j| same(i, j))
+            .map(|group| pairs(group.len()))
+            .sum::<f64>()
+    };
+    let total = pairs(x.len());
+    let x_ties = tied(&order, &|i, j| x[i] == x[j]);
+    let joint_ties = tied(&order, &|i, j| x[i] == x[j] && y[i] == y[j]);
+
+    let mut ys = order.iter().map(|&i| y[i]).collect::<Vec<_>>();
+    let swaps = merge_sort_swaps(&mut ys, &mut vec![0.0; x.len()]);
+    let y_ties = ys
+        .chunk_by(|a, b| a == b)
+        .map(|group| pairs(group.len()))
+        .sum::<f64>();
+
+    let concordant_minus_discordant = total - x_ties - y_ties + joint_ties - 2.0 * swaps as f64;
+    concordant_minus_discordant / ((total - x_ties) * (total - y_ties)).sqrt()
+}
+
+/// Sorts `values` and returns the number of swaps of adjacent values that sorting them takes.
+fn merge_sort_swaps(values: &mut [f64], buffer: &mut [f64]) -> usize {
+    if values.len() < 2 {
+        return 0;
+    }
+    let (length, middle) = (values.len(), values.len() / 2);
+    let (left, right) = values.split_at_mut(middle);
+    let mut swaps = merge_sort_swaps(left, buffer) + merge_sort_swaps(right, buffer);
+
+    let (mut i, mut j) = (0, 0);
+    for slot in buffer[..length].iter_mut() {
+        if j == right.len() || (i < left.len() && left[i] <= right[j]) {
+            *slot = left[i];
+            i += 1;
+        } else {
+            *slot = right[j];
+            swaps += left.len() - i;
+            j += 1;
+        }
+    }
+    values.copy_from_slice(&buffer[..length]);
+    swaps
+}
+
+/// Two-sided paired permutation test for the difference between the Spearman correlations of
+/// `a` and `b` with `gold`.
+///
+/// Scores are converted to ranks first so that models with different score scales can be
+/// swapped item by item. Returns the observed difference and its p-value.
+pub fn paired_permutation_test(
+    gold: &[f64],
+    a: &[f64],
+    b: &[f64],
+    rounds: usize,
+    rng: &mut impl Rng,
+) -> (f64, f64) {
+    let gold = ranks(gold);
+    let a = ranks(a);
+    let b = ranks(b);
+
+    let observed = pearson(&a, &gold) - pearson(&b, &gold);
+
+    let mut permuted_a = a.clone();
+    let mut permuted_b = b.clone();
+    let mut extreme = 0;
+    for _ in 0..rounds {
+        for i in 0..gold.len() {
+            if rng.gen::<bool>() {
+                permuted_a[i] = b[i];
+                permuted_b[i] = a[i];
+            } else {
+                permuted_a[i] = a[i];
+                permuted_b[i] = b[i];
+            }
+        }
+
+        let difference = pearson(&permuted_a, &gold) - pearson(&permuted_b, &gold);
+        if difference.abs() >= observed.abs() {
+            extreme += 1;
+        }
+    }
+
+    (observed, (extreme + 1) as f64 / (rounds + 1) as f64)
+}
+
+/// Confidence level of the `--bootstrap` intervals.
+pub const BOOTSTRAP_CONFIDENCE: f64 = 0.95;
+
+/// Percentile bootstrap interval of `statistic` over `samples` items: the statistic is
+/// recomputed on `rounds` resamples of the item positions drawn with replacement, and the
+/// interval spans the middle [BOOTSTRAP_CONFIDENCE] of its values. Resamples on which the
+/// statistic is undefined, such as a correlation of constant scores, are left out.
+pub fn bootstrap_interval(
+    samples: usize,
+    rounds: u32,
+    rng: &mut impl Rng,
+    statistic: impl Fn(&[usize]) -> f64,
+) -> (f64, f64) {
+    let mut values = (0..rounds)
+        .map(|_| {
+            let resample = (0..samples)
+                .map(|_| rng.gen_range(0..samples))
+                .collect::<Vec<_>>();
+            statistic(&resample)
+        })
+        .filter(|value| value.is_finite())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return (f64::NAN, f64::NAN);
+    }
+    values.sort_by(f64::total_cmp);
+    let percentile =
+        |quantile: f64| values[((values.len() - 1) as f64 * quantile).round() as usize];
+    let tail = (1.0 - BOOTSTRAP_CONFIDENCE) / 2.0;
+    (percentile(tail), percentile(1.0 - tail))
+}
+
+/// The values of `values` at the positions of a resample.
+pub fn resampled(values: &[f64], resample: &[usize]) -> Vec<f64> {
+    resample.iter().map(|&i| values[i]).collect()
+}
+
+/// Header of a column of `--bootstrap` intervals of a statistic.
+pub fn interval_header(statistic: &str) -> String {
+    format!("{statistic} {:.0}% CI", BOOTSTRAP_CONFIDENCE * 100.0)
+}
+
+pub fn format_interval((low, high): (f64, f64)) -> String {
+    format!(
+        "[{}, {}]",
+        table::format_score(low),
+        table::format_score(high)
+    )
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+/// Population standard deviation.
+pub fn std_dev(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    (values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64)
+        .sqrt()
+}
+
+/// Prints the distribution of the pairwise scores of `matrix`, between embeddings of
+/// `dimensions`, along with its closest and farthest pairs, and with `--bootstrap` rounds the
+/// confidence intervals of the mean and median over resampled pairs.
+pub fn print_summary(
+    input_strings: &[String],
+    dimensions: usize,
+    matrix: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+    bootstrap: Option<(u32, &mut StdRng)>,
+) {
+    let pairs = sorted_pairs(matrix, distance_metric, None);
+    let (Some(closest), Some(farthest)) = (pairs.first(), pairs.last()) else {
+        println!("Not enough documents for pairwise statistics");
+        return;
+    };
+
+    let scores = pairs.iter().map(|pair| pair.score).collect::<Vec<_>>();
+    let pair = |pair: &Pair| {
+        format!(
+            "{} / {} ({})",
+            format_header(pair.i, &input_strings[pair.i]),
+            format_header(pair.j, &input_strings[pair.j]),
+            table::format_score(pair.score)
+        )
+    };
+
+    let mut table = vec![
+        vec!["statistic".to_string(), distance_metric.to_string()],
+        vec!["pairs".to_string(), scores.len().to_string()],
+        vec!["dimensions".to_string(), dimensions.to_string()],
+        vec!["mean".to_string(), table::format_score(mean(&scores))],
+        vec!["median".to_string(), table::format_score(median(&scores))],
+        vec!["std".to_string(), table::format_score(std_dev(&scores))],
+        vec![
+            "min".to_string(),
+            table::format_score(scores.iter().copied().fold(f64::INFINITY, f64::min)),
+        ],
+        vec![
+            "max".to_string(),
+            table::format_score(scores.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+        ],
+        vec!["closest".to_string(), pair(closest)],
+        vec!["farthest".to_string(), pair(farthest)],
+    ];
+    if let Some((rounds, rng)) = bootstrap {
+        let mean_interval = bootstrap_interval(scores.len(), rounds, rng, |resample| {
+            mean(&resampled(&scores, resample))
+        });
+        let median_interval = bootstrap_interval(scores.len(), rounds, rng, |resample| {
+            median(&resampled(&scores, resample))
+        });
+        table.insert(
+            4,
+            vec![interval_header("mean"), format_interval(mean_interval)],
+        );
+        table.insert(
+            6,
+            vec![interval_header("median"), format_interval(median_interval)],
+        );
+    }
+
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn ranks_average_ties() {
+        assert_eq!(ranks(&[3.0, 1.0, 2.0]), [3.0, 1.0, 2.0]);
+        assert_eq!(ranks(&[1.0, 2.0, 2.0, 5.0]), [1.0, 2.5, 2.5, 4.0]);
+        assert_eq!(ranks(&[7.0, 7.0, 7.0]), [2.0, 2.0, 2.0]);
+        assert!(ranks(&[]).is_empty());
+    }
+
+    #[test]
+    fn spearman_only_depends_on_the_order() {
+        let x = [1.0, 2.0, 3.0, 4.0];
+        assert!((spearman(&x, &[10.0, 20.0, 300.0, 4000.0]) - 1.0).abs() < 1e-12);
+        assert!((spearman(&x, &[4.0, 3.0, 2.0, 1.0]) + 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn kendall_counts_concordant_pairs_with_ties() {
+        let x = [1.0, 2.0, 3.0, 4.0];
+        assert!((kendall(&x, &[10.0, 20.0, 300.0, 4000.0]) - 1.0).abs() < 1e-12);
+        assert!((kendall(&x, &[4.0, 3.0, 2.0, 1.0]) + 1.0).abs() < 1e-12);
+        // 4 concordant and 2 discordant pairs
+        assert!((kendall(&x, &[2.0, 1.0, 4.0, 3.0]) - 1.0 / 3.0).abs() < 1e-12);
+        // 7 concordant and 1 discordant pairs, 1 tied in x only and 1 in y only
+        let tau = kendall(&[1.0, 2.0, 2.0, 3.0, 4.0], &[1.0, 3.0, 2.0, 2.0, 5.0]);
+        assert!((tau - 2.0 / 3.0).abs() < 1e-12, "{tau}");
+    }
+
+    #[test]
+    fn identical_models_are_not_significantly_different() {
+        let gold = (0..30).map(f64::from).collect::<Vec<_>>();
+        let scores = gold
+            .iter()
+            .map(|score| score * 2.0 + 1.0)
+            .collect::<Vec<_>>();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let (difference, p_value) = paired_permutation_test(&gold, &scores, &scores, 200, &mut rng);
+        assert_eq!(difference, 0.0);
+        assert_eq!(p_value, 1.0);
+    }
+
+    #[test]
+    fn a_much_better_model_is_significant() {
+        let gold = (0..40).map(f64::from).collect::<Vec<_>>();
+        let good = gold.clone();
+        // Hashing the positions scrambles the order without any correlation to the gold
+        let random = (0..40u64)
+            .map(|i| (i.wrapping_mul(2654435761) % 97) as f64)
+            .collect::<Vec<_>>();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let (difference, p_value) = paired_permutation_test(&gold, &good, &random, 500, &mut rng);
+        assert!(difference > 0.5, "{difference}");
+        assert!(p_value < 0.01, "{p_value}");
+        // The smallest p-value a test of 500 rounds can report
+        assert!(p_value >= 1.0 / 501.0);
+    }
+
+    #[test]
+    fn bootstrap_intervals_contain_the_statistic_and_narrow_with_more_samples() {
+        let interval = |samples: u32| {
+            let values = (0..samples)
+                .map(|i| f64::from(i.wrapping_mul(2654435761) % 101))
+                .collect::<Vec<_>>();
+            let mut rng = StdRng::seed_from_u64(0);
+            let (low, high) = bootstrap_interval(values.len(), 500, &mut rng, |resample| {
+                mean(&resampled(&values, resample))
+            });
+            assert!(low < mean(&values) && mean(&values) < high, "{low} {high}");
+            high - low
+        };
+        assert!(interval(400) < interval(25) / 2.0);
+
+        // A correlation of constant scores is undefined on every resample
+        let mut rng = StdRng::seed_from_u64(0);
+        let (low, high) = bootstrap_interval(1, 10, &mut rng, |resample| {
+            pearson(&resampled(&[1.0], resample), &resampled(&[2.0], resample))
+        });
+        assert!(low.is_nan() && high.is_nan());
+    }
+
+    #[test]
+    fn summary_statistics() {
+        let values = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(mean(&values), 2.5);
+        assert_eq!(median(&values), 2.5);
+        assert_eq!(median(&[5.0, 1.0, 3.0]), 3.0);
+        assert!((std_dev(&values) - 1.25f64.sqrt()).abs() < 1e-12);
+    }
+}
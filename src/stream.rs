@@ -0,0 +1,489 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    time::Duration,
+};
+
+use chrono::Utc;
+use clap::{Args, ValueEnum};
+use redis::{
+    aio::MultiplexedConnection,
+    streams::{StreamReadOptions, StreamReadReply},
+    AsyncCommands,
+};
+use rskafka::{
+    client::{
+        partition::{Compression, OffsetAt, PartitionClient, UnknownTopicHandling},
+        ClientBuilder,
+    },
+    record::Record,
+    BackoffConfig,
+};
+use serde::Serialize;
+
+use crate::{allowlist, cache::EmbeddingCache, models, DistanceMetric, Provider, ProviderArgs};
+
+/// Most bytes of records fetched from Kafka at once.
+const KAFKA_MAX_FETCH_BYTES: i32 = 1 << 20;
+/// How long a Kafka fetch waits for new records before returning none.
+const KAFKA_MAX_WAIT_MS: i32 = 1_000;
+/// How long a Kafka request is retried before the run fails, e.g. while no broker answers.
+const KAFKA_RETRY_DEADLINE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Broker {
+    Redis,
+    Kafka,
+}
+
+impl Display for Broker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Broker::Redis => write!(f, "redis"),
+            Broker::Kafka => write!(f, "kafka"),
+        }
+    }
+}
+
+/// Experimental: consumes documents from a Redis stream or a Kafka topic and publishes, for each
+/// of them, its nearest neighbor among the documents seen so far.
+#[derive(Args, Debug)]
+pub struct StreamArgs {
+    /// Where documents are read from and events published to
+    #[arg(long, default_value_t = Broker::Redis)]
+    broker: Broker,
+    #[arg(long, default_value = "redis://127.0.0.1/")]
+    redis_url: String,
+    /// Bootstrap brokers of the Kafka cluster, comma-separated
+    #[arg(long, value_delimiter = ',', default_value = "127.0.0.1:9092")]
+    kafka_brokers: Vec<String>,
+    /// Partition of the Kafka topics to read from and publish to
+    #[arg(long, default_value_t = 0)]
+    kafka_partition: i32,
+    /// Stream or topic to read documents from
+    #[arg(long)]
+    input_stream: String,
+    /// Stream or topic to publish nearest-neighbor events to
+    #[arg(long)]
+    output_stream: String,
+    /// Field of the input entries holding the document text. Kafka records are JSON objects
+    #[arg(long, default_value = "text")]
+    field: String,
+    /// Entry id to start reading after, `$` for new entries only or `0` for the whole stream.
+    /// With Kafka, the offset to start reading from
+    #[arg(long, default_value = "$")]
+    start_id: String,
+    /// Maximum number of entries embedded in one request
+    #[arg(long, default_value_t = 100)]
+    batch_size: usize,
+    /// Number of most recent documents searched for neighbors; older ones are forgotten
+    #[arg(long, default_value_t = 100_000)]
+    max_seen: usize,
+    #[arg(short, long, default_value_t = Provider::Openai)]
+    provider: Provider,
+    #[command(flatten)]
+    provider_args: ProviderArgs,
+    #[arg(short, long, value_parser = models::parse_model)]
+    embedding_model: String,
+    #[arg(short, long, default_value_t = DistanceMetric::Cosine)]
+    distance_metric: DistanceMetric,
+    /// Score from which an entry is flagged as a duplicate of its nearest neighbor
+    #[arg(long)]
+    duplicate_threshold: Option<f64>,
+}
+
+/// An entry read from the input, without text if it lacks the `--field`.
+struct Entry {
+    id: String,
+    text: Option<String>,
+}
+
+/// The nearest neighbor published for an entry.
+#[derive(Serialize, Debug, PartialEq)]
+struct Event {
+    id: String,
+    neighbor: String,
+    score: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate: Option<bool>,
+}
+
+impl Event {
+    /// Fields of the Redis stream entry.
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("id", self.id.clone()),
+            ("neighbor", self.neighbor.clone()),
+            ("score", self.score.to_string()),
+        ];
+        if let Some(duplicate) = self.duplicate {
+            fields.push(("duplicate", duplicate.to_string()));
+        }
+        fields
+    }
+}
+
+/// Connection to the broker, along with the position of the next entries to read.
+enum Connection {
+    Redis {
+        connection: MultiplexedConnection,
+        last_id: String,
+    },
+    Kafka {
+        input: Box<PartitionClient>,
+        output: Box<PartitionClient>,
+        offset: i64,
+    },
+}
+
+impl Connection {
+    async fn open(args: &StreamArgs) -> Self {
+        match args.broker {
+            Broker::Redis => {
+                allowlist::check(&args.redis_url);
+                let client = redis::Client::open(args.redis_url.as_str()).unwrap();
+                let connection = client
+                    .get_multiplexed_async_connection()
+                    .await
+                    .unwrap_or_else(|error| {
+                        panic!("Failed to connect to {}: {error}", args.redis_url)
+                    });
+                Connection::Redis {
+                    connection,
+                    last_id: args.start_id.clone(),
+                }
+            }
+            Broker::Kafka => {
+                for broker in &args.kafka_brokers {
+                    allowlist::check(&format!("kafka://{broker}"));
+                }
+                let backoff = BackoffConfig {
+                    deadline: Some(KAFKA_RETRY_DEADLINE),
+                    ..Default::default()
+                };
+                let client = ClientBuilder::new(args.kafka_brokers.clone())
+                    .backoff_config(backoff)
+                    .build()
+                    .await
+                    .unwrap_or_else(|error| {
+                        panic!(
+                            "Failed to connect to {}: {error}",
+                            args.kafka_brokers.join(",")
+                        )
+                    });
+                let partition = |topic: &str| {
+                    let client = &client;
+                    let topic = topic.to_string();
+                    async move {
+                        client
+                            .partition_client(
+                                topic.clone(),
+                                args.kafka_partition,
+                                UnknownTopicHandling::Retry,
+                            )
+                            .await
+                            .unwrap_or_else(|error| {
+                                panic!("Failed to open the Kafka topic {topic}: {error}")
+                            })
+                    }
+                };
+                let input = Box::new(partition(&args.input_stream).await);
+                let output = Box::new(partition(&args.output_stream).await);
+
+                let offset = match kafka_start_offset(&args.start_id) {
+                    Some(offset) => offset,
+                    None => input
+                        .get_offset(OffsetAt::Latest)
+                        .await
+                        .unwrap_or_else(|error| {
+                            panic!(
+                                "Failed to read the offset of {}: {error}",
+                                args.input_stream
+                            )
+                        }),
+                };
+                Connection::Kafka {
+                    input,
+                    output,
+                    offset,
+                }
+            }
+        }
+    }
+
+    /// Waits for the next entries of the input, at most `--batch-size` of them from Redis.
+    async fn read(&mut self, args: &StreamArgs) -> Vec<Entry> {
+        match self {
+            Connection::Redis {
+                connection,
+                last_id,
+            } => {
+                let options = StreamReadOptions::default().count(args.batch_size).block(0);
+                let reply: StreamReadReply = connection
+                    .xread_options(&[&args.input_stream], &[&*last_id], &options)
+                    .await
+                    .unwrap();
+
+                let entries = reply
+                    .keys
+                    .into_iter()
+                    .flat_map(|key| key.ids)
+                    .collect::<Vec<_>>();
+                if let Some(last) = entries.last() {
+                    *last_id = last.id.clone();
+                }
+
+                entries
+                    .into_iter()
+                    .map(|entry| Entry {
+                        text: entry.get::<String>(&args.field),
+                        id: entry.id,
+                    })
+                    .collect()
+            }
+            Connection::Kafka { input, offset, .. } => {
+                let (records, _) = input
+                    .fetch_records(*offset, 1..KAFKA_MAX_FETCH_BYTES, KAFKA_MAX_WAIT_MS)
+                    .await
+                    .unwrap_or_else(|error| {
+                        panic!("Failed to read from {}: {error}", args.input_stream)
+                    });
+                if let Some(last) = records.last() {
+                    *offset = last.offset + 1;
+                }
+
+                records
+                    .into_iter()
+                    .map(|record| Entry {
+                        id: record.offset.to_string(),
+                        text: record
+                            .record
+                            .value
+                            .and_then(|value| kafka_text(&value, &args.field)),
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    async fn publish(&mut self, args: &StreamArgs, events: &[Event]) {
+        match self {
+            Connection::Redis { connection, .. } => {
+                for event in events {
+                    let _: String = connection
+                        .xadd(&args.output_stream, "*", &event.fields())
+                        .await
+                        .unwrap();
+                }
+            }
+            Connection::Kafka { output, .. } => {
+                let records = events
+                    .iter()
+                    .map(|event| Record {
+                        key: Some(event.id.clone().into_bytes()),
+                        value: Some(serde_json::to_vec(event).unwrap()),
+                        headers: BTreeMap::new(),
+                        timestamp: Utc::now(),
+                    })
+                    .collect();
+                output
+                    .produce(records, Compression::NoCompression)
+                    .await
+                    .unwrap_or_else(|error| {
+                        panic!("Failed to publish to {}: {error}", args.output_stream)
+                    });
+            }
+        }
+    }
+}
+
+/// Offset a Kafka consumer starts from for `--start-id`, `None` for the end of the topic.
+fn kafka_start_offset(start_id: &str) -> Option<i64> {
+    if start_id == "$" {
+        return None;
+    }
+
+    let offset = start_id
+        .parse()
+        .unwrap_or_else(|_| panic!("Kafka offsets are numbers or `$`, not {start_id}"));
+    Some(offset)
+}
+
+/// The text in `field` of a Kafka record holding a JSON object.
+fn kafka_text(value: &[u8], field: &str) -> Option<String> {
+    let object = serde_json::from_slice::<serde_json::Value>(value).ok()?;
+    object.get(field)?.as_str().map(str::to_string)
+}
+
+/// The `capacity` most recently seen documents, the only ones searched for neighbors so that
+/// memory and search time stay bounded however long the stream runs.
+struct Seen {
+    capacity: usize,
+    entries: VecDeque<(String, Vec<f64>)>,
+}
+
+impl Seen {
+    fn new(capacity: usize) -> Self {
+        Seen {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, id: String, vector: Vec<f64>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((id, vector));
+    }
+
+    fn nearest(&self, vector: &[f64], distance_metric: &DistanceMetric) -> Option<(&str, f64)> {
+        self.entries
+            .iter()
+            .map(|(id, other)| (id.as_str(), distance_metric.distance(vector, other)))
+            .max_by(|(_, a), (_, b)| distance_metric.cmp_closeness(*a, *b))
+    }
+}
+
+pub async fn run(args: StreamArgs) {
+    let mut connection = Connection::open(&args).await;
+
+    // Repeated documents are only paid for once, across runs too
+    let mut cache =
+        EmbeddingCache::open(&args.provider, &args.provider_args, &args.embedding_model);
+    let batch_size = args.batch_size.min(args.provider.max_batch_size());
+    let mut seen = Seen::new(args.max_seen);
+
+    loop {
+        let (ids, texts): (Vec<_>, Vec<_>) = connection
+            .read(&args)
+            .await
+            .into_iter()
+            .filter_map(|entry| match entry.text {
+                Some(text) => Some((entry.id, text)),
+                None => {
+                    eprintln!("Skipping entry {} without a {} field", entry.id, args.field);
+                    None
+                }
+            })
+            .unzip();
+        if texts.is_empty() {
+            continue;
+        }
+
+        let (embeddings, _) = cache
+            .embed(
+                &args.provider,
+                &args.provider_args,
+                &args.embedding_model,
+                &texts,
+                batch_size,
+            )
+            .await;
+
+        let mut events = vec![];
+        for (id, embedding) in ids.into_iter().zip(embeddings) {
+            if let Some((neighbor, score)) = seen.nearest(&embedding.vec, &args.distance_metric) {
+                let duplicate = args
+                    .duplicate_threshold
+                    .map(|threshold| args.distance_metric.cmp_closeness(score, threshold).is_ge());
+                events.push(Event {
+                    id: id.clone(),
+                    neighbor: neighbor.to_string(),
+                    score,
+                    duplicate,
+                });
+            }
+
+            seen.push(id, embedding.vec);
+        }
+        connection.publish(&args, &events).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_is_the_closest_seen_document() {
+        let mut seen = Seen::new(10);
+        assert_eq!(seen.nearest(&[1.0, 0.0], &DistanceMetric::Cosine), None);
+
+        seen.push("1-0".to_string(), vec![0.0, 1.0]);
+        seen.push("2-0".to_string(), vec![1.0, 0.1]);
+        let (neighbor, score) = seen.nearest(&[1.0, 0.0], &DistanceMetric::Cosine).unwrap();
+        assert_eq!(neighbor, "2-0");
+        assert!(score > 0.99);
+
+        // Lower is closer for distances
+        let (neighbor, _) = seen.nearest(&[0.0, 2.0], &DistanceMetric::L2).unwrap();
+        assert_eq!(neighbor, "1-0");
+    }
+
+    #[test]
+    fn only_the_most_recent_documents_are_kept() {
+        let mut seen = Seen::new(2);
+        for (id, vector) in [("a", [1.0, 0.0]), ("b", [0.0, 1.0]), ("c", [-1.0, 0.0])] {
+            seen.push(id.to_string(), vector.to_vec());
+        }
+
+        assert_eq!(seen.entries.len(), 2);
+        // `a` is forgotten, although it is an exact match
+        let (neighbor, _) = seen.nearest(&[1.0, 0.0], &DistanceMetric::Cosine).unwrap();
+        assert_eq!(neighbor, "b");
+
+        let mut none = Seen::new(0);
+        none.push("a".to_string(), vec![1.0]);
+        assert!(none.entries.is_empty());
+    }
+
+    #[test]
+    fn events_only_carry_a_duplicate_flag_with_a_threshold() {
+        let mut event = Event {
+            id: "4".to_string(),
+            neighbor: "2".to_string(),
+            score: 0.5,
+            duplicate: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"id":"4","neighbor":"2","score":0.5}"#
+        );
+        assert_eq!(event.fields().len(), 3);
+
+        event.duplicate = Some(true);
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"id":"4","neighbor":"2","score":0.5,"duplicate":true}"#
+        );
+        assert_eq!(event.fields()[3], ("duplicate", "true".to_string()));
+    }
+
+    #[test]
+    fn kafka_records_hold_the_text_in_a_json_field() {
+        assert_eq!(
+            kafka_text(br#"{"text": "hello", "lang": "en"}"#, "text"),
+            Some("hello".to_string())
+        );
+        assert_eq!(kafka_text(br#"{"body": "hello"}"#, "text"), None);
+        assert_eq!(kafka_text(br#"{"text": 3}"#, "text"), None);
+        assert_eq!(kafka_text(b"plain text", "text"), None);
+    }
+
+    #[test]
+    fn kafka_starts_from_the_end_or_an_offset() {
+        assert_eq!(kafka_start_offset("$"), None);
+        assert_eq!(kafka_start_offset("0"), Some(0));
+        assert_eq!(kafka_start_offset("42"), Some(42));
+    }
+
+    #[test]
+    #[should_panic(expected = "Kafka offsets are numbers")]
+    fn kafka_rejects_redis_entry_ids() {
+        kafka_start_offset("1526919030474-0");
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    fmt::Display,
+    io::{stdout, IsTerminal},
+    sync::OnceLock,
+};
+
+use clap::ValueEnum;
+use console::Alignment;
+use itertools::Itertools;
+use pretty_table::table::generate_table_string_vec;
+
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Display for ColorChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorChoice::Auto => write!(f, "auto"),
+            ColorChoice::Always => write!(f, "always"),
+            ColorChoice::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// `auto` colors only when stdout is a terminal and `NO_COLOR` is not set.
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Auto => stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum OutputFormat {
+    /// Plain text table for the terminal
+    Table,
+    /// GitHub-flavored Markdown table
+    Markdown,
+    /// HTML table with every score shaded from red (far) to green (close)
+    Html,
+    /// Parquet file of every pair, to redirect to a file
+    Parquet,
+    /// Only the score of every pair, one per line, for shell scripts
+    Scalar,
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Table => write!(f, "table"),
+            OutputFormat::Markdown => write!(f, "markdown"),
+            OutputFormat::Html => write!(f, "html"),
+            OutputFormat::Parquet => write!(f, "parquet"),
+            OutputFormat::Scalar => write!(f, "scalar"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum Notation {
+    /// Decimal numbers
+    Plain,
+    /// Mantissa and exponent, e.g. `9.5e-1`
+    Scientific,
+    /// Scores times 100 followed by `%`, for cosine similarities
+    Percent,
+}
+
+impl Display for Notation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Notation::Plain => write!(f, "plain"),
+            Notation::Scientific => write!(f, "scientific"),
+            Notation::Percent => write!(f, "percent"),
+        }
+    }
+}
+
+/// Decimals shown for percentages without a `--precision`.
+const DEFAULT_PERCENT_PRECISION: usize = 2;
+
+/// How printed scores are written, set once from `--precision` and `--number-format`. Scores print
+/// in full otherwise.
+static NUMBER_FORMAT: OnceLock<(Option<usize>, Notation)> = OnceLock::new();
+
+pub fn set_number_format(precision: Option<usize>, notation: Notation) {
+    NUMBER_FORMAT
+        .set((precision, notation))
+        .expect("Number format already set");
+}
+
+/// A score as printed in tables and scalar output.
+pub fn format_score(score: f64) -> String {
+    match NUMBER_FORMAT.get() {
+        Some((precision, notation)) => format_number(score, *precision, notation),
+        None => score.to_string(),
+    }
+}
+
+fn format_number(score: f64, precision: Option<usize>, notation: &Notation) -> String {
+    match (notation, precision) {
+        (Notation::Plain, Some(precision)) => format!("{score:.precision$}"),
+        (Notation::Plain, None) => score.to_string(),
+        (Notation::Scientific, Some(precision)) => format!("{score:.precision$e}"),
+        (Notation::Scientific, None) => format!("{score:e}"),
+        (Notation::Percent, precision) => {
+            let precision = precision.unwrap_or(DEFAULT_PERCENT_PRECISION);
+            format!("{:.precision$}%", score * 100.0)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Color {
+    Green,
+    Red,
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        match self {
+            Color::Green => "\x1b[32m",
+            Color::Red => "\x1b[31m",
+        }
+    }
+}
+
+/// Width of the tables printed plain when the terminal width isn't known from `COLUMNS`.
+const MAX_TABLE_WIDTH: usize = 200;
+
+/// Prints `rows`, whose first row is the header, as a terminal table.
+pub fn print(rows: Vec<Vec<String>>) {
+    print_colored(rows, |_, _| None);
+}
+
+/// Whether the locale of the terminal, from `LC_ALL`, `LC_CTYPE` or `LANG` as the C library reads
+/// them, displays UTF-8. Terminals without a locale, like Windows consoles, are assumed to.
+fn utf8_locale() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|locale| !locale.is_empty())
+        .is_none_or(|locale| {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+/// Whether `rows` are printed plain rather than with pretty_table, which pads cells by their
+/// length in bytes, so misaligns any cell that isn't ASCII, and whose borders turn tables wider
+/// than the terminal into an unreadable mess once wrapped. Dumb terminals always get plain
+/// tables.
+fn plain(rows: &[Vec<String>], dumb: bool, max_width: usize) -> bool {
+    let width = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0) + 3)
+        .sum::<usize>()
+        + 1;
+    dumb || width > max_width || rows.iter().flatten().any(|cell| !cell.is_ascii())
+}
+
+/// `rows` as lines of left-aligned columns two spaces apart, the header underlined with dashes,
+/// measured in terminal columns so that wide characters such as CJK count twice. Characters a
+/// non-UTF-8 terminal can't display become `?`.
+fn plain_lines(
+    rows: &[Vec<String>],
+    utf8: bool,
+    color: impl Fn(usize, usize) -> Option<Color>,
+) -> Vec<String> {
+    let rows = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| {
+                    let cell = cell.replace('\n', " ");
+                    match utf8 {
+                        true => cell,
+                        false => cell
+                            .chars()
+                            .map(|c| if c.is_ascii() { c } else { '?' })
+                            .collect(),
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let widths = (0..rows[0].len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| console::measure_text_width(&row[column]))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+
+    let line = |row: usize, cells: &[String]| {
+        let cells = cells.iter().enumerate().map(|(column, cell)| {
+            let cell = match row.checked_sub(1).and_then(|row| color(row, column)) {
+                Some(color) => format!("{}{cell}{RESET}", color.code()),
+                None => cell.clone(),
+            };
+            console::pad_str(&cell, widths[column], Alignment::Left, None).into_owned()
+        });
+        cells.collect::<Vec<_>>().join("  ").trim_end().to_string()
+    };
+    let mut lines = vec![line(0, &rows[0])];
+    lines.push(widths.iter().map(|width| "-".repeat(*width)).join("  "));
+    lines.extend(
+        rows.iter()
+            .enumerate()
+            .skip(1)
+            .map(|(row, cells)| line(row, cells)),
+    );
+    lines
+}
+
+/// Prints `rows` with pretty_table, coloring the cell at `(row, column)` of the data rows (the
+/// header row excluded) with whatever `color` returns for it.
+///
+/// Tables pretty_table would garble are printed as plain aligned columns instead.
+pub fn print_colored(rows: Vec<Vec<String>>, color: impl Fn(usize, usize) -> Option<Color>) {
+    let dumb = std::env::var("TERM").is_ok_and(|term| term == "dumb");
+    let max_width = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(MAX_TABLE_WIDTH);
+    if plain(&rows, dumb, max_width) {
+        for line in plain_lines(&rows, utf8_locale(), color) {
+            println!("{line}");
+        }
+        return;
+    }
+
+    // pretty_table pads every column to its longest cell plus two spaces, measured in bytes
+    let widths = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0) + 2)
+        .collect::<Vec<_>>();
+
+    let mut lines = generate_table_string_vec(rows.clone(), None);
+    for (row, cells) in rows.iter().enumerate().skip(1) {
+        let line = &mut lines[3 + (row - 1) * 2];
+
+        // Insert from the right so that the earlier offsets stay valid
+        let mut offset = 1 + widths.iter().map(|width| width + 1).sum::<usize>();
+        for (column, cell) in cells.iter().enumerate().rev() {
+            offset -= widths[column] + 1;
+            let Some(color) = color(row - 1, column) else {
+                continue;
+            };
+
+            let start = offset + (widths[column] - cell.len()) / 2;
+            line.insert_str(start + cell.len(), RESET);
+            line.insert_str(start, color.code());
+        }
+    }
+
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+/// Scores at which a cell counts as very close (green) or very far (red), in the units of the
+/// distance metric.
+pub struct Thresholds {
+    pub close: f64,
+    pub far: f64,
+    pub higher_is_closer: bool,
+}
+
+impl Thresholds {
+    /// Default thresholds: the closest and the farthest quarter of `scores`.
+    pub fn quartiles(scores: &[f64], higher_is_closer: bool) -> Self {
+        let mut sorted = scores.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let quantile = |q: f64| sorted[((sorted.len() - 1) as f64 * q).round() as usize];
+
+        let (low, high) = if sorted.is_empty() {
+            (f64::NEG_INFINITY, f64::INFINITY)
+        } else {
+            (quantile(0.25), quantile(0.75))
+        };
+
+        if higher_is_closer {
+            Thresholds {
+                close: high,
+                far: low,
+                higher_is_closer,
+            }
+        } else {
+            Thresholds {
+                close: low,
+                far: high,
+                higher_is_closer,
+            }
+        }
+    }
+
+    pub fn color(&self, score: f64) -> Option<Color> {
+        let (close, far) = if self.higher_is_closer {
+            (score >= self.close, score <= self.far)
+        } else {
+            (score <= self.close, score >= self.far)
+        };
+
+        match (close, far) {
+            (true, _) => Some(Color::Green),
+            (_, true) => Some(Color::Red),
+            _ => None,
+        }
+    }
+}
+
+/// Prints `rows`, whose first row is the header, as a GitHub-flavored Markdown table.
+pub fn print_markdown(rows: &[Vec<String>]) {
+    let cell = |cell: &String| cell.replace('|', "\\|").replace('\n', " ");
+    let line = |row: &Vec<String>| format!("| {} |", row.iter().map(cell).join(" | "));
+
+    println!("{}", line(&rows[0]));
+    println!("|{}", " --- |".repeat(rows[0].len()));
+    for row in &rows[1..] {
+        println!("{}", line(row));
+    }
+}
+
+/// Prints `rows`, whose first row is the header, as an HTML table. The cell at `(row, column)`
+/// of the data rows gets the background of whatever `closeness` returns for it, from 0 for the
+/// farthest score to 1 for the closest.
+pub fn print_html(rows: &[Vec<String>], closeness: impl Fn(usize, usize) -> Option<f64>) {
+    println!("<table>");
+    println!("  <thead>");
+    println!(
+        "    <tr>{}</tr>",
+        rows[0]
+            .iter()
+            .map(|cell| format!("<th>{}</th>", escape_html(cell)))
+            .join("")
+    );
+    println!("  </thead>");
+    println!("  <tbody>");
+    for (row, cells) in rows[1..].iter().enumerate() {
+        let cells = cells.iter().enumerate().map(|(column, cell)| {
+            match closeness(row, column) {
+                // Red at 0 to green at 1, light enough for black text on either
+                Some(closeness) => format!(
+                    "<td style=\"background-color: hsl({:.0}, 70%, 80%)\">{}</td>",
+                    closeness.clamp(0.0, 1.0) * 120.0,
+                    escape_html(cell)
+                ),
+                None => format!("<td>{}</td>", escape_html(cell)),
+            }
+        });
+        println!("    <tr>{}</tr>", cells.collect::<String>());
+    }
+    println!("  </tbody>");
+    println!("</table>");
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Where `score` falls between the farthest (0) and the closest (1) of `scores`.
+pub fn closeness(scores: &[f64], higher_is_closer: bool) -> impl Fn(f64) -> f64 {
+    let (min, max) = scores
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), score| {
+            (min.min(*score), max.max(*score))
+        });
+
+    move |score| {
+        if max <= min {
+            return 1.0;
+        }
+        let position = (score - min) / (max - min);
+        if higher_is_closer {
+            position
+        } else {
+            1.0 - position
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_follow_their_precision_and_notation() {
+        let score = 0.876543;
+        assert_eq!(format_number(score, None, &Notation::Plain), "0.876543");
+        assert_eq!(format_number(score, Some(3), &Notation::Plain), "0.877");
+        assert_eq!(
+            format_number(score, Some(2), &Notation::Scientific),
+            "8.77e-1"
+        );
+        assert_eq!(format_number(1250.0, None, &Notation::Scientific), "1.25e3");
+        assert_eq!(format_number(score, None, &Notation::Percent), "87.65%");
+        assert_eq!(format_number(score, Some(0), &Notation::Percent), "88%");
+    }
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn ascii_tables_fitting_the_terminal_are_pretty() {
+        let table = rows(&[&["doc_i", "score"], &["0: cats", "0.5"]]);
+        assert!(!plain(&table, false, 80));
+        assert!(plain(&table, true, 80));
+        // |  doc_i  | score |: 1 + 10 + 8
+        assert!(!plain(&table, false, 19));
+        assert!(plain(&table, false, 18));
+        assert!(plain(&rows(&[&["doc_i"], &["0: café"]]), false, 80));
+    }
+
+    #[test]
+    fn plain_tables_align_characters() {
+        let table = rows(&[&["doc_i", "score"], &["0: café", "0.5"], &["1: a", "0.25"]]);
+        assert_eq!(
+            plain_lines(&table, true, |_, _| None),
+            [
+                "doc_i    score",
+                "-------  -----",
+                "0: café  0.5",
+                "1: a     0.25"
+            ]
+        );
+        assert_eq!(plain_lines(&table, false, |_, _| None)[2], "0: caf?  0.5");
+    }
+
+    #[test]
+    fn plain_tables_align_wide_characters() {
+        let table = rows(&[&["doc_i", "score"], &["0: 東京", "0.5"], &["1: a", "0.25"]]);
+        assert_eq!(
+            plain_lines(&table, true, |_, _| None),
+            [
+                "doc_i    score",
+                "-------  -----",
+                "0: 東京  0.5",
+                "1: a     0.25"
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_tables_keep_colors() {
+        let table = rows(&[&["", "0: a"], &["0: a", "1"]]);
+        let lines = plain_lines(&table, true, |row, column| {
+            (row == 0 && column == 1).then_some(Color::Green)
+        });
+        assert_eq!(lines[2], format!("0: a  {}1{RESET}", Color::Green.code()));
+    }
+}
@@ -0,0 +1,91 @@
+/// Placeholder of a template standing for the text.
+const PLACEHOLDER: &str = "{}";
+
+/// What a text is embedded as, e.g. `query: {}` for the instruction prefixes that models like e5
+/// and bge expect. Texts keep their own name in the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(String);
+
+impl Template {
+    pub fn apply(&self, text: &str) -> String {
+        self.0.replacen(PLACEHOLDER, text, 1)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Parses a template having the `{}` placeholder once.
+pub fn parse(template: &str) -> Result<Template, String> {
+    match template.matches(PLACEHOLDER).count() {
+        1 => Ok(Template(template.to_string())),
+        0 => Err(format!(
+            "{template:?} has no {PLACEHOLDER} standing for the text, e.g. \"query: {PLACEHOLDER}\""
+        )),
+        _ => Err(format!(
+            "{template:?} has several {PLACEHOLDER}, only one can stand for the text"
+        )),
+    }
+}
+
+/// Parses the template of one input, `INPUT=TEMPLATE`.
+pub fn parse_input(input_template: &str) -> Result<(String, Template), String> {
+    let (input, template) = input_template.split_once('=').ok_or_else(|| {
+        format!("{input_template:?} isn't INPUT=TEMPLATE, e.g. \"queries.json=query: {{}}\"")
+    })?;
+    Ok((input.to_string(), parse(template)?))
+}
+
+/// The texts as embedded, by the template of their input if it has one or `default`. `inputs`
+/// is the input of every text, or empty when they have none.
+pub fn apply(
+    texts: &[String],
+    inputs: &[&str],
+    default: Option<&Template>,
+    input_templates: &[(String, Template)],
+) -> Vec<String> {
+    texts
+        .iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let input = inputs.get(i);
+            let template = input_templates
+                .iter()
+                .find(|(template_input, _)| Some(&template_input.as_str()) == input)
+                .map(|(_, template)| template)
+                .or(default);
+            template.map_or_else(|| text.clone(), |template| template.apply(text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_have_one_placeholder() {
+        assert_eq!(parse("query: {}").unwrap().apply("cats"), "query: cats");
+        assert!(parse("query:").unwrap_err().contains("no {}"));
+        assert!(parse("{} and {}").unwrap_err().contains("several"));
+        let (input, template) = parse_input("docs.json=passage: {}").unwrap();
+        assert_eq!(input, "docs.json");
+        assert_eq!(template.apply("a=b"), "passage: a=b");
+        assert!(parse_input("passage: {}").is_err());
+    }
+
+    #[test]
+    fn input_templates_override_the_default() {
+        let texts = ["a".to_string(), "b".to_string(), "c".to_string()];
+        let default = parse("passage: {}").unwrap();
+        let input_templates = [("queries.json".to_string(), parse("query: {}").unwrap())];
+        let inputs = ["queries.json", "docs.json", "docs.json"];
+        assert_eq!(
+            apply(&texts, &inputs, Some(&default), &input_templates),
+            ["query: a", "passage: b", "passage: c"]
+        );
+        assert_eq!(apply(&texts, &inputs, None, &input_templates)[1], "b");
+        assert_eq!(apply(&texts, &[], Some(&default), &[])[2], "passage: c");
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{estimate::count_tokens, ledger::price_per_million_tokens, table, Provider, EMPTY};
+
+/// What one tenant's documents of a run cost.
+#[derive(Debug, PartialEq)]
+pub struct TenantCost {
+    /// Absent for the documents without a tenant
+    pub tenant: Option<String>,
+    pub documents: usize,
+    /// Texts of its documents that were sent to the provider
+    pub embedded: usize,
+    pub tokens: u64,
+    /// Estimated cost in USD, absent for models without a known price
+    pub cost: Option<f64>,
+}
+
+/// Attributes the `uncached` texts of a run to the tenants of the documents they belong to.
+///
+/// `documents` holds the texts embedded for every document (the document itself, or its chunks)
+/// and `tenants` the tenant of every document. A text shared by several documents is only
+/// embedded once, so it is charged to the first document that has it.
+pub fn attribute(
+    provider: &Provider,
+    model: &str,
+    tenants: &[Option<String>],
+    documents: &[&[String]],
+    uncached: &[String],
+) -> Vec<TenantCost> {
+    let mut uncached = uncached.iter().collect::<HashSet<_>>();
+    let mut billed = BTreeMap::<Option<&String>, (usize, Vec<String>)>::new();
+    for (tenant, texts) in tenants.iter().zip(documents) {
+        let (documents, embedded) = billed.entry(tenant.as_ref()).or_default();
+        *documents += 1;
+        for text in *texts {
+            if uncached.remove(text) {
+                embedded.push(text.clone());
+            }
+        }
+    }
+
+    let price = price_per_million_tokens(provider, model);
+    billed
+        .into_iter()
+        .map(|(tenant, (documents, embedded))| {
+            let tokens = count_tokens(provider, &embedded);
+            TenantCost {
+                tenant: tenant.cloned(),
+                documents,
+                embedded: embedded.len(),
+                tokens,
+                cost: price.map(|price| price * tokens as f64 / 1_000_000.0),
+            }
+        })
+        .collect()
+}
+
+/// Prints the documents, embedded texts, tokens and estimated cost of every tenant and their
+/// total, to stderr when stdout holds the results.
+pub fn print_report(costs: &[TenantCost], to_stderr: bool) {
+    let format_cost =
+        |cost: Option<f64>| cost.map_or("unknown".to_string(), |cost| format!("${cost:.6}"));
+    let mut table = vec![vec![
+        "tenant".to_string(),
+        "documents".to_string(),
+        "embedded".to_string(),
+        "tokens".to_string(),
+        "estimated cost".to_string(),
+    ]];
+    for cost in costs {
+        table.push(vec![
+            cost.tenant.clone().unwrap_or(EMPTY.to_string()),
+            cost.documents.to_string(),
+            cost.embedded.to_string(),
+            cost.tokens.to_string(),
+            format_cost(cost.cost),
+        ]);
+    }
+    let total_cost = costs
+        .iter()
+        .try_fold(0.0, |total, cost| cost.cost.map(|cost| total + cost));
+    table.push(vec![
+        "total".to_string(),
+        costs
+            .iter()
+            .map(|cost| cost.documents)
+            .sum::<usize>()
+            .to_string(),
+        costs
+            .iter()
+            .map(|cost| cost.embedded)
+            .sum::<usize>()
+            .to_string(),
+        costs
+            .iter()
+            .map(|cost| cost.tokens)
+            .sum::<u64>()
+            .to_string(),
+        format_cost(total_cost),
+    ]);
+
+    if to_stderr {
+        for row in &table {
+            eprintln!("{}", row.join("\t"));
+        }
+    } else {
+        table::print(table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn embedded_texts_are_charged_to_the_first_tenant_having_them() {
+        let tenants = [
+            Some("acme".to_string()),
+            Some("globex".to_string()),
+            None,
+            Some("acme".to_string()),
+        ];
+        let documents = [
+            texts(&["shared text"]),
+            texts(&["shared text", "globex chunk"]),
+            texts(&["cached"]),
+            texts(&["acme text"]),
+        ];
+        let documents = documents.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let uncached = texts(&["shared text", "globex chunk", "acme text"]);
+
+        let costs = attribute(
+            &Provider::Openai,
+            "text-embedding-3-small",
+            &tenants,
+            &documents,
+            &uncached,
+        );
+        let attributed = costs
+            .iter()
+            .map(|cost| (cost.tenant.as_deref(), cost.documents, cost.embedded))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributed,
+            [(None, 1, 0), (Some("acme"), 2, 2), (Some("globex"), 1, 1)]
+        );
+        assert_eq!(costs[0].tokens, 0);
+        assert_eq!(costs[0].cost, Some(0.0));
+        let tokens = count_tokens(&Provider::Openai, &texts(&["globex chunk"]));
+        assert_eq!(costs[2].tokens, tokens);
+        assert_eq!(costs[2].cost, Some(tokens as f64 * 0.02 / 1_000_000.0));
+    }
+
+    #[test]
+    fn models_without_a_price_have_no_cost() {
+        let documents = [texts(&["text"])];
+        let documents = documents.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let costs = attribute(
+            &Provider::Openai,
+            "custom-model",
+            &[Some("acme".to_string())],
+            &documents,
+            &texts(&["text"]),
+        );
+        assert_eq!(costs[0].cost, None);
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{anchor, format_header, table, DistanceMetric};
+
+/// Shared terms printed without a `--limit`.
+pub const DEFAULT_SHARED_TERMS: usize = 20;
+
+/// Words too common to explain why two texts are similar.
+const STOPWORDS: &[&str] = &[
+    "a", "about", "after", "all", "an", "and", "are", "as", "at", "be", "been", "but", "by", "can",
+    "do", "for", "from", "had", "has", "have", "he", "her", "his", "how", "i", "if", "in", "into",
+    "is", "it", "its", "me", "my", "no", "not", "of", "on", "or", "our", "she", "so", "than",
+    "that", "the", "their", "them", "then", "there", "they", "this", "to", "up", "was", "we",
+    "were", "what", "when", "which", "who", "will", "with", "you", "your",
+];
+
+/// Parses `--shared-terms` and `--explain`: two documents, each an id, text or position, separated by a comma.
+pub fn parse_pair(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once(',') {
+        Some((first, second)) if !first.is_empty() && !second.is_empty() => {
+            Ok((first.to_string(), second.to_string()))
+        }
+        _ => Err("expected two documents separated by a comma, e.g. `0,3`".to_string()),
+    }
+}
+
+/// Positions of the two documents of `flag` among the input documents.
+pub fn resolve(
+    flag: &str,
+    (first, second): &(String, String),
+    input_ids: &[String],
+    input_strings: &[String],
+) -> (usize, usize) {
+    let find = |reference: &String| {
+        anchor::find(reference, input_ids, input_strings).unwrap_or_else(|| {
+            panic!(
+                "{flag} {reference} is neither the id, text nor position of an input \
+                 document"
+            )
+        })
+    };
+    (find(first), find(second))
+}
+
+/// Whether a lowercase word says something about a text. Single letters are mostly the remains
+/// of contractions and possessives.
+fn salient(word: &str) -> bool {
+    word.chars().count() > 1 && !STOPWORDS.contains(&word)
+}
+
+/// The lowercase words of `text`, in order.
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The words of `text` that aren't stopwords or single letters, in order.
+pub fn salient_words(text: &str) -> Vec<String> {
+    words(text)
+        .into_iter()
+        .filter(|word| salient(word))
+        .collect()
+}
+
+/// The words and bigrams of `text` that aren't stopwords or single letters, with their counts.
+fn terms(text: &str) -> HashMap<String, usize> {
+    let words = words(text);
+
+    let mut terms = HashMap::new();
+    for word in words.iter().filter(|word| salient(word)) {
+        *terms.entry(word.to_string()).or_default() += 1;
+    }
+    for pair in words.windows(2) {
+        if pair.iter().all(|word| salient(word)) {
+            *terms.entry(pair.join(" ")).or_default() += 1;
+        }
+    }
+    terms
+}
+
+/// A term of both texts, its count in each and its weight.
+#[derive(Debug, PartialEq)]
+pub struct SharedTerm {
+    pub term: String,
+    pub first: usize,
+    pub second: usize,
+    pub weight: f64,
+}
+
+/// The terms `first` and `second` have in common, weighted by their lower count and by their
+/// inverse document frequency across the `corpus`, so rare shared terms rank first; and the
+/// Jaccard similarity of all the terms of both texts.
+pub fn shared_terms(first: &str, second: &str, corpus: &[String]) -> (Vec<SharedTerm>, f64) {
+    let (first_terms, second_terms) = (terms(first), terms(second));
+    let corpus_terms = corpus
+        .iter()
+        .map(|text| terms(text).into_keys().collect::<HashSet<_>>())
+        .collect::<Vec<_>>();
+    let idf = |term: &String| {
+        let frequency = corpus_terms
+            .iter()
+            .filter(|terms| terms.contains(term))
+            .count();
+        ((1 + corpus.len()) as f64 / (1 + frequency) as f64).ln() + 1.0
+    };
+
+    let mut shared = first_terms
+        .iter()
+        .filter_map(|(term, first)| {
+            second_terms.get(term).map(|second| SharedTerm {
+                term: term.clone(),
+                first: *first,
+                second: *second,
+                weight: (*first).min(*second) as f64 * idf(term),
+            })
+        })
+        .collect::<Vec<_>>();
+    shared.sort_by(|a, b| b.weight.total_cmp(&a.weight).then(a.term.cmp(&b.term)));
+
+    let union = first_terms
+        .keys()
+        .chain(second_terms.keys())
+        .collect::<HashSet<_>>()
+        .len();
+    let jaccard = if union == 0 {
+        0.0
+    } else {
+        shared.len() as f64 / union as f64
+    };
+    (shared, jaccard)
+}
+
+/// Prints the embedding score of documents `i` and `j` next to the `limit` most salient terms
+/// they share, as a lexical sanity check of the score.
+pub fn print_shared_terms(
+    (i, j): (usize, usize),
+    input_strings: &[String],
+    score: f64,
+    distance_metric: &DistanceMetric,
+    limit: usize,
+) {
+    let (first, second) = (
+        format_header(i, &input_strings[i]),
+        format_header(j, &input_strings[j]),
+    );
+    println!("{distance_metric} between {first} and {second}: {score}");
+
+    let (shared, jaccard) = shared_terms(&input_strings[i], &input_strings[j], input_strings);
+    if shared.is_empty() {
+        println!("No shared terms");
+        return;
+    }
+    let mut table = vec![vec![
+        "term".to_string(),
+        format!("in {i}"),
+        format!("in {j}"),
+        "weight".to_string(),
+    ]];
+    table.extend(shared.iter().take(limit).map(|term| {
+        vec![
+            term.term.clone(),
+            term.first.to_string(),
+            term.second.to_string(),
+            format!("{:.3}", term.weight),
+        ]
+    }));
+    table::print(table);
+    println!("{} shared terms, term jaccard {jaccard}", shared.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terms_are_words_and_bigrams_without_stopwords() {
+        let terms = terms("The central bank raised interest rates, and the bank's rates rose.");
+        assert_eq!(terms["bank"], 2);
+        assert_eq!(terms["rates"], 2);
+        assert_eq!(terms["interest rates"], 1);
+        assert_eq!(terms["central bank"], 1);
+        assert!(!terms.contains_key("the"));
+        assert!(!terms.contains_key("the central"));
+        assert!(!terms.contains_key("s"));
+    }
+
+    #[test]
+    fn rare_shared_terms_rank_first() {
+        let corpus = [
+            "the bank raised interest rates",
+            "the bank cut interest rates",
+            "the bank opened a branch",
+        ]
+        .map(String::from);
+        let (shared, jaccard) = shared_terms(&corpus[0], &corpus[1], &corpus);
+        let terms = shared
+            .iter()
+            .map(|term| term.term.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(terms, ["interest", "interest rates", "rates", "bank"]);
+        // 4 shared terms out of 7 in each text
+        assert_eq!(jaccard, 4.0 / 10.0);
+        assert_eq!(shared_terms("a cat", "the dog", &corpus).0, []);
+    }
+
+    #[test]
+    fn pairs_are_two_documents() {
+        assert_eq!(parse_pair("0,3"), Ok(("0".to_string(), "3".to_string())));
+        assert!(parse_pair("0").is_err());
+        assert!(parse_pair("0,").is_err());
+        let ids = ["a", "b"].map(String::from);
+        let texts = ["cats", "dogs"].map(String::from);
+        assert_eq!(
+            resolve("--shared-terms", &("b".into(), "cats".into()), &ids, &texts),
+            (1, 0)
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::{
+    metrics,
+    search::{exact_neighbors, recall},
+    stats, table, DistanceMetric,
+};
+
+/// Score of every pair of distinct vectors, in the order of the upper triangle.
+pub fn pair_scores(vectors: &[Vec<f64>], distance_metric: &DistanceMetric) -> Vec<f64> {
+    (0..vectors.len())
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            (i + 1..vectors.len()).map(move |j| distance_metric.distance(&vectors[i], &vectors[j]))
+        })
+        .collect()
+}
+
+/// How far the pairwise scores and the top neighbors of some queries drift from those of the
+/// reference vectors.
+#[derive(Debug)]
+pub struct Drift {
+    pub mean: f64,
+    pub max: f64,
+    /// Rank correlation of the scores with the reference ones
+    pub spearman: f64,
+    /// Mean recall of the reference neighbors
+    pub recall: f64,
+}
+
+impl Drift {
+    /// The drift of the scores and neighbors of `vectors` from `reference_scores` and
+    /// `reference_neighbors`, the top `k` neighbors of `queries`.
+    pub fn measure(
+        vectors: &[Vec<f64>],
+        reference_scores: &[f64],
+        reference_neighbors: &[HashSet<usize>],
+        queries: &[usize],
+        k: usize,
+        distance_metric: &DistanceMetric,
+    ) -> Self {
+        let scores = pair_scores(vectors, distance_metric);
+        let drifts = scores
+            .iter()
+            .zip(reference_scores)
+            .map(|(score, reference_score)| (score - reference_score).abs())
+            .collect::<Vec<_>>();
+        let neighbors = exact_neighbors(vectors, queries, k, distance_metric);
+        let recalls = reference_neighbors
+            .iter()
+            .zip(&neighbors)
+            .map(|(reference, neighbors)| recall(reference, neighbors))
+            .sum::<f64>();
+        Drift {
+            mean: stats::mean(&drifts),
+            max: drifts.iter().copied().fold(0.0, f64::max),
+            spearman: stats::spearman(&scores, reference_scores),
+            recall: recalls / queries.len() as f64,
+        }
+    }
+
+    /// The mean and max drift, spearman and recall cells of a table row.
+    pub fn cells(&self) -> [String; 4] {
+        [self.mean, self.max, self.spearman, self.recall].map(table::format_score)
+    }
+}
+
+/// The vectors truncated to their first `dimensions`, scaled back to unit length when
+/// `normalize` is set.
+fn truncate(vectors: &[Vec<f64>], dimensions: usize, normalize: bool) -> Vec<Vec<f64>> {
+    vectors
+        .iter()
+        .map(|vector| {
+            let mut vector = vector[..dimensions].to_vec();
+            if normalize {
+                metrics::normalize(&mut vector);
+            }
+            vector
+        })
+        .collect()
+}
+
+/// The drift of the vectors truncated to each of `dimensions` shorter than the vectors, fewest
+/// dimensions first.
+fn sweep(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    dimensions: &[usize],
+    normalize: bool,
+    k: usize,
+    distance_metric: &DistanceMetric,
+) -> Vec<(usize, Drift)> {
+    let full_dimensions = vectors[0].len();
+    let full_scores = pair_scores(vectors, distance_metric);
+    let full_neighbors = exact_neighbors(vectors, queries, k, distance_metric);
+    dimensions
+        .iter()
+        .copied()
+        .filter(|&d| d > 0 && d < full_dimensions)
+        .sorted()
+        .map(|dimensions| {
+            let truncated = truncate(vectors, dimensions, normalize);
+            let drift = Drift::measure(
+                &truncated,
+                &full_scores,
+                &full_neighbors,
+                queries,
+                k,
+                distance_metric,
+            );
+            (dimensions, drift)
+        })
+        .collect()
+}
+
+/// Recomputes the scores with the vectors truncated to each of `dimensions`, as matryoshka
+/// models allow, and prints how far the scores and the top `k` neighbors of `queries` drift
+/// from those of the full vectors.
+///
+/// Truncated vectors are scaled back to unit length when `normalize` is set, as the full ones
+/// were.
+pub fn print_report(
+    vectors: &[Vec<f64>],
+    queries: &[usize],
+    dimensions: &[usize],
+    normalize: bool,
+    k: usize,
+    distance_metric: &DistanceMetric,
+) {
+    let mut table = vec![vec![
+        "dimensions".to_string(),
+        "mean drift".to_string(),
+        "max drift".to_string(),
+        "spearman".to_string(),
+        format!("recall@{k}"),
+    ]];
+    for (dimensions, drift) in sweep(vectors, queries, dimensions, normalize, k, distance_metric) {
+        table.push(
+            std::iter::once(dimensions.to_string())
+                .chain(drift.cells())
+                .collect(),
+        );
+    }
+    table.push(vec![
+        vectors[0].len().to_string(),
+        "0".to_string(),
+        "0".to_string(),
+        "1".to_string(),
+        "1".to_string(),
+    ]);
+
+    table::print(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vectors() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 0.0, 0.0, 1.0],
+            vec![1.0, 0.1, 1.0, 0.0],
+            vec![0.0, 1.0, 0.0, 1.0],
+            vec![0.2, 1.0, 1.0, 0.0],
+        ]
+    }
+
+    #[test]
+    fn pair_scores_follow_the_upper_triangle() {
+        let vectors = [vec![0.0, 0.0], vec![3.0, 4.0], vec![0.0, 1.0]];
+        assert_eq!(
+            pair_scores(&vectors, &DistanceMetric::L2),
+            [5.0, 1.0, 3f64.hypot(3.0)]
+        );
+    }
+
+    #[test]
+    fn truncated_vectors_keep_their_first_dimensions() {
+        assert_eq!(truncate(&vectors()[..1], 2, false), [vec![1.0, 0.0]]);
+        assert_eq!(truncate(&[vec![3.0, 4.0, 1.0]], 2, true), [vec![0.6, 0.8]]);
+    }
+
+    #[test]
+    fn the_sweep_skips_dimensions_the_vectors_lack() {
+        let queries = [0, 1, 2, 3];
+        let sweep = sweep(
+            &vectors(),
+            &queries,
+            &[4, 2, 9, 0, 1],
+            false,
+            1,
+            &DistanceMetric::L2,
+        );
+        assert_eq!(sweep.iter().map(|(d, _)| *d).collect::<Vec<_>>(), [1, 2]);
+
+        // With their first two dimensions only, the nearest neighbors swap: 0 and 1 look alike
+        // as do 2 and 3, while in full 0 is nearest 2 and 1 nearest 3
+        let (_, two) = &sweep[1];
+        assert_eq!(two.recall, 0.0);
+        assert!(two.max > 0.0 && two.max >= two.mean);
+        assert!(two.spearman < 1.0);
+    }
+
+    #[test]
+    fn no_drift_without_truncation() {
+        let vectors = vectors();
+        let queries = [0, 2];
+        let scores = pair_scores(&vectors, &DistanceMetric::Cosine);
+        let neighbors = exact_neighbors(&vectors, &queries, 2, &DistanceMetric::Cosine);
+        let drift = Drift::measure(
+            &vectors,
+            &scores,
+            &neighbors,
+            &queries,
+            2,
+            &DistanceMetric::Cosine,
+        );
+        assert_eq!((drift.mean, drift.max, drift.recall), (0.0, 0.0, 1.0));
+        assert!((drift.spearman - 1.0).abs() < 1e-12);
+        assert_eq!(drift.cells(), ["0", "0", "1", "1"]);
+    }
+}
@@ -0,0 +1,470 @@
+use std::{io, sync::Arc};
+
+use console::{pad_str, style, truncate_str, Alignment, Key, Term};
+
+use crate::{table, DistanceMetric};
+
+/// Width of the column of the documents' labels.
+const LABEL_WIDTH: usize = 24;
+/// Narrowest column of scores, which widens to fit the longest score under `--precision`.
+const CELL_WIDTH: usize = 10;
+/// Lines taken by the column headers and the status line.
+const CHROME_LINES: usize = 2;
+const HELP: &str =
+    "arrows/hjkl move, s sort by row, o order, / search, n next, enter inspect, q quit";
+/// Switches to the terminal's alternate screen, and back, so the view leaves no trace.
+const ENTER_ALTERNATE_SCREEN: &str = "\x1b[?1049h";
+const LEAVE_ALTERNATE_SCREEN: &str = "\x1b[?1049l";
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Browse,
+    /// Typing the query of `/`
+    Search(String),
+    /// Showing the full texts of the selected cell
+    Inspect,
+}
+
+/// State of the interactive matrix.
+struct View<'a> {
+    texts: &'a [String],
+    matrix: &'a [Vec<f64>],
+    distance_metric: &'a DistanceMetric,
+    /// Documents in the order of the rows, and of the columns
+    order: Vec<usize>,
+    /// Selected row and column, as positions in `order`
+    row: usize,
+    column: usize,
+    /// First row and column shown
+    top: usize,
+    left: usize,
+    /// Rows and columns of scores that fit in the terminal
+    rows_shown: usize,
+    columns_shown: usize,
+    /// Width of a column of scores
+    cell_width: usize,
+    mode: Mode,
+    query: String,
+    status: String,
+}
+
+impl<'a> View<'a> {
+    fn new(
+        texts: &'a [String],
+        matrix: &'a [Vec<f64>],
+        distance_metric: &'a DistanceMetric,
+    ) -> Self {
+        View {
+            texts,
+            matrix,
+            distance_metric,
+            order: (0..texts.len()).collect(),
+            row: 0,
+            column: 0,
+            top: 0,
+            left: 0,
+            rows_shown: 1,
+            columns_shown: 1,
+            cell_width: matrix
+                .iter()
+                .flatten()
+                .map(|score| table::format_score(*score).chars().count() + 2)
+                .max()
+                .unwrap_or(0)
+                .max(CELL_WIDTH),
+            mode: Mode::Browse,
+            query: String::new(),
+            status: HELP.to_string(),
+        }
+    }
+
+    /// Fits the view to a terminal of `width` columns and `height` lines, scrolling to keep the
+    /// selected cell shown.
+    fn resize(&mut self, width: usize, height: usize) {
+        self.rows_shown = height.saturating_sub(CHROME_LINES).max(1);
+        self.columns_shown = (width.saturating_sub(LABEL_WIDTH) / self.cell_width).max(1);
+        self.top = scrolled(self.top, self.row, self.rows_shown);
+        self.left = scrolled(self.left, self.column, self.columns_shown);
+    }
+
+    fn selected(&self) -> (usize, usize) {
+        (self.order[self.row], self.order[self.column])
+    }
+
+    /// Handles a key, returning whether the view stays open.
+    fn press(&mut self, key: Key) -> bool {
+        if self.order.is_empty() {
+            return false;
+        }
+        match &mut self.mode {
+            Mode::Inspect => self.mode = Mode::Browse,
+            Mode::Search(query) => match key {
+                Key::Char(character) => query.push(character),
+                Key::Backspace => {
+                    query.pop();
+                }
+                Key::Enter => {
+                    self.query = std::mem::take(query);
+                    self.mode = Mode::Browse;
+                    self.find_next();
+                }
+                Key::Escape | Key::CtrlC => self.mode = Mode::Browse,
+                _ => {}
+            },
+            Mode::Browse => {
+                let last = self.texts.len().saturating_sub(1);
+                match key {
+                    Key::Char('q') | Key::Escape | Key::CtrlC => return false,
+                    Key::ArrowUp | Key::Char('k') => self.row = self.row.saturating_sub(1),
+                    Key::ArrowDown | Key::Char('j') => self.row = (self.row + 1).min(last),
+                    Key::ArrowLeft | Key::Char('h') => self.column = self.column.saturating_sub(1),
+                    Key::ArrowRight | Key::Char('l') => self.column = (self.column + 1).min(last),
+                    Key::PageUp => self.row = self.row.saturating_sub(self.rows_shown),
+                    Key::PageDown => self.row = (self.row + self.rows_shown).min(last),
+                    Key::Home => self.column = 0,
+                    Key::End => self.column = last,
+                    Key::Char('s') => self.sort_by_selected_row(),
+                    Key::Char('o') => self.restore_order(),
+                    Key::Char('/') => self.mode = Mode::Search(String::new()),
+                    Key::Char('n') => self.find_next(),
+                    Key::Enter => self.mode = Mode::Inspect,
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+
+    /// Orders the rows and columns by closeness to the document of the selected row, which
+    /// comes first.
+    fn sort_by_selected_row(&mut self) {
+        let (document, _) = self.selected();
+        let scores = &self.matrix[document];
+        self.order.sort_by(|&a, &b| {
+            (b == document)
+                .cmp(&(a == document))
+                .then(self.distance_metric.cmp_closeness(scores[b], scores[a]))
+        });
+        (self.row, self.column) = (0, 0);
+        self.status = format!("Sorted by {} to document {document}", self.distance_metric);
+    }
+
+    fn restore_order(&mut self) {
+        let (row, column) = self.selected();
+        self.order = (0..self.texts.len()).collect();
+        (self.row, self.column) = (row, column);
+        self.status = HELP.to_string();
+    }
+
+    /// Selects the next row after the selected one whose text contains the query, ignoring case.
+    fn find_next(&mut self) {
+        if self.query.is_empty() {
+            return;
+        }
+        let query = self.query.to_lowercase();
+        let found = (1..=self.order.len())
+            .map(|offset| (self.row + offset) % self.order.len())
+            .find(|&row| self.texts[self.order[row]].to_lowercase().contains(&query));
+        self.status = match found {
+            Some(row) => {
+                self.row = row;
+                format!("Found {:?} in document {}", self.query, self.order[row])
+            }
+            None => format!("No document contains {:?}", self.query),
+        };
+    }
+
+    /// The lines of the view, as `resize` fitted it.
+    fn render(&self, width: usize) -> Vec<String> {
+        if self.order.is_empty() {
+            return vec![
+                "No documents to browse".to_string(),
+                "Press any key to quit".to_string(),
+            ];
+        }
+        if self.mode == Mode::Inspect {
+            return self.render_inspection(width);
+        }
+        let columns = self.left..(self.left + self.columns_shown).min(self.order.len());
+        let mut header = " ".repeat(LABEL_WIDTH);
+        for column in columns.clone() {
+            let cell = pad_str(
+                &format!("#{}", self.order[column]),
+                self.cell_width,
+                Alignment::Right,
+                None,
+            )
+            .to_string();
+            header.push_str(&style(cell).bold().to_string());
+        }
+        let mut lines = vec![header];
+
+        for row in self.top..(self.top + self.rows_shown).min(self.order.len()) {
+            let document = self.order[row];
+            let label = format!("{document}: {}", self.texts[document].replace('\n', " "));
+            let mut line = pad_str(&label, LABEL_WIDTH - 1, Alignment::Left, Some("…")).to_string();
+            line.push(' ');
+            for column in columns.clone() {
+                let score = self.matrix[document][self.order[column]];
+                let cell = table::format_score(score);
+                let cell = pad_str(&cell, self.cell_width, Alignment::Right, None).to_string();
+                let cell = match (row == self.row, column == self.column) {
+                    (true, true) => style(cell).reverse(),
+                    (true, false) | (false, true) => style(cell).cyan(),
+                    (false, false) => style(cell),
+                };
+                line.push_str(&cell.to_string());
+            }
+            lines.push(line);
+        }
+
+        let status = match &self.mode {
+            Mode::Search(query) => format!("/{query}"),
+            _ => {
+                let (row, column) = self.selected();
+                format!(
+                    "{row} × {column}: {} {} | {}",
+                    self.distance_metric,
+                    table::format_score(self.matrix[row][column]),
+                    self.status
+                )
+            }
+        };
+        lines.push(truncate_str(&status, width, "…").to_string());
+        lines
+    }
+
+    /// The full texts of the documents of the selected cell, and their score.
+    fn render_inspection(&self, width: usize) -> Vec<String> {
+        let (row, column) = self.selected();
+        let mut lines = vec![
+            format!(
+                "{} of {row} and {column}: {}",
+                self.distance_metric, self.matrix[row][column]
+            ),
+            String::new(),
+        ];
+        for document in [row, column] {
+            lines.push(style(format!("Document {document}")).bold().to_string());
+            lines.extend(wrap(&self.texts[document], width.max(1)));
+            lines.push(String::new());
+        }
+        lines.push("Press any key to go back".to_string());
+        lines
+    }
+}
+
+/// First of `shown` lines scrolled from `first` just enough to show `selected`.
+fn scrolled(first: usize, selected: usize, shown: usize) -> usize {
+    if selected < first {
+        selected
+    } else if selected >= first + shown {
+        selected + 1 - shown
+    } else {
+        first
+    }
+}
+
+/// Lines of `text` at most `width` characters long.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    text.lines()
+        .flat_map(|line| {
+            let characters = line.chars().collect::<Vec<_>>();
+            if characters.is_empty() {
+                return vec![String::new()];
+            }
+            characters
+                .chunks(width)
+                .map(|chunk| chunk.iter().collect())
+                .collect()
+        })
+        .collect()
+}
+
+/// Leaves the alternate screen and shows the cursor again.
+fn restore(term: &Term) {
+    let _ = term.show_cursor();
+    let _ = term.write_str(LEAVE_ALTERNATE_SCREEN);
+}
+
+type PanicHook = Box<dyn Fn(&std::panic::PanicHookInfo<'_>) + Send + Sync>;
+
+/// Restores the terminal when the view ends, whether it returns, fails or panics.
+struct TerminalGuard {
+    term: Term,
+    /// Panic hook of before the view, called after restoring the terminal so that the panic
+    /// message is printed on the normal screen
+    previous_hook: Arc<PanicHook>,
+}
+
+impl TerminalGuard {
+    fn enter(term: Term) -> io::Result<Self> {
+        let previous_hook = Arc::new(std::panic::take_hook());
+        let hook = previous_hook.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            restore(&Term::stdout());
+            hook(info);
+        }));
+        term.write_str(ENTER_ALTERNATE_SCREEN)?;
+        term.hide_cursor()?;
+        Ok(TerminalGuard {
+            term,
+            previous_hook,
+        })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore(&self.term);
+        if !std::thread::panicking() {
+            let previous_hook = self.previous_hook.clone();
+            std::panic::set_hook(Box::new(move |info| previous_hook(info)));
+        }
+    }
+}
+
+/// Browses the distance `matrix` of the documents of `texts` until the user quits.
+pub fn run(
+    texts: &[String],
+    matrix: &[Vec<f64>],
+    distance_metric: &DistanceMetric,
+) -> io::Result<()> {
+    let guard = TerminalGuard::enter(Term::stdout())?;
+    let term = &guard.term;
+    let mut view = View::new(texts, matrix, distance_metric);
+    loop {
+        let (height, width) = term.size();
+        let (height, width) = (usize::from(height), usize::from(width));
+        view.resize(width, height);
+        let lines = view.render(width);
+        term.clear_screen()?;
+        term.write_str(&lines[..lines.len().min(height)].join("\r\n"))?;
+        if !view.press(term.read_key()?) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> (Vec<String>, Vec<Vec<f64>>) {
+        let texts = ["apple pie", "car engine", "apple tart", "motor oil"]
+            .map(String::from)
+            .to_vec();
+        let matrix = vec![
+            vec![1.0, 0.1, 0.9, 0.2],
+            vec![0.1, 1.0, 0.2, 0.8],
+            vec![0.9, 0.2, 1.0, 0.3],
+            vec![0.2, 0.8, 0.3, 1.0],
+        ];
+        (texts, matrix)
+    }
+
+    fn plain(lines: Vec<String>) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| console::strip_ansi_codes(line).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn the_view_scrolls_to_the_selected_cell() {
+        let (texts, matrix) = corpus();
+        let mut view = View::new(&texts, &matrix, &DistanceMetric::Cosine);
+        view.resize(LABEL_WIDTH + 2 * CELL_WIDTH, CHROME_LINES + 2);
+        for key in [
+            Key::ArrowDown,
+            Key::ArrowDown,
+            Key::ArrowRight,
+            Key::ArrowRight,
+        ] {
+            assert!(view.press(key));
+        }
+        view.resize(LABEL_WIDTH + 2 * CELL_WIDTH, CHROME_LINES + 2);
+        assert_eq!((view.top, view.left), (1, 1));
+
+        let lines = plain(view.render(80));
+        assert_eq!(lines.len(), CHROME_LINES + 2);
+        assert!(lines[0].ends_with("#1        #2"), "{:?}", lines[0]);
+        assert!(lines[1].starts_with("1: car engine"));
+        assert!(lines[2].ends_with("0.2         1"), "{:?}", lines[2]);
+        assert!(lines[3].starts_with("2 × 2: cosine 1 |"));
+        assert!(!view.press(Key::Char('q')));
+    }
+
+    #[test]
+    fn an_empty_matrix_has_nothing_to_browse() {
+        let mut view = View::new(&[], &[], &DistanceMetric::Cosine);
+        view.resize(80, 24);
+        assert_eq!(view.render(80)[0], "No documents to browse");
+        assert!(!view.press(Key::Enter));
+    }
+
+    #[test]
+    fn score_columns_fit_the_longest_score() {
+        let (texts, matrix) = corpus();
+        let view = View::new(&texts, &matrix, &DistanceMetric::Cosine);
+        assert_eq!(view.cell_width, CELL_WIDTH);
+        let precise = vec![
+            vec![1.0, 0.123_456_789_012_345],
+            vec![0.123_456_789_012_345, 1.0],
+        ];
+        let view = View::new(&texts[..2], &precise, &DistanceMetric::Cosine);
+        assert_eq!(view.cell_width, "0.123456789012345".len() + 2);
+    }
+
+    #[test]
+    fn sorting_puts_the_closest_documents_first() {
+        let (texts, matrix) = corpus();
+        let mut view = View::new(&texts, &matrix, &DistanceMetric::Cosine);
+        view.press(Key::ArrowDown);
+        view.press(Key::Char('s'));
+        assert_eq!(view.order, [1, 3, 2, 0]);
+        view.press(Key::ArrowDown);
+        view.press(Key::Char('o'));
+        assert_eq!(view.order, [0, 1, 2, 3]);
+        assert_eq!(view.selected(), (3, 1));
+
+        let distances = matrix
+            .iter()
+            .map(|row| row.iter().map(|score| 1.0 - score).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let mut view = View::new(&texts, &distances, &DistanceMetric::CosineDistance);
+        view.press(Key::Char('s'));
+        assert_eq!(view.order, [0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn search_selects_the_next_matching_document() {
+        let (texts, matrix) = corpus();
+        let mut view = View::new(&texts, &matrix, &DistanceMetric::Cosine);
+        for key in "/APPLE".chars().map(Key::Char).chain([Key::Enter]) {
+            view.press(key);
+        }
+        assert_eq!(view.selected().0, 2);
+        view.press(Key::Char('n'));
+        assert_eq!(view.selected().0, 0);
+        for key in "/tofu".chars().map(Key::Char).chain([Key::Enter]) {
+            view.press(key);
+        }
+        assert_eq!(view.selected().0, 0);
+        assert!(view.status.contains("No document"));
+    }
+
+    #[test]
+    fn inspecting_shows_both_texts_and_their_score() {
+        let (texts, matrix) = corpus();
+        let mut view = View::new(&texts, &matrix, &DistanceMetric::Cosine);
+        view.press(Key::ArrowRight);
+        view.press(Key::Enter);
+        let lines = plain(view.render(6));
+        assert_eq!(lines[0], "cosine of 0 and 1: 0.1");
+        assert_eq!(lines[2..5], ["Document 0", "apple ", "pie"]);
+        assert_eq!(lines[6..9], ["Document 1", "car en", "gine"]);
+        assert!(view.press(Key::Char('q')));
+        assert_eq!(view.mode, Mode::Browse);
+    }
+}
@@ -0,0 +1,454 @@
+use std::{fmt::Display, io::Cursor, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float32Type, Float64Type, UInt64Type},
+    Array, ArrayRef, RecordBatch,
+};
+use arrow_ipc::reader::{FileReader, StreamReader};
+use arrow_schema::{ArrowError, DataType};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::allowlist;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum VectorStore {
+    Qdrant,
+    /// LanceDB Cloud or Enterprise, through its REST API
+    Lancedb,
+}
+
+impl Display for VectorStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VectorStore::Qdrant => write!(f, "qdrant"),
+            VectorStore::Lancedb => write!(f, "lancedb"),
+        }
+    }
+}
+
+impl VectorStore {
+    /// URL of the store when `--store-url` isn't given.
+    pub fn default_url(&self) -> String {
+        match self {
+            VectorStore::Qdrant => {
+                std::env::var("QDRANT_URL").unwrap_or("http://localhost:6333".to_string())
+            }
+            VectorStore::Lancedb => std::env::var("LANCEDB_URL")
+                .unwrap_or_else(|_| panic!("--store lancedb needs --store-url or LANCEDB_URL")),
+        }
+    }
+
+    /// Environment variable holding the API key of the store, if any.
+    fn api_key_variable(&self) -> &'static str {
+        match self {
+            VectorStore::Qdrant => "QDRANT_API_KEY",
+            VectorStore::Lancedb => "LANCEDB_API_KEY",
+        }
+    }
+}
+
+/// A point of the collection returned by a search.
+#[derive(Debug, PartialEq)]
+pub struct Hit {
+    pub id: String,
+    /// Field holding the text of the point, or its id if it has none
+    pub document: String,
+    pub vector: Vec<f64>,
+}
+
+/// Where and what to search in a vector store.
+pub struct Collection<'a> {
+    pub store: &'a VectorStore,
+    pub url: String,
+    /// Qdrant collection or LanceDB table
+    pub name: &'a str,
+    /// Vector searched in collections of several vectors per point
+    pub vector_name: Option<&'a str>,
+    /// Field the text of every point is read from
+    pub text_field: &'a str,
+}
+
+/// Column LanceDB tables store their vectors in unless told otherwise.
+const LANCEDB_VECTOR_COLUMN: &str = "vector";
+
+#[derive(Deserialize)]
+struct QdrantResponse {
+    result: Vec<QdrantPoint>,
+}
+
+#[derive(Deserialize)]
+struct QdrantPoint {
+    id: Value,
+    #[serde(default)]
+    payload: Option<serde_json::Map<String, Value>>,
+    vector: Value,
+}
+
+impl Collection<'_> {
+    /// The `limit` points of the collection closest to `vector` according to the store's own
+    /// index and distance, along with their stored vectors.
+    pub async fn search(&self, vector: &[f64], limit: usize) -> Vec<Hit> {
+        let (path, body) = match self.store {
+            VectorStore::Qdrant => (
+                format!("collections/{}/points/search", self.name),
+                self.qdrant_request(vector, limit),
+            ),
+            VectorStore::Lancedb => (
+                format!("v1/table/{}/query/", self.name),
+                self.lancedb_request(vector, limit),
+            ),
+        };
+        let url = format!("{}/{path}", self.url.trim_end_matches('/'));
+        allowlist::check(&url);
+
+        let mut request = allowlist::http_client().post(&url).json(&body);
+        if let Ok(api_key) = std::env::var(self.store.api_key_variable()) {
+            request = match self.store {
+                VectorStore::Qdrant => request.header("api-key", api_key),
+                VectorStore::Lancedb => request.header("x-api-key", api_key),
+            };
+        }
+
+        let response = request.send().await.unwrap_or_else(|error| {
+            panic!("Failed to reach {} at {}: {error}", self.store, self.url)
+        });
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            panic!(
+                "Searching {} in {} failed with {status}: {body}",
+                self.name, self.store
+            );
+        }
+
+        match self.store {
+            VectorStore::Qdrant => {
+                let response = response
+                    .json::<QdrantResponse>()
+                    .await
+                    .unwrap_or_else(|error| panic!("Invalid Qdrant response: {error}"));
+                self.qdrant_hits(response)
+            }
+            VectorStore::Lancedb => {
+                let stream = response
+                    .headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|value| value.contains("stream"));
+                let body = response
+                    .bytes()
+                    .await
+                    .unwrap_or_else(|error| panic!("Failed to read the LanceDB response: {error}"));
+                self.lancedb_hits(&body, stream)
+                    .unwrap_or_else(|error| panic!("Invalid LanceDB response: {error}"))
+            }
+        }
+    }
+
+    fn qdrant_request(&self, vector: &[f64], limit: usize) -> Value {
+        let vector = match self.vector_name {
+            Some(name) => json!({ "name": name, "vector": vector }),
+            None => json!(vector),
+        };
+        json!({
+            "vector": vector,
+            "limit": limit,
+            "with_payload": true,
+            "with_vector": true,
+        })
+    }
+
+    fn qdrant_hits(&self, response: QdrantResponse) -> Vec<Hit> {
+        response
+            .result
+            .into_iter()
+            .map(|point| {
+                let id = match point.id {
+                    Value::String(id) => id,
+                    id => id.to_string(),
+                };
+                // Collections of named vectors return every vector of the point by name
+                let vector = match (self.vector_name, point.vector) {
+                    (Some(name), Value::Object(mut vectors)) => {
+                        vectors.remove(name).unwrap_or(Value::Null)
+                    }
+                    (_, vector) => vector,
+                };
+                let vector = serde_json::from_value(vector).unwrap_or_else(|_| {
+                    panic!("Point {id} of {} has no dense vector to compare", self.name)
+                });
+                let document = point
+                    .payload
+                    .and_then(|mut payload| payload.remove(self.text_field))
+                    .and_then(|text| text.as_str().map(str::to_string))
+                    .unwrap_or_else(|| id.clone());
+
+                Hit {
+                    id,
+                    document,
+                    vector,
+                }
+            })
+            .collect()
+    }
+
+    fn lancedb_vector_column(&self) -> &str {
+        self.vector_name.unwrap_or(LANCEDB_VECTOR_COLUMN)
+    }
+
+    fn lancedb_request(&self, vector: &[f64], limit: usize) -> Value {
+        json!({
+            "vector": vector,
+            "k": limit,
+            "vector_column": self.lancedb_vector_column(),
+            "columns": [self.text_field, self.lancedb_vector_column()],
+            "with_row_id": true,
+        })
+    }
+
+    /// Hits of a LanceDB query answered with an Arrow IPC file, or stream if `stream`.
+    fn lancedb_hits(&self, body: &[u8], stream: bool) -> Result<Vec<Hit>, ArrowError> {
+        let batches = if stream {
+            StreamReader::try_new(Cursor::new(body), None)?.collect::<Result<Vec<_>, _>>()?
+        } else {
+            FileReader::try_new(Cursor::new(body), None)?.collect::<Result<Vec<_>, _>>()?
+        };
+
+        let mut hits = vec![];
+        for batch in batches {
+            let vectors = column(&batch, self.lancedb_vector_column())?;
+            let texts = batch.column_by_name(self.text_field);
+            let row_ids = batch.column_by_name("_rowid");
+            for row in 0..batch.num_rows() {
+                let id = match row_ids.and_then(|ids| ids.as_primitive_opt::<UInt64Type>()) {
+                    Some(ids) => ids.value(row).to_string(),
+                    None => hits.len().to_string(),
+                };
+                let document = texts
+                    .and_then(|texts| text(texts, row))
+                    .unwrap_or_else(|| id.clone());
+                hits.push(Hit {
+                    vector: vector(vectors, row)?,
+                    id,
+                    document,
+                });
+            }
+        }
+        Ok(hits)
+    }
+}
+
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a ArrayRef, ArrowError> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| ArrowError::SchemaError(format!("no {name} column")))
+}
+
+fn text(texts: &ArrayRef, row: usize) -> Option<String> {
+    if texts.is_null(row) {
+        return None;
+    }
+    match texts.data_type() {
+        DataType::Utf8 => Some(texts.as_string::<i32>().value(row).to_string()),
+        DataType::LargeUtf8 => Some(texts.as_string::<i64>().value(row).to_string()),
+        _ => None,
+    }
+}
+
+/// Row `row` of a column of fixed-size or variable-size lists of floats.
+fn vector(vectors: &ArrayRef, row: usize) -> Result<Vec<f64>, ArrowError> {
+    let values: Arc<dyn Array> = match vectors.data_type() {
+        DataType::FixedSizeList(..) => vectors.as_fixed_size_list().value(row),
+        DataType::List(_) => vectors.as_list::<i32>().value(row),
+        data_type => {
+            return Err(ArrowError::SchemaError(format!(
+                "vectors of type {data_type} aren't lists of floats"
+            )))
+        }
+    };
+    match values.data_type() {
+        DataType::Float32 => Ok(values
+            .as_primitive::<Float32Type>()
+            .values()
+            .iter()
+            .map(|value| *value as f64)
+            .collect()),
+        DataType::Float64 => Ok(values.as_primitive::<Float64Type>().values().to_vec()),
+        data_type => Err(ArrowError::SchemaError(format!(
+            "vector values of type {data_type} aren't floats"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{
+        builder::{FixedSizeListBuilder, Float32Builder},
+        StringArray, UInt64Array,
+    };
+    use arrow_ipc::writer::{FileWriter, StreamWriter};
+    use arrow_schema::{Field, Schema};
+
+    fn collection<'a>(store: &'a VectorStore, vector_name: Option<&'a str>) -> Collection<'a> {
+        Collection {
+            store,
+            url: "http://localhost".to_string(),
+            name: "docs",
+            vector_name,
+            text_field: "text",
+        }
+    }
+
+    /// A LanceDB answer of two rows with 2-dimensional f32 vectors.
+    fn lancedb_batch() -> RecordBatch {
+        let mut vectors = FixedSizeListBuilder::new(Float32Builder::new(), 2);
+        for vector in [[1.0, 0.5], [0.0, 2.0]] {
+            vectors.values().append_slice(&vector);
+            vectors.append(true);
+        }
+        let vectors = vectors.finish();
+        let schema = Schema::new(vec![
+            Field::new("text", DataType::Utf8, true),
+            Field::new("vector", vectors.data_type().clone(), true),
+            Field::new("_rowid", DataType::UInt64, false),
+        ]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from(vec![Some("refunds"), None])),
+                Arc::new(vectors),
+                Arc::new(UInt64Array::from(vec![7, 9])),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn lancedb_expected() -> Vec<Hit> {
+        vec![
+            Hit {
+                id: "7".to_string(),
+                document: "refunds".to_string(),
+                vector: vec![1.0, 0.5],
+            },
+            Hit {
+                id: "9".to_string(),
+                document: "9".to_string(),
+                vector: vec![0.0, 2.0],
+            },
+        ]
+    }
+
+    #[test]
+    fn qdrant_requests_name_the_vector_to_search() {
+        let store = VectorStore::Qdrant;
+        assert_eq!(
+            collection(&store, None).qdrant_request(&[1.0, 2.0], 3),
+            json!({"vector": [1.0, 2.0], "limit": 3, "with_payload": true, "with_vector": true})
+        );
+        assert_eq!(
+            collection(&store, Some("dense")).qdrant_request(&[1.0], 1)["vector"],
+            json!({"name": "dense", "vector": [1.0]})
+        );
+    }
+
+    #[test]
+    fn qdrant_points_become_hits() {
+        let store = VectorStore::Qdrant;
+        let response = serde_json::from_value(json!({"result": [
+            {"id": 1, "score": 0.9, "payload": {"text": "refunds"}, "vector": [1.0, 0.0]},
+            {"id": "a1b2", "score": 0.8, "payload": {}, "vector": [0.0, 1.0]},
+        ]}))
+        .unwrap();
+
+        assert_eq!(
+            collection(&store, None).qdrant_hits(response),
+            [
+                Hit {
+                    id: "1".to_string(),
+                    document: "refunds".to_string(),
+                    vector: vec![1.0, 0.0],
+                },
+                Hit {
+                    id: "a1b2".to_string(),
+                    document: "a1b2".to_string(),
+                    vector: vec![0.0, 1.0],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn qdrant_named_vectors_are_picked_by_name() {
+        let store = VectorStore::Qdrant;
+        let response = serde_json::from_value(json!({"result": [
+            {"id": 1, "vector": {"sparse": {"indices": [0], "values": [1.0]}, "dense": [0.5]}},
+        ]}))
+        .unwrap();
+
+        let hits = collection(&store, Some("dense")).qdrant_hits(response);
+        assert_eq!(hits[0].vector, [0.5]);
+    }
+
+    #[test]
+    fn lancedb_requests_select_the_text_and_vector_columns() {
+        let store = VectorStore::Lancedb;
+        assert_eq!(
+            collection(&store, Some("embedding")).lancedb_request(&[1.0], 5),
+            json!({
+                "vector": [1.0],
+                "k": 5,
+                "vector_column": "embedding",
+                "columns": ["text", "embedding"],
+                "with_row_id": true,
+            })
+        );
+    }
+
+    #[test]
+    fn lancedb_arrow_files_become_hits() {
+        let batch = lancedb_batch();
+        let mut file = vec![];
+        let mut writer = FileWriter::try_new(&mut file, &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        let store = VectorStore::Lancedb;
+        let hits = collection(&store, None).lancedb_hits(&file, false).unwrap();
+        assert_eq!(hits, lancedb_expected());
+    }
+
+    #[test]
+    fn lancedb_arrow_streams_become_hits() {
+        let batch = lancedb_batch();
+        let mut stream = vec![];
+        let mut writer = StreamWriter::try_new(&mut stream, &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        let store = VectorStore::Lancedb;
+        let hits = collection(&store, None)
+            .lancedb_hits(&stream, true)
+            .unwrap();
+        assert_eq!(hits, lancedb_expected());
+    }
+
+    #[test]
+    fn lancedb_answers_without_vectors_are_rejected() {
+        let batch = lancedb_batch();
+        let mut stream = vec![];
+        let mut writer = StreamWriter::try_new(&mut stream, &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        let store = VectorStore::Lancedb;
+        let hits = collection(&store, Some("missing")).lancedb_hits(&stream, true);
+        assert!(hits.is_err());
+    }
+}
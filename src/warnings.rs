@@ -0,0 +1,110 @@
+use std::sync::Mutex;
+
+/// Warnings of the current run, kept for the machine-readable outputs.
+static WARNINGS: Mutex<Warnings> = Mutex::new(Warnings::new());
+
+/// Warnings raised since they were last taken, each kept once however often it's raised.
+struct Warnings {
+    messages: Vec<String>,
+    /// Warnings kept since the start of the run, taken or not
+    raised: usize,
+}
+
+impl Warnings {
+    const fn new() -> Self {
+        Warnings {
+            messages: Vec::new(),
+            raised: 0,
+        }
+    }
+
+    /// Keeps `message` unless it's already kept, returning whether it was new.
+    fn push(&mut self, message: String) -> bool {
+        if self.messages.contains(&message) {
+            return false;
+        }
+        self.messages.push(message);
+        self.raised += 1;
+        true
+    }
+
+    fn take(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// Why `--deny-warnings` fails the run, if it raised any warning.
+    fn denied(&self) -> Result<(), String> {
+        match self.raised {
+            0 => Ok(()),
+            1 => Err("1 warning was raised and --deny-warnings is set".to_string()),
+            raised => Err(format!(
+                "{raised} warnings were raised and --deny-warnings is set"
+            )),
+        }
+    }
+}
+
+/// Prints a non-fatal warning to stderr and keeps it for the next [take], once until then.
+pub fn warn(message: String) {
+    let mut warnings = WARNINGS.lock().unwrap();
+    if !warnings.messages.contains(&message) {
+        eprintln!("{message}");
+    }
+    warnings.push(message);
+}
+
+/// The warnings raised since the previous call.
+pub fn take() -> Vec<String> {
+    WARNINGS.lock().unwrap().take()
+}
+
+/// Ends the run with an error if it raised any warning, for `--deny-warnings`.
+pub fn deny() {
+    if let Err(message) = WARNINGS.lock().unwrap().denied() {
+        eprintln!("error: {message}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_warnings_are_kept_once() {
+        let mut warnings = Warnings::new();
+        assert!(warnings.push("--normalize rescaled 3 vectors to unit length".to_string()));
+        assert!(warnings.push("Failed to write the shared cache: timeout".to_string()));
+        assert!(!warnings.push("--normalize rescaled 3 vectors to unit length".to_string()));
+        assert_eq!(
+            warnings.take(),
+            [
+                "--normalize rescaled 3 vectors to unit length",
+                "Failed to write the shared cache: timeout"
+            ]
+        );
+        assert!(warnings.take().is_empty());
+
+        // Once taken, a warning is kept again the next time it's raised
+        assert!(warnings.push("Failed to write the shared cache: timeout".to_string()));
+        assert_eq!(warnings.raised, 3);
+    }
+
+    #[test]
+    fn any_warning_of_the_run_is_denied() {
+        let mut warnings = Warnings::new();
+        assert_eq!(warnings.denied(), Ok(()));
+        warnings.push("first".to_string());
+        assert_eq!(
+            warnings.denied().unwrap_err(),
+            "1 warning was raised and --deny-warnings is set"
+        );
+        // Warnings already reported in a machine-readable output still count
+        warnings.take();
+        warnings.push("second".to_string());
+        assert_eq!(
+            warnings.denied().unwrap_err(),
+            "2 warnings were raised and --deny-warnings is set"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    io::{stdout, IsTerminal},
+    path::Path,
+    time::Duration,
+};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::{analyze, files, warnings, Args};
+
+/// Time editors get to finish saving before the input file is read again.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Runs the analysis, then again every time the input file is saved. The cache only embeds the
+/// documents that were added or edited since the previous run.
+pub async fn run(args: &Args) {
+    let input_file = &args.input_file[0];
+    assert!(
+        !Path::new(input_file).is_dir(),
+        "--watch needs a JSON input file, {input_file} is a directory"
+    );
+    let path = std::path::absolute(input_file)
+        .unwrap_or_else(|error| panic!("Failed to resolve {input_file}: {error}"));
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .expect("Failed to start watching files");
+    // Editors often save by replacing the file, which would end a watch on the file itself
+    watcher
+        .watch(path.parent().unwrap(), RecursiveMode::NonRecursive)
+        .unwrap_or_else(|error| panic!("Failed to watch {input_file}: {error}"));
+
+    loop {
+        // A half-edited file waits for the next save instead of ending the watch
+        match check_input(input_file) {
+            Ok(()) => {
+                if stdout().is_terminal() {
+                    print!("\x1b[2J\x1b[H");
+                }
+                analyze(args).await;
+            }
+            Err(error) => warnings::warn(format!("Skipping {input_file}: {error}")),
+        }
+
+        // Nothing reports the warnings of a run once printed, so they don't pile up
+        warnings::take();
+        eprintln!("Watching {input_file} for changes (Ctrl-C to stop)");
+        wait_for_change(&mut receiver, &path).await;
+    }
+}
+
+/// Whether the input file is a complete JSON array of documents.
+fn check_input(input_file: &str) -> Result<(), String> {
+    let contents = files::read_text(input_file).map_err(|error| error.to_string())?;
+    serde_json::from_str::<Vec<String>>(&contents).map_err(|error| error.to_string())?;
+    Ok(())
+}
+
+/// Whether `event` of the directory of `path` modifies it.
+fn changes(event: notify::Result<Event>, path: &Path) -> bool {
+    match event {
+        Ok(event) => {
+            !matches!(event.kind, EventKind::Access(_))
+                // Only the directory of `path` is watched
+                && event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == path.file_name())
+        }
+        Err(error) => {
+            warnings::warn(format!("Error watching {}: {error}", path.display()));
+            false
+        }
+    }
+}
+
+/// Waits for an event modifying `path`, then for the other events of the same save.
+async fn wait_for_change(receiver: &mut UnboundedReceiver<notify::Result<Event>>, path: &Path) {
+    while let Some(event) = receiver.recv().await {
+        if changes(event, path) {
+            break;
+        }
+    }
+    tokio::time::sleep(DEBOUNCE).await;
+    while receiver.try_recv().is_ok() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use notify::event::{AccessKind, CreateKind, DataChange, ModifyKind};
+
+    use super::*;
+
+    fn event(kind: EventKind, path: &str) -> notify::Result<Event> {
+        Ok(Event::new(kind).add_path(path.into()))
+    }
+
+    fn modify(path: &str) -> notify::Result<Event> {
+        event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), path)
+    }
+
+    #[test]
+    fn only_events_modifying_the_input_are_changes() {
+        let path = Path::new("/work/docs.json");
+        assert!(changes(modify("/work/docs.json"), path));
+        assert!(changes(
+            event(EventKind::Create(CreateKind::File), "/work/docs.json"),
+            path
+        ));
+        assert!(!changes(modify("/work/notes.txt"), path));
+        assert!(!changes(
+            event(EventKind::Access(AccessKind::Any), "/work/docs.json"),
+            path
+        ));
+    }
+
+    #[test]
+    fn half_edited_inputs_are_skipped() {
+        let path = std::env::temp_dir().join(format!(
+            "distance-calculator-watch-{}-docs.json",
+            std::process::id()
+        ));
+        let input_file = path.to_str().unwrap();
+        std::fs::write(&path, r#"["cat", "dog"]"#).unwrap();
+        assert_eq!(check_input(input_file), Ok(()));
+        std::fs::write(&path, r#"["cat", "do"#).unwrap();
+        assert!(check_input(input_file).unwrap_err().contains("EOF"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(check_input(input_file).is_err());
+    }
+
+    #[tokio::test]
+    async fn the_events_of_one_save_are_debounced() {
+        let path = Path::new("/work/docs.json");
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        sender
+            .send(event(EventKind::Access(AccessKind::Any), "/work/docs.json"))
+            .unwrap();
+        sender.send(modify("/work/notes.txt")).unwrap();
+        sender.send(modify("/work/docs.json")).unwrap();
+        let (late_sender, started) = (sender.clone(), Instant::now());
+        tokio::spawn(async move {
+            tokio::time::sleep(DEBOUNCE / 4).await;
+            late_sender.send(modify("/work/docs.json")).unwrap();
+        });
+
+        wait_for_change(&mut receiver, path).await;
+        assert!(started.elapsed() >= DEBOUNCE);
+        // The event written during the debounce belonged to the same save
+        assert!(receiver.try_recv().is_err());
+
+        // Events not changing the input don't end the next wait
+        sender.send(modify("/work/notes.txt")).unwrap();
+        let wait = tokio::time::timeout(DEBOUNCE, wait_for_change(&mut receiver, path));
+        assert!(wait.await.is_err());
+    }
+}
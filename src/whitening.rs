@@ -0,0 +1,347 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+
+/// Whitened dimensions kept by default, as in BERT-whitening.
+pub const DEFAULT_WHITEN_DIMENSIONS: u16 = 256;
+/// Sweeps of the Jacobi eigenvalue algorithm before giving up on converging further.
+const JACOBI_SWEEPS: usize = 50;
+/// Variance below which a direction, relative to the largest, is taken as empty.
+const MIN_RELATIVE_VARIANCE: f64 = 1e-10;
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum VectorTransform {
+    /// Compare the vectors as they are
+    None,
+    /// Subtract the mean vector of the documents from every vector
+    Center,
+    /// Center, rotate onto the principal components of the documents and scale each to unit
+    /// variance, keeping the `--whiten-dims` components of most variance
+    Whiten,
+}
+
+impl Display for VectorTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VectorTransform::None => write!(f, "none"),
+            VectorTransform::Center => write!(f, "center"),
+            VectorTransform::Whiten => write!(f, "whiten"),
+        }
+    }
+}
+
+/// A transform fitted on the vectors of the documents, to apply to them and their chunks.
+pub struct Fitted {
+    mean: Vec<f64>,
+    /// Principal components over the square root of their variance, `None` to only center
+    components: Option<Vec<Vec<f64>>>,
+}
+
+impl Fitted {
+    /// Fits `transform` on `vectors`, keeping at most `dimensions` components when whitening.
+    pub fn new(transform: &VectorTransform, vectors: &[&[f64]], dimensions: usize) -> Self {
+        let width = vectors.first().map_or(0, |vector| vector.len());
+        let mut mean = vec![0.0; width];
+        if *transform == VectorTransform::None {
+            return Fitted {
+                mean,
+                components: None,
+            };
+        }
+        for vector in vectors {
+            for (total, value) in mean.iter_mut().zip(*vector) {
+                *total += value / vectors.len() as f64;
+            }
+        }
+        let components = (*transform == VectorTransform::Whiten).then(|| {
+            let centered = vectors
+                .iter()
+                .map(|vector| subtract(vector, &mean))
+                .collect::<Vec<_>>();
+            whitening_components(&centered, dimensions)
+        });
+        Fitted { mean, components }
+    }
+
+    pub fn apply(&self, vector: &mut Vec<f64>) {
+        let centered = subtract(vector, &self.mean);
+        *vector = match &self.components {
+            Some(components) => components
+                .iter()
+                .map(|component| dot(&centered, component))
+                .collect(),
+            None => centered,
+        };
+    }
+
+    /// Dimensions of the transformed vectors.
+    pub fn dimensions(&self) -> usize {
+        self.components
+            .as_ref()
+            .map_or(self.mean.len(), |components| components.len())
+    }
+}
+
+/// Warning that whitening `documents` vectors into `dimensions` kept every direction they span,
+/// which leaves them all equally close. Fewer than two documents have nothing to compare.
+pub fn collapse_warning(
+    transform: &VectorTransform,
+    dimensions: usize,
+    documents: usize,
+) -> Option<String> {
+    (*transform == VectorTransform::Whiten && documents >= 2 && dimensions + 1 >= documents).then(
+        || {
+            format!(
+                "--vector-transform whiten kept every direction the {documents} documents span, \
+                 which leaves them all equally close; lower --whiten-dims below {} to compare \
+                 them",
+                documents - 1
+            )
+        },
+    )
+}
+
+fn dot(first: &[f64], second: &[f64]) -> f64 {
+    first.iter().zip(second).map(|(a, b)| a * b).sum()
+}
+
+fn subtract(vector: &[f64], mean: &[f64]) -> Vec<f64> {
+    vector
+        .iter()
+        .zip(mean)
+        .map(|(value, mean)| value - mean)
+        .collect()
+}
+
+/// The principal components of the centered rows of most variance, each divided by the square
+/// root of its variance so that projecting onto them whitens. The eigenvectors come from the
+/// covariance matrix, or from the Gram matrix of the rows when there are fewer rows than
+/// dimensions, which spans the same components at a fraction of the size.
+fn whitening_components(centered: &[Vec<f64>], dimensions: usize) -> Vec<Vec<f64>> {
+    let rows = centered.len();
+    let width = centered.first().map_or(0, Vec::len);
+    if rows < 2 {
+        return vec![];
+    }
+    let degrees = (rows - 1) as f64;
+    let gram = rows < width;
+    let size = if gram { rows } else { width };
+    let matrix = (0..size)
+        .into_par_iter()
+        .map(|p| {
+            (0..size)
+                .map(|q| {
+                    if gram {
+                        dot(&centered[p], &centered[q]) / degrees
+                    } else {
+                        centered.iter().map(|row| row[p] * row[q]).sum::<f64>() / degrees
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let (variances, eigenvectors) = symmetric_eigen(matrix);
+    let largest = variances.first().copied().unwrap_or(0.0);
+    variances
+        .iter()
+        .zip(eigenvectors)
+        .take_while(|(variance, _)| **variance > largest * MIN_RELATIVE_VARIANCE)
+        .take(dimensions)
+        .map(|(variance, eigenvector)| {
+            let component = if gram {
+                // Rows combined by the eigenvector of the Gram matrix have a norm of
+                // sqrt(degrees * variance)
+                let norm = (degrees * variance).sqrt();
+                (0..width)
+                    .map(|k| {
+                        centered
+                            .iter()
+                            .zip(&eigenvector)
+                            .map(|(row, weight)| row[k] * weight)
+                            .sum::<f64>()
+                            / norm
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                eigenvector
+            };
+            component
+                .into_iter()
+                .map(|value| value / variance.sqrt())
+                .collect()
+        })
+        .collect()
+}
+
+/// Eigenvalues of a symmetric matrix in decreasing order, with their unit eigenvectors, by the
+/// cyclic Jacobi eigenvalue algorithm.
+fn symmetric_eigen(mut matrix: Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let size = matrix.len();
+    // Columns are the eigenvectors
+    let mut rotations = (0..size)
+        .map(|i| (0..size).map(|j| f64::from(u8::from(i == j))).collect())
+        .collect::<Vec<Vec<f64>>>();
+    let total = matrix
+        .iter()
+        .flatten()
+        .map(|value| value * value)
+        .sum::<f64>();
+
+    for _ in 0..JACOBI_SWEEPS {
+        let off_diagonal = (0..size)
+            .flat_map(|p| (0..size).filter(move |q| *q != p).map(move |q| (p, q)))
+            .map(|(p, q)| matrix[p][q] * matrix[p][q])
+            .sum::<f64>();
+        if off_diagonal <= total * f64::EPSILON * f64::EPSILON {
+            break;
+        }
+        for p in 0..size {
+            for q in p + 1..size {
+                if matrix[p][q] == 0.0 {
+                    continue;
+                }
+                let theta = (matrix[q][q] - matrix[p][p]) / (2.0 * matrix[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let cos = 1.0 / (t * t + 1.0).sqrt();
+                let sin = t * cos;
+                for row in matrix.iter_mut().chain(rotations.iter_mut()) {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = cos * kp - sin * kq;
+                    row[q] = sin * kp + cos * kq;
+                }
+                let (head, tail) = matrix.split_at_mut(q);
+                for (pk, qk) in head[p].iter_mut().zip(tail[0].iter_mut()) {
+                    (*pk, *qk) = (cos * *pk - sin * *qk, sin * *pk + cos * *qk);
+                }
+            }
+        }
+    }
+
+    let mut order = (0..size).collect::<Vec<_>>();
+    order.sort_by(|a, b| matrix[*b][*b].total_cmp(&matrix[*a][*a]));
+    let eigenvalues = order.iter().map(|i| matrix[*i][*i]).collect();
+    let eigenvectors = order
+        .iter()
+        .map(|i| rotations.iter().map(|row| row[*i]).collect())
+        .collect();
+    (eigenvalues, eigenvectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vectors() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 2.0, 0.5],
+            vec![2.0, 1.0, 0.0],
+            vec![0.0, 0.5, 1.0],
+            vec![3.0, 3.5, 2.0],
+            vec![1.5, 0.0, 1.0],
+        ]
+    }
+
+    fn transformed(
+        transform: &VectorTransform,
+        vectors: &[Vec<f64>],
+        dimensions: usize,
+    ) -> Vec<Vec<f64>> {
+        let rows = vectors.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let fitted = Fitted::new(transform, &rows, dimensions);
+        vectors
+            .iter()
+            .map(|vector| {
+                let mut vector = vector.clone();
+                fitted.apply(&mut vector);
+                vector
+            })
+            .collect()
+    }
+
+    fn covariance(vectors: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        let width = vectors[0].len();
+        (0..width)
+            .map(|p| {
+                (0..width)
+                    .map(|q| {
+                        vectors.iter().map(|row| row[p] * row[q]).sum::<f64>()
+                            / (vectors.len() - 1) as f64
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn eigenvectors_diagonalize_symmetric_matrices() {
+        let matrix = vec![
+            vec![4.0, 1.0, 0.5],
+            vec![1.0, 3.0, 0.2],
+            vec![0.5, 0.2, 1.0],
+        ];
+        let (eigenvalues, eigenvectors) = symmetric_eigen(matrix.clone());
+        assert!(eigenvalues.windows(2).all(|pair| pair[0] >= pair[1]));
+        for (eigenvalue, eigenvector) in eigenvalues.iter().zip(&eigenvectors) {
+            for (row, value) in matrix.iter().zip(eigenvector) {
+                assert!((dot(row, eigenvector) - eigenvalue * value).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_and_single_document_corpora_are_transformed_without_warning() {
+        for transform in [VectorTransform::Center, VectorTransform::Whiten] {
+            assert!(transformed(&transform, &[], 8).is_empty());
+            let single = transformed(&transform, &vectors()[..1], 8);
+            assert_eq!(single.len(), 1);
+            assert!(single[0].iter().all(|value| value.abs() < 1e-12));
+            for documents in [0, 1] {
+                assert_eq!(collapse_warning(&transform, 8, documents), None);
+            }
+        }
+        assert!(collapse_warning(&VectorTransform::Whiten, 8, 5)
+            .unwrap()
+            .contains("below 4"));
+        assert_eq!(collapse_warning(&VectorTransform::Whiten, 3, 5), None);
+        assert_eq!(collapse_warning(&VectorTransform::Center, 8, 5), None);
+    }
+
+    #[test]
+    fn centering_removes_the_mean() {
+        let centered = transformed(&VectorTransform::Center, &vectors(), 0);
+        for k in 0..3 {
+            assert!(centered.iter().map(|row| row[k]).sum::<f64>().abs() < 1e-12);
+        }
+        for (value, expected) in centered[1].iter().zip([0.5, -0.4, -0.9]) {
+            assert!((value - expected).abs() < 1e-12);
+        }
+        assert_eq!(
+            transformed(&VectorTransform::None, &vectors(), 0),
+            vectors()
+        );
+    }
+
+    #[test]
+    fn whitened_vectors_have_unit_covariance() {
+        let whitened = transformed(&VectorTransform::Whiten, &vectors(), 3);
+        for (p, row) in covariance(&whitened).iter().enumerate() {
+            for (q, value) in row.iter().enumerate() {
+                let expected = if p == q { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-9, "{p} {q} {value}");
+            }
+        }
+
+        // Fewer documents than dimensions whiten through the Gram matrix
+        let wide = vectors()
+            .into_iter()
+            .map(|row| row.iter().chain(&row).map(|value| value * value).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let whitened = transformed(&VectorTransform::Whiten, &wide, 2);
+        assert_eq!(whitened[0].len(), 2);
+        let covariance = covariance(&whitened);
+        assert!((covariance[0][0] - 1.0).abs() < 1e-9);
+        assert!(covariance[0][1].abs() < 1e-9);
+    }
+}